@@ -0,0 +1,187 @@
+//! Measures the systems relying on the spatial index: the influence of 10k ships, and the selection of objects on the
+//! map, paused and with time running, compared against linear scans over all the spheres. Run with
+//! `cargo bench --bench spatial_index`.
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use bevy::{ecs::system::SystemState, math::DVec3, prelude::*};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rust_space_trading::{
+    physics::influence::{update_influence, HillRadius, InfluenceIndex},
+    prelude::*,
+    ui::gui::{SelectionIndex, SelectionRadius},
+};
+
+const SHIPS: usize = 10_000;
+const SELECTABLE: [usize; 3] = [100, 1_000, 10_000];
+const CLICKS: usize = 1_000;
+
+fn random_vector(rng: &mut StdRng, scale: f64) -> DVec3 {
+    DVec3::new(
+        rng.gen_range(-scale..scale),
+        rng.gen_range(-scale..scale),
+        rng.gen_range(-scale..scale),
+    )
+}
+
+fn per_item(elapsed: Duration, items: usize) -> f64 {
+    elapsed.as_nanos() as f64 / items as f64
+}
+
+/// A game with all the bodies down to the comets, and ships scattered around them
+fn game_with_ships(rng: &mut StdRng) -> App {
+    let mut app = App::new();
+    app.add_plugins(
+        ClientPlugin::testing()
+            .with_bodies(BodiesConfig::SmallestBodyType(BodyType::Comet))
+            .in_mode(ClientMode::Singleplayer),
+    );
+    app.world_mut().resource_mut::<Time<Virtual>>().pause();
+    app.update();
+    let world = app.world_mut();
+    let bodies: Vec<_> = world
+        .query::<(&Position, &HillRadius)>()
+        .iter(world)
+        .filter(|(_, radius)| radius.0 > 0.)
+        .map(|(pos, radius)| (pos.0, radius.0.min(1e9)))
+        .collect();
+    for i in 0..SHIPS {
+        // Ships gather around the bodies, and some of them are inside the spheres of influence of their moons
+        let (center, radius) = bodies[rng.gen_range(0..bodies.len())];
+        world.send_event(ShipEvent::Create(ShipInfo {
+            id: id_from(&format!("s{i}")),
+            spawn_pos: center + random_vector(rng, radius),
+            spawn_speed: DVec3::ZERO,
+        }));
+    }
+    app.update();
+    app
+}
+
+fn influence(rng: &mut StdRng) {
+    let mut app = game_with_ships(rng);
+    let world = app.world_mut();
+
+    let mut system = IntoSystem::into_system(update_influence);
+    system.initialize(world);
+    let start = Instant::now();
+    system.run((), world);
+    let indexed = start.elapsed();
+
+    let mut state = SystemState::<(
+        Query<&Position, With<ShipInfo>>,
+        Res<InfluenceIndex>,
+        Query<(&Position, &HillRadius, &BodyInfo)>,
+        Res<BodiesMapping>,
+    )>::new(world);
+    let (ships, index, bodies, mapping) = state.get(world);
+    let start = Instant::now();
+    for pos in ships.iter() {
+        black_box(Influenced::new(pos, &index, &bodies, &mapping));
+    }
+    let single = start.elapsed();
+
+    let start = Instant::now();
+    for pos in ships.iter() {
+        black_box(
+            index
+                .0
+                .iter()
+                .filter(|sphere| sphere.contains(pos.0))
+                .count(),
+        );
+    }
+    let scan = start.elapsed();
+
+    println!(
+        "{} ships, {} bodies: update_influence {:.1} ns/ship, Influenced::new {:.1} ns/ship, scan {:.1} ns/ship",
+        ships.iter().count(),
+        index.0.len(),
+        per_item(indexed, SHIPS),
+        per_item(single, SHIPS),
+        per_item(scan, SHIPS),
+    );
+}
+
+fn selection(rng: &mut StdRng) {
+    println!(
+        "{:>8} {:>16} {:>16} {:>16} {:>16}",
+        "objects", "rebuild (µs)", "click (ns)", "running (ns)", "scan (ns)"
+    );
+    for size in SELECTABLE {
+        let mut world = World::new();
+        for _ in 0..size {
+            let pos = random_vector(rng, 1e5).as_vec3();
+            world.spawn((
+                GlobalTransform::from_translation(pos),
+                SelectionRadius {
+                    min_radius: 1.,
+                    actual_radius: rng.gen_range(0.1..100.),
+                },
+            ));
+        }
+        let mut query = world.query::<(Entity, &GlobalTransform, &SelectionRadius)>();
+        let mut transforms = world.query::<&mut GlobalTransform>();
+        let clicks: Vec<_> = (0..CLICKS)
+            .map(|_| random_vector(rng, 1e5).with_z(0.))
+            .collect();
+        let mut index = SelectionIndex::default();
+
+        let start = Instant::now();
+        index.rebuild(query.iter(&world), 1.);
+        let rebuild = start.elapsed();
+
+        let start = Instant::now();
+        for click in &clicks {
+            black_box(index.select(query.iter(&world), 1., *click));
+        }
+        let indexed = start.elapsed();
+
+        // With time running, the objects move between every click so the index is always outdated
+        let mut running = Duration::ZERO;
+        for click in &clicks {
+            for mut transform in transforms.iter_mut(&mut world) {
+                *transform = GlobalTransform::from_translation(transform.translation() + Vec3::X);
+            }
+            index.invalidate();
+            let start = Instant::now();
+            black_box(index.select(query.iter(&world), 1., *click));
+            running += start.elapsed();
+        }
+
+        let start = Instant::now();
+        for click in &clicks {
+            black_box(
+                query
+                    .iter(&world)
+                    .filter(|(_, pos, radius)| {
+                        pos.translation()
+                            .xy()
+                            .as_dvec2()
+                            .extend(0.)
+                            .distance(*click)
+                            <= radius.radius(1.) as f64
+                    })
+                    .count(),
+            );
+        }
+        let scan = start.elapsed();
+
+        println!(
+            "{:>8} {:>16.1} {:>16.1} {:>16.1} {:>16.1}",
+            size,
+            rebuild.as_secs_f64() * 1e6,
+            per_item(indexed, CLICKS),
+            per_item(running, CLICKS),
+            per_item(scan, CLICKS),
+        );
+    }
+}
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(0);
+    influence(&mut rng);
+    selection(&mut rng);
+}
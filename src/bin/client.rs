@@ -1,7 +1,13 @@
 use std::env;
 
 use bevy::app::App;
-use rust_space_trading::{prelude::*, ui::gui::GuiPlugin, utils::args::get_keymap};
+use rust_space_trading::{
+    prelude::*,
+    ui::gui::GuiPlugin,
+    utils::args::{
+        get_body_dynamics, get_diagnostics_file, get_keymap, get_perturbations, get_player_company,
+    },
+};
 
 fn main() {
     #[allow(unused_variables)]
@@ -9,17 +15,23 @@ fn main() {
     #[cfg(feature = "asteroids")]
     let singleplayer_bodies_config = BodiesConfig::SmallestBodyType(BodyType::Comet);
 
-    App::new()
-        .add_plugins((
-            ClientPlugin {
-                singleplayer_bodies_config,
-                ..Default::default()
-            },
-            TuiPlugin {
-                keymap: get_keymap(env::args()).unwrap(),
-                ..Default::default()
-            },
-            GuiPlugin,
-        ))
-        .run();
+    let mut app = App::new();
+    app.add_plugins((
+        ClientPlugin {
+            singleplayer_bodies_config,
+            ..Default::default()
+        },
+        TuiPlugin {
+            keymap: get_keymap(env::args()).unwrap(),
+            ..Default::default()
+        },
+        GuiPlugin,
+    ))
+    .insert_resource(get_body_dynamics(env::args()))
+    .insert_resource(get_perturbations(env::args()))
+    .insert_resource(get_player_company(env::args()).unwrap());
+    if let Some(file) = get_diagnostics_file(env::args()) {
+        app.insert_resource(file);
+    }
+    app.run();
 }
@@ -1,7 +1,13 @@
-use std::net::{IpAddr, Ipv4Addr};
+use std::{
+    env,
+    net::{IpAddr, Ipv4Addr},
+};
 
 use bevy::app::App;
-use rust_space_trading::prelude::*;
+use rust_space_trading::{
+    prelude::*,
+    utils::args::{get_body_dynamics, get_perturbations},
+};
 
 fn main() {
     App::new()
@@ -12,5 +18,7 @@ fn main() {
             },
             bevy::app::ScheduleRunnerPlugin::default(),
         ))
+        .insert_resource(get_body_dynamics(env::args()))
+        .insert_resource(get_perturbations(env::args()))
         .run();
 }
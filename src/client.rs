@@ -7,9 +7,14 @@ use bevy_quinnet::client::{
 };
 
 use crate::{
-    game::GamePlugin,
-    network::{ClientChannel, ServerMessage},
-    objects::prelude::BodiesConfig,
+    game::{GamePlugin, GameSeed},
+    network::{ClientChannel, ClientMessage, ServerMessage},
+    objects::{
+        contracts::ContractEvent,
+        markets::{TradeEvent, TradeOutcome},
+        prelude::{BodiesConfig, BodiesMapping, PlayerCompany},
+        ships::{trajectory::TrajectoryEvent, ShipEvent},
+    },
     prelude::{GameTime, ToggleTime},
     utils::ecs::exit_on_error_if_app,
 };
@@ -65,11 +70,16 @@ impl Plugin for ClientPlugin {
         ))
         .insert_resource(self.network_info.clone())
         .insert_resource(self.singleplayer_bodies_config.clone())
+        .init_resource::<PlayerCompany>()
         .insert_state(self.initial_mode)
         .add_systems(
             OnEnter(ClientMode::Multiplayer),
             start_connection.pipe(exit_on_error_if_app),
         )
+        .add_systems(
+            OnExit(ClientMode::Multiplayer),
+            close_connection.pipe(exit_on_error_if_app),
+        )
         .add_systems(
             OnEnter(ClientMode::Explorer),
             move |mut toggle: ResMut<ToggleTime>, mut time: ResMut<GameTime>| {
@@ -83,7 +93,14 @@ impl Plugin for ClientPlugin {
         )
         .add_systems(
             Update,
-            handle_server_messages.run_if(in_state(ClientMode::Multiplayer)),
+            (
+                handle_server_messages,
+                send_ships,
+                send_trades,
+                send_trajectories,
+                send_contract_actions,
+            )
+                .run_if(in_state(ClientMode::Multiplayer)),
         );
     }
 }
@@ -112,6 +129,7 @@ fn start_connection(
     mut client: ResMut<QuinnetClient>,
     client_info: Res<ClientNetworkInfo>,
     server_info: Res<ServerNetworkInfo>,
+    player: Res<PlayerCompany>,
 ) -> color_eyre::Result<()> {
     let ClientNetworkInfo(ca, cp) = *client_info;
     let ServerNetworkInfo(sa, sp) = *server_info;
@@ -120,6 +138,15 @@ fn start_connection(
         CertificateVerificationMode::SkipVerification,
         ClientChannel::channels_configuration(),
     )?;
+    // The server has no company for the player until it joins
+    client
+        .connection_mut()
+        .send_message_on(ClientChannel::Commands, ClientMessage::Join(player.0))?;
+    Ok(())
+}
+
+fn close_connection(mut client: ResMut<QuinnetClient>) -> color_eyre::Result<()> {
+    client.close_all_connections()?;
     Ok(())
 }
 
@@ -135,6 +162,9 @@ fn handle_server_messages(
     mut commands: Commands,
     mut time: ResMut<GameTime>,
     mut sync: ResMut<NextState<SyncStatus>>,
+    mut mode: ResMut<NextState<ClientMode>>,
+    mut outcomes: EventWriter<TradeOutcome>,
+    mapping: Option<Res<BodiesMapping>>,
 ) {
     while let Some((_, message)) = client
         .connection_mut()
@@ -145,7 +175,157 @@ fn handle_server_messages(
                 commands.insert_resource(bodies);
                 sync.set(SyncStatus::Synced);
             }
+            ServerMessage::GameSeed(seed) => commands.insert_resource(GameSeed(seed)),
             ServerMessage::UpdateTime(simtick) => time.simtick = simtick,
+            ServerMessage::UpdateMarkets(markets) => {
+                let Some(mapping) = &mapping else {
+                    continue;
+                };
+                for (id, market) in markets {
+                    if let Some(entity) = mapping.0.get(&id) {
+                        commands.entity(*entity).insert(market);
+                    }
+                }
+            }
+            ServerMessage::UpdateContracts(contracts) => commands.insert_resource(contracts),
+            ServerMessage::JoinRejected(company) => {
+                println!("Company {company} is already played by another client");
+                mode.set(ClientMode::None);
+            }
+            ServerMessage::TradeOutcome {
+                mut outcome,
+                market,
+            } => {
+                // The market is the entity of the body on the server
+                if let TradeOutcome::Completed { market: entity, .. } = &mut outcome {
+                    let Some(local) = market.and_then(|id| mapping.as_ref()?.0.get(&id).copied())
+                    else {
+                        continue;
+                    };
+                    *entity = local;
+                }
+                outcomes.send(outcome);
+            }
+        }
+    }
+}
+
+/// The server creates the ships of the player too, and gives them to its company
+fn send_ships(mut client: ResMut<QuinnetClient>, mut ships: EventReader<ShipEvent>) {
+    for event in ships.read() {
+        if let ShipEvent::Create(info) = event {
+            client.connection_mut().try_send_message_on(
+                ClientChannel::Commands,
+                ClientMessage::CreateShip(info.clone()),
+            );
         }
     }
 }
+
+/// Trajectories are also written to the files of the server, which checks that the player owns the ship
+fn send_trajectories(
+    mut client: ResMut<QuinnetClient>,
+    mut trajectories: EventReader<TrajectoryEvent>,
+) {
+    for event in trajectories.read() {
+        client.connection_mut().try_send_message_on(
+            ClientChannel::Commands,
+            ClientMessage::Trajectory(event.clone()),
+        );
+    }
+}
+
+/// Trades are only applied by the server, which is sent the orders requested by the player and trades on behalf of
+/// its company
+fn send_trades(mut client: ResMut<QuinnetClient>, mut trades: EventReader<TradeEvent>) {
+    for trade in trades.read() {
+        client.connection_mut().try_send_message_on(
+            ClientChannel::Commands,
+            ClientMessage::Trade(trade.order.clone()),
+        );
+    }
+}
+
+/// Contracts are only taken by the server, on behalf of the company of the player
+fn send_contract_actions(
+    mut client: ResMut<QuinnetClient>,
+    mut events: EventReader<ContractEvent>,
+) {
+    for event in events.read() {
+        client.connection_mut().try_send_message_on(
+            ClientChannel::Commands,
+            ClientMessage::Contract(event.action.clone()),
+        );
+    }
+}
+
+/// Helpers for the tests running the simulation step by step
+#[cfg(test)]
+pub mod testing {
+    use std::f64::consts::PI;
+
+    use bevy::{app::FixedMain, math::DVec3, prelude::*};
+
+    use crate::{physics::G, prelude::*, utils::algebra::circular_orbit_around_body};
+
+    /// A singleplayer game with paused virtual time, so that the simulation only advances through [run_for] and
+    /// [run_until]
+    pub fn paused_game(plugin: ClientPlugin) -> App {
+        let mut app = App::new();
+        app.add_plugins(plugin.in_mode(ClientMode::Singleplayer));
+        app.world_mut().resource_mut::<Time<Virtual>>().pause();
+        app.update();
+        app
+    }
+
+    pub fn spawn_ship(app: &mut App, id: &str, spawn_pos: DVec3, spawn_speed: DVec3) -> Entity {
+        let id = id_from(id);
+        app.world_mut().send_event(ShipEvent::Create(ShipInfo {
+            id,
+            spawn_pos,
+            spawn_speed,
+        }));
+        app.update();
+        app.world().resource::<ShipsMapping>().0[&id]
+    }
+
+    /// Spawns the ship "s" on a circular orbit around a body, returning the body and the period of the orbit
+    pub fn spawn_in_orbit(app: &mut App, body: &str, radius: f64) -> (Entity, f64) {
+        let world = app.world_mut();
+        let body = world.resource::<BodiesMapping>().0[&id_from(body)];
+        let (&mass, &body_pos, &body_speed) = world
+            .query::<(&Mass, &Position, &Velocity)>()
+            .get(world, body)
+            .unwrap();
+        let (spawn_pos, spawn_speed) =
+            circular_orbit_around_body(radius, mass.0, body_pos.0, body_speed.0);
+        spawn_ship(app, "s", spawn_pos, spawn_speed);
+        (body, 2. * PI * radius.powf(1.5) / (G * mass.0).sqrt())
+    }
+
+    pub fn start_action(app: &mut App) {
+        app.world_mut()
+            .resource_mut::<NextState<GameStage>>()
+            .set(GameStage::Action);
+        app.update();
+    }
+
+    /// Runs the given number of updates, each followed by the fixed schedules
+    pub fn run_for(app: &mut App, updates: usize) {
+        for _ in 0..updates {
+            app.update();
+            FixedMain::run_fixed_main(app.world_mut());
+        }
+    }
+
+    pub fn run_until(app: &mut App, mut condition: impl FnMut(&World) -> bool) {
+        while !condition(app.world()) {
+            run_for(app, 1);
+        }
+    }
+
+    /// Runs the simulation until the given game time
+    pub fn run_to_time(app: &mut App, time: f64) {
+        run_until(app, |world| world.resource::<GameTime>().time() >= time);
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    fs::create_dir_all,
+    fs::{create_dir_all, read_to_string, write},
     path::{Path, PathBuf},
 };
 
@@ -10,15 +10,21 @@ use crate::{
     client::ClientMode,
     objects::{
         bodies::BodiesPlugin,
-        prelude::BodiesMapping,
+        commodities,
+        companies::{self, CompaniesMapping, COMPANIES_PATH},
+        contracts::{self, Contracts, CONTRACTS_PATH},
+        economy, markets,
+        prelude::{BodiesMapping, Commodities, LagrangeMapping},
         ships::{trajectory::TRAJECTORIES_PATH, ShipsMapping, ShipsPlugin},
         ObjectsUpdate,
     },
+    physics::{diagnostics::OrbitDiagnostics, nbody::NBodyDrift},
     physics::{
         influence::InfluenceUpdate, orbit::OrbitsUpdate, prelude::ToggleTime, PhysicsPlugin,
         PhysicsUpdate,
     },
     ui::gui::GUIUpdate,
+    utils::ecs::exit_on_error_if_app,
 };
 
 pub mod prelude {
@@ -26,6 +32,7 @@ pub mod prelude {
 }
 
 pub const GAME_FILES_PATH: &str = "gamefiles";
+pub const SEED_PATH: &str = "seed";
 
 /// This plugin's role is to handle everything that is about the main game, and that is common to both the server and the client
 #[derive(Default)]
@@ -54,7 +61,16 @@ impl Plugin for GamePlugin {
         } else {
             app.add_plugins(DefaultPlugins)
         }
-        .add_plugins((PhysicsPlugin, BodiesPlugin, ShipsPlugin))
+        .add_plugins((
+            PhysicsPlugin,
+            BodiesPlugin,
+            ShipsPlugin,
+            commodities::plugin,
+            markets::plugin,
+            economy::plugin,
+            companies::plugin,
+            contracts::plugin,
+        ))
         .add_computed_state::<InGame>()
         .add_computed_state::<Authoritative>()
         .add_sub_state::<GameStage>()
@@ -66,6 +82,13 @@ impl Plugin for GamePlugin {
         )
         .configure_sets(Update, ObjectsUpdate.run_if(in_state(Loaded)))
         .configure_sets(FixedUpdate, PhysicsUpdate.run_if(in_state(Loaded)))
+        .add_systems(
+            OnEnter(Loaded),
+            load_seed
+                .pipe(exit_on_error_if_app)
+                .before(ObjectsUpdate)
+                .run_if(in_state(Authoritative)),
+        )
         .add_systems(OnExit(Loaded), clear_loaded)
         .add_systems(OnEnter(GameStage::Action), enable_time)
         .add_systems(OnEnter(GameStage::Preparation), disable_time);
@@ -85,6 +108,9 @@ impl Default for TempDirectory {
 pub struct GameFiles {
     pub root: PathBuf,
     pub trajectories: PathBuf,
+    pub companies: PathBuf,
+    pub contracts: PathBuf,
+    pub seed: PathBuf,
 }
 
 impl GameFiles {
@@ -92,13 +118,35 @@ impl GameFiles {
         let root: PathBuf = path.as_ref().into();
         let trajectories = root.join(TRAJECTORIES_PATH);
         create_dir_all(trajectories)?;
+        let companies = root.join(COMPANIES_PATH);
+        create_dir_all(&companies)?;
         Ok(Self {
             trajectories: root.join(TRAJECTORIES_PATH),
+            companies,
+            contracts: root.join(CONTRACTS_PATH),
+            seed: root.join(SEED_PATH),
             root,
         })
     }
 }
 
+/// The seed from which all the random draws of a game are derived, chosen when its files are created
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameSeed(pub u64);
+
+/// Reads the seed of the game files, or saves a new one if there is none
+fn load_seed(mut commands: Commands, files: Res<GameFiles>) -> color_eyre::Result<()> {
+    let seed = if files.seed.exists() {
+        read_to_string(&files.seed)?.trim().parse()?
+    } else {
+        let seed: u64 = rand::random();
+        write(&files.seed, seed.to_string())?;
+        seed
+    };
+    commands.insert_resource(GameSeed(seed));
+    Ok(())
+}
+
 /// This state represents whether the app is running the main game (singleplayer or multiplayer) or not, and is loaded
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct InGame;
@@ -180,7 +228,14 @@ fn clear_loaded(mut commands: Commands, query: Query<Entity, With<ClearOnUnload>
         commands.entity(e).despawn();
     }
     commands.remove_resource::<BodiesMapping>();
+    commands.remove_resource::<LagrangeMapping>();
     commands.remove_resource::<ShipsMapping>();
+    commands.remove_resource::<Commodities>();
+    commands.remove_resource::<CompaniesMapping>();
+    commands.remove_resource::<Contracts>();
+    commands.remove_resource::<NBodyDrift>();
+    commands.remove_resource::<OrbitDiagnostics>();
+    commands.remove_resource::<GameSeed>();
 }
 
 fn enable_time(mut toggle: ResMut<ToggleTime>) {
@@ -194,7 +249,12 @@ fn disable_time(mut toggle: ResMut<ToggleTime>) {
 mod tests {
     use bevy::{app::App, math::DVec3, state::state::State};
 
-    use crate::{objects::ships::ShipEvent, prelude::*};
+    use crate::{
+        game::{GameFiles, GameSeed},
+        objects::ships::ShipEvent,
+        prelude::*,
+        utils::hash::hash,
+    };
 
     fn new_app() -> App {
         let mut app = App::new();
@@ -228,4 +288,23 @@ mod tests {
             GameStage::Preparation
         );
     }
+
+    #[test]
+    fn test_seed() {
+        let app = new_app();
+        let GameSeed(seed) = *app.world().resource::<GameSeed>();
+        let path = &app.world().resource::<GameFiles>().seed;
+        assert_eq!(std::fs::read_to_string(path).unwrap(), seed.to_string());
+
+        // The seed of existing game files is kept
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        std::fs::write(&app.world().resource::<GameFiles>().seed, "42").unwrap();
+        app.update();
+        assert_eq!(*app.world().resource::<GameSeed>(), GameSeed(42));
+        assert_eq!(
+            app.world().resource::<EconomyConfig>().seed,
+            hash(&(42_u64, "economy"))
+        );
+    }
 }
@@ -15,6 +15,7 @@ pub struct Keymap {
     pub start_menu: StartMenuKeymap,
     pub fleet_screen: FleetScreenKeymap,
     pub editor: EditorKeymap,
+    pub contracts_screen: ContractsScreenKeymap,
 }
 
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
@@ -43,6 +44,7 @@ pub struct FleetScreenKeymap {
     pub validate_new_ship: Key,
     pub delete_char: Key,
     pub enter_explorer: Key,
+    pub open_contracts: Key,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -51,6 +53,18 @@ pub struct EditorKeymap {
     pub select_previous: Key,
     pub back: Key,
     pub new_node: Key,
+    pub command: Key,
+    pub validate_command: Key,
+    pub delete_char: Key,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContractsScreenKeymap {
+    pub select_next: Key,
+    pub select_previous: Key,
+    pub back: Key,
+    pub accept: Key,
+    pub abandon: Key,
 }
 
 impl Keymap {
@@ -90,6 +104,7 @@ pub struct TreeViewKeymap {
     pub enter_search: Key,
     pub focus: Key,
     pub autoscale: Key,
+    pub toggle_synodic: Key,
     pub back: Key,
     pub speed_up: Key,
     pub slow_down: Key,
@@ -125,6 +140,7 @@ impl Default for TreeViewKeymap {
             enter_search: Key::from_str_unchecked("/"),
             focus: Key::from_str_unchecked("f"),
             autoscale: Key::from_str_unchecked("x"),
+            toggle_synodic: Key::from_str_unchecked("r"),
             back: Key::from_str_unchecked("esc"),
             speed_up: Key::from_str_unchecked(">"),
             slow_down: Key::from_str_unchecked("<"),
@@ -171,6 +187,7 @@ impl Default for FleetScreenKeymap {
             validate_new_ship: Key::from_str_unchecked("enter"),
             delete_char: Key::from_str_unchecked("backspace"),
             enter_explorer: Key::from_str_unchecked("e"),
+            open_contracts: Key::from_str_unchecked("c"),
         }
     }
 }
@@ -182,6 +199,21 @@ impl Default for EditorKeymap {
             select_previous: Key::from_str_unchecked("up"),
             back: Key::from_str_unchecked("esc"),
             new_node: Key::from_str_unchecked("n"),
+            command: Key::from_str_unchecked("c"),
+            validate_command: Key::from_str_unchecked("enter"),
+            delete_char: Key::from_str_unchecked("backspace"),
+        }
+    }
+}
+
+impl Default for ContractsScreenKeymap {
+    fn default() -> Self {
+        Self {
+            select_next: Key::from_str_unchecked("down"),
+            select_previous: Key::from_str_unchecked("up"),
+            back: Key::from_str_unchecked("esc"),
+            accept: Key::from_str_unchecked("enter"),
+            abandon: Key::from_str_unchecked("x"),
         }
     }
 }
@@ -194,4 +226,9 @@ mod tests {
     fn test_default_keymap() {
         Keymap::default();
     }
+
+    #[test]
+    fn test_read_keymap_file() {
+        Keymap::from_toml_file("keymap.toml").unwrap();
+    }
 }
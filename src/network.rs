@@ -3,7 +3,14 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use bevy_quinnet::shared::channels::{ChannelId, ChannelType, ChannelsConfiguration};
 use serde::{Deserialize, Serialize};
 
-use crate::prelude::BodiesConfig;
+use crate::{
+    objects::{
+        contracts::{ContractAction, Contracts},
+        markets::{Market, TradeOrder, TradeOutcome},
+        ships::{trajectory::TrajectoryEvent, ShipInfo},
+    },
+    prelude::{BodiesConfig, BodyID, CompanyID},
+};
 
 pub const SERVER_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 5000);
 pub const CLIENT_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0);
@@ -11,13 +18,44 @@ pub const CLIENT_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127
 #[derive(Serialize, Deserialize)]
 pub enum ServerMessage {
     BodiesConfig(BodiesConfig),
+    /// The [GameSeed](crate::game::GameSeed) of the server
+    GameSeed(u64),
     UpdateTime(u64),
+    /// The markets of the bodies, whose prices and stocks are only computed by the server
+    UpdateMarkets(Vec<(BodyID, Market)>),
+    /// All the contracts, sent whenever one of them changes
+    UpdateContracts(Contracts),
+    /// The company asked for by the client is already played by another client
+    JoinRejected(CompanyID),
+    /// The outcome of a trade of the client, along with the body of the market since entities differ between the
+    /// server and the clients
+    TradeOutcome {
+        outcome: TradeOutcome,
+        market: Option<BodyID>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum ClientMessage {
+    /// Sent on connection with the company of the player, which the server creates if needed. The server binds it
+    /// to the client, and the other messages act on its behalf
+    Join(CompanyID),
+    /// A new ship, owned by the company of the client
+    CreateShip(ShipInfo),
+    /// A trade of a ship, which has to be owned by the company of the client
+    Trade(TradeOrder),
+    /// A change of the trajectory of a ship, only applied if it is owned by the company of the client
+    Trajectory(TrajectoryEvent),
+    /// Takes or gives up a contract on behalf of the company of the client
+    Contract(ContractAction),
 }
 
 #[repr(u8)]
 pub enum ServerChannel {
     Once,
     PeriodicUpdates,
+    /// Periodic updates too large to fit in a datagram
+    ReliableUpdates,
 }
 
 impl From<ServerChannel> for ChannelId {
@@ -30,6 +68,7 @@ impl ServerChannel {
         ChannelsConfiguration::from_types(vec![
             ChannelType::OrderedReliable,
             ChannelType::Unreliable,
+            ChannelType::OrderedReliable,
         ])
         .unwrap()
     }
@@ -37,7 +76,8 @@ impl ServerChannel {
 
 #[repr(u8)]
 pub enum ClientChannel {
-    None,
+    /// Commands of the player, validated by the server
+    Commands,
 }
 
 impl From<ClientChannel> for ChannelId {
@@ -1,7 +1,12 @@
 use bevy::prelude::SystemSet;
 
 pub mod bodies;
+pub mod commodities;
+pub mod companies;
+pub mod contracts;
+pub mod economy;
 pub mod id;
+pub mod markets;
 pub mod ships;
 
 pub mod prelude {
@@ -9,10 +14,23 @@ pub mod prelude {
     pub use super::bodies::{
         bodies_config::BodiesConfig,
         body_data::{BodyData, BodyType},
+        lagrange::{LagrangeConfig, LagrangeInfo, LagrangeMapping, LagrangePoint},
         BodiesMapping, BodyID, BodyInfo, PrimaryBody,
     };
+    pub use super::commodities::{Commodities, CommodityData, CommodityID};
+    pub use super::companies::{Company, CompanyID, Owner, PlayerCompany};
+    pub use super::contracts::{
+        Contract, ContractAction, ContractEvent, ContractKind, ContractStatus, ContractUpdate,
+        Contracts,
+    };
+    pub use super::economy::{EconomyConfig, Production, Recipe};
     pub use super::id::id_from;
-    pub use super::ships::{ShipEvent, ShipID, ShipInfo, ShipsMapping};
+    pub use super::markets::{Market, MarketConfig, TradeEvent, TradeOrder, TradeOutcome};
+    pub use super::ships::{
+        cargo::{CargoError, CargoHold},
+        propulsion::{Isp, ShipMass},
+        ShipEvent, ShipID, ShipInfo, ShipsMapping,
+    };
 }
 
 #[derive(SystemSet, Debug, Clone, Copy, Hash, PartialEq, Eq)]
@@ -14,6 +14,7 @@ use super::ObjectsUpdate;
 
 pub mod bodies_config;
 pub mod body_data;
+pub mod lagrange;
 mod main_bodies;
 
 pub type BodyID = ArrayString<MAX_ID_LENGTH>;
@@ -48,7 +49,8 @@ pub struct BodiesPlugin;
 
 impl Plugin for BodiesPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(Loaded), build_system.in_set(ObjectsUpdate));
+        app.add_plugins(lagrange::plugin)
+            .add_systems(OnEnter(Loaded), build_system.in_set(ObjectsUpdate));
     }
 }
 
@@ -69,6 +71,7 @@ pub fn build_system(mut commands: Commands, config: Res<BodiesConfig>) {
         let mut entity = commands.spawn((
             Position::default(),
             EllipticalOrbit::from(&data),
+            BodyRotation::from(&data),
             Mass(data.mass),
             BodyInfo(data),
             Velocity::default(),
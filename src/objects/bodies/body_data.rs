@@ -53,7 +53,48 @@ pub struct BodyData {
     pub revolution_period: f64,
     // Time required to rotate around itself (in earth hours)
     pub rotation_period: f64,
+    // Angle between the rotation axis and the normal of the reference plane (in degrees)
+    pub axial_tilt: f64,
 
     pub radius: f64,
     pub mass: f64,
+
+    // Gravity field
+    // Radius at the equator (in km), the reference radius of the zonal harmonics
+    pub equatorial_radius: f64,
+    // Second zonal harmonic, which measures how much the body is flattened at its poles
+    pub j2: f64,
+
+    pub atmosphere: Option<Atmosphere>,
+    // Radius of the sphere of influence (in km) overriding the computed one, see
+    // [InfluenceModel](crate::physics::influence::InfluenceModel)
+    pub sphere_of_influence: Option<f64>,
+}
+
+/// Density (in kg/m³) below which an atmosphere is neglected
+pub const MIN_ATMOSPHERE_DENSITY: f64 = 1e-11;
+
+/// An exponential model of the atmosphere of a body
+#[derive(Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub struct Atmosphere {
+    /// Altitude over which the density is divided by e (in km)
+    pub scale_height: f64,
+    /// Density at the surface (in kg/m³)
+    pub surface_density: f64,
+}
+
+impl Atmosphere {
+    /// The altitude of the top of the atmosphere (in km)
+    pub fn height(&self) -> f64 {
+        self.scale_height * (self.surface_density / MIN_ATMOSPHERE_DENSITY).ln().max(0.)
+    }
+
+    /// The density at the given altitude (in kg/m³)
+    pub fn density_at(&self, altitude: f64) -> f64 {
+        if altitude > self.height() {
+            return 0.;
+        }
+        self.surface_density * (-altitude.max(0.) / self.scale_height).exp()
+    }
 }
@@ -0,0 +1,316 @@
+//! Lagrange points of the pairs formed by a body and its host.
+use arrayvec::ArrayString;
+use bevy::{math::DVec3, prelude::*, utils::HashMap};
+
+use crate::{
+    game::{ClearOnUnload, Loaded},
+    physics::{
+        frames::FrameState,
+        orbit::{update_global, OrbitsUpdate},
+        predictions::bodies_coordinates_at,
+        prelude::*,
+    },
+};
+
+use super::{BodiesMapping, BodyID, BodyInfo};
+
+const BISECTION_ITERATIONS: usize = 100;
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<LagrangeConfig>()
+        .add_systems(
+            OnEnter(Loaded),
+            spawn_lagrange_points
+                .after(update_global)
+                .in_set(OrbitsUpdate),
+        )
+        .add_systems(
+            FixedUpdate,
+            update_lagrange_points
+                .after(update_global)
+                .in_set(OrbitsUpdate),
+        );
+}
+
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct LagrangeConfig {
+    /// Only bodies at least as massive as this (in kg) get Lagrange points with their host, which leaves out asteroids
+    /// and small moons by default. Every pair gets them with 0.
+    pub min_mass: f64,
+}
+
+impl Default for LagrangeConfig {
+    fn default() -> Self {
+        Self { min_mass: 1e22 }
+    }
+}
+
+/// One of the five Lagrange points of a pair of bodies, the secondary body orbiting the primary one
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LagrangePoint {
+    pub primary: Entity,
+    pub secondary: Entity,
+    /// From 1 to 5
+    pub index: u8,
+}
+
+/// How a Lagrange point is identified and displayed
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct LagrangeInfo {
+    pub id: BodyID,
+    pub name: String,
+    /// The secondary body of the pair, under which the point is listed
+    pub parent: BodyID,
+}
+
+#[derive(Resource, Default)]
+pub struct LagrangeMapping(pub HashMap<BodyID, Entity>);
+
+/// The positions of the Lagrange points in the synodic frame of two bodies, in units of the distance between them,
+/// given the mass ratio `mu = m2 / (m1 + m2)`
+pub fn lagrange_offsets(mu: f64) -> [DVec3; 5] {
+    // Net acceleration along the x axis of the synodic frame, which increases between the singularities
+    let acceleration = |x: f64| {
+        let (r1, r2) = (x + mu, x - 1. + mu);
+        x - (1. - mu) * r1 / r1.abs().powi(3) - mu * r2 / r2.abs().powi(3)
+    };
+    let solve = |mut low: f64, mut high: f64| {
+        for _ in 0..BISECTION_ITERATIONS {
+            let mid = (low + high) / 2.;
+            if acceleration(mid) > 0. {
+                high = mid;
+            } else {
+                low = mid;
+            }
+        }
+        (low + high) / 2.
+    };
+    let triangle = DVec3::new(0.5 - mu, 3f64.sqrt() / 2., 0.);
+    [
+        DVec3::X * solve(-mu, 1. - mu),
+        DVec3::X * solve(1. - mu, 2.),
+        DVec3::X * solve(-2., -mu),
+        triangle,
+        triangle * DVec3::new(1., -1., 1.),
+    ]
+}
+
+/// The global position and velocity of a Lagrange point given the position, velocity and mass of both bodies
+pub fn lagrange_coordinates(
+    index: u8,
+    primary: (DVec3, DVec3, f64),
+    secondary: (DVec3, DVec3, f64),
+) -> (DVec3, DVec3) {
+    let frame = FrameState::synodic(primary, secondary);
+    let distance = (secondary.0 - primary.0).length();
+    let mu = secondary.2 / (primary.2 + secondary.2);
+    let offset = lagrange_offsets(mu)[(index - 1) as usize];
+    frame.to_global(offset * distance, DVec3::ZERO)
+}
+
+impl LagrangePoint {
+    /// The global position and velocity of the point at any game time, computed from the orbits of the bodies
+    pub fn coordinates_at(
+        &self,
+        bodies: &Query<(&EllipticalOrbit, &BodyInfo)>,
+        mapping: &HashMap<BodyID, Entity>,
+        time: f64,
+    ) -> Option<(DVec3, DVec3)> {
+        let masses = (
+            bodies.get(self.primary).ok()?.1 .0.mass,
+            bodies.get(self.secondary).ok()?.1 .0.mass,
+        );
+        let coords = bodies_coordinates_at(
+            [self.primary, self.secondary].into_iter(),
+            bodies,
+            mapping,
+            time,
+        );
+        Some(lagrange_coordinates(
+            self.index,
+            (coords[0].0, coords[0].1, masses.0),
+            (coords[1].0, coords[1].1, masses.1),
+        ))
+    }
+}
+
+fn lagrange_id(secondary: BodyID, index: u8) -> Option<BodyID> {
+    ArrayString::from(&format!("{secondary}-l{index}")).ok()
+}
+
+#[allow(clippy::type_complexity)]
+fn spawn_lagrange_points(
+    mut commands: Commands,
+    bodies: Query<(Entity, &BodyInfo, &Position, &Velocity)>,
+    mapping: Res<BodiesMapping>,
+    config: Res<LagrangeConfig>,
+) {
+    let mut lagrange_mapping = HashMap::new();
+    for (secondary, BodyInfo(data), pos, speed) in bodies.iter() {
+        if data.mass < config.min_mass {
+            continue;
+        }
+        let Some((primary, primary_data, primary_pos, primary_speed)) = data
+            .host_body
+            .and_then(|host| mapping.0.get(&host))
+            .and_then(|e| bodies.get(*e).ok())
+        else {
+            continue;
+        };
+        for index in 1..=5 {
+            let Some(id) = lagrange_id(data.id, index) else {
+                continue;
+            };
+            let (point_pos, point_speed) = lagrange_coordinates(
+                index,
+                (primary_pos.0, primary_speed.0, primary_data.0.mass),
+                (pos.0, speed.0, data.mass),
+            );
+            let entity = commands
+                .spawn((
+                    LagrangePoint {
+                        primary,
+                        secondary,
+                        index,
+                    },
+                    LagrangeInfo {
+                        id,
+                        name: format!("{}-{} L{index}", primary_data.0.name, data.name),
+                        parent: data.id,
+                    },
+                    Position(point_pos),
+                    Velocity(point_speed),
+                    TransformBundle::default(),
+                    ClearOnUnload,
+                ))
+                .id();
+            lagrange_mapping.insert(id, entity);
+        }
+    }
+    commands.insert_resource(LagrangeMapping(lagrange_mapping));
+}
+
+#[allow(clippy::type_complexity)]
+fn update_lagrange_points(
+    mut points: Query<(&mut Position, &mut Velocity, &LagrangePoint)>,
+    bodies: Query<(&Position, &Velocity, &BodyInfo), Without<LagrangePoint>>,
+) {
+    points
+        .par_iter_mut()
+        .for_each(|(mut pos, mut speed, point)| {
+            if let (Ok(primary), Ok(secondary)) =
+                (bodies.get(point.primary), bodies.get(point.secondary))
+            {
+                (pos.0, speed.0) = lagrange_coordinates(
+                    point.index,
+                    (primary.0 .0, primary.1 .0, primary.2 .0.mass),
+                    (secondary.0 .0, secondary.1 .0, secondary.2 .0.mass),
+                );
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::system::SystemState, math::DVec3};
+
+    use crate::{
+        client::testing::{paused_game, run_for, start_action},
+        physics::time::GAMETIME_PER_SIMTICK,
+        prelude::*,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_lagrange_offsets() {
+        // Sun-Earth system, see https://en.wikipedia.org/wiki/Lagrange_point
+        let mu = 3.0035e-6;
+        let [l1, l2, l3, l4, l5] = lagrange_offsets(mu);
+        let hill = (mu / 3.).cbrt();
+        assert!((l1.x - (1. - hill)).abs() < 1e-4);
+        assert!((l2.x - (1. + hill)).abs() < 1e-4);
+        assert!((l3.x + 1. + 5. * mu / 12.).abs() < 1e-6);
+        // L4 and L5 are at the same distance from both bodies
+        for l in [l4, l5] {
+            assert!(((l - DVec3::new(-mu, 0., 0.)).length() - 1.).abs() < 1e-12);
+            assert!(((l - DVec3::new(1. - mu, 0., 0.)).length() - 1.).abs() < 1e-12);
+        }
+        assert!(l4.y > 0. && l5.y < 0.);
+    }
+
+    #[test]
+    fn test_lagrange_points() {
+        let mut app = paused_game(ClientPlugin::testing());
+        start_action(&mut app);
+        run_for(&mut app, 20);
+        let world = app.world_mut();
+        let id = lagrange_id(id_from("terre"), 4).unwrap();
+        let entity = world.resource::<LagrangeMapping>().0[&id];
+        let mapping = world.resource::<BodiesMapping>().0.clone();
+        let simtick = world.resource::<GameTime>().simtick;
+        let (&point, info, &pos) = world
+            .query::<(&LagrangePoint, &LagrangeInfo, &Position)>()
+            .get(world, entity)
+            .unwrap();
+        assert_eq!(info.name, "Sun-Earth L4");
+        assert_eq!(point.secondary, mapping[&id_from("terre")]);
+        #[allow(clippy::type_complexity)]
+        let mut system_state: SystemState<Query<(&EllipticalOrbit, &BodyInfo)>> =
+            SystemState::new(world);
+        let bodies = system_state.get(world);
+        // The live position matches the one computed from the orbits at the same time
+        let (expected, _) = point
+            .coordinates_at(&bodies, &mapping, simtick as f64 * GAMETIME_PER_SIMTICK)
+            .unwrap();
+        assert!((pos.0 - expected).length() < 1.);
+    }
+
+    #[test]
+    fn test_major_bodies_only() {
+        let mut app = App::new();
+        app.add_plugins(
+            ClientPlugin::testing()
+                .with_bodies(BodiesConfig::SmallestBodyType(BodyType::Comet))
+                .in_mode(ClientMode::Singleplayer),
+        );
+        app.update();
+        app.update();
+        let world = app.world_mut();
+        let mapping = world.resource::<LagrangeMapping>().0.clone();
+        assert!(mapping.contains_key(&lagrange_id(id_from("lune"), 1).unwrap()));
+        let mut points = world.query::<&LagrangePoint>();
+        assert_eq!(points.iter(world).count(), mapping.len());
+        // Asteroids, comets and small moons have no Lagrange points
+        let mut masses = world.query::<&BodyInfo>();
+        for point in points.iter(world) {
+            let BodyInfo(data) = masses.get(world, point.secondary).unwrap();
+            assert!(data.mass >= LagrangeConfig::default().min_mass);
+        }
+    }
+
+    #[test]
+    fn test_every_pair() {
+        let mut app = App::new();
+        app.add_plugins(
+            ClientPlugin::testing()
+                .with_bodies(BodiesConfig::SmallestBodyType(BodyType::Moon))
+                .in_mode(ClientMode::Singleplayer),
+        );
+        app.insert_resource(LagrangeConfig { min_mass: 0. });
+        app.update();
+        app.update();
+        let world = app.world_mut();
+        let mapping = world.resource::<BodiesMapping>().0.clone();
+        // Bodies whose host is loaded too
+        let hosted = world
+            .query::<&BodyInfo>()
+            .iter(world)
+            .filter(|BodyInfo(data)| {
+                data.host_body
+                    .is_some_and(|host| mapping.contains_key(&host))
+            })
+            .count();
+        assert_eq!(world.resource::<LagrangeMapping>().0.len(), 5 * hosted);
+    }
+}
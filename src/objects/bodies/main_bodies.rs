@@ -1,14 +1,15 @@
-use std::{fs::File, io::Read};
+use std::{f64::consts::PI, fs::File, io::Read};
 
 use serde::{de::Visitor, Deserialize, Deserializer};
 
 use crate::{
     objects::id::{id_from, MAX_ID_LENGTH},
+    physics::G,
     utils::de::deserialize_options,
 };
 
 use super::{
-    body_data::{BodyData, BodyType},
+    body_data::{Atmosphere, BodyData, BodyType},
     BodyID,
 };
 
@@ -114,11 +115,24 @@ pub struct MainBodyData {
     // Time required to rotate around itself (in earth hours)
     #[serde(alias = "sideralRotation")]
     pub rotation_period: f64,
+    #[serde(default)]
+    pub axial_tilt: f64,
 
     #[serde(alias = "meanRadius")]
     radius: f64,
     #[serde(deserialize_with = "deserialize_options")]
     mass: Mass,
+    #[serde(alias = "equaRadius", default)]
+    equatorial_radius: f64,
+    // Not provided by the API, taken from https://ssd.jpl.nasa.gov/planets/phys_par.html
+    #[serde(default)]
+    j2: f64,
+    // Not provided by the API either, taken from https://nssdc.gsfc.nasa.gov/planetary/factsheet/ (at the 1 bar
+    // level for the giant planets)
+    #[serde(default)]
+    atmosphere: Option<Atmosphere>,
+    #[serde(default)]
+    sphere_of_influence: Option<f64>,
 }
 
 impl From<MainBodyData> for BodyData {
@@ -143,8 +157,18 @@ impl From<MainBodyData> for BodyData {
             apoapsis: value.apoapsis as f64,
             revolution_period: value.revolution_period,
             rotation_period: value.rotation_period,
+            axial_tilt: value.axial_tilt,
             radius: value.radius,
-            mass: value.mass.into(),
+            mass: (&value.mass).into(),
+            // Unknown equatorial radii are given as zero, the mean radius is used instead
+            equatorial_radius: if value.equatorial_radius > 0. {
+                value.equatorial_radius
+            } else {
+                value.radius
+            },
+            j2: value.j2,
+            atmosphere: value.atmosphere,
+            sphere_of_influence: value.sphere_of_influence,
         }
     }
 }
@@ -156,8 +180,8 @@ struct Mass {
     mass_exponent: i32,
 }
 
-impl From<Mass> for f64 {
-    fn from(value: Mass) -> Self {
+impl From<&Mass> for f64 {
+    fn from(value: &Mass) -> Self {
         value.mass_value * 10f64.powi(value.mass_exponent)
     }
 }
@@ -188,13 +212,45 @@ fn fix_bodies(mut bodies: Vec<MainBodyData>) -> std::io::Result<Vec<MainBodyData
         .iter_mut()
         .filter(|data| data.host_body.is_none() && data.id != SUN_ID.into())
         .for_each(|body| body.host_body = Some(SUN_ID.into()));
+    // The data file gives no period for open orbits, so it is computed from the mass of the host
+    let periods: Vec<_> = bodies
+        .iter()
+        .enumerate()
+        .filter(|(_, data)| data.eccentricity >= 1. && data.revolution_period == 0.)
+        .filter_map(|(i, data)| {
+            let host = bodies
+                .iter()
+                .find(|host| data.host_body.as_ref() == Some(&host.id))?;
+            Some((i, open_orbit_period(data, (&host.mass).into())))
+        })
+        .collect();
+    for (i, period) in periods {
+        bodies[i].revolution_period = period;
+    }
     Ok(bodies)
 }
 
+/// Time needed for the mean anomaly of an open orbit to advance by 360 degrees (in days)
+fn open_orbit_period(data: &MainBodyData, host_mass: f64) -> f64 {
+    let (e, q) = (data.eccentricity, data.periapsis as f64);
+    let mu = G * host_mass;
+    if e > 1. {
+        let a = if data.semimajor_axis != 0 {
+            data.semimajor_axis.abs() as f64
+        } else {
+            q / (e - 1.)
+        };
+        2. * PI * (a.powi(3) / mu).sqrt()
+    } else {
+        2. * PI * (2. * q.powi(3) / mu).sqrt()
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use crate::physics::{orbit::ConicType, prelude::EllipticalOrbit};
     use serde_json::from_str;
 
     #[test]
@@ -264,8 +320,13 @@ mod tests {
                 apoapsis: 405500.,
                 revolution_period: 27.32170,
                 rotation_period: 655.72800,
+                axial_tilt: 6.68,
                 radius: 1737.,
-                mass: 7.346e22
+                mass: 7.346e22,
+                equatorial_radius: 1738.1,
+                j2: 0.,
+                atmosphere: None,
+                sphere_of_influence: None,
             }
         );
     }
@@ -288,10 +349,86 @@ mod tests {
             .iter()
             .filter(|data| matches!(data.body_type, BodyType::Planet))
         {
-            assert!(planet.host_body.is_some_and(|id| id == id_from(SUN_ID)))
+            assert!(planet.host_body.is_some_and(|id| id == id_from(SUN_ID)));
+            assert!(planet.j2 > 0.);
         }
     }
 
+    #[test]
+    fn test_hyperbolic_comet() {
+        // C/1980 E1 (Bowell), which the data file gives no period for
+        let data = r#"
+        {
+            "bodies": [
+                {
+                    "englishName": "Sun",
+                    "moons": null,
+                    "semimajorAxis": 0,
+                    "perihelion": 0,
+                    "aphelion": 0,
+                    "eccentricity": 0.0,
+                    "inclination": 0.0,
+                    "mass": { "massValue": 1.989, "massExponent": 30 },
+                    "meanRadius": 695508.0,
+                    "sideralOrbit": 0.0,
+                    "sideralRotation": 0.0,
+                    "aroundPlanet": null,
+                    "mainAnomaly": 0.0,
+                    "argPeriapsis": 0.0,
+                    "longAscNode": 0.0,
+                    "bodyType": "Star",
+                    "rel": "https://api.le-systeme-solaire.net/rest/bodies/soleil"
+                },
+                {
+                    "englishName": "C/1980 E1 (Bowell)",
+                    "moons": null,
+                    "semimajorAxis": 8721716000,
+                    "perihelion": 503243000,
+                    "aphelion": 0,
+                    "eccentricity": 1.0577,
+                    "inclination": 1.6613,
+                    "mass": null,
+                    "meanRadius": 0.0,
+                    "sideralOrbit": 0.0,
+                    "sideralRotation": 0.0,
+                    "aroundPlanet": null,
+                    "mainAnomaly": 0.0,
+                    "argPeriapsis": 135.09,
+                    "longAscNode": 114.56,
+                    "bodyType": "Comet",
+                    "rel": "https://api.le-systeme-solaire.net/rest/bodies/c1980e1-bowell"
+                }
+            ]
+        }
+        "#;
+        #[derive(Deserialize)]
+        struct Input {
+            bodies: Vec<MainBodyData>,
+        }
+        let input: Input = from_str(data).unwrap();
+        let comet = fix_bodies(input.bodies)
+            .unwrap()
+            .into_iter()
+            .map(BodyData::from)
+            .find(|data| data.body_type == BodyType::Comet)
+            .unwrap();
+        assert!(comet.revolution_period > 0.);
+
+        let mut orbit = EllipticalOrbit::from(&comet);
+        assert_eq!(orbit.conic_type(), ConicType::Hyperbolic);
+        orbit.update_pos(0.);
+        assert!((orbit.local_pos.length() / comet.periapsis - 1.).abs() < 1e-6);
+        // The comet leaves the system after its perihelion, the same way it came
+        let distance = |orbit: &mut EllipticalOrbit, time| {
+            orbit.update_pos(time);
+            orbit.local_pos.length()
+        };
+        let (before, after) = (distance(&mut orbit, -1000.), distance(&mut orbit, 1000.));
+        assert!(after.is_finite() && after > 2. * comet.periapsis);
+        assert!((before / after - 1.).abs() < 1e-9);
+        assert!(distance(&mut orbit, 2000.) > after);
+    }
+
     #[test]
     fn test_id_single() {
         let id: MainBodyID = from_str(
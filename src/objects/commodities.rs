@@ -0,0 +1,80 @@
+//! A "Commodity" is a kind of goods that ships can carry in their cargo holds.
+use std::{fs::File, io::Read};
+
+use arrayvec::ArrayString;
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::game::Loaded;
+
+use super::id::MAX_ID_LENGTH;
+use super::ObjectsUpdate;
+
+const COMMODITIES_FILE_PATH: &str = "commodities.json";
+/// The commodity that ships burn in their engines
+pub const PROPELLANT_ID: &str = "propellant";
+
+pub type CommodityID = ArrayString<MAX_ID_LENGTH>;
+
+pub fn plugin(app: &mut App) {
+    app.add_systems(OnEnter(Loaded), load_commodities.in_set(ObjectsUpdate));
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CommodityData {
+    pub id: CommodityID,
+    pub name: String,
+    /// Mass of a unit of the commodity (in kg)
+    pub unit_mass: f64,
+    /// Reference price of a unit of the commodity, around which market prices fluctuate (in credits)
+    pub base_price: f64,
+}
+
+/// Registry of all the commodities that exist in the game
+#[derive(Resource, Default, Debug, Clone)]
+pub struct Commodities(pub HashMap<CommodityID, CommodityData>);
+
+impl Commodities {
+    pub fn get(&self, id: &CommodityID) -> Option<&CommodityData> {
+        self.0.get(id)
+    }
+}
+
+impl FromIterator<CommodityData> for Commodities {
+    fn from_iter<T: IntoIterator<Item = CommodityData>>(iter: T) -> Self {
+        Self(iter.into_iter().map(|data| (data.id, data)).collect())
+    }
+}
+
+pub fn read_commodities() -> std::io::Result<Commodities> {
+    let mut file = File::open(COMMODITIES_FILE_PATH)?;
+    let mut buf = String::new();
+    file.read_to_string(&mut buf)?;
+    #[derive(Deserialize)]
+    struct Input {
+        commodities: Vec<CommodityData>,
+    }
+    let input: Input = serde_json::from_str(&buf).map_err(std::io::Error::from)?;
+    Ok(input.commodities.into_iter().collect())
+}
+
+pub fn load_commodities(mut commands: Commands) {
+    commands.insert_resource(read_commodities().expect("Failed to read commodities"));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::objects::id::id_from;
+
+    use super::read_commodities;
+
+    #[test]
+    fn test_read_commodities() {
+        let commodities = read_commodities().unwrap();
+        assert_eq!(commodities.0.len(), 8);
+        let water = commodities.get(&id_from("water")).unwrap();
+        assert_eq!(water.name, "Water");
+        assert_eq!(water.unit_mass, 1000.);
+    }
+}
@@ -0,0 +1,381 @@
+//! A "Company" owns ships and pays for what they do, every change of its credits
+//! being recorded in its ledger.
+use std::{
+    fs::{read_dir, File, OpenOptions},
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use arrayvec::ArrayString;
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::{Authoritative, ClearOnUnload, GameFiles, Loaded},
+    utils::ecs::exit_on_error_if_app,
+};
+
+use super::{
+    commodities::CommodityID,
+    contracts::ContractID,
+    id::MAX_ID_LENGTH,
+    prelude::{ShipID, ShipInfo},
+    ships::create_ships,
+    ObjectsUpdate,
+};
+
+pub const COMPANIES_PATH: &str = "companies";
+/// Credits that new companies start with
+pub const STARTING_CREDITS: f64 = 1e5;
+const LEDGER_EXTENSION: &str = "ledger";
+
+pub type CompanyID = ArrayString<MAX_ID_LENGTH>;
+
+pub fn plugin(app: &mut App) {
+    app.add_event::<CompanyEvent>()
+        .add_systems(
+            OnEnter(Loaded),
+            (
+                load_companies,
+                adopt_unowned_ships
+                    .after(create_ships)
+                    .run_if(resource_exists::<PlayerCompany>),
+            )
+                .chain()
+                .in_set(ObjectsUpdate),
+        )
+        .add_systems(
+            Update,
+            (
+                handle_company_events,
+                save_companies.pipe(exit_on_error_if_app),
+            )
+                .chain()
+                .in_set(ObjectsUpdate)
+                .run_if(in_state(Authoritative)),
+        );
+}
+
+/// The company of the local player, which is the only one whose ships the player can command
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct PlayerCompany(pub CompanyID);
+
+impl Default for PlayerCompany {
+    fn default() -> Self {
+        Self(ArrayString::from("player").unwrap())
+    }
+}
+
+/// The company owning a ship
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct Owner(pub CompanyID);
+
+#[derive(Resource, Default)]
+pub struct CompaniesMapping(pub HashMap<CompanyID, Entity>);
+
+#[derive(Event, Clone, Debug, PartialEq)]
+pub enum CompanyEvent {
+    /// Creates a company with the starting credits if it does not exist yet, such as the one of a joining client
+    Create(CompanyID),
+}
+
+/// Number of entries of the ledger of a company that are already written to the game files
+#[derive(Component, Clone, Copy, Debug, Default)]
+struct SavedLedger(usize);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Transaction {
+    Purchase {
+        ship: ShipID,
+        commodity: CommodityID,
+        quantity: u32,
+        unit_price: f64,
+    },
+    Sale {
+        ship: ShipID,
+        commodity: CommodityID,
+        quantity: u32,
+        unit_price: f64,
+    },
+    Fuel {
+        ship: ShipID,
+        /// Mass of the bought propellant (in kg)
+        propellant: f64,
+        price: f64,
+    },
+    Fee {
+        ship: ShipID,
+        reason: String,
+    },
+    ContractReward {
+        contract: ContractID,
+    },
+    ContractPenalty {
+        contract: ContractID,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LedgerEntry {
+    pub tick: u64,
+    /// Change of the credits of the company, negative for expenses
+    pub amount: f64,
+    pub transaction: Transaction,
+}
+
+#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Company {
+    pub id: CompanyID,
+    pub name: String,
+    credits: f64,
+    /// Saved apart from the rest of the company, one entry per line, so that new entries are only appended
+    #[serde(skip)]
+    ledger: Vec<LedgerEntry>,
+}
+
+impl Company {
+    pub fn new(id: CompanyID, name: String, credits: f64) -> Self {
+        Self {
+            id,
+            name,
+            credits,
+            ledger: Vec::new(),
+        }
+    }
+
+    pub fn credits(&self) -> f64 {
+        self.credits
+    }
+
+    /// All the transactions of the company, from the oldest to the most recent
+    pub fn ledger(&self) -> &[LedgerEntry] {
+        &self.ledger
+    }
+
+    /// Appends a transaction to the ledger and applies it to the credits
+    pub fn record(&mut self, tick: u64, amount: f64, transaction: Transaction) {
+        self.credits += amount;
+        self.ledger.push(LedgerEntry {
+            tick,
+            amount,
+            transaction,
+        });
+    }
+}
+
+#[derive(Debug)]
+pub enum CompanyError {
+    Io(std::io::Error),
+    De(toml::de::Error),
+    Ser(toml::ser::Error),
+    Ledger(serde_json::Error),
+}
+
+impl From<std::io::Error> for CompanyError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<toml::de::Error> for CompanyError {
+    fn from(value: toml::de::Error) -> Self {
+        Self::De(value)
+    }
+}
+
+impl From<toml::ser::Error> for CompanyError {
+    fn from(value: toml::ser::Error) -> Self {
+        Self::Ser(value)
+    }
+}
+
+impl From<serde_json::Error> for CompanyError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Ledger(value)
+    }
+}
+
+impl std::fmt::Display for CompanyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompanyError::Io(err) => write!(f, "Error when reading company: {}", err),
+            CompanyError::De(err) => write!(f, "Error when deserializing company: {}", err),
+            CompanyError::Ser(err) => write!(f, "Error when serializing company: {}", err),
+            CompanyError::Ledger(err) => write!(f, "Error in the ledger of the company: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for CompanyError {}
+
+fn build_path(dir: impl AsRef<Path>, id: CompanyID) -> PathBuf {
+    dir.as_ref().join(id.to_string())
+}
+
+/// Reads a company and its ledger, which is stored next to it
+pub fn read_company(path: impl AsRef<Path>) -> Result<Company, CompanyError> {
+    let mut file = File::open(&path)?;
+    let mut buf = String::new();
+    file.read_to_string(&mut buf)?;
+    let mut company = toml::from_str::<Company>(&buf)?;
+    if let Ok(ledger) = File::open(path.as_ref().with_extension(LEDGER_EXTENSION)) {
+        for line in BufReader::new(ledger).lines() {
+            company.ledger.push(serde_json::from_str(&line?)?);
+        }
+    }
+    Ok(company)
+}
+
+/// Writes a company without its ledger
+pub fn write_company(dir: impl AsRef<Path>, company: &Company) -> Result<(), CompanyError> {
+    let s = toml::to_string_pretty(company)?;
+    Ok(File::create(build_path(dir, company.id))?.write_all(s.as_bytes())?)
+}
+
+/// Appends entries to the ledger of a company
+pub fn append_ledger(
+    dir: impl AsRef<Path>,
+    id: CompanyID,
+    entries: &[LedgerEntry],
+) -> Result<(), CompanyError> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(build_path(dir, id).with_extension(LEDGER_EXTENSION))?;
+    for entry in entries {
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    }
+    Ok(())
+}
+
+fn spawn_company(commands: &mut Commands, company: Company) -> Entity {
+    let saved = SavedLedger(company.ledger.len());
+    commands.spawn((company, saved, ClearOnUnload)).id()
+}
+
+/// Spawns the companies saved in the game files, and creates the company of the player if it does not exist yet
+fn load_companies(mut commands: Commands, dir: Res<GameFiles>, player: Option<Res<PlayerCompany>>) {
+    let mut companies: Vec<_> = read_dir(&dir.companies)
+        .map(|dir| {
+            dir.flatten()
+                .filter(|entry| entry.path().extension().is_none())
+                .filter_map(|entry| read_company(entry.path()).ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    if let Some(PlayerCompany(id)) = player.as_deref() {
+        if !companies.iter().any(|c| c.id == *id) {
+            companies.push(Company::new(*id, id.to_string(), STARTING_CREDITS));
+        }
+    }
+    let mapping = companies
+        .into_iter()
+        .map(|company| (company.id, spawn_company(&mut commands, company)))
+        .collect();
+    commands.insert_resource(CompaniesMapping(mapping));
+}
+
+/// Ships without owner, which existed before the companies, are given to the player
+fn adopt_unowned_ships(
+    mut commands: Commands,
+    ships: Query<Entity, (With<ShipInfo>, Without<Owner>)>,
+    player: Res<PlayerCompany>,
+) {
+    for ship in ships.iter() {
+        commands.entity(ship).insert(Owner(player.0));
+    }
+}
+
+fn handle_company_events(
+    mut commands: Commands,
+    mut reader: EventReader<CompanyEvent>,
+    mut mapping: ResMut<CompaniesMapping>,
+) {
+    for event in reader.read() {
+        match event {
+            CompanyEvent::Create(id) => {
+                if !mapping.0.contains_key(id) {
+                    let company = Company::new(*id, id.to_string(), STARTING_CREDITS);
+                    let entity = spawn_company(&mut commands, company);
+                    mapping.0.insert(*id, entity);
+                }
+            }
+        }
+    }
+}
+
+/// Only the new entries of the ledgers are written, the rest of the companies being small
+fn save_companies(
+    mut companies: Query<(&Company, &mut SavedLedger), Changed<Company>>,
+    dir: Res<GameFiles>,
+) -> color_eyre::Result<()> {
+    for (company, mut saved) in companies.iter_mut() {
+        write_company(&dir.companies, company)?;
+        append_ledger(&dir.companies, company.id, &company.ledger[saved.0..])?;
+        saved.0 = company.ledger.len();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::app::App;
+
+    use crate::{game::GameFiles, objects::id::id_from, prelude::*};
+
+    use super::*;
+
+    #[test]
+    fn test_company_files() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        app.update();
+        let player = app.world().resource::<PlayerCompany>().0;
+        let entity = app.world().resource::<CompaniesMapping>().0[&player];
+        let mut company = app.world_mut().get_mut::<Company>(entity).unwrap();
+        assert_eq!(company.credits(), STARTING_CREDITS);
+        company.record(
+            3,
+            -10.,
+            Transaction::Fee {
+                ship: id_from("s"),
+                reason: "Docking".into(),
+            },
+        );
+        app.update();
+
+        let dir = &app.world().resource::<GameFiles>().companies;
+        let saved = read_company(dir.join(player.to_string())).unwrap();
+        assert_eq!(saved.credits(), STARTING_CREDITS - 10.);
+        assert_eq!(saved.ledger().len(), 1);
+        assert_eq!(saved, *app.world().get::<Company>(entity).unwrap());
+
+        // The ledger is appended to instead of being written again
+        let mut company = app.world_mut().get_mut::<Company>(entity).unwrap();
+        company.record(
+            4,
+            -5.,
+            Transaction::Fee {
+                ship: id_from("s"),
+                reason: "Docking".into(),
+            },
+        );
+        app.update();
+        let dir = &app.world().resource::<GameFiles>().companies;
+        let ledger =
+            std::fs::read_to_string(dir.join(format!("{player}.{LEDGER_EXTENSION}"))).unwrap();
+        assert_eq!(ledger.lines().count(), 2);
+        let saved = read_company(dir.join(player.to_string())).unwrap();
+        assert_eq!(saved, *app.world().get::<Company>(entity).unwrap());
+    }
+
+    #[test]
+    fn test_unowned_ships_adopted() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        let ship = app.world_mut().spawn(ShipInfo::default()).id();
+        app.update();
+        let player = app.world().resource::<PlayerCompany>().0;
+        assert_eq!(app.world().get::<Owner>(ship), Some(&Owner(player)));
+    }
+}
@@ -0,0 +1,884 @@
+//! A "Contract" is a delivery, transport or survey job posted by a body or a faction,
+//! rewarded when a ship of the contractor reaches the destination before the deadline.
+use std::{
+    collections::BTreeMap,
+    fs::{read_to_string, write},
+};
+
+use bevy::{math::DVec3, prelude::*};
+use rand::{rngs::StdRng, seq::IteratorRandom, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::{Authoritative, GameFiles, Loaded},
+    physics::{prelude::*, time::TickEvent},
+    utils::{ecs::exit_on_error_if_app, hash::hash},
+};
+
+use super::{
+    commodities::{Commodities, CommodityID},
+    companies::{CompaniesMapping, Company, CompanyID, Owner, Transaction},
+    economy::EconomyConfig,
+    markets::{Market, MarketGood},
+    prelude::{BodiesMapping, BodyID, BodyInfo, ShipID, ShipInfo},
+    ships::cargo::CargoHold,
+    ObjectsUpdate,
+};
+
+/// The NPC factions that post contracts in addition to the bodies
+const FACTIONS: [&str; 3] = [
+    "Belt Miners Guild",
+    "Inner Planets Survey",
+    "Free Traders Union",
+];
+
+pub const CONTRACTS_PATH: &str = "contracts";
+
+pub type ContractID = u64;
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<ContractsConfig>()
+        .add_event::<ContractEvent>()
+        .add_event::<ContractUpdate>()
+        .add_systems(
+            OnEnter(Loaded),
+            load_contracts
+                .pipe(exit_on_error_if_app)
+                .in_set(ObjectsUpdate)
+                .run_if(in_state(Authoritative)),
+        )
+        .add_systems(
+            Update,
+            (
+                handle_contract_events,
+                save_contracts
+                    .pipe(exit_on_error_if_app)
+                    .run_if(on_event::<ContractUpdate>()),
+            )
+                .chain()
+                .in_set(ObjectsUpdate)
+                .run_if(in_state(Authoritative)),
+        )
+        .add_systems(
+            FixedUpdate,
+            (post_contracts, progress_contracts, expire_contracts)
+                .chain()
+                .run_if(
+                    in_state(Loaded)
+                        .and_then(in_state(Authoritative))
+                        .and_then(on_event::<TickEvent>()),
+                ),
+        );
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct ContractsConfig {
+    /// Number of ticks between two postings of a contract
+    pub posting_period: u64,
+    /// Maximum number of contracts that wait to be accepted at the same time
+    pub max_open: usize,
+    /// Number of ticks that contractors have to fulfill a contract
+    pub duration: u64,
+    /// Ships reach a body when their distance to its center is less than this many body radii
+    pub arrival_distance: f64,
+}
+
+impl Default for ContractsConfig {
+    fn default() -> Self {
+        Self {
+            posting_period: 100,
+            max_open: 10,
+            duration: 5000,
+            arrival_distance: 1.5,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Issuer {
+    Body(BodyID),
+    Faction(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContractKind {
+    Delivery,
+    Transport,
+    Survey,
+}
+
+impl std::fmt::Display for ContractKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ContractKind::Delivery => "Delivery",
+            ContractKind::Transport => "Transport",
+            ContractKind::Survey => "Survey",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ContractStatus {
+    Open,
+    Accepted {
+        contractor: CompanyID,
+        /// Whether the cargo of a transport contract has been loaded by a ship of the contractor
+        picked_up: bool,
+    },
+    Completed {
+        contractor: CompanyID,
+        ship: ShipID,
+    },
+    Failed {
+        contractor: CompanyID,
+    },
+    /// The deadline passed before anyone accepted the contract
+    Expired,
+}
+
+impl std::fmt::Display for ContractStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContractStatus::Open => write!(f, "Open"),
+            ContractStatus::Accepted {
+                contractor,
+                picked_up: true,
+            } => write!(f, "Accepted by {} (cargo picked up)", contractor),
+            ContractStatus::Accepted { contractor, .. } => write!(f, "Accepted by {}", contractor),
+            ContractStatus::Completed { contractor, ship } => {
+                write!(f, "Completed by {} with ship {}", contractor, ship)
+            }
+            ContractStatus::Failed { contractor } => write!(f, "Failed by {}", contractor),
+            ContractStatus::Expired => write!(f, "Expired"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Contract {
+    pub id: ContractID,
+    pub issuer: Issuer,
+    pub kind: ContractKind,
+    /// Body whose market provides the cargo of a transport contract
+    pub origin: Option<BodyID>,
+    pub destination: BodyID,
+    /// Units of a commodity to bring to the destination, for delivery and transport contracts
+    pub cargo: Option<(CommodityID, u32)>,
+    /// Last tick at which the contract can be completed
+    pub deadline: u64,
+    /// Credits paid to the contractor on completion
+    pub reward: f64,
+    /// Credits paid by the contractor on failure
+    pub penalty: f64,
+    pub status: ContractStatus,
+}
+
+impl Contract {
+    pub fn contractor(&self) -> Option<&CompanyID> {
+        match &self.status {
+            ContractStatus::Accepted { contractor, .. }
+            | ContractStatus::Completed { contractor, .. }
+            | ContractStatus::Failed { contractor } => Some(contractor),
+            _ => None,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self.status,
+            ContractStatus::Completed { .. }
+                | ContractStatus::Failed { .. }
+                | ContractStatus::Expired
+        )
+    }
+}
+
+/// All the contracts of the game, finished ones included
+#[derive(Resource, Default, Clone, Debug, Serialize, Deserialize)]
+pub struct Contracts {
+    pub contracts: BTreeMap<ContractID, Contract>,
+    next_id: ContractID,
+}
+
+impl Contracts {
+    /// Posts a new contract, with an identifier set automatically
+    pub fn post(&mut self, contract: Contract) -> ContractID {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.contracts.insert(id, Contract { id, ..contract });
+        id
+    }
+
+    pub fn open(&self) -> impl Iterator<Item = &Contract> {
+        self.contracts
+            .values()
+            .filter(|c| c.status == ContractStatus::Open)
+    }
+}
+
+/// An action of a company on a contract
+#[derive(Event, Clone, Debug, PartialEq)]
+pub struct ContractEvent {
+    pub company: CompanyID,
+    pub action: ContractAction,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ContractAction {
+    Accept(ContractID),
+    /// Gives up an accepted contract, which counts as a failure
+    Abandon(ContractID),
+}
+
+/// Changes of the status of the contracts, sent by the authoritative instance
+#[derive(Event, Clone, Debug, PartialEq)]
+pub enum ContractUpdate {
+    Posted(ContractID),
+    Accepted(ContractID),
+    PickedUp {
+        contract: ContractID,
+        ship: ShipID,
+    },
+    Completed {
+        contract: ContractID,
+        ship: ShipID,
+    },
+    Failed(ContractID),
+    Expired(ContractID),
+    Rejected {
+        event: ContractEvent,
+        reason: String,
+    },
+}
+
+/// Reads the contracts saved in the game files, if any
+fn load_contracts(mut commands: Commands, files: Res<GameFiles>) -> color_eyre::Result<()> {
+    let contracts = if files.contracts.exists() {
+        serde_json::from_str(&read_to_string(&files.contracts)?)?
+    } else {
+        Contracts::default()
+    };
+    commands.insert_resource(contracts);
+    Ok(())
+}
+
+fn save_contracts(contracts: Res<Contracts>, files: Res<GameFiles>) -> color_eyre::Result<()> {
+    write(&files.contracts, serde_json::to_string(&*contracts)?)?;
+    Ok(())
+}
+
+/// Pays the reward or the penalty of a contract to its contractor
+fn settle(
+    contract: &Contract,
+    tick: u64,
+    companies: &mut Query<&mut Company>,
+    mapping: &CompaniesMapping,
+) {
+    let Some(contractor) = contract.contractor() else {
+        return;
+    };
+    if let Some(mut company) = mapping
+        .0
+        .get(contractor)
+        .and_then(|e| companies.get_mut(*e).ok())
+    {
+        match contract.status {
+            ContractStatus::Completed { .. } => company.record(
+                tick,
+                contract.reward,
+                Transaction::ContractReward {
+                    contract: contract.id,
+                },
+            ),
+            ContractStatus::Failed { .. } => company.record(
+                tick,
+                -contract.penalty,
+                Transaction::ContractPenalty {
+                    contract: contract.id,
+                },
+            ),
+            _ => {}
+        }
+    }
+}
+
+/// Validates the acceptance and abandonment of contracts
+fn handle_contract_events(
+    mut reader: EventReader<ContractEvent>,
+    mut updates: EventWriter<ContractUpdate>,
+    mut contracts: ResMut<Contracts>,
+    mut companies: Query<&mut Company>,
+    mapping: Res<CompaniesMapping>,
+    time: Res<GameTime>,
+) {
+    for event in reader.read() {
+        let ContractEvent { company, action } = event;
+        let (ContractAction::Accept(contract) | ContractAction::Abandon(contract)) = action;
+        let reject = |reason: &str| ContractUpdate::Rejected {
+            event: event.clone(),
+            reason: reason.into(),
+        };
+        if !mapping.0.contains_key(company) {
+            updates.send(reject("Unknown company"));
+            continue;
+        }
+        let Some(c) = contracts.contracts.get_mut(contract) else {
+            updates.send(reject("Unknown contract"));
+            continue;
+        };
+        match action {
+            ContractAction::Accept(_) => {
+                if c.status != ContractStatus::Open {
+                    updates.send(reject("The contract is not open"));
+                    continue;
+                }
+                c.status = ContractStatus::Accepted {
+                    contractor: *company,
+                    picked_up: false,
+                };
+                updates.send(ContractUpdate::Accepted(*contract));
+            }
+            ContractAction::Abandon(_) => {
+                if !matches!(&c.status, ContractStatus::Accepted { contractor, .. } if contractor == company)
+                {
+                    updates.send(reject("The contract is not accepted by this company"));
+                    continue;
+                }
+                c.status = ContractStatus::Failed {
+                    contractor: *company,
+                };
+                settle(c, time.tick(), &mut companies, &mapping);
+                updates.send(ContractUpdate::Failed(*contract));
+            }
+        }
+    }
+}
+
+fn random_faction(rng: &mut impl Rng) -> Issuer {
+    Issuer::Faction(FACTIONS[rng.gen_range(0..FACTIONS.len())].to_owned())
+}
+
+/// Posts a contract every period. Bodies ask for the commodity their market lacks the most, and factions post
+/// transport contracts from bodies with a market and survey contracts.
+fn post_contracts(
+    mut contracts: ResMut<Contracts>,
+    mut updates: EventWriter<ContractUpdate>,
+    bodies: Query<(&BodyInfo, Option<&Market>)>,
+    commodities: Res<Commodities>,
+    config: Res<ContractsConfig>,
+    economy: Res<EconomyConfig>,
+    time: Res<GameTime>,
+) {
+    let tick = time.tick();
+    if !tick.is_multiple_of(config.posting_period) || contracts.open().count() >= config.max_open {
+        return;
+    }
+    // Bodies and commodities are sorted so that the same ones are drawn for the same seed
+    let mut bodies: Vec<_> = bodies.iter().collect();
+    bodies.sort_by_key(|(BodyInfo(data), _)| data.id);
+    let mut commodity_ids: Vec<_> = commodities.0.keys().copied().collect();
+    commodity_ids.sort();
+    if bodies.len() < 2 || commodity_ids.is_empty() {
+        return;
+    }
+    let mut rng = StdRng::seed_from_u64(hash(&(economy.seed, tick)));
+    let (BodyInfo(destination), market) = bodies[rng.gen_range(0..bodies.len())];
+    let (BodyInfo(origin), origin_market) = bodies
+        .iter()
+        .filter(|(BodyInfo(data), _)| data.id != destination.id)
+        .choose(&mut rng)
+        .unwrap();
+    // Only bodies with a market can ask for deliveries
+    let scarcest = market.and_then(|market| {
+        market
+            .goods
+            .iter()
+            .filter(|(_, good)| good.stock < good.target_stock)
+            .min_by(|(a_id, a), (b_id, b)| {
+                let ratio = |good: &MarketGood| good.stock as f64 / good.target_stock as f64;
+                ratio(a).total_cmp(&ratio(b)).then(a_id.cmp(b_id))
+            })
+            .map(|(id, _)| *id)
+    });
+    let contract = Contract {
+        id: 0,
+        issuer: Issuer::Body(destination.id),
+        kind: ContractKind::Delivery,
+        origin: None,
+        destination: destination.id,
+        cargo: None,
+        deadline: tick + config.duration,
+        reward: 0.,
+        penalty: 0.,
+        status: ContractStatus::Open,
+    };
+    let contract = match (rng.gen_range(0..3), scarcest) {
+        (0, Some(commodity)) => {
+            let quantity = rng.gen_range(1..=10);
+            let value = commodities.get(&commodity).map_or(0., |c| c.base_price) * quantity as f64;
+            Contract {
+                cargo: Some((commodity, quantity)),
+                reward: 2. * value + 1000.,
+                penalty: value,
+                ..contract
+            }
+        }
+        (0 | 1, _) if origin_market.is_some() => {
+            let commodity = commodity_ids[rng.gen_range(0..commodity_ids.len())];
+            let quantity = rng.gen_range(1..=10);
+            Contract {
+                issuer: random_faction(&mut rng),
+                kind: ContractKind::Transport,
+                origin: Some(origin.id),
+                cargo: Some((commodity, quantity)),
+                reward: 500. * quantity as f64,
+                penalty: 1000. * quantity as f64,
+                ..contract
+            }
+        }
+        _ => Contract {
+            issuer: random_faction(&mut rng),
+            kind: ContractKind::Survey,
+            reward: 3000.,
+            penalty: 500.,
+            ..contract
+        },
+    };
+    updates.send(ContractUpdate::Posted(contracts.post(contract)));
+}
+
+/// Picks up the cargo of transport contracts from the market of their origin, which must have it in stock, and completes the contracts whose destination is reached by a ship of
+/// the contractor carrying the cargo, which is then unloaded to the market of the destination
+#[allow(clippy::too_many_arguments)]
+fn progress_contracts(
+    mut contracts: ResMut<Contracts>,
+    mut updates: EventWriter<ContractUpdate>,
+    mut ships: Query<(&ShipInfo, &Position, &Owner, &mut CargoHold)>,
+    mut markets: Query<&mut Market>,
+    bodies: Query<(&Position, &BodyInfo)>,
+    bodies_mapping: Res<BodiesMapping>,
+    mut companies: Query<&mut Company>,
+    companies_mapping: Res<CompaniesMapping>,
+    commodities: Res<Commodities>,
+    config: Res<ContractsConfig>,
+    time: Res<GameTime>,
+) {
+    let reached = |body: &BodyID, ship_pos: DVec3| {
+        bodies_mapping
+            .0
+            .get(body)
+            .and_then(|e| bodies.get(*e).ok())
+            .is_some_and(|(Position(pos), BodyInfo(data))| {
+                pos.distance(ship_pos) < data.radius * config.arrival_distance
+            })
+    };
+    for contract in contracts.contracts.values_mut() {
+        let ContractStatus::Accepted {
+            contractor,
+            picked_up,
+        } = contract.status
+        else {
+            continue;
+        };
+        for (info, Position(pos), Owner(owner), mut hold) in ships.iter_mut() {
+            if *owner != contractor {
+                continue;
+            }
+            if contract.kind == ContractKind::Transport && !picked_up {
+                let Some((commodity, quantity)) = contract.cargo else {
+                    continue;
+                };
+                let Some(origin) = contract.origin.filter(|origin| reached(origin, *pos)) else {
+                    continue;
+                };
+                let Some(mut market) = bodies_mapping
+                    .0
+                    .get(&origin)
+                    .and_then(|e| markets.get_mut(*e).ok())
+                else {
+                    continue;
+                };
+                let Some(good) = market
+                    .goods
+                    .get_mut(&commodity)
+                    .filter(|good| good.stock >= quantity)
+                else {
+                    continue;
+                };
+                if hold.load(&commodities, commodity, quantity).is_err() {
+                    continue;
+                }
+                good.stock -= quantity;
+                contract.status = ContractStatus::Accepted {
+                    contractor,
+                    picked_up: true,
+                };
+                updates.send(ContractUpdate::PickedUp {
+                    contract: contract.id,
+                    ship: info.id,
+                });
+                break;
+            }
+            if !reached(&contract.destination, *pos) {
+                continue;
+            }
+            if let Some((commodity, quantity)) = contract.cargo {
+                if hold.unload(&commodities, commodity, quantity).is_err() {
+                    continue;
+                }
+                if let Some(mut market) = bodies_mapping
+                    .0
+                    .get(&contract.destination)
+                    .and_then(|e| markets.get_mut(*e).ok())
+                {
+                    if let Some(good) = market.goods.get_mut(&commodity) {
+                        good.stock = good.stock.saturating_add(quantity);
+                    }
+                }
+            }
+            contract.status = ContractStatus::Completed {
+                contractor,
+                ship: info.id,
+            };
+            settle(contract, time.tick(), &mut companies, &companies_mapping);
+            updates.send(ContractUpdate::Completed {
+                contract: contract.id,
+                ship: info.id,
+            });
+            break;
+        }
+    }
+}
+
+/// Closes the contracts whose deadline has passed, making their contractor pay the penalty
+fn expire_contracts(
+    mut contracts: ResMut<Contracts>,
+    mut updates: EventWriter<ContractUpdate>,
+    mut companies: Query<&mut Company>,
+    mapping: Res<CompaniesMapping>,
+    time: Res<GameTime>,
+) {
+    let tick = time.tick();
+    for contract in contracts.contracts.values_mut() {
+        if contract.is_finished() || tick <= contract.deadline {
+            continue;
+        }
+        match contract.status {
+            ContractStatus::Open => {
+                contract.status = ContractStatus::Expired;
+                updates.send(ContractUpdate::Expired(contract.id));
+            }
+            ContractStatus::Accepted { contractor, .. } => {
+                contract.status = ContractStatus::Failed { contractor };
+                settle(contract, tick, &mut companies, &mapping);
+                updates.send(ContractUpdate::Failed(contract.id));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::system::RunSystemOnce, math::DVec3, prelude::*};
+
+    use crate::{
+        client::testing::{paused_game, spawn_ship},
+        game::GameFiles,
+        objects::{
+            companies::{CompaniesMapping, CompanyEvent},
+            ships::cargo::CargoHold,
+        },
+        physics::time::SIMTICKS_PER_TICK,
+        prelude::*,
+    };
+
+    use super::{
+        expire_contracts, progress_contracts, Contract, ContractAction, ContractEvent,
+        ContractKind, ContractStatus, ContractUpdate, Contracts, ContractsConfig, Issuer,
+    };
+
+    /// An app with a ship of the player near the given body, and no contract posted automatically
+    fn app_with_ship_near(body: &str) -> App {
+        let mut app = paused_game(ClientPlugin::testing());
+        app.insert_resource(ContractsConfig {
+            max_open: 0,
+            ..Default::default()
+        });
+        let world = app.world_mut();
+        let entity = world.resource::<BodiesMapping>().0[&id_from(body)];
+        let (pos, BodyInfo(data)) = world
+            .query::<(&Position, &BodyInfo)>()
+            .get(world, entity)
+            .unwrap();
+        let spawn_pos = pos.0 + DVec3::new(data.radius * 1.2, 0., 0.);
+        spawn_ship(&mut app, "s", spawn_pos, DVec3::ZERO);
+        let player = app.world().resource::<PlayerCompany>().0;
+        app.world_mut().send_event(ShipEvent::SetOwner {
+            ship: id_from("s"),
+            owner: Some(player),
+        });
+        app.update();
+        app
+    }
+
+    fn contract(
+        kind: ContractKind,
+        cargo: Option<(&str, u32)>,
+        status: ContractStatus,
+    ) -> Contract {
+        Contract {
+            id: 0,
+            issuer: Issuer::Faction("Test".into()),
+            kind,
+            origin: (kind == ContractKind::Transport).then(|| id_from("mars")),
+            destination: id_from("terre"),
+            cargo: cargo.map(|(id, quantity)| (id_from(id), quantity)),
+            deadline: 100,
+            reward: 1000.,
+            penalty: 500.,
+            status,
+        }
+    }
+
+    fn accepted(app: &App) -> ContractStatus {
+        ContractStatus::Accepted {
+            contractor: app.world().resource::<PlayerCompany>().0,
+            picked_up: false,
+        }
+    }
+
+    fn credits(app: &App) -> f64 {
+        let world = app.world();
+        let player = world.resource::<PlayerCompany>().0;
+        let entity = world.resource::<CompaniesMapping>().0[&player];
+        world.get::<Company>(entity).unwrap().credits()
+    }
+
+    fn post(app: &mut App, contract: Contract) -> u64 {
+        app.world_mut().resource_mut::<Contracts>().post(contract)
+    }
+
+    fn status(app: &App, id: u64) -> &ContractStatus {
+        &app.world().resource::<Contracts>().contracts[&id].status
+    }
+
+    #[test]
+    fn test_accept_and_abandon() {
+        let mut app = app_with_ship_near("terre");
+        let id = post(
+            &mut app,
+            contract(ContractKind::Survey, None, ContractStatus::Open),
+        );
+        let player = app.world().resource::<PlayerCompany>().0;
+        let credits_before = credits(&app);
+
+        // Only existing companies can take contracts
+        app.world_mut().send_event(ContractEvent {
+            company: id_from("rival"),
+            action: ContractAction::Accept(id),
+        });
+        app.update();
+        assert_eq!(*status(&app, id), ContractStatus::Open);
+
+        app.world_mut().send_event(ContractEvent {
+            company: player,
+            action: ContractAction::Accept(id),
+        });
+        app.update();
+        assert_eq!(*status(&app, id), accepted(&app));
+
+        app.world_mut().send_event(ContractEvent {
+            company: player,
+            action: ContractAction::Abandon(id),
+        });
+        app.update();
+        assert_eq!(
+            *status(&app, id),
+            ContractStatus::Failed { contractor: player }
+        );
+        assert_eq!(credits(&app), credits_before - 500.);
+    }
+
+    #[test]
+    fn test_accept_by_other_company() {
+        let mut app = app_with_ship_near("terre");
+        let id = post(
+            &mut app,
+            contract(ContractKind::Survey, None, ContractStatus::Open),
+        );
+        let (player, rival) = (app.world().resource::<PlayerCompany>().0, id_from("rival"));
+        app.world_mut().send_event(CompanyEvent::Create(rival));
+        app.update();
+
+        app.world_mut().send_event(ContractEvent {
+            company: rival,
+            action: ContractAction::Accept(id),
+        });
+        app.update();
+        let accepted_by_rival = ContractStatus::Accepted {
+            contractor: rival,
+            picked_up: false,
+        };
+        assert_eq!(*status(&app, id), accepted_by_rival);
+
+        // The player cannot give up the contract of another company
+        app.world_mut().send_event(ContractEvent {
+            company: player,
+            action: ContractAction::Abandon(id),
+        });
+        app.update();
+        assert_eq!(*status(&app, id), accepted_by_rival);
+    }
+
+    #[test]
+    fn test_survey() {
+        let mut app = app_with_ship_near("terre");
+        let status_accepted = accepted(&app);
+        let id = post(
+            &mut app,
+            contract(ContractKind::Survey, None, status_accepted),
+        );
+        let credits_before = credits(&app);
+        app.world_mut().run_system_once(progress_contracts);
+        assert!(matches!(
+            status(&app, id),
+            ContractStatus::Completed { ship, .. } if *ship == id_from("s")
+        ));
+        assert_eq!(credits(&app), credits_before + 1000.);
+    }
+
+    #[test]
+    fn test_delivery() {
+        let mut app = app_with_ship_near("terre");
+        let status_accepted = accepted(&app);
+        let id = post(
+            &mut app,
+            contract(ContractKind::Delivery, Some(("water", 5)), status_accepted),
+        );
+        let water = id_from("water");
+
+        // Without the cargo, reaching the destination is not enough
+        app.world_mut().run_system_once(progress_contracts);
+        assert!(matches!(status(&app, id), ContractStatus::Accepted { .. }));
+
+        let world = app.world_mut();
+        let commodities = world.resource::<Commodities>().clone();
+        let ship = world.resource::<ShipsMapping>().0[&id_from("s")];
+        let earth = world.resource::<BodiesMapping>().0[&id_from("terre")];
+        let stock = world.get::<Market>(earth).unwrap().goods[&water].stock;
+        world
+            .get_mut::<CargoHold>(ship)
+            .unwrap()
+            .load(&commodities, water, 5)
+            .unwrap();
+        world.run_system_once(progress_contracts);
+        assert!(matches!(status(&app, id), ContractStatus::Completed { .. }));
+        let world = app.world();
+        assert_eq!(world.get::<CargoHold>(ship).unwrap().quantity(&water), 0);
+        assert_eq!(
+            world.get::<Market>(earth).unwrap().goods[&water].stock,
+            stock + 5
+        );
+    }
+
+    #[test]
+    fn test_transport_pick_up() {
+        let mut app = app_with_ship_near("mars");
+        let status_accepted = accepted(&app);
+        let id = post(
+            &mut app,
+            contract(ContractKind::Transport, Some(("ore", 2)), status_accepted),
+        );
+        let ore = id_from("ore");
+        let world = app.world_mut();
+        let ship = world.resource::<ShipsMapping>().0[&id_from("s")];
+        let mars = world.resource::<BodiesMapping>().0[&id_from("mars")];
+        let stock = world.get::<Market>(mars).unwrap().goods[&ore].stock;
+        world.run_system_once(progress_contracts);
+        assert!(matches!(
+            status(&app, id),
+            ContractStatus::Accepted {
+                picked_up: true,
+                ..
+            }
+        ));
+        let world = app.world();
+        assert_eq!(world.get::<CargoHold>(ship).unwrap().quantity(&ore), 2);
+        assert_eq!(
+            world.get::<Market>(mars).unwrap().goods[&ore].stock,
+            stock - 2
+        );
+    }
+
+    #[test]
+    fn test_transport_out_of_stock() {
+        let mut app = app_with_ship_near("mars");
+        let status_accepted = accepted(&app);
+        let id = post(
+            &mut app,
+            contract(ContractKind::Transport, Some(("ore", 2)), status_accepted),
+        );
+        let ore = id_from("ore");
+        let world = app.world_mut();
+        let mars = world.resource::<BodiesMapping>().0[&id_from("mars")];
+        world
+            .get_mut::<Market>(mars)
+            .unwrap()
+            .goods
+            .get_mut(&ore)
+            .unwrap()
+            .stock = 1;
+        world.run_system_once(progress_contracts);
+        assert_eq!(*status(&app, id), accepted(&app));
+        let world = app.world();
+        let ship = world.resource::<ShipsMapping>().0[&id_from("s")];
+        assert_eq!(world.get::<CargoHold>(ship).unwrap().quantity(&ore), 0);
+        assert_eq!(world.get::<Market>(mars).unwrap().goods[&ore].stock, 1);
+    }
+
+    #[test]
+    fn test_contracts_file() {
+        let mut app = app_with_ship_near("terre");
+        let id = post(
+            &mut app,
+            contract(
+                ContractKind::Transport,
+                Some(("ore", 2)),
+                ContractStatus::Open,
+            ),
+        );
+        app.world_mut().send_event(ContractUpdate::Posted(id));
+        app.update();
+        let path = &app.world().resource::<GameFiles>().contracts;
+        let saved: Contracts =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(
+            saved.contracts,
+            app.world().resource::<Contracts>().contracts
+        );
+    }
+
+    #[test]
+    fn test_expire() {
+        let mut app = app_with_ship_near("mars");
+        let status_accepted = accepted(&app);
+        let open = post(
+            &mut app,
+            contract(ContractKind::Survey, None, ContractStatus::Open),
+        );
+        let failed = post(
+            &mut app,
+            contract(ContractKind::Survey, None, status_accepted),
+        );
+        let credits_before = credits(&app);
+        app.world_mut().resource_mut::<GameTime>().simtick = 101 * SIMTICKS_PER_TICK;
+        app.world_mut().run_system_once(expire_contracts);
+        assert_eq!(*status(&app, open), ContractStatus::Expired);
+        assert!(matches!(
+            status(&app, failed),
+            ContractStatus::Failed { .. }
+        ));
+        assert_eq!(credits(&app), credits_before - 500.);
+    }
+}
@@ -0,0 +1,277 @@
+//! Production and consumption of commodities by the celestial bodies, following
+//! seeded recipes that take from and feed their markets.
+use std::{collections::BTreeMap, fs::File, io::Read};
+
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::{Authoritative, GameSeed, Loaded},
+    physics::{prelude::*, time::TickEvent},
+    utils::hash::hash,
+};
+
+use super::{
+    commodities::CommodityID,
+    markets::{setup_markets, update_prices, Market},
+    prelude::{BodyData, BodyID, BodyInfo, BodyType},
+    ObjectsUpdate,
+};
+
+const ECONOMY_FILE_PATH: &str = "economy.json";
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<EconomyConfig>()
+        .add_systems(
+            OnEnter(Loaded),
+            (
+                seed_economy.run_if(resource_exists::<GameSeed>),
+                setup_production.after(setup_markets),
+            )
+                .in_set(ObjectsUpdate),
+        )
+        // Clients receive the seed of the game from the server
+        .add_systems(
+            Update,
+            seed_economy.run_if(resource_exists_and_changed::<GameSeed>),
+        )
+        .add_systems(
+            FixedUpdate,
+            run_production.before(update_prices).run_if(
+                in_state(Loaded)
+                    .and_then(in_state(Authoritative))
+                    .and_then(on_event::<TickEvent>()),
+            ),
+        );
+}
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct EconomyConfig {
+    /// Derived from the [GameSeed]
+    pub seed: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Recipe {
+    pub name: String,
+    /// Units of each commodity consumed by a run, which only happens if they are all in stock
+    #[serde(default)]
+    pub inputs: BTreeMap<CommodityID, u32>,
+    /// Units of each commodity produced by a run
+    #[serde(default)]
+    pub outputs: BTreeMap<CommodityID, u32>,
+    /// Probability that the recipe runs at each tick
+    #[serde(default = "always")]
+    pub chance: f64,
+}
+
+fn always() -> f64 {
+    1.
+}
+
+impl Recipe {
+    /// Runs the recipe once if its inputs are in stock, and returns whether it ran
+    pub fn run(&self, market: &mut Market) -> bool {
+        let in_stock = self.inputs.iter().all(|(id, quantity)| {
+            market
+                .goods
+                .get(id)
+                .is_some_and(|good| good.stock >= *quantity)
+        });
+        if !in_stock {
+            return false;
+        }
+        for (id, quantity) in &self.inputs {
+            market.goods.get_mut(id).unwrap().stock -= quantity;
+        }
+        for (id, quantity) in &self.outputs {
+            if let Some(good) = market.goods.get_mut(id) {
+                good.stock = good.stock.saturating_add(*quantity);
+            }
+        }
+        true
+    }
+}
+
+/// The recipes run by a body
+#[derive(Component, Clone, Debug, Default, PartialEq)]
+pub struct Production(pub Vec<Recipe>);
+
+impl Production {
+    pub fn run(&self, market: &mut Market, rng: &mut impl Rng) {
+        for recipe in &self.0 {
+            // The draw happens even if the inputs are missing, so that a recipe does not change the outcome of others
+            if rng.gen_bool(recipe.chance.clamp(0., 1.)) {
+                recipe.run(market);
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct BodyTypeRecipes {
+    body_type: BodyType,
+    recipes: Vec<Recipe>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct BodyRecipes {
+    id: BodyID,
+    recipes: Vec<Recipe>,
+}
+
+/// The recipes of the data file
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct EconomyData {
+    #[serde(default)]
+    body_types: Vec<BodyTypeRecipes>,
+    #[serde(default)]
+    bodies: Vec<BodyRecipes>,
+}
+
+impl EconomyData {
+    /// The recipes of the body if it has overrides, or the ones of its type otherwise
+    pub fn recipes_for(&self, data: &BodyData) -> Vec<Recipe> {
+        self.bodies
+            .iter()
+            .find(|b| b.id == data.id)
+            .map(|b| &b.recipes)
+            .or_else(|| {
+                self.body_types
+                    .iter()
+                    .find(|t| t.body_type == data.body_type)
+                    .map(|t| &t.recipes)
+            })
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+pub fn read_economy() -> std::io::Result<EconomyData> {
+    let mut file = File::open(ECONOMY_FILE_PATH)?;
+    let mut buf = String::new();
+    file.read_to_string(&mut buf)?;
+    serde_json::from_str(&buf).map_err(std::io::Error::from)
+}
+
+fn setup_production(mut commands: Commands, bodies: Query<(Entity, &BodyInfo), With<Market>>) {
+    let economy = read_economy().expect("Failed to read economy");
+    for (entity, BodyInfo(data)) in bodies.iter() {
+        commands
+            .entity(entity)
+            .insert(Production(economy.recipes_for(data)));
+    }
+}
+
+fn seed_economy(mut config: ResMut<EconomyConfig>, seed: Res<GameSeed>) {
+    config.seed = hash(&(seed.0, "economy"));
+}
+
+fn run_production(
+    mut bodies: Query<(&BodyInfo, &Production, &mut Market)>,
+    config: Res<EconomyConfig>,
+    time: Res<GameTime>,
+) {
+    bodies
+        .par_iter_mut()
+        .for_each(|(BodyInfo(data), production, mut market)| {
+            let mut rng = StdRng::seed_from_u64(hash(&(config.seed, data.id, time.tick())));
+            production.run(&mut market, &mut rng);
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::objects::{
+        markets::{Market, MarketGood},
+        prelude::*,
+    };
+
+    use super::{read_economy, Production, Recipe};
+
+    fn market() -> Market {
+        Market {
+            goods: ["water", "food"]
+                .into_iter()
+                .map(|id| (id_from(id), MarketGood::new(10, 1.)))
+                .collect(),
+            spread: 0.,
+        }
+    }
+
+    #[test]
+    fn test_recipe() {
+        let (water, food) = (id_from("water"), id_from("food"));
+        let recipe = Recipe {
+            name: "Agriculture".into(),
+            inputs: [(water, 4)].into_iter().collect(),
+            outputs: [(food, 1)].into_iter().collect(),
+            chance: 1.,
+        };
+        let mut market = market();
+        assert!(recipe.run(&mut market));
+        assert!(recipe.run(&mut market));
+        assert!(!recipe.run(&mut market));
+        assert_eq!(market.goods[&water].stock, 2);
+        assert_eq!(market.goods[&food].stock, 12);
+    }
+
+    #[test]
+    fn test_production_is_deterministic() {
+        let production = Production(vec![
+            Recipe {
+                name: "Ice mining".into(),
+                outputs: [(id_from("water"), 1)].into_iter().collect(),
+                chance: 0.5,
+                ..Default::default()
+            },
+            Recipe {
+                name: "Population".into(),
+                inputs: [(id_from("food"), 1)].into_iter().collect(),
+                chance: 0.3,
+                ..Default::default()
+            },
+        ]);
+        let simulate = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut market = market();
+            for _ in 0..100 {
+                production.run(&mut market, &mut rng);
+            }
+            market
+        };
+        assert_eq!(simulate(0), simulate(0));
+        let market = simulate(0);
+        assert!(market.goods[&id_from("water")].stock > 10);
+        assert_eq!(market.goods[&id_from("food")].stock, 0);
+    }
+
+    #[test]
+    fn test_recipes_for() {
+        let economy = read_economy().unwrap();
+        let earth = BodyData {
+            id: id_from("terre"),
+            body_type: BodyType::Planet,
+            ..Default::default()
+        };
+        let planet = BodyData {
+            id: id_from("nowhere"),
+            ..earth.clone()
+        };
+        let star = BodyData {
+            body_type: BodyType::Star,
+            ..planet.clone()
+        };
+        let earth_recipes = economy.recipes_for(&earth);
+        assert!(earth_recipes.iter().any(|r| r.name == "Electronics"));
+        assert_ne!(earth_recipes, economy.recipes_for(&planet));
+        assert!(!economy.recipes_for(&planet).is_empty());
+        assert!(economy.recipes_for(&star).is_empty());
+    }
+}
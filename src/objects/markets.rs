@@ -0,0 +1,764 @@
+//! A "Market" is where ships close to a celestial body buy and sell commodities,
+//! at prices following the stock of the market.
+use bevy::{math::DVec3, prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::{Authoritative, Loaded},
+    physics::{prelude::*, time::TickEvent},
+};
+
+use super::{
+    bodies::build_system,
+    commodities::{load_commodities, Commodities, CommodityID, PROPELLANT_ID},
+    companies::{CompaniesMapping, Company, CompanyID, Owner, Transaction},
+    prelude::{id_from, BodyInfo, BodyType, ShipID, ShipMass, ShipsMapping},
+    ships::cargo::{update_cargo_mass, CargoError, CargoHold},
+    ObjectsUpdate,
+};
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<MarketConfig>()
+        .add_event::<TradeEvent>()
+        .add_event::<TradeOutcome>()
+        .add_systems(
+            OnEnter(Loaded),
+            setup_markets
+                .after(build_system)
+                .after(load_commodities)
+                .in_set(ObjectsUpdate),
+        )
+        .add_systems(
+            Update,
+            handle_trade_events
+                .before(update_cargo_mass)
+                .in_set(ObjectsUpdate)
+                .run_if(in_state(Authoritative)),
+        )
+        .add_systems(
+            FixedUpdate,
+            update_prices.run_if(
+                in_state(Loaded)
+                    .and_then(in_state(Authoritative))
+                    .and_then(on_event::<TickEvent>()),
+            ),
+        );
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct MarketConfig {
+    /// Ships can trade with the market of a body when their distance to its center is less than this many body radii
+    pub trade_distance: f64,
+    /// Distance (in km) under which ships can trade with markets that are not attached to a body, such as stations
+    pub station_trade_distance: f64,
+    /// Relative difference between the ask and bid prices of new markets
+    pub spread: f64,
+    /// How strongly the equilibrium price reacts to the ratio between the wanted stock and the actual stock
+    pub elasticity: f64,
+    /// Fraction of the gap to the equilibrium price that prices close at each tick
+    pub adjustment_rate: f64,
+    /// Stock that new markets want to keep for every commodity
+    pub initial_stock: u32,
+    /// Credits charged to the owner of a ship for every completed trade
+    pub trade_fee: f64,
+}
+
+impl Default for MarketConfig {
+    fn default() -> Self {
+        Self {
+            trade_distance: 1.5,
+            station_trade_distance: 10.,
+            spread: 0.1,
+            elasticity: 0.5,
+            adjustment_rate: 0.05,
+            initial_stock: 1000,
+            trade_fee: 10.,
+        }
+    }
+}
+
+/// The state of a commodity in a market
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MarketGood {
+    /// Number of units that the market can sell
+    pub stock: u32,
+    /// Number of units that the market wants to keep, at which the equilibrium price is the base price
+    pub target_stock: u32,
+    /// Price between the bid and ask prices (in credits)
+    pub price: f64,
+}
+
+impl MarketGood {
+    pub fn new(stock: u32, base_price: f64) -> Self {
+        Self {
+            stock,
+            target_stock: stock,
+            price: base_price,
+        }
+    }
+
+    /// The price towards which the market price evolves, given the current supply and demand
+    pub fn equilibrium_price(&self, base_price: f64, elasticity: f64) -> f64 {
+        // The stock is offset by one unit so that the price stays finite when the market is out of stock
+        let ratio = (self.target_stock as f64 + 1.) / (self.stock as f64 + 1.);
+        base_price * ratio.powf(elasticity)
+    }
+}
+
+#[derive(Component, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Market {
+    pub goods: HashMap<CommodityID, MarketGood>,
+    /// Relative difference between the ask and bid prices
+    pub spread: f64,
+}
+
+impl Market {
+    /// Price at which the market sells a unit of a commodity to a ship
+    pub fn ask(&self, commodity: &CommodityID) -> Option<f64> {
+        self.goods
+            .get(commodity)
+            .map(|good| good.price * (1. + self.spread / 2.))
+    }
+
+    /// Price at which the market buys a unit of a commodity from a ship
+    pub fn bid(&self, commodity: &CommodityID) -> Option<f64> {
+        self.goods
+            .get(commodity)
+            .map(|good| good.price * (1. - self.spread / 2.))
+    }
+
+    /// Moves the prices towards their equilibrium
+    pub fn update_prices(&mut self, commodities: &Commodities, config: &MarketConfig) {
+        for (id, good) in self.goods.iter_mut() {
+            if let Some(data) = commodities.get(id) {
+                let target = good.equilibrium_price(data.base_price, config.elasticity);
+                good.price += (target - good.price) * config.adjustment_rate;
+            }
+        }
+    }
+}
+
+/// A trade requested by a company, which has to own the ship
+#[derive(Event, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TradeEvent {
+    pub company: CompanyID,
+    pub order: TradeOrder,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TradeOrder {
+    /// The ship buys units of a commodity from the closest market in range
+    Buy {
+        ship: ShipID,
+        commodity: CommodityID,
+        quantity: u32,
+    },
+    /// The ship sells units of a commodity to the closest market in range
+    Sell {
+        ship: ShipID,
+        commodity: CommodityID,
+        quantity: u32,
+    },
+    /// The ship buys units of propellant from the closest market in range, which go to its tanks instead of its hold
+    Refuel { ship: ShipID, quantity: u32 },
+}
+
+impl TradeOrder {
+    pub fn ship(&self) -> ShipID {
+        match self {
+            TradeOrder::Buy { ship, .. }
+            | TradeOrder::Sell { ship, .. }
+            | TradeOrder::Refuel { ship, .. } => *ship,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TradeError {
+    UnknownShip(ShipID),
+    /// The ship has no owner to pay for the trade
+    NoOwner(ShipID),
+    /// The ship is not owned by the company requesting the trade
+    NotOwned(ShipID),
+    UnknownCompany(CompanyID),
+    InsufficientCredits {
+        required: f64,
+        available: f64,
+    },
+    NoMarketInRange,
+    NotTraded(CommodityID),
+    NotEnoughStock {
+        requested: u32,
+        available: u32,
+    },
+    /// The bought propellant does not fit in the tanks of the ship (in kg)
+    TankOverfill {
+        requested: f64,
+        available: f64,
+    },
+    Cargo(CargoError),
+}
+
+impl From<CargoError> for TradeError {
+    fn from(value: CargoError) -> Self {
+        Self::Cargo(value)
+    }
+}
+
+impl std::fmt::Display for TradeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TradeError::UnknownShip(id) => write!(f, "Unknown ship \"{}\"", id),
+            TradeError::NoOwner(id) => write!(f, "Ship \"{}\" has no owner", id),
+            TradeError::NotOwned(id) => {
+                write!(f, "Ship \"{}\" is not owned by the trading company", id)
+            }
+            TradeError::UnknownCompany(id) => write!(f, "Unknown company \"{}\"", id),
+            TradeError::InsufficientCredits {
+                required,
+                available,
+            } => write!(
+                f,
+                "Couldn't pay {:.2} credits because only {:.2} are available",
+                required, available
+            ),
+            TradeError::NoMarketInRange => write!(f, "No market is close enough to trade"),
+            TradeError::NotTraded(id) => write!(f, "The market does not trade \"{}\"", id),
+            TradeError::NotEnoughStock {
+                requested,
+                available,
+            } => write!(
+                f,
+                "Couldn't buy {} units because the market only has {}",
+                requested, available
+            ),
+            TradeError::TankOverfill {
+                requested,
+                available,
+            } => write!(
+                f,
+                "Couldn't fit {:.0} kg of propellant in tanks with {:.0} kg of free space",
+                requested, available
+            ),
+            TradeError::Cargo(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for TradeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TradeError::Cargo(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// The result of a [TradeEvent], once it has been validated
+#[derive(Event, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TradeOutcome {
+    Completed {
+        trade: TradeEvent,
+        market: Entity,
+        /// Price of a unit of the commodity (in credits)
+        unit_price: f64,
+    },
+    Rejected {
+        trade: TradeEvent,
+        error: TradeError,
+    },
+}
+
+pub fn setup_markets(
+    mut commands: Commands,
+    bodies: Query<(Entity, &BodyInfo)>,
+    commodities: Res<Commodities>,
+    config: Res<MarketConfig>,
+) {
+    for (entity, BodyInfo(data)) in bodies.iter() {
+        if data.body_type == BodyType::Star {
+            continue;
+        }
+        commands.entity(entity).insert(Market {
+            goods: commodities
+                .0
+                .values()
+                .map(|c| (c.id, MarketGood::new(config.initial_stock, c.base_price)))
+                .collect(),
+            spread: config.spread,
+        });
+    }
+}
+
+pub fn update_prices(
+    mut markets: Query<&mut Market>,
+    commodities: Res<Commodities>,
+    config: Res<MarketConfig>,
+) {
+    markets
+        .iter_mut()
+        .for_each(|mut market| market.update_prices(&commodities, &config));
+}
+
+/// Finds the closest market that the ship can trade with
+pub fn market_in_range<'a>(
+    ship_pos: DVec3,
+    markets: impl Iterator<Item = (Entity, &'a Position, Option<&'a BodyInfo>)>,
+    config: &MarketConfig,
+) -> Option<Entity> {
+    markets
+        .filter_map(|(entity, pos, info)| {
+            let distance = (pos.0 - ship_pos).length();
+            let range = info.map_or(config.station_trade_distance, |BodyInfo(data)| {
+                data.radius * config.trade_distance
+            });
+            (distance < range).then_some((entity, distance))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(entity, _)| entity)
+}
+
+/// The parts of a ship and of its owner that a trade changes
+struct Trader<'a> {
+    ship: ShipID,
+    pos: DVec3,
+    hold: &'a mut CargoHold,
+    mass: &'a mut ShipMass,
+    company: &'a mut Company,
+}
+
+impl Trader<'_> {
+    fn can_afford(&self, cost: f64) -> Result<(), TradeError> {
+        let available = self.company.credits();
+        if cost > available {
+            Err(TradeError::InsufficientCredits {
+                required: cost,
+                available,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn check_stock(market: &Market, commodity: &CommodityID, quantity: u32) -> Result<(), TradeError> {
+    let available = market.goods.get(commodity).map_or(0, |good| good.stock);
+    if quantity > available {
+        Err(TradeError::NotEnoughStock {
+            requested: quantity,
+            available,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+fn execute_trade(
+    trade: &TradeEvent,
+    trader: Trader,
+    markets: &mut Query<(Entity, &Position, Option<&BodyInfo>, &mut Market)>,
+    commodities: &Commodities,
+    config: &MarketConfig,
+    tick: u64,
+) -> Result<(Entity, f64), TradeError> {
+    let entity = market_in_range(
+        trader.pos,
+        markets.iter().map(|(e, pos, info, _)| (e, pos, info)),
+        config,
+    )
+    .ok_or(TradeError::NoMarketInRange)?;
+    let (_, _, _, mut market) = markets.get_mut(entity).unwrap();
+    let ship = trader.ship;
+    let fee = config.trade_fee;
+    let unit_price = match trade.order {
+        TradeOrder::Buy {
+            commodity,
+            quantity,
+            ..
+        } => {
+            let unit_price = market
+                .ask(&commodity)
+                .ok_or(TradeError::NotTraded(commodity))?;
+            let price = unit_price * quantity as f64;
+            check_stock(&market, &commodity, quantity)?;
+            trader.can_afford(price + fee)?;
+            trader.hold.load(commodities, commodity, quantity)?;
+            market.goods.get_mut(&commodity).unwrap().stock -= quantity;
+            trader.company.record(
+                tick,
+                -price,
+                Transaction::Purchase {
+                    ship,
+                    commodity,
+                    quantity,
+                    unit_price,
+                },
+            );
+            unit_price
+        }
+        TradeOrder::Sell {
+            commodity,
+            quantity,
+            ..
+        } => {
+            let unit_price = market
+                .bid(&commodity)
+                .ok_or(TradeError::NotTraded(commodity))?;
+            let price = unit_price * quantity as f64;
+            trader.can_afford(fee - price)?;
+            trader.hold.unload(commodities, commodity, quantity)?;
+            market.goods.get_mut(&commodity).unwrap().stock += quantity;
+            trader.company.record(
+                tick,
+                price,
+                Transaction::Sale {
+                    ship,
+                    commodity,
+                    quantity,
+                    unit_price,
+                },
+            );
+            unit_price
+        }
+        TradeOrder::Refuel { quantity, .. } => {
+            let commodity = id_from(PROPELLANT_ID);
+            let (Some(unit_price), Some(data)) =
+                (market.ask(&commodity), commodities.get(&commodity))
+            else {
+                return Err(TradeError::NotTraded(commodity));
+            };
+            let price = unit_price * quantity as f64;
+            check_stock(&market, &commodity, quantity)?;
+            let propellant = data.unit_mass * quantity as f64;
+            let available = trader.mass.tank_capacity - trader.mass.propellant;
+            if propellant > available {
+                return Err(TradeError::TankOverfill {
+                    requested: propellant,
+                    available,
+                });
+            }
+            trader.can_afford(price + fee)?;
+            trader.mass.propellant += propellant;
+            market.goods.get_mut(&commodity).unwrap().stock -= quantity;
+            trader.company.record(
+                tick,
+                -price,
+                Transaction::Fuel {
+                    ship,
+                    propellant,
+                    price,
+                },
+            );
+            unit_price
+        }
+    };
+    if fee > 0. {
+        trader.company.record(
+            tick,
+            -fee,
+            Transaction::Fee {
+                ship,
+                reason: "Market fee".into(),
+            },
+        );
+    }
+    Ok((entity, unit_price))
+}
+
+/// Validates the trades and applies them to the cargo holds, the markets and the credits of the owners
+#[allow(clippy::too_many_arguments)]
+fn handle_trade_events(
+    mut reader: EventReader<TradeEvent>,
+    mut writer: EventWriter<TradeOutcome>,
+    mut ships: Query<(&Position, &mut CargoHold, &mut ShipMass, Option<&Owner>)>,
+    mut markets: Query<(Entity, &Position, Option<&BodyInfo>, &mut Market)>,
+    mut companies: Query<&mut Company>,
+    mapping: Res<ShipsMapping>,
+    companies_mapping: Res<CompaniesMapping>,
+    commodities: Res<Commodities>,
+    config: Res<MarketConfig>,
+    time: Res<GameTime>,
+) {
+    for trade in reader.read() {
+        let ship = trade.order.ship();
+        let mut result = || {
+            let (pos, mut hold, mut mass, owner) = mapping
+                .0
+                .get(&ship)
+                .and_then(|e| ships.get_mut(*e).ok())
+                .ok_or(TradeError::UnknownShip(ship))?;
+            let &Owner(owner) = owner.ok_or(TradeError::NoOwner(ship))?;
+            if owner != trade.company {
+                return Err(TradeError::NotOwned(ship));
+            }
+            let mut company = companies_mapping
+                .0
+                .get(&owner)
+                .and_then(|e| companies.get_mut(*e).ok())
+                .ok_or(TradeError::UnknownCompany(owner))?;
+            let trader = Trader {
+                ship,
+                pos: pos.0,
+                hold: &mut hold,
+                mass: &mut mass,
+                company: &mut company,
+            };
+            execute_trade(
+                trade,
+                trader,
+                &mut markets,
+                &commodities,
+                &config,
+                time.tick(),
+            )
+        };
+        writer.send(match result() {
+            Ok((market, unit_price)) => TradeOutcome::Completed {
+                trade: trade.clone(),
+                market,
+                unit_price,
+            },
+            Err(error) => TradeOutcome::Rejected {
+                trade: trade.clone(),
+                error,
+            },
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::event::Events, math::DVec3, prelude::*};
+
+    use crate::{
+        client::testing::{paused_game, spawn_ship},
+        objects::{
+            companies::CompaniesMapping,
+            ships::cargo::{CargoError, CargoHold},
+        },
+        prelude::*,
+    };
+
+    use super::{
+        Market, MarketConfig, MarketGood, TradeError, TradeEvent, TradeOrder, TradeOutcome,
+    };
+
+    #[test]
+    fn test_prices_follow_stock() {
+        let commodities: Commodities = [CommodityData {
+            id: id_from("water"),
+            name: "Water".into(),
+            unit_mass: 1000.,
+            base_price: 20.,
+        }]
+        .into_iter()
+        .collect();
+        let config = MarketConfig::default();
+        let water = id_from("water");
+        let mut market = Market {
+            goods: [(water, MarketGood::new(100, 20.))].into_iter().collect(),
+            spread: 0.1,
+        };
+        assert!(market.ask(&water).unwrap() > market.bid(&water).unwrap());
+
+        // Scarcity makes the price rise
+        market.goods.get_mut(&water).unwrap().stock = 10;
+        market.update_prices(&commodities, &config);
+        let scarce = market.goods[&water].price;
+        assert!(scarce > 20.);
+        market.update_prices(&commodities, &config);
+        assert!(market.goods[&water].price > scarce);
+
+        // Abundance makes it fall under the base price
+        market.goods.get_mut(&water).unwrap().stock = 10000;
+        for _ in 0..200 {
+            market.update_prices(&commodities, &config);
+        }
+        assert!(market.goods[&water].price < 20.);
+    }
+
+    fn app_with_ship_near_earth(distance_in_radii: f64, owned: bool) -> App {
+        let mut app = paused_game(ClientPlugin::testing());
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0[&id_from("terre")];
+        let (earth_pos, BodyInfo(data)) = world
+            .query::<(&Position, &BodyInfo)>()
+            .get(world, earth)
+            .unwrap();
+        let spawn_pos = earth_pos.0 + DVec3::new(data.radius * distance_in_radii, 0., 0.);
+        spawn_ship(&mut app, "s", spawn_pos, DVec3::ZERO);
+        if owned {
+            let player = app.world().resource::<PlayerCompany>().0;
+            app.world_mut().send_event(ShipEvent::SetOwner {
+                ship: id_from("s"),
+                owner: Some(player),
+            });
+            app.update();
+        }
+        app
+    }
+
+    fn player_company(app: &App) -> &Company {
+        let world = app.world();
+        let player = world.resource::<PlayerCompany>().0;
+        let entity = world.resource::<CompaniesMapping>().0[&player];
+        world.get::<Company>(entity).unwrap()
+    }
+
+    fn trade(app: &mut App, trade: TradeEvent) -> TradeOutcome {
+        app.world_mut().send_event(trade);
+        app.update();
+        let mut outcomes = app.world_mut().resource_mut::<Events<TradeOutcome>>();
+        outcomes.drain().last().unwrap()
+    }
+
+    #[test]
+    fn test_trade() {
+        let mut app = app_with_ship_near_earth(1.2, true);
+        let (ship, water) = (id_from("s"), id_from("water"));
+        let company = app.world().resource::<PlayerCompany>().0;
+        let credits = player_company(&app).credits();
+        let buy = TradeEvent {
+            company,
+            order: TradeOrder::Buy {
+                ship,
+                commodity: water,
+                quantity: 5,
+            },
+        };
+        let TradeOutcome::Completed {
+            market, unit_price, ..
+        } = trade(&mut app, buy)
+        else {
+            panic!("trade should have been completed");
+        };
+        let player = player_company(&app);
+        let fee = MarketConfig::default().trade_fee;
+        assert!((player.credits() - (credits - 5. * unit_price - fee)).abs() < 1e-6);
+        assert_eq!(player.ledger().len(), 2);
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0[&id_from("terre")];
+        assert_eq!(market, earth);
+        let stock = world.get::<Market>(earth).unwrap().goods[&water].stock;
+        assert_eq!(stock, MarketConfig::default().initial_stock - 5);
+        let entity = world.resource::<ShipsMapping>().0[&ship];
+        assert_eq!(world.get::<CargoHold>(entity).unwrap().quantity(&water), 5);
+        assert_eq!(world.get::<ShipMass>(entity).unwrap().cargo, 5000.);
+
+        let sell = TradeEvent {
+            company,
+            order: TradeOrder::Sell {
+                ship,
+                commodity: water,
+                quantity: 6,
+            },
+        };
+        assert_eq!(
+            trade(&mut app, sell.clone()),
+            TradeOutcome::Rejected {
+                trade: sell,
+                error: TradeError::Cargo(CargoError::NotEnoughCargo {
+                    commodity: water,
+                    requested: 6,
+                    available: 5
+                })
+            }
+        );
+
+        let world = app.world_mut();
+        let propellant = world.get::<ShipMass>(entity).unwrap().propellant;
+        assert!(matches!(
+            trade(
+                &mut app,
+                TradeEvent {
+                    company,
+                    order: TradeOrder::Refuel { ship, quantity: 2 },
+                }
+            ),
+            TradeOutcome::Completed { .. }
+        ));
+        let world = app.world();
+        let entity = world.resource::<ShipsMapping>().0[&ship];
+        let mass = *world.get::<ShipMass>(entity).unwrap();
+        assert_eq!(mass.propellant, propellant + 2000.);
+
+        // The tanks cannot be filled past their capacity
+        let refuel = TradeEvent {
+            company,
+            order: TradeOrder::Refuel {
+                ship,
+                quantity: 100,
+            },
+        };
+        assert_eq!(
+            trade(&mut app, refuel.clone()),
+            TradeOutcome::Rejected {
+                trade: refuel,
+                error: TradeError::TankOverfill {
+                    requested: 1e5,
+                    available: mass.tank_capacity - mass.propellant
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn test_trade_with_ship_of_other_company() {
+        let mut app = app_with_ship_near_earth(1.2, true);
+        let buy = TradeEvent {
+            company: id_from("other"),
+            order: TradeOrder::Buy {
+                ship: id_from("s"),
+                commodity: id_from("water"),
+                quantity: 1,
+            },
+        };
+        assert_eq!(
+            trade(&mut app, buy.clone()),
+            TradeOutcome::Rejected {
+                trade: buy,
+                error: TradeError::NotOwned(id_from("s"))
+            }
+        );
+    }
+
+    #[test]
+    fn test_trade_without_owner() {
+        let mut app = app_with_ship_near_earth(1.2, false);
+        let buy = TradeEvent {
+            company: app.world().resource::<PlayerCompany>().0,
+            order: TradeOrder::Buy {
+                ship: id_from("s"),
+                commodity: id_from("water"),
+                quantity: 1,
+            },
+        };
+        assert_eq!(
+            trade(&mut app, buy.clone()),
+            TradeOutcome::Rejected {
+                trade: buy,
+                error: TradeError::NoOwner(id_from("s"))
+            }
+        );
+    }
+
+    #[test]
+    fn test_trade_out_of_range() {
+        let mut app = app_with_ship_near_earth(3., true);
+        let buy = TradeEvent {
+            company: app.world().resource::<PlayerCompany>().0,
+            order: TradeOrder::Buy {
+                ship: id_from("s"),
+                commodity: id_from("water"),
+                quantity: 1,
+            },
+        };
+        assert_eq!(
+            trade(&mut app, buy.clone()),
+            TradeOutcome::Rejected {
+                trade: buy,
+                error: TradeError::NoMarketInRange
+            }
+        );
+    }
+}
@@ -3,16 +3,23 @@
 
 use arrayvec::ArrayString;
 use bevy::{math::DVec3, prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
 
 use crate::game::{ClearOnUnload, Loaded};
-use crate::physics::influence::HillRadius;
+use crate::physics::influence::{HillRadius, InfluenceIndex};
 use crate::physics::leapfrog::get_acceleration;
+use crate::physics::perturbations::{DragProfile, RadiationProfile};
 use crate::physics::prelude::*;
 
+use super::companies::{CompanyID, Owner};
 use super::id::MAX_ID_LENGTH;
-use super::prelude::{BodiesMapping, BodyInfo, PrimaryBody};
+use super::prelude::{BodiesMapping, BodyInfo};
 use super::ObjectsUpdate;
+use cargo::CargoHold;
+use propulsion::{Isp, ShipMass};
 
+pub mod cargo;
+pub mod propulsion;
 pub mod trajectory;
 
 // pub(crate) struct ShipID(u64);
@@ -36,7 +43,7 @@ pub struct ShipsPlugin;
 
 impl Plugin for ShipsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(trajectory::plugin)
+        app.add_plugins((trajectory::plugin, propulsion::plugin, cargo::plugin))
             .add_event::<ShipEvent>()
             .add_systems(Update, handle_ship_events.in_set(ObjectsUpdate))
             .add_systems(OnEnter(Loaded), create_ships.in_set(ObjectsUpdate));
@@ -45,7 +52,7 @@ impl Plugin for ShipsPlugin {
 
 pub type ShipID = ArrayString<MAX_ID_LENGTH>;
 
-#[derive(Component, Clone, Default, PartialEq)]
+#[derive(Component, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct ShipInfo {
     pub id: ShipID,
     pub spawn_pos: DVec3,
@@ -55,13 +62,29 @@ pub struct ShipInfo {
 #[derive(Resource, Default)]
 pub struct ShipsMapping(pub HashMap<ShipID, Entity>);
 
+impl ShipsMapping {
+    /// The ships owned by a company
+    pub fn owned_by(&self, company: &CompanyID, owners: &Query<&Owner>) -> Vec<(ShipID, Entity)> {
+        self.0
+            .iter()
+            .filter(|(_, e)| owners.get(**e).is_ok_and(|Owner(o)| o == company))
+            .map(|(id, e)| (*id, *e))
+            .collect()
+    }
+}
+
 #[derive(Event)]
 pub enum ShipEvent {
     Create(ShipInfo),
     Remove(ShipID),
+    /// Transfers a ship to a company, or leaves it without owner
+    SetOwner {
+        ship: ShipID,
+        owner: Option<CompanyID>,
+    },
 }
 
-fn create_ships(mut commands: Commands) {
+pub fn create_ships(mut commands: Commands) {
     commands.insert_resource(ShipsMapping::default());
 }
 
@@ -71,15 +94,14 @@ fn handle_ship_events(
     mut ships: ResMut<ShipsMapping>,
     bodies: Query<(&Position, &HillRadius, &BodyInfo)>,
     mapping: Res<BodiesMapping>,
-    main_body: Query<&BodyInfo, With<PrimaryBody>>,
+    index: Res<InfluenceIndex>,
 ) {
     for event in reader.read() {
         match event {
             ShipEvent::Create(info) => {
                 let pos = Position(info.spawn_pos);
                 ships.0.entry(info.id).or_insert({
-                    let influence =
-                        Influenced::new(&pos, &bodies, mapping.as_ref(), main_body.single().0.id);
+                    let influence = Influenced::new(&pos, &index, &bodies, mapping.as_ref());
                     commands
                         .spawn((
                             info.clone(),
@@ -92,6 +114,11 @@ fn handle_ship_events(
                             influence,
                             pos,
                             Velocity(info.spawn_speed),
+                            ShipMass::default(),
+                            Isp::default(),
+                            CargoHold::default(),
+                            RadiationProfile::default(),
+                            DragProfile::default(),
                             TransformBundle::from_transform(Transform::from_xyz(0., 0., 1.)),
                             ClearOnUnload,
                         ))
@@ -103,6 +130,14 @@ fn handle_ship_events(
                     commands.entity(e).despawn()
                 }
             }
+            ShipEvent::SetOwner { ship, owner } => {
+                if let Some(e) = ships.0.get(ship) {
+                    match owner {
+                        Some(owner) => commands.entity(*e).insert(Owner(*owner)),
+                        None => commands.entity(*e).remove::<Owner>(),
+                    };
+                }
+            }
         }
     }
 }
@@ -0,0 +1,226 @@
+//! Cargo holds of ships, which carry commodities whose mass adds up to the mass of the ship.
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::objects::{
+    commodities::{Commodities, CommodityID},
+    ObjectsUpdate,
+};
+
+use super::propulsion::ShipMass;
+
+pub fn plugin(app: &mut App) {
+    app.add_systems(Update, update_cargo_mass.in_set(ObjectsUpdate));
+}
+
+/// The commodities carried by a ship, with the number of units of each of them
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct CargoHold {
+    /// Maximum mass of the carried commodities (in kg)
+    pub capacity: f64,
+    pub contents: HashMap<CommodityID, u32>,
+}
+
+impl Default for CargoHold {
+    fn default() -> Self {
+        Self {
+            capacity: 2e4,
+            contents: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CargoError {
+    UnknownCommodity(CommodityID),
+    OverCapacity {
+        /// Mass that was requested to be loaded (in kg)
+        requested: f64,
+        /// Mass that can still be loaded (in kg)
+        available: f64,
+    },
+    NotEnoughCargo {
+        commodity: CommodityID,
+        requested: u32,
+        available: u32,
+    },
+}
+
+impl std::fmt::Display for CargoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CargoError::UnknownCommodity(id) => write!(f, "Unknown commodity \"{}\"", id),
+            CargoError::OverCapacity {
+                requested,
+                available,
+            } => write!(
+                f,
+                "Couldn't load {} kg of cargo because only {} kg are available",
+                requested, available
+            ),
+            CargoError::NotEnoughCargo {
+                commodity,
+                requested,
+                available,
+            } => write!(
+                f,
+                "Couldn't unload {} units of \"{}\" because only {} are carried",
+                requested, commodity, available
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CargoError {}
+
+impl CargoHold {
+    pub fn with_capacity(capacity: f64) -> Self {
+        Self {
+            capacity,
+            contents: HashMap::new(),
+        }
+    }
+
+    /// Number of units of a commodity in the hold
+    pub fn quantity(&self, commodity: &CommodityID) -> u32 {
+        self.contents.get(commodity).copied().unwrap_or_default()
+    }
+
+    /// Total mass of the carried commodities (in kg)
+    pub fn mass(&self, commodities: &Commodities) -> f64 {
+        self.contents
+            .iter()
+            .filter_map(|(id, quantity)| {
+                commodities
+                    .get(id)
+                    .map(|data| data.unit_mass * *quantity as f64)
+            })
+            .sum()
+    }
+
+    /// Mass that can still be loaded (in kg)
+    pub fn free_capacity(&self, commodities: &Commodities) -> f64 {
+        (self.capacity - self.mass(commodities)).max(0.)
+    }
+
+    /// Loads units of a commodity, leaving the hold untouched if they do not fit or if there are none
+    pub fn load(
+        &mut self,
+        commodities: &Commodities,
+        commodity: CommodityID,
+        quantity: u32,
+    ) -> Result<(), CargoError> {
+        let data = commodities
+            .get(&commodity)
+            .ok_or(CargoError::UnknownCommodity(commodity))?;
+        if quantity == 0 {
+            return Ok(());
+        }
+        let requested = data.unit_mass * quantity as f64;
+        let available = self.free_capacity(commodities);
+        if requested > available {
+            return Err(CargoError::OverCapacity {
+                requested,
+                available,
+            });
+        }
+        *self.contents.entry(commodity).or_default() += quantity;
+        Ok(())
+    }
+
+    /// Unloads units of a commodity, leaving the hold untouched if not enough of them are carried
+    pub fn unload(
+        &mut self,
+        commodities: &Commodities,
+        commodity: CommodityID,
+        quantity: u32,
+    ) -> Result<(), CargoError> {
+        if commodities.get(&commodity).is_none() {
+            return Err(CargoError::UnknownCommodity(commodity));
+        }
+        let available = self.quantity(&commodity);
+        if quantity > available {
+            return Err(CargoError::NotEnoughCargo {
+                commodity,
+                requested: quantity,
+                available,
+            });
+        }
+        if quantity == available {
+            self.contents.remove(&commodity);
+        } else {
+            self.contents.insert(commodity, available - quantity);
+        }
+        Ok(())
+    }
+}
+
+pub fn update_cargo_mass(
+    mut ships: Query<(&CargoHold, &mut ShipMass), Changed<CargoHold>>,
+    commodities: Res<Commodities>,
+) {
+    for (hold, mut mass) in ships.iter_mut() {
+        mass.cargo = hold.mass(&commodities);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::objects::{
+        commodities::{Commodities, CommodityData},
+        id::id_from,
+    };
+
+    use super::{CargoError, CargoHold};
+
+    fn commodities() -> Commodities {
+        [("water", 1000.), ("medicine", 50.)]
+            .into_iter()
+            .map(|(id, unit_mass)| CommodityData {
+                id: id_from(id),
+                name: id.into(),
+                unit_mass,
+                base_price: 1.,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_load_unload() {
+        let commodities = commodities();
+        let water = id_from("water");
+        let medicine = id_from("medicine");
+        let mut hold = CargoHold::with_capacity(5000.);
+
+        hold.load(&commodities, water, 4).unwrap();
+        hold.load(&commodities, medicine, 20).unwrap();
+        assert_eq!(hold.mass(&commodities), 5000.);
+        assert_eq!(
+            hold.load(&commodities, medicine, 1),
+            Err(CargoError::OverCapacity {
+                requested: 50.,
+                available: 0.
+            })
+        );
+        assert_eq!(
+            hold.load(&commodities, id_from("gold"), 1),
+            Err(CargoError::UnknownCommodity(id_from("gold")))
+        );
+
+        assert_eq!(
+            hold.unload(&commodities, water, 5),
+            Err(CargoError::NotEnoughCargo {
+                commodity: water,
+                requested: 5,
+                available: 4
+            })
+        );
+        hold.unload(&commodities, water, 4).unwrap();
+        assert_eq!(hold.quantity(&water), 0);
+        assert!(!hold.contents.contains_key(&water));
+        assert_eq!(hold.free_capacity(&commodities), 4000.);
+
+        hold.load(&commodities, water, 0).unwrap();
+        assert!(!hold.contents.contains_key(&water));
+    }
+}
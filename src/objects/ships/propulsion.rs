@@ -0,0 +1,146 @@
+//! Propellant consumption of ships, following the Tsiolkovsky rocket equation.
+use bevy::prelude::*;
+
+use crate::physics::SECONDS_PER_DAY;
+
+use super::ShipID;
+
+/// Standard gravity, used to convert a specific impulse into an exhaust velocity (in km/s²)
+pub const STANDARD_GRAVITY: f64 = 9.80665e-3;
+
+pub fn plugin(app: &mut App) {
+    app.add_event::<PropellantEvent>();
+}
+
+/// The mass of a ship (in kg), split between the structure, the remaining propellant and the carried cargo
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct ShipMass {
+    pub dry: f64,
+    pub propellant: f64,
+    /// Mass of propellant that the tanks can hold
+    pub tank_capacity: f64,
+    /// Mass of the cargo hold contents, kept up to date from the [CargoHold](super::cargo::CargoHold) of the ship
+    pub cargo: f64,
+}
+
+impl Default for ShipMass {
+    fn default() -> Self {
+        Self {
+            dry: 1e4,
+            propellant: 3e4,
+            tank_capacity: 5e4,
+            cargo: 0.,
+        }
+    }
+}
+
+/// Specific impulse of the engines of a ship (in seconds)
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct Isp(pub f64);
+
+impl Default for Isp {
+    fn default() -> Self {
+        Self(450.)
+    }
+}
+
+impl Isp {
+    /// The velocity of the exhaust gases (in km/d)
+    pub fn exhaust_velocity(&self) -> f64 {
+        self.0 * STANDARD_GRAVITY * SECONDS_PER_DAY
+    }
+}
+
+impl ShipMass {
+    pub fn total(&self) -> f64 {
+        self.empty() + self.propellant
+    }
+
+    /// The mass of the ship once all its propellant is burnt
+    pub fn empty(&self) -> f64 {
+        self.dry + self.cargo
+    }
+
+    /// The velocity change that burning all the remaining propellant produces (in km/d)
+    pub fn delta_v(&self, isp: &Isp) -> f64 {
+        if self.empty() <= 0. {
+            return f64::INFINITY;
+        }
+        isp.exhaust_velocity() * (self.total() / self.empty()).ln()
+    }
+
+    /// The propellant needed to produce the given velocity change (in kg)
+    pub fn propellant_for(&self, delta_v: f64, isp: &Isp) -> f64 {
+        self.total() * (1. - (-delta_v / isp.exhaust_velocity()).exp())
+    }
+
+    /// Consumes the propellant needed for a velocity change, truncating it to what the remaining propellant allows.
+    /// Returns the velocity change that is actually produced.
+    pub fn burn(&mut self, delta_v: f64, isp: &Isp) -> f64 {
+        let delta_v = delta_v.min(self.delta_v(isp));
+        self.propellant = (self.propellant - self.propellant_for(delta_v, isp)).max(0.);
+        delta_v
+    }
+}
+
+/// The delta-v budget of a ship, which is unlimited for ships without propulsion components
+pub fn delta_v_budget(propulsion: Option<(&ShipMass, &Isp)>) -> f64 {
+    propulsion.map_or(f64::INFINITY, |(mass, isp)| mass.delta_v(isp))
+}
+
+#[derive(Event, Clone, Debug, PartialEq)]
+pub enum PropellantEvent {
+    /// A burn was shortened because the ship ran out of propellant
+    BurnTruncated {
+        ship: ShipID,
+        requested: f64,
+        applied: f64,
+    },
+    /// A maneuver node was not added to the trajectory because the ship cannot afford the total velocity change
+    NodeRejected {
+        ship: ShipID,
+        tick: u64,
+        required: f64,
+        budget: f64,
+    },
+    /// A maneuver node was rejected because its burn would start before the previous one has ended
+    BurnOverlap { ship: ShipID, tick: u64 },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rocket_equation() {
+        let isp = Isp(300.);
+        let mut mass = ShipMass {
+            dry: 1000.,
+            propellant: 1000.,
+            tank_capacity: 1000.,
+            cargo: 0.,
+        };
+        let budget = mass.delta_v(&isp);
+        assert!((budget - isp.exhaust_velocity() * 2f64.ln()).abs() < 1e-6);
+
+        // Velocity changes add up
+        assert_eq!(mass.burn(budget / 4., &isp), budget / 4.);
+        assert!((mass.delta_v(&isp) - 3. * budget / 4.).abs() < 1e-6 * budget);
+        let remaining = mass.delta_v(&isp);
+        assert_eq!(mass.burn(budget, &isp), remaining);
+        assert!(mass.propellant < 1e-6);
+        assert_eq!(mass.burn(budget, &isp), 0.);
+    }
+
+    #[test]
+    fn test_cargo_reduces_delta_v() {
+        let isp = Isp(300.);
+        let empty = ShipMass::default();
+        let loaded = ShipMass {
+            cargo: 1e4,
+            ..empty
+        };
+        assert_eq!(loaded.total(), empty.total() + 1e4);
+        assert!(loaded.delta_v(&isp) < empty.delta_v(&isp));
+    }
+}
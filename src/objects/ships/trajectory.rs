@@ -16,12 +16,19 @@ use std::sync::Mutex;
 use crate::{
     game::{Authoritative, GameFiles},
     objects::prelude::{BodiesMapping, BodyID},
-    physics::{prelude::*, time::TickEvent},
+    physics::{
+        leapfrog::ActiveBurn,
+        prelude::*,
+        time::{SimStepSize, TickEvent, GAMETIME_PER_SIMTICK, SIMTICKS_PER_TICK},
+    },
     prelude::{exit_on_error_if_app, GameStage},
     utils::algebra::orbital_to_global_matrix,
 };
 
-use super::{ShipID, ShipInfo, ShipsMapping};
+use super::{
+    propulsion::{delta_v_budget, Isp, PropellantEvent, ShipMass},
+    ShipID, ShipInfo, ShipsMapping,
+};
 
 pub const TRAJECTORIES_PATH: &str = "trajectories";
 
@@ -55,8 +62,28 @@ pub struct TrajectoryUpdate;
 #[derive(Component, Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ManeuverNode {
     pub name: String,
+    /// The total velocity change, in the forward/right/down frame relative to the origin body (in km/d)
     pub thrust: DVec3,
     pub origin: BodyID,
+    /// Duration of the burn (in days). The velocity change is instantaneous if neither this nor the thrust magnitude
+    /// are set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    /// Acceleration provided by the engines during the burn (in km/d²), which sets the duration when it is not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thrust_magnitude: Option<f64>,
+}
+
+impl ManeuverNode {
+    /// Duration of the burn spreading the velocity change, `None` if the velocity change is instantaneous
+    pub fn burn_duration(&self) -> Option<f64> {
+        self.duration
+            .or_else(|| {
+                self.thrust_magnitude
+                    .map(|magnitude| self.thrust.length() / magnitude)
+            })
+            .filter(|duration| *duration > 0. && duration.is_finite())
+    }
 }
 
 /// A succession of maneuver nodes sorted by order of time, with a single node per server tick
@@ -66,6 +93,29 @@ pub struct Trajectory {
     pub nodes: BTreeMap<u64, ManeuverNode>,
 }
 
+impl Trajectory {
+    /// The total velocity change of the nodes starting from the given tick
+    pub fn delta_v_from(&self, tick: u64) -> f64 {
+        self.nodes
+            .range(tick..)
+            .map(|(_, node)| node.thrust.length())
+            .sum()
+    }
+
+    /// The tick of the first node starting before the finite burn of the previous one has ended
+    pub fn overlapping_node(&self) -> Option<u64> {
+        let tick_duration = SIMTICKS_PER_TICK as f64 * GAMETIME_PER_SIMTICK;
+        self.nodes
+            .iter()
+            .zip(self.nodes.iter().skip(1))
+            .find(|((tick, node), (next, _))| {
+                node.burn_duration()
+                    .is_some_and(|duration| duration > (*next - *tick) as f64 * tick_duration)
+            })
+            .map(|(_, (next, _))| *next)
+    }
+}
+
 /// A trajectory taken by an object, storing a peekable queue of all remaining maneuver nodes
 #[derive(Component, Debug)]
 pub struct CurrentTrajectory {
@@ -78,9 +128,14 @@ impl CurrentTrajectory {
             queue: trajectory.nodes.into_iter().peekable(),
         }
     }
+
+    /// Whether all the maneuver nodes have been executed
+    pub fn is_finished(&self) -> bool {
+        self.queue.len() == 0
+    }
 }
 
-#[derive(Event, Debug, Clone)]
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
 pub enum TrajectoryEvent {
     Create {
         ship: ShipID,
@@ -98,10 +153,24 @@ pub enum TrajectoryEvent {
     },
 }
 
+impl TrajectoryEvent {
+    pub fn ship(&self) -> ShipID {
+        match self {
+            TrajectoryEvent::Create { ship, .. }
+            | TrajectoryEvent::Delete(ship)
+            | TrajectoryEvent::AddNode { ship, .. }
+            | TrajectoryEvent::RemoveNode { ship, .. } => *ship,
+        }
+    }
+}
+
 #[derive(Event, Debug)]
 pub struct VelocityUpdate {
     pub ship_id: ShipID,
+    /// The velocity change in global coordinates
     pub thrust: DVec3,
+    /// Duration of the burn, the velocity change is instantaneous if `None`
+    pub duration: Option<f64>,
 }
 
 #[derive(Debug)]
@@ -182,6 +251,7 @@ fn follow_trajectory(
                     events.lock().unwrap().push(VelocityUpdate {
                         ship_id: info.id,
                         thrust,
+                        duration: n.burn_duration(),
                     });
                 }
                 t.queue.next();
@@ -191,14 +261,65 @@ fn follow_trajectory(
     velocity_events.send_batch(Arc::try_unwrap(events).unwrap().into_inner().unwrap());
 }
 
+/// Applies instantaneous velocity changes and starts finite burns, truncating them when the propellant runs out
+#[allow(clippy::too_many_arguments)]
 pub fn handle_thrusts(
+    mut commands: Commands,
     mut velocity_events: EventReader<VelocityUpdate>,
-    mut speeds: Query<&mut Velocity>,
+    mut propellant_events: EventWriter<PropellantEvent>,
+    mut ships: Query<(&mut Velocity, Option<(&mut ShipMass, &Isp)>)>,
+    burns: Query<(Entity, &ActiveBurn)>,
     mapping: Res<ShipsMapping>,
+    time: Res<GameTime>,
+    step: Res<SimStepSize>,
 ) {
+    let start = time.simtick.saturating_sub(step.0) as f64 * GAMETIME_PER_SIMTICK;
+    burns
+        .iter()
+        .filter(|(_, burn)| burn.end <= start)
+        .for_each(|(e, _)| {
+            commands.entity(e).remove::<ActiveBurn>();
+        });
     for event in velocity_events.read() {
         if let Some(entity) = mapping.0.get(&event.ship_id) {
-            speeds.get_mut(*entity).unwrap().0 += event.thrust;
+            let Ok((mut speed, propulsion)) = ships.get_mut(*entity) else {
+                continue;
+            };
+            // A finite burn never replaces one still running, which would drop its remaining velocity change
+            if event.duration.is_some()
+                && burns.get(*entity).is_ok_and(|(_, burn)| burn.end > start)
+            {
+                propellant_events.send(PropellantEvent::BurnOverlap {
+                    ship: event.ship_id,
+                    tick: time.tick(),
+                });
+                continue;
+            }
+            let requested = event.thrust.length();
+            let applied = propulsion.map_or(requested, |(mut mass, isp)| mass.burn(requested, isp));
+            if applied < requested {
+                propellant_events.send(PropellantEvent::BurnTruncated {
+                    ship: event.ship_id,
+                    requested,
+                    applied,
+                });
+            }
+            let ratio = if requested > 0. {
+                applied / requested
+            } else {
+                0.
+            };
+            match event.duration {
+                // A truncated burn keeps the same acceleration but ends earlier
+                Some(duration) => {
+                    commands.entity(*entity).insert(ActiveBurn {
+                        acceleration: event.thrust / duration,
+                        start,
+                        end: start + duration * ratio,
+                    });
+                }
+                None => speed.0 += event.thrust * ratio,
+            }
         }
     }
 }
@@ -237,29 +358,49 @@ pub fn remove_old_nodes(dir: Res<GameFiles>, time: Res<GameTime>) {
     }
 }
 
+/// Writes the trajectory changes to the game files, rejecting nodes the ship cannot afford or that overlap a burn
 pub fn handle_trajectory_event(
     mut reader: EventReader<TrajectoryEvent>,
+    mut propellant_events: EventWriter<PropellantEvent>,
     dir: Res<GameFiles>,
+    ships_mapping: Option<Res<ShipsMapping>>,
+    ships: Query<(&ShipMass, &Isp)>,
+    time: Res<GameTime>,
 ) -> color_eyre::Result<()> {
     use TrajectoryEvent::*;
     for event in reader.read() {
-        let path = build_path(
-            &dir.trajectories,
-            *match event {
-                Create { ship, .. } => ship,
-                Delete(s) => s,
-                AddNode { ship, .. } => ship,
-                RemoveNode { ship, .. } => ship,
-            },
-        );
+        let path = build_path(&dir.trajectories, event.ship());
         match event {
             Create { trajectory, .. } => {
                 write_trajectory(path, trajectory)?;
             }
             Delete(_) => remove_file(path).unwrap_or_default(),
-            AddNode { node, tick, .. } => {
+            AddNode { ship, node, tick } => {
                 let mut t = read_trajectory(&path).unwrap_or_default();
                 t.nodes.insert(*tick, node.clone());
+                let required = t.delta_v_from(time.tick());
+                let budget = delta_v_budget(
+                    ships_mapping
+                        .as_ref()
+                        .and_then(|mapping| mapping.0.get(ship))
+                        .and_then(|e| ships.get(*e).ok()),
+                );
+                if required > budget {
+                    propellant_events.send(PropellantEvent::NodeRejected {
+                        ship: *ship,
+                        tick: *tick,
+                        required,
+                        budget,
+                    });
+                    continue;
+                }
+                if let Some(overlap) = t.overlapping_node() {
+                    propellant_events.send(PropellantEvent::BurnOverlap {
+                        ship: *ship,
+                        tick: overlap,
+                    });
+                    continue;
+                }
                 write_trajectory(path, &t)?;
             }
             RemoveNode { tick, .. } => {
@@ -285,7 +426,12 @@ mod tests {
         state::state::NextState,
     };
 
-    use crate::{objects::ships::ShipEvent, physics::time::SIMTICKS_PER_TICK, prelude::*};
+    use crate::{
+        client::testing::{paused_game, run_for, spawn_ship, start_action},
+        objects::ships::ShipEvent,
+        physics::time::SIMTICKS_PER_TICK,
+        prelude::*,
+    };
 
     use super::*;
 
@@ -304,6 +450,8 @@ mod tests {
                     name: "1".to_owned(),
                     thrust: DVec3::new(1e4, 0., 0.),
                     origin: id_from("soleil"),
+                    duration: None,
+                    thrust_magnitude: None,
                 },
             )]),
         }
@@ -392,4 +540,195 @@ mod tests {
             .single(app.world());
         assert!((ship_speed.0 - DVec3::new(0., 2e4, 0.)).length() < 10.);
     }
+
+    #[test]
+    fn test_finite_burn() {
+        let mut app = paused_game(ClientPlugin::testing());
+        let id = id_from("s");
+        spawn_ship(
+            &mut app,
+            "s",
+            DVec3::new(0., 0., 1e10),
+            DVec3::new(0., 1e4, 0.),
+        );
+        let mut trajectory = new_trajectory();
+        // The velocity change is spread over 50 simticks
+        trajectory.nodes.get_mut(&1).unwrap().duration = Some(50. * GAMETIME_PER_SIMTICK);
+        app.world_mut().send_event(TrajectoryEvent::Create {
+            ship: id,
+            trajectory,
+        });
+        app.update();
+        start_action(&mut app);
+        let mut speeds = Vec::new();
+        for _ in 0..80 {
+            run_for(&mut app, 1);
+            let world = app.world_mut();
+            let speed = world
+                .query_filtered::<&Velocity, With<ShipInfo>>()
+                .single(world)
+                .0;
+            speeds.push((world.resource::<GameTime>().simtick, speed.y));
+        }
+        let speed_at = |simtick| speeds.iter().find(|(s, _)| *s == simtick).unwrap().1;
+        // Like instantaneous thrusts, the burn starts at the beginning of the step leading to the node's tick
+        assert!((speed_at(SIMTICKS_PER_TICK - 1) - 1e4).abs() < 1.);
+        assert!((speed_at(SIMTICKS_PER_TICK + 24) - 1.5e4).abs() < 2e2);
+        assert!((speed_at(SIMTICKS_PER_TICK + 60) - 2e4).abs() < 1.);
+        let world = app.world_mut();
+        assert!(world.query::<&ActiveBurn>().iter(world).next().is_none());
+    }
+
+    #[test]
+    fn test_burn_truncated() {
+        let mut app = paused_game(ClientPlugin::testing());
+        let id = id_from("s");
+        spawn_ship(
+            &mut app,
+            "s",
+            DVec3::new(0., 0., 1e10),
+            DVec3::new(0., 1e4, 0.),
+        );
+        app.world_mut().send_event(TrajectoryEvent::Create {
+            ship: id,
+            trajectory: new_trajectory(),
+        });
+        app.update();
+        // Only half of the node's velocity change can be afforded
+        let world = app.world_mut();
+        let isp = Isp::default();
+        let (mut mass, ..) = world
+            .query::<(&mut ShipMass, &ShipInfo)>()
+            .single_mut(world);
+        mass.propellant = mass.dry * ((5e3 / isp.exhaust_velocity()).exp() - 1.);
+        start_action(&mut app);
+        run_for(&mut app, 2 * SIMTICKS_PER_TICK as usize);
+        let world = app.world_mut();
+        let (speed, mass) = world
+            .query_filtered::<(&Velocity, &ShipMass), With<ShipInfo>>()
+            .single(world);
+        assert!((speed.0.y - 1.5e4).abs() < 10.);
+        assert!(mass.propellant < 1e-6);
+    }
+
+    #[test]
+    fn test_node_rejected() {
+        let mut app = new_app();
+        let id = id_from("s");
+        app.world_mut().send_event(ShipEvent::Create(ShipInfo {
+            id,
+            spawn_pos: DVec3::new(1e6, 0., 0.),
+            spawn_speed: DVec3::new(0., 1e6, 0.),
+        }));
+        app.update();
+        let world = app.world_mut();
+        let budget = world
+            .query::<(&ShipMass, &Isp)>()
+            .iter(world)
+            .map(|(mass, isp)| mass.delta_v(isp))
+            .next()
+            .unwrap();
+        let node = |thrust| ManeuverNode {
+            name: "1".to_owned(),
+            thrust: DVec3::new(thrust, 0., 0.),
+            origin: id_from("soleil"),
+            duration: None,
+            thrust_magnitude: None,
+        };
+        world.send_event(TrajectoryEvent::AddNode {
+            ship: id,
+            node: node(budget / 2.),
+            tick: 1,
+        });
+        app.update();
+        // The second node would exceed the budget
+        app.world_mut().send_event(TrajectoryEvent::AddNode {
+            ship: id,
+            node: node(budget / 2. + 1.),
+            tick: 2,
+        });
+        app.update();
+        let path = &app.world().resource::<GameFiles>().trajectories;
+        let trajectory = read_ship_trajectory(path, id).unwrap();
+        assert_eq!(trajectory.nodes.keys().collect::<Vec<_>>(), vec![&1]);
+        let events = app.world().resource::<Events<PropellantEvent>>();
+        assert!(matches!(
+            events.iter_current_update_events().next(),
+            Some(PropellantEvent::NodeRejected { tick: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_overlapping_burn_rejected() {
+        let mut app = new_app();
+        let id = id_from("s");
+        app.world_mut().send_event(ShipEvent::Create(ShipInfo {
+            id,
+            spawn_pos: DVec3::new(1e6, 0., 0.),
+            spawn_speed: DVec3::new(0., 1e6, 0.),
+        }));
+        app.update();
+        // The first burn lasts 5 ticks
+        let node = |duration| ManeuverNode {
+            name: "1".to_owned(),
+            thrust: DVec3::new(1., 0., 0.),
+            origin: id_from("soleil"),
+            duration,
+            thrust_magnitude: None,
+        };
+        let mut add_node = |node, tick| {
+            app.world_mut().send_event(TrajectoryEvent::AddNode {
+                ship: id,
+                node,
+                tick,
+            });
+            app.update();
+            app.world_mut()
+                .resource_mut::<Events<PropellantEvent>>()
+                .drain()
+                .next()
+        };
+        assert!(add_node(node(Some(50. * GAMETIME_PER_SIMTICK)), 1).is_none());
+        assert!(matches!(
+            add_node(node(None), 3),
+            Some(PropellantEvent::BurnOverlap { tick: 3, .. })
+        ));
+        assert!(add_node(node(None), 6).is_none());
+        let path = &app.world().resource::<GameFiles>().trajectories;
+        let trajectory = read_ship_trajectory(path, id).unwrap();
+        assert_eq!(trajectory.nodes.keys().collect::<Vec<_>>(), vec![&1, &6]);
+    }
+
+    #[test]
+    fn test_running_burn_kept() {
+        let mut app = paused_game(ClientPlugin::testing());
+        let id = id_from("s");
+        spawn_ship(
+            &mut app,
+            "s",
+            DVec3::new(0., 0., 1e10),
+            DVec3::new(0., 1e4, 0.),
+        );
+        // Written directly, so the overlap is only caught when the nodes are executed
+        let mut trajectory = new_trajectory();
+        let mut first = trajectory.nodes.remove(&1).unwrap();
+        first.duration = Some(50. * GAMETIME_PER_SIMTICK);
+        let second = first.clone();
+        trajectory.nodes.insert(1, first);
+        trajectory.nodes.insert(3, second);
+        app.world_mut().send_event(TrajectoryEvent::Create {
+            ship: id,
+            trajectory,
+        });
+        app.update();
+        start_action(&mut app);
+        run_for(&mut app, 8 * SIMTICKS_PER_TICK as usize);
+        let world = app.world_mut();
+        let speed = world
+            .query_filtered::<&Velocity, With<ShipInfo>>()
+            .single(world)
+            .0;
+        // Only the first burn was applied, in full
+        assert!((speed.y - 2e4).abs() < 1.);
+    }
 }
@@ -6,13 +6,23 @@ use time::{TimeUpdate, ToggleTime};
 
 use crate::objects::ships::trajectory::TrajectoryUpdate;
 
+pub mod atmosphere;
+pub mod collision;
+pub mod diagnostics;
+pub mod frames;
 pub mod influence;
+pub mod integrator;
 pub mod leapfrog;
+pub mod nbody;
+pub mod on_rails;
 pub mod orbit;
+pub mod perturbations;
 pub mod predictions;
+pub mod rotation;
 pub mod time;
+pub mod transfer;
 
-const SECONDS_PER_DAY: f64 = 24. * 3600.;
+pub const SECONDS_PER_DAY: f64 = 24. * 3600.;
 
 /// Gravitationnal constant in km3kg-1d-2
 pub const G: f64 = 6.6743e-11 * SECONDS_PER_DAY * SECONDS_PER_DAY * 1e-9;
@@ -21,8 +31,9 @@ pub(crate) mod prelude {
     pub use super::{
         influence::Influenced,
         leapfrog::Acceleration,
-        orbit::{EllipticalOrbit, SystemSize},
+        orbit::{EllipticalOrbit, OsculatingOrbit, SystemSize},
         predictions::Prediction,
+        rotation::{BodyRotation, SurfaceLocation},
         time::{GameTime, ToggleTime},
         Mass, Position, Velocity,
     };
@@ -42,9 +53,16 @@ pub struct PhysicsPlugin;
 impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins((
+            atmosphere::plugin,
+            collision::plugin,
+            diagnostics::plugin,
             orbit::plugin,
+            rotation::plugin,
             influence::plugin,
             leapfrog::plugin,
+            nbody::plugin,
+            on_rails::plugin,
+            perturbations::plugin,
             time::plugin,
         ))
         .configure_sets(
@@ -0,0 +1,211 @@
+//! Warnings about orbits crossing the atmosphere of a body.
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::objects::prelude::*;
+
+use super::{orbit::update_osculating_orbits, prelude::*, time::TickEvent, PhysicsUpdate};
+
+pub fn plugin(app: &mut App) {
+    app.add_event::<PeriapsisInAtmosphere>().add_systems(
+        FixedUpdate,
+        detect_atmospheric_passes
+            .after(update_osculating_orbits)
+            .in_set(PhysicsUpdate)
+            .run_if(on_event::<TickEvent>()),
+    );
+}
+
+/// Sent when the periapsis of a ship goes below the top of the atmosphere of its main influencer
+#[derive(Event, Clone, Copy, Debug, PartialEq)]
+pub struct PeriapsisInAtmosphere {
+    pub ship: Entity,
+    pub body: Entity,
+    /// Altitude of the periapsis above the surface (in km)
+    pub altitude: f64,
+}
+
+/// The atmosphere of a body that an orbit crosses, with the altitude of its periapsis
+pub fn periapsis_in_atmosphere(orbit: &OsculatingOrbit, body: &BodyData) -> Option<f64> {
+    let altitude = orbit.orbit.periapsis - body.radius;
+    body.atmosphere
+        .filter(|atmosphere| altitude < atmosphere.height())
+        .map(|_| altitude)
+}
+
+fn detect_atmospheric_passes(
+    ships: Query<(Entity, &OsculatingOrbit), With<ShipInfo>>,
+    bodies: Query<&BodyInfo>,
+    mut passes: Local<HashMap<Entity, Entity>>,
+    mut events: EventWriter<PeriapsisInAtmosphere>,
+) {
+    let mut new_passes = HashMap::new();
+    for (ship, orbit) in ships.iter() {
+        let Some(altitude) = bodies
+            .get(orbit.host)
+            .ok()
+            .and_then(|BodyInfo(data)| periapsis_in_atmosphere(orbit, data))
+        else {
+            continue;
+        };
+        // Only warn once per dip
+        if passes.get(&ship) != Some(&orbit.host) {
+            events.send(PeriapsisInAtmosphere {
+                ship,
+                body: orbit.host,
+                altitude,
+            });
+        }
+        new_passes.insert(ship, orbit.host);
+    }
+    *passes = new_passes;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use bevy::{ecs::system::SystemState, math::DVec3};
+
+    use crate::{
+        client::testing::{paused_game, run_until, spawn_ship, start_action},
+        objects::bodies::body_data::Atmosphere,
+        physics::{
+            diagnostics::orbital_invariants,
+            frames::ReferenceFrame,
+            influence::HillRadius,
+            integrator::Integrator,
+            perturbations::{drag_velocity, DragProfile, Perturbations, SurfaceCoefficients},
+            predictions::PredictionStart,
+            time::{SimStepSize, GAMETIME_PER_SIMTICK},
+            G,
+        },
+        prelude::*,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_atmosphere_model() {
+        let atmosphere = Atmosphere {
+            scale_height: 8.5,
+            surface_density: 1.217,
+        };
+        assert!((150. ..250.).contains(&atmosphere.height()));
+        assert_eq!(atmosphere.density_at(-1.), 1.217);
+        assert!((atmosphere.density_at(8.5) - 1.217 / std::f64::consts::E).abs() < 1e-12);
+        assert_eq!(atmosphere.density_at(atmosphere.height() + 1.), 0.);
+
+        let speed = DVec3::new(3., 4., 0.);
+        let slowed = drag_velocity(speed, 1e-3, 1e-2, 1e-3);
+        assert!(slowed.length() < speed.length());
+        assert!(slowed.normalize().dot(speed.normalize()) > 1. - 1e-12);
+        // Stays stable whatever the step
+        assert!(drag_velocity(speed, 1., 1., 1e6).length() < 1e-3);
+    }
+
+    /// Returns the orbital energy around the Earth of a ship on an orbit grazing the atmosphere after one revolution,
+    /// with the events sent and the distance between the live position and the predicted one
+    fn aerobraking(drag: bool) -> (f64, f64, Vec<PeriapsisInAtmosphere>, f64) {
+        let mut app = paused_game(ClientPlugin::testing());
+        let perturbations = Perturbations {
+            drag,
+            ..Default::default()
+        };
+        app.insert_resource(perturbations.clone());
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0[&id_from("terre")];
+        let (&earth_pos, &earth_speed, BodyInfo(data)) = world
+            .query::<(&Position, &Velocity, &BodyInfo)>()
+            .get(world, earth)
+            .unwrap();
+        let (radius, earth_mass) = (data.radius, data.mass);
+        let (periapsis, apoapsis) = (radius + 120., radius + 1000.);
+        let mu = G * earth_mass;
+        let apoapsis_speed = (mu * 2. * periapsis / (apoapsis * (apoapsis + periapsis))).sqrt();
+        let (spawn_pos, spawn_speed) = (
+            earth_pos.0 + DVec3::X * apoapsis,
+            earth_speed.0 + DVec3::Y * apoapsis_speed,
+        );
+        let period =
+            2. * std::f64::consts::PI * (((periapsis + apoapsis) / 2.).powi(3) / mu).sqrt();
+        let (initial_energy, _) =
+            orbital_invariants(DVec3::X * apoapsis, DVec3::Y * apoapsis_speed, earth_mass);
+        spawn_ship(&mut app, "s", spawn_pos, spawn_speed);
+
+        let world = app.world_mut();
+        let simticks = (period / GAMETIME_PER_SIMTICK).ceil() as usize;
+        let (influence, acc, mass, drag_profile) = world
+            .query::<(&Influenced, &Acceleration, &ShipMass, &DragProfile)>()
+            .single(world);
+        let start = PredictionStart {
+            pos: spawn_pos,
+            speed: spawn_speed,
+            acc: acc.current,
+            simtick: 0,
+            delta_v: f64::INFINITY,
+            surface: SurfaceCoefficients {
+                ballistic: drag_profile.ballistic_coefficient(mass),
+                ..Default::default()
+            },
+        };
+        let influence = influence.clone();
+        #[allow(clippy::type_complexity)]
+        let mut system_state: SystemState<(
+            Res<BodiesMapping>,
+            Query<(&EllipticalOrbit, &BodyInfo, &HillRadius)>,
+        )> = SystemState::new(world);
+        let (mapping, mut bodies) = system_state.get(world);
+        let predictions = start.compute_predictions(
+            simticks,
+            &influence,
+            ReferenceFrame::Inertial,
+            &mut bodies.as_query_lens(),
+            &mapping.0,
+            &BTreeMap::new(),
+            &Integrator::default(),
+            &perturbations,
+            None,
+        );
+
+        app.world_mut().resource_mut::<SimStepSize>().0 = 1;
+        start_action(&mut app);
+        let mut reader = app
+            .world()
+            .resource::<Events<PeriapsisInAtmosphere>>()
+            .get_reader();
+        let mut events = Vec::new();
+        run_until(&mut app, |world| {
+            events.extend(reader.read(world.resource::<Events<PeriapsisInAtmosphere>>()));
+            world.resource::<GameTime>().simtick >= simticks as u64
+        });
+        let world = app.world_mut();
+        let (&pos, &speed) = world
+            .query_filtered::<(&Position, &Velocity), With<ShipInfo>>()
+            .single(world);
+        let (&earth_pos, &earth_speed) = world
+            .query::<(&Position, &Velocity)>()
+            .get(world, earth)
+            .unwrap();
+        let (energy, _) =
+            orbital_invariants(pos.0 - earth_pos.0, speed.0 - earth_speed.0, earth_mass);
+        let prediction_error = (predictions.coords[simticks - 1].0 - pos.0).length();
+        (initial_energy, energy, events, prediction_error)
+    }
+
+    #[test]
+    fn test_aerobraking() {
+        let (initial_energy, energy, events, prediction_error) = aerobraking(false);
+        assert!(((energy - initial_energy) / initial_energy).abs() < 1e-4);
+        // The warning does not depend on the drag being simulated
+        assert_eq!(events.len(), 1);
+        assert!((events[0].altitude - 120.).abs() < 10.);
+        assert!(prediction_error < 1., "{prediction_error} km");
+
+        let (initial_energy, drag_energy, events, prediction_error) = aerobraking(true);
+        assert_eq!(events.len(), 1);
+        // The orbit shrinks
+        assert!(drag_energy < energy);
+        assert!((drag_energy - initial_energy) / initial_energy.abs() < -1e-4);
+        assert!(prediction_error < 1., "{prediction_error} km");
+    }
+}
@@ -0,0 +1,215 @@
+//! Detection of ships crashing into celestial bodies.
+use bevy::{math::DVec3, prelude::*};
+
+use crate::objects::prelude::*;
+
+use super::{
+    leapfrog::LeapfrogUpdate,
+    orbit::OrbitsUpdate,
+    rotation::{BodyRotation, SurfaceLocation},
+    time::GameTime,
+    PhysicsUpdate, Position, Velocity,
+};
+
+pub fn plugin(app: &mut App) {
+    app.add_event::<ShipImpact>()
+        .init_resource::<ImpactPolicy>()
+        .add_systems(
+            FixedUpdate,
+            (
+                update_landed
+                    .after(OrbitsUpdate)
+                    .before(LeapfrogUpdate)
+                    .in_set(PhysicsUpdate),
+                handle_impacts.after(LeapfrogUpdate).in_set(PhysicsUpdate),
+            ),
+        );
+}
+
+/// Sent when a ship hits the surface of a body
+#[derive(Event, Clone, Copy, Debug, PartialEq)]
+pub struct ShipImpact {
+    pub ship: Entity,
+    pub body: Entity,
+    pub simtick: u64,
+}
+
+/// What happens to a ship that hits the surface of a body
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImpactPolicy {
+    /// The ship is destroyed
+    #[default]
+    Remove,
+    /// The ship stays on the surface of the body, at the impact point
+    Land,
+}
+
+/// A ship resting on the surface of a body, which is not affected by gravity anymore and rotates with the body
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Landed {
+    pub body: Entity,
+    /// The position of the ship relative to the center of the body, in the body-fixed frame
+    pub offset: DVec3,
+}
+
+impl Landed {
+    pub fn location(&self) -> SurfaceLocation {
+        SurfaceLocation::from_body_fixed(self.offset)
+    }
+}
+
+/// Returns the fraction of the segment going from `start` to `end` (both relative to the center of a body) at which
+/// it first enters the sphere of the given radius, if it does
+pub fn segment_impact(start: DVec3, end: DVec3, radius: f64) -> Option<f64> {
+    if start.length_squared() <= radius * radius {
+        return Some(0.);
+    }
+    let direction = end - start;
+    let a = direction.length_squared();
+    let b = 2. * start.dot(direction);
+    let c = start.length_squared() - radius * radius;
+    let discriminant = b * b - 4. * a * c;
+    if a == 0. || discriminant < 0. {
+        return None;
+    }
+    let t = (-b - discriminant.sqrt()) / (2. * a);
+    (0. ..=1.).contains(&t).then_some(t)
+}
+
+/// Finds the first body hit by an object moving from `start` to `end`, given the positions of the bodies at both
+/// ends of the step and their radii. Returns the index of the body, the fraction of the step and the relative impact position
+pub fn first_impact(
+    start: DVec3,
+    end: DVec3,
+    bodies: impl Iterator<Item = (DVec3, DVec3, f64)>,
+) -> Option<(usize, f64, DVec3)> {
+    bodies
+        .enumerate()
+        .filter_map(|(i, (body_start, body_end, radius))| {
+            let (start, end) = (start - body_start, end - body_end);
+            segment_impact(start, end, radius).map(|t| (i, t, start.lerp(end, t)))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+fn handle_impacts(
+    mut commands: Commands,
+    mut impacts: EventReader<ShipImpact>,
+    mut ships_mapping: ResMut<ShipsMapping>,
+    ships: Query<(&ShipInfo, &Position)>,
+    bodies: Query<(&Position, Option<&BodyRotation>)>,
+    policy: Res<ImpactPolicy>,
+    time: Res<GameTime>,
+) {
+    for &ShipImpact { ship, body, .. } in impacts.read() {
+        let Ok((info, pos)) = ships.get(ship) else {
+            continue;
+        };
+        match *policy {
+            ImpactPolicy::Remove => {
+                ships_mapping.0.remove(&info.id);
+                commands.entity(ship).despawn();
+            }
+            ImpactPolicy::Land => {
+                if let Ok((body_pos, rotation)) = bodies.get(body) {
+                    let offset = pos.0 - body_pos.0;
+                    commands.entity(ship).insert(Landed {
+                        body,
+                        offset: rotation.map_or(offset, |r| r.to_body_fixed(offset, time.time())),
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn update_landed(
+    mut ships: Query<(&mut Position, &mut Velocity, &Landed)>,
+    bodies: Query<(&Position, &Velocity, Option<&BodyRotation>), Without<Landed>>,
+) {
+    ships.iter_mut().for_each(|(mut pos, mut speed, landed)| {
+        if let Ok((body_pos, body_speed, rotation)) = bodies.get(landed.body) {
+            let offset = rotation.map_or(landed.offset, |r| r.orientation() * landed.offset);
+            pos.0 = body_pos.0 + offset;
+            speed.0 = body_speed.0 + rotation.map_or(DVec3::ZERO, |r| r.surface_speed(offset));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{math::DVec3, prelude::*};
+
+    use crate::{
+        client::testing::{paused_game, run_for, spawn_ship, start_action},
+        prelude::*,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_segment_impact() {
+        let radius = 10.;
+        let t = segment_impact(DVec3::new(-20., 0., 0.), DVec3::new(20., 0., 0.), radius);
+        assert_eq!(t, Some(0.25));
+        assert_eq!(
+            segment_impact(DVec3::new(-20., 20., 0.), DVec3::new(20., 20., 0.), radius),
+            None
+        );
+        assert_eq!(
+            segment_impact(DVec3::new(-40., 0., 0.), DVec3::new(-20., 0., 0.), radius),
+            None
+        );
+        assert_eq!(
+            segment_impact(DVec3::new(5., 0., 0.), DVec3::new(20., 0., 0.), radius),
+            Some(0.)
+        );
+    }
+
+    fn fall_on_earth(policy: ImpactPolicy) -> App {
+        let mut app = paused_game(ClientPlugin::testing());
+        app.insert_resource(policy);
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0[&id_from("terre")];
+        let (&earth_pos, &earth_speed) = world
+            .query::<(&Position, &Velocity)>()
+            .get(world, earth)
+            .unwrap();
+        // Heading straight to the Earth at 10 km/s
+        spawn_ship(
+            &mut app,
+            "s",
+            earth_pos.0 + DVec3::new(2e4, 0., 0.),
+            earth_speed.0 + DVec3::new(-864000., 0., 0.),
+        );
+        start_action(&mut app);
+        run_for(&mut app, 40);
+        app
+    }
+
+    #[test]
+    fn test_impact_remove() {
+        let mut app = fall_on_earth(ImpactPolicy::Remove);
+        let world = app.world_mut();
+        assert!(world.query::<&ShipInfo>().iter(world).next().is_none());
+        assert!(world.resource::<ShipsMapping>().0.is_empty());
+    }
+
+    #[test]
+    fn test_impact_land() {
+        let mut app = fall_on_earth(ImpactPolicy::Land);
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0[&id_from("terre")];
+        let radius = world
+            .query::<&BodyInfo>()
+            .get(world, earth)
+            .unwrap()
+            .0
+            .radius;
+        let &earth_pos = world.query::<&Position>().get(world, earth).unwrap();
+        let (pos, landed) = world.query::<(&Position, &Landed)>().single(world);
+
+        assert_eq!(landed.body, earth);
+        assert!(((pos.0 - earth_pos.0).length() - radius).abs() < 1.);
+    }
+}
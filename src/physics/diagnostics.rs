@@ -0,0 +1,321 @@
+//! Diagnostics measuring the drift of the orbital invariants of ships.
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use bevy::{
+    app::AppExit,
+    math::DVec3,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+
+use crate::{
+    game::Loaded,
+    objects::{prelude::*, ships::trajectory::VelocityUpdate},
+    utils::ecs::exit_on_error_if_app,
+};
+
+use super::{
+    collision::Landed,
+    leapfrog::{ActiveBurn, LeapfrogUpdate},
+    prelude::*,
+    time::TickEvent,
+    PhysicsUpdate, G,
+};
+
+/// Maximum number of samples kept for each ship, older samples being dropped first
+pub const MAX_SAMPLES: usize = 100_000;
+
+pub fn plugin(app: &mut App) {
+    app.add_systems(
+        OnEnter(Loaded),
+        insert_diagnostics.run_if(resource_exists::<DiagnosticsFile>),
+    )
+    .add_systems(
+        OnExit(Loaded),
+        write_diagnostics
+            .pipe(exit_on_error_if_app)
+            .run_if(resource_exists::<DiagnosticsFile>),
+    )
+    .add_systems(
+        Last,
+        write_diagnostics
+            .pipe(exit_on_error_if_app)
+            .run_if(resource_exists::<DiagnosticsFile>.and_then(on_event::<AppExit>())),
+    )
+    .add_systems(
+        FixedUpdate,
+        record_drift
+            .after(LeapfrogUpdate)
+            .in_set(PhysicsUpdate)
+            .run_if(resource_exists::<OrbitDiagnostics>.and_then(on_event::<TickEvent>())),
+    );
+}
+
+/// The CSV file the diagnostics are written to when leaving the game, which are only recorded if this resource exists
+#[derive(Resource, Clone, Debug)]
+pub struct DiagnosticsFile(pub PathBuf);
+
+/// The specific orbital energy (in km²/d²) and angular momentum (in km²/d) of an object orbiting a body
+pub fn orbital_invariants(
+    relative_pos: DVec3,
+    relative_speed: DVec3,
+    host_mass: f64,
+) -> (f64, DVec3) {
+    (
+        relative_speed.length_squared() / 2. - G * host_mass / relative_pos.length(),
+        relative_pos.cross(relative_speed),
+    )
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DriftSample {
+    pub simtick: u64,
+    pub energy: f64,
+    pub angular_momentum: DVec3,
+    /// Change of the energy since the reference sample, relative to the reference energy
+    pub energy_drift: f64,
+    /// Norm of the change of the angular momentum since the reference sample, relative to the reference norm
+    pub momentum_drift: f64,
+}
+
+/// The drift of a ship since it started orbiting its current main influencer
+#[derive(Clone, Debug)]
+pub struct ShipDiagnostics {
+    pub host: Entity,
+    pub reference_energy: f64,
+    pub reference_momentum: DVec3,
+    pub samples: VecDeque<DriftSample>,
+}
+
+impl ShipDiagnostics {
+    fn new(host: Entity, energy: f64, angular_momentum: DVec3) -> Self {
+        Self {
+            host,
+            reference_energy: energy,
+            reference_momentum: angular_momentum,
+            samples: VecDeque::new(),
+        }
+    }
+
+    fn record(&mut self, simtick: u64, energy: f64, angular_momentum: DVec3) {
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(DriftSample {
+            simtick,
+            energy,
+            angular_momentum,
+            energy_drift: ((energy - self.reference_energy) / self.reference_energy).abs(),
+            momentum_drift: (angular_momentum - self.reference_momentum).length()
+                / self.reference_momentum.length(),
+        });
+    }
+
+    /// The largest energy drift among the recorded samples
+    pub fn max_energy_drift(&self) -> f64 {
+        self.samples
+            .iter()
+            .map(|s| s.energy_drift)
+            .fold(0., f64::max)
+    }
+
+    /// The largest angular momentum drift among the recorded samples
+    pub fn max_momentum_drift(&self) -> f64 {
+        self.samples
+            .iter()
+            .map(|s| s.momentum_drift)
+            .fold(0., f64::max)
+    }
+}
+
+/// Drift of the orbital invariants of every ship in flight, recorded at each tick
+#[derive(Resource, Default, Debug)]
+pub struct OrbitDiagnostics {
+    pub ships: HashMap<ShipID, ShipDiagnostics>,
+}
+
+impl OrbitDiagnostics {
+    /// Writes every sample as CSV, with one line per ship and tick
+    pub fn write_csv(&self, mut writer: impl Write) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "ship,simtick,energy,angular_momentum,energy_drift,momentum_drift"
+        )?;
+        let mut ids: Vec<_> = self.ships.keys().collect();
+        ids.sort();
+        for id in ids {
+            for sample in &self.ships[id].samples {
+                writeln!(
+                    writer,
+                    "{},{},{:e},{:e},{:e},{:e}",
+                    id,
+                    sample.simtick,
+                    sample.energy,
+                    sample.angular_momentum.length(),
+                    sample.energy_drift,
+                    sample.momentum_drift
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn insert_diagnostics(mut commands: Commands) {
+    commands.init_resource::<OrbitDiagnostics>();
+}
+
+fn write_diagnostics(
+    diagnostics: Option<Res<OrbitDiagnostics>>,
+    file: Res<DiagnosticsFile>,
+) -> color_eyre::Result<()> {
+    if let Some(diagnostics) = diagnostics {
+        diagnostics.write_csv(BufWriter::new(File::create(&file.0)?))?;
+    }
+    Ok(())
+}
+
+#[allow(clippy::type_complexity)]
+fn record_drift(
+    mut diagnostics: ResMut<OrbitDiagnostics>,
+    mut thrusts: EventReader<VelocityUpdate>,
+    ships: Query<
+        (
+            &ShipInfo,
+            &Position,
+            &Velocity,
+            &Influenced,
+            Has<ActiveBurn>,
+        ),
+        Without<Landed>,
+    >,
+    bodies: Query<(&Position, &Velocity, &Mass)>,
+    time: Res<GameTime>,
+) {
+    let maneuvering: HashSet<_> = thrusts.read().map(|thrust| thrust.ship_id).collect();
+    for (info, pos, speed, influence, burning) in ships.iter() {
+        // Maneuvers change the invariants, so the drift is measured again once they are over
+        if burning || maneuvering.contains(&info.id) {
+            diagnostics.ships.remove(&info.id);
+            continue;
+        }
+        let Some((host, (host_pos, host_speed, host_mass))) = influence
+            .main_influencer
+            .and_then(|host| Some((host, bodies.get(host).ok()?)))
+        else {
+            continue;
+        };
+        let (energy, angular_momentum) =
+            orbital_invariants(pos.0 - host_pos.0, speed.0 - host_speed.0, host_mass.0);
+        let ship = diagnostics
+            .ships
+            .entry(info.id)
+            .or_insert_with(|| ShipDiagnostics::new(host, energy, angular_momentum));
+        // The invariants relative to a new host cannot be compared to the previous ones
+        if ship.host != host {
+            *ship = ShipDiagnostics::new(host, energy, angular_momentum);
+        }
+        ship.record(time.simtick, energy, angular_momentum);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::app::AppExit;
+    use tempfile::tempdir;
+
+    use crate::{
+        client::testing::{paused_game, run_to_time, spawn_in_orbit, start_action},
+        objects::ships::trajectory::{ManeuverNode, Trajectory, TrajectoryEvent},
+        physics::time::SIMTICKS_PER_TICK,
+        prelude::*,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_orbit_diagnostics() {
+        let mut app = paused_game(ClientPlugin::testing());
+        app.init_resource::<OrbitDiagnostics>();
+        let (earth, period) = spawn_in_orbit(&mut app, "terre", 1e5);
+        start_action(&mut app);
+        run_to_time(&mut app, period);
+        let diagnostics = app.world().resource::<OrbitDiagnostics>();
+        let ship = &diagnostics.ships[&id_from("s")];
+        assert_eq!(ship.host, earth);
+        assert!(ship.samples.len() > 100);
+        // The Sun perturbs the orbit a bit, but the energy of a circular orbit is well conserved by the leapfrog
+        assert!(
+            ship.max_energy_drift() < 1e-3,
+            "{}",
+            ship.max_energy_drift()
+        );
+        assert!(
+            ship.max_momentum_drift() < 1e-3,
+            "{}",
+            ship.max_momentum_drift()
+        );
+
+        let mut csv = Vec::new();
+        diagnostics.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("ship,simtick,energy,angular_momentum,energy_drift,momentum_drift")
+        );
+        assert_eq!(lines.count(), ship.samples.len());
+    }
+
+    #[test]
+    fn test_drift_reset_after_maneuver() {
+        let mut app = paused_game(ClientPlugin::testing());
+        app.init_resource::<OrbitDiagnostics>();
+        let (_, period) = spawn_in_orbit(&mut app, "terre", 1e5);
+        let node = ManeuverNode {
+            name: "1".to_owned(),
+            thrust: DVec3::new(1e4, 0., 0.),
+            origin: id_from("terre"),
+            duration: None,
+            thrust_magnitude: None,
+        };
+        app.world_mut().send_event(TrajectoryEvent::Create {
+            ship: id_from("s"),
+            trajectory: Trajectory {
+                nodes: [(5, node)].into(),
+            },
+        });
+        app.update();
+        start_action(&mut app);
+        run_to_time(&mut app, period / 2.);
+        let ship = &app.world().resource::<OrbitDiagnostics>().ships[&id_from("s")];
+        // The samples start after the node, which changed the energy of the orbit
+        assert!(ship.samples[0].simtick > 5 * SIMTICKS_PER_TICK);
+        assert!(
+            ship.max_energy_drift() < 1e-3,
+            "{}",
+            ship.max_energy_drift()
+        );
+    }
+
+    #[test]
+    fn test_diagnostics_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("drift.csv");
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer))
+            .insert_resource(DiagnosticsFile(path.clone()));
+        app.update();
+        app.update();
+        assert!(app.world().contains_resource::<OrbitDiagnostics>());
+        app.world_mut().send_event(AppExit::Success);
+        app.update();
+        let csv = std::fs::read_to_string(path).unwrap();
+        assert!(csv.starts_with("ship,simtick"));
+    }
+}
@@ -0,0 +1,201 @@
+//! Reference frames in which predictions can be expressed and displayed.
+use bevy::{
+    math::{DMat3, DQuat, DVec3},
+    prelude::*,
+    utils::HashMap,
+};
+
+use crate::objects::prelude::*;
+
+use super::{orbit::EllipticalOrbit, predictions::bodies_coordinates_at, Position, Velocity};
+
+/// A reference frame, whose state depends on the bodies it is attached to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReferenceFrame {
+    /// The global frame, centered on the primary body
+    #[default]
+    Inertial,
+    /// A non-rotating frame centered on a body
+    BodyCentered(Entity),
+    /// A frame centered on the barycenter of two bodies, rotating so that both stay fixed on the x axis, with the
+    /// secondary body on the positive side and the z axis along their orbital angular momentum
+    Synodic { primary: Entity, secondary: Entity },
+}
+
+/// The origin, axes and rotation speed of a reference frame at a given time, expressed in the global frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameState {
+    pub origin: DVec3,
+    pub origin_speed: DVec3,
+    /// The columns are the axes of the frame
+    pub axes: DMat3,
+    /// The angular velocity of the frame (in radians per day)
+    pub angular_velocity: DVec3,
+}
+
+impl Default for FrameState {
+    fn default() -> Self {
+        Self {
+            origin: DVec3::ZERO,
+            origin_speed: DVec3::ZERO,
+            axes: DMat3::IDENTITY,
+            angular_velocity: DVec3::ZERO,
+        }
+    }
+}
+
+impl FrameState {
+    pub fn translating(origin: DVec3, origin_speed: DVec3) -> Self {
+        Self {
+            origin,
+            origin_speed,
+            ..Default::default()
+        }
+    }
+
+    /// The synodic frame of two bodies given their positions, velocities and masses
+    pub fn synodic(primary: (DVec3, DVec3, f64), secondary: (DVec3, DVec3, f64)) -> Self {
+        let ((p1, v1, m1), (p2, v2, m2)) = (primary, secondary);
+        let total = m1 + m2;
+        let origin = (p1 * m1 + p2 * m2) / total;
+        let origin_speed = (v1 * m1 + v2 * m2) / total;
+        let (r, v) = (p2 - p1, v2 - v1);
+        let momentum = r.cross(v);
+        if r.length_squared() == 0. || momentum.length_squared() == 0. {
+            return Self::translating(origin, origin_speed);
+        }
+        let x = r.normalize();
+        let z = momentum.normalize();
+        Self {
+            origin,
+            origin_speed,
+            axes: DMat3::from_cols(x, z.cross(x), z),
+            angular_velocity: momentum / r.length_squared(),
+        }
+    }
+
+    pub fn rotation(&self) -> DQuat {
+        DQuat::from_mat3(&self.axes)
+    }
+
+    /// Converts a global position and velocity into this frame
+    pub fn to_frame(&self, pos: DVec3, speed: DVec3) -> (DVec3, DVec3) {
+        let rel_pos = pos - self.origin;
+        let rel_speed = speed - self.origin_speed - self.angular_velocity.cross(rel_pos);
+        let inverse = self.axes.transpose();
+        (inverse * rel_pos, inverse * rel_speed)
+    }
+
+    /// Converts a position and velocity expressed in this frame into the global frame
+    pub fn to_global(&self, pos: DVec3, speed: DVec3) -> (DVec3, DVec3) {
+        let rel_pos = self.axes * pos;
+        (
+            self.origin + rel_pos,
+            self.origin_speed + self.axes * speed + self.angular_velocity.cross(rel_pos),
+        )
+    }
+}
+
+impl ReferenceFrame {
+    /// Computes the state of the frame given the position, velocity and mass of the bodies it is attached to.
+    ///
+    /// Falls back to the inertial frame if the coordinates of a body are not known.
+    pub fn state_with(
+        &self,
+        mut coords: impl FnMut(Entity) -> Option<(DVec3, DVec3, f64)>,
+    ) -> FrameState {
+        match *self {
+            ReferenceFrame::Inertial => None,
+            ReferenceFrame::BodyCentered(body) => {
+                coords(body).map(|(pos, speed, _)| FrameState::translating(pos, speed))
+            }
+            ReferenceFrame::Synodic { primary, secondary } => coords(primary)
+                .zip(coords(secondary))
+                .map(|(p, s)| FrameState::synodic(p, s)),
+        }
+        .unwrap_or_default()
+    }
+
+    /// The current state of the frame
+    pub fn state(&self, bodies: &Query<(&Position, &Velocity, &BodyInfo)>) -> FrameState {
+        self.state_with(|e| {
+            bodies
+                .get(e)
+                .ok()
+                .map(|(pos, speed, info)| (pos.0, speed.0, info.0.mass))
+        })
+    }
+
+    /// The state of the frame at any game time, computed from the orbits of the bodies
+    pub fn state_at(
+        &self,
+        bodies: &Query<(&EllipticalOrbit, &BodyInfo)>,
+        mapping: &HashMap<BodyID, Entity>,
+        time: f64,
+    ) -> FrameState {
+        self.state_with(|e| {
+            let mass = bodies.get(e).ok()?.1 .0.mass;
+            let (pos, speed) = bodies_coordinates_at([e].into_iter(), bodies, mapping, time)[0];
+            Some((pos, speed, mass))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::system::SystemState, math::DVec3, prelude::*};
+
+    use crate::{physics::time::GAMETIME_PER_SIMTICK, prelude::*};
+
+    use super::*;
+
+    #[test]
+    fn test_frame_round_trip() {
+        let state = FrameState::synodic(
+            (DVec3::new(1., 2., 3.), DVec3::new(0., 1., 0.), 3.),
+            (DVec3::new(5., 2., 3.), DVec3::new(0., 3., 1.), 1.),
+        );
+        assert!((state.origin - DVec3::new(2., 2., 3.)).length() < 1e-12);
+        let (pos, speed) = (DVec3::new(-4., 7., 1.), DVec3::new(2., -1., 5.));
+        let (frame_pos, frame_speed) = state.to_frame(pos, speed);
+        let (global_pos, global_speed) = state.to_global(frame_pos, frame_speed);
+        assert!((global_pos - pos).length() < 1e-12);
+        assert!((global_speed - speed).length() < 1e-12);
+        // The secondary body is on the positive x axis
+        let (secondary, _) = state.to_frame(DVec3::new(5., 2., 3.), DVec3::ZERO);
+        assert!((secondary - DVec3::new(3., 0., 0.)).length() < 1e-12);
+    }
+
+    #[test]
+    fn test_synodic_frame() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Explorer));
+        app.update();
+        app.update();
+        let world = app.world_mut();
+        let mapping = world.resource::<BodiesMapping>().0.clone();
+        let (sun, earth) = (mapping[&id_from("soleil")], mapping[&id_from("terre")]);
+        let frame = ReferenceFrame::Synodic {
+            primary: sun,
+            secondary: earth,
+        };
+        #[allow(clippy::type_complexity)]
+        let mut system_state: SystemState<Query<(&EllipticalOrbit, &BodyInfo)>> =
+            SystemState::new(world);
+        let bodies = system_state.get(world);
+        // Both bodies stay still in their synodic frame
+        let earth_coords = |time| {
+            let (pos, speed) =
+                bodies_coordinates_at([earth].into_iter(), &bodies, &mapping, time)[0];
+            frame.state_at(&bodies, &mapping, time).to_frame(pos, speed)
+        };
+        let (start, _) = earth_coords(0.);
+        for simtick in [1000, 50_000, 200_000] {
+            let (pos, speed) = earth_coords(simtick as f64 * GAMETIME_PER_SIMTICK);
+            assert!(pos.y.abs() + pos.z.abs() < 1e-6 * start.length());
+            // The orbit of the Earth is slightly eccentric
+            assert!((pos.x - start.x).abs() < 0.04 * start.x);
+            assert!(speed.length() < 1e-3 * pos.length());
+        }
+    }
+}
@@ -1,19 +1,47 @@
-use bevy::{math::DVec3, prelude::*};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::game::Loaded;
 use crate::objects::prelude::*;
 
-use crate::objects::bodies::BodyID;
+use crate::utils::bvh::{Bvh, Sphere};
 
+use super::leapfrog::LeapfrogUpdate;
+use super::on_rails::steps_on_rails;
 use super::time::TickEvent;
+use super::PhysicsUpdate;
 use super::Position;
 
 pub fn plugin(app: &mut App) {
-    app.add_systems(OnEnter(Loaded), setup_hill_spheres.in_set(InfluenceUpdate))
+    app.init_resource::<InfluenceModel>()
+        .init_resource::<InfluenceIndex>()
+        .add_systems(
+            OnEnter(Loaded),
+            (setup_hill_spheres, update_influence_index)
+                .chain()
+                .in_set(InfluenceUpdate),
+        )
+        .add_systems(
+            Update,
+            (setup_hill_spheres, update_influence_index)
+                .chain()
+                .run_if(in_state(Loaded).and_then(resource_changed::<InfluenceModel>)),
+        )
         .add_systems(
             FixedUpdate,
-            update_influence
-                .in_set(InfluenceUpdate)
+            (
+                (
+                    update_influence_index,
+                    // Ships on rails are only integrated after the bodies moved farther than their spheres of
+                    // influence, so their crossings are left to the detection below
+                    update_influence.run_if(not(steps_on_rails)),
+                )
+                    .chain()
+                    .in_set(InfluenceUpdate),
+                // Ships and bodies are at the same game time once the ships have been integrated, which matters when
+                // bodies move farther than the size of a sphere of influence during a step
+                detect_crossings.after(LeapfrogUpdate).in_set(PhysicsUpdate),
+            )
                 .run_if(on_event::<TickEvent>()),
         );
 }
@@ -21,11 +49,45 @@ pub fn plugin(app: &mut App) {
 #[derive(SystemSet, Debug, PartialEq, Eq, Hash, Clone)]
 pub struct InfluenceUpdate;
 
+/// How the radius of the sphere of influence of a body is computed
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InfluenceModel {
+    /// Hill sphere at the periapsis, `a(1-e)·(m/3(M+m))^(1/3)`
+    #[default]
+    Hill,
+    /// Laplace sphere, `a·(m/M)^(2/5)`, which is the usual choice for patched conics
+    Laplace,
+    /// The radius given by the data of each body, the Hill sphere being used for bodies without one
+    Fixed,
+}
+
+impl InfluenceModel {
+    /// The radius of the sphere of influence of a body orbiting a host of the given mass, never smaller than the body
+    pub fn radius(&self, data: &BodyData, host_mass: f64) -> f64 {
+        let hill = || {
+            data.semimajor_axis
+                * (1. - data.eccentricity)
+                * (data.mass / (3. * (host_mass + data.mass))).powf(1. / 3.)
+        };
+        match self {
+            InfluenceModel::Hill => hill(),
+            InfluenceModel::Laplace => data.semimajor_axis * (data.mass / host_mass).powf(0.4),
+            InfluenceModel::Fixed => data.sphere_of_influence.unwrap_or_else(hill),
+        }
+        .max(data.radius)
+    }
+}
+
+/// The radius of the sphere of influence of a body, computed with the [InfluenceModel] (in km)
 #[derive(Component, Clone, Copy)]
 pub struct HillRadius(pub f64);
 
+/// Index of the spheres of influence of the bodies, rebuilt every time the bodies move
+#[derive(Resource, Default)]
+pub struct InfluenceIndex(pub Bvh<Entity>);
+
 /// Component storing the bodies that influence the object's trajectory
-#[derive(Component, Default, Debug)]
+#[derive(Component, Default, Debug, Clone)]
 pub struct Influenced {
     pub main_influencer: Option<Entity>,
     pub influencers: Vec<Entity>,
@@ -34,40 +96,32 @@ pub struct Influenced {
 impl Influenced {
     pub fn new(
         Position(object_pos): &Position,
+        index: &InfluenceIndex,
         bodies: &Query<(&Position, &HillRadius, &BodyInfo)>,
         mapping: &BodiesMapping,
-        main_body: BodyID,
     ) -> Self {
+        let spheres: Vec<_> = index.0.containing(*object_pos).collect();
         // if an object is not in a bodie's sphere of influence, it is not in its children's either
-        fn influencers_rec(
-            body: BodyID,
-            query: &Query<(&Position, &HillRadius, &BodyInfo)>,
-            mapping: &BodiesMapping,
-            object_pos: &DVec3,
-            influences: &mut Vec<(Entity, f64)>,
-        ) {
-            if let Some(e) = mapping.0.get(&body) {
-                let (Position(body_pos), HillRadius(hill_radius), BodyInfo(data)) =
-                    query.get(*e).unwrap();
-                let r = *object_pos - *body_pos;
-                let dist = r.length();
-                if dist < *hill_radius {
-                    influences.push((*e, *hill_radius));
-                    data.orbiting_bodies.iter().for_each(|child| {
-                        influencers_rec(*child, query, mapping, object_pos, influences);
-                    })
+        let in_hosts_spheres = |entity: Entity| {
+            let mut host = bodies.get(entity).ok().and_then(|b| b.2 .0.host_body);
+            while let Some(e) = host.and_then(|id| mapping.0.get(&id)) {
+                if !spheres.iter().any(|s| s.value == *e) {
+                    return false;
                 }
+                host = bodies.get(*e).ok().and_then(|b| b.2 .0.host_body);
             }
-        }
-
-        let mut influences = Vec::new();
-        influencers_rec(main_body, bodies, mapping, object_pos, &mut influences);
+            true
+        };
+        let influences: Vec<_> = spheres
+            .iter()
+            .filter(|s| in_hosts_spheres(s.value))
+            .collect();
         Influenced {
             main_influencer: influences
                 .iter()
-                .min_by(|a, b| a.1.total_cmp(&b.1))
-                .map(|a| a.0),
-            influencers: influences.into_iter().map(|a| a.0).collect(),
+                .min_by(|a, b| a.radius.total_cmp(&b.radius))
+                .map(|s| s.value),
+            influencers: influences.into_iter().map(|s| s.value).collect(),
         }
     }
 }
@@ -77,6 +131,7 @@ fn setup_hill_spheres(
     query: Query<&BodyInfo>,
     primary: Query<(Entity, &BodyInfo), With<PrimaryBody>>,
     mapping: Res<BodiesMapping>,
+    model: Res<InfluenceModel>,
 ) {
     let mut queue = vec![(primary.single().1 .0.id, 0.)];
     let mut i = 0;
@@ -84,11 +139,9 @@ fn setup_hill_spheres(
         let (id, parent_mass) = queue[i];
         if let Some(entity) = mapping.0.get(&id) {
             if let Ok(BodyInfo(data)) = query.get(*entity) {
-                let radius = (data.semimajor_axis
-                    * (1. - data.eccentricity)
-                    * (data.mass / (3. * (parent_mass + data.mass))).powf(1. / 3.))
-                .max(data.radius);
-                commands.entity(*entity).insert(HillRadius(radius));
+                commands
+                    .entity(*entity)
+                    .insert(HillRadius(model.radius(data, parent_mass)));
                 queue.extend(data.orbiting_bodies.iter().map(|c| (*c, data.mass)));
             }
         }
@@ -99,17 +152,60 @@ fn setup_hill_spheres(
         .insert(HillRadius(f64::INFINITY));
 }
 
-fn update_influence(
+fn update_influence_index(
+    mut index: ResMut<InfluenceIndex>,
+    bodies: Query<(Entity, &Position, &HillRadius)>,
+) {
+    index.0 = bodies
+        .iter()
+        .map(|(e, pos, radius)| Sphere::new(pos.0, radius.0, e))
+        .collect();
+}
+
+/// Computes the influence of all the objects from the spheres of influence containing them
+pub fn update_influence(
+    mut influenced: Query<(&Position, &mut Influenced)>,
+    index: Res<InfluenceIndex>,
+    bodies: Query<(&Position, &HillRadius, &BodyInfo)>,
+    mapping: Res<BodiesMapping>,
+) {
+    influenced
+        .par_iter_mut()
+        .for_each(|(object_pos, mut influence)| {
+            *influence = Influenced::new(object_pos, &index, &bodies, mapping.as_ref());
+        });
+}
+
+/// Computes again the influence of the objects that left the sphere of influence of their main influencer or entered
+/// the one of its children during the step
+fn detect_crossings(
     mut influenced: Query<(&Position, &mut Influenced)>,
+    index: Res<InfluenceIndex>,
     bodies: Query<(&Position, &HillRadius, &BodyInfo)>,
     mapping: Res<BodiesMapping>,
-    main_body: Query<&BodyInfo, With<PrimaryBody>>,
 ) {
-    let main_body = main_body.single().0.id;
     influenced
         .par_iter_mut()
         .for_each(|(object_pos, mut influence)| {
-            *influence = Influenced::new(object_pos, &bodies, mapping.as_ref(), main_body);
+            let Some((host_pos, host_radius, BodyInfo(host))) = influence
+                .main_influencer
+                .and_then(|host| bodies.get(host).ok())
+            else {
+                return;
+            };
+            // Same test as the index, so that the influence computed again agrees with the crossing
+            let inside = |pos: &Position, radius: &HillRadius| {
+                Sphere::new(pos.0, radius.0, ()).contains(object_pos.0)
+            };
+            let crossed = !inside(host_pos, host_radius)
+                || host
+                    .orbiting_bodies
+                    .iter()
+                    .filter_map(|id| bodies.get(*mapping.0.get(id)?).ok())
+                    .any(|(pos, radius, _)| inside(pos, radius));
+            if crossed {
+                *influence = Influenced::new(object_pos, &index, &bodies, mapping.as_ref());
+            }
         });
 }
 
@@ -117,7 +213,11 @@ fn update_influence(
 mod tests {
     use bevy::app::App;
 
-    use crate::{prelude::*, utils::algebra::circular_orbit_around_body};
+    use crate::{
+        physics::influence::{HillRadius, InfluenceModel},
+        prelude::*,
+        utils::algebra::circular_orbit_around_body,
+    };
     #[test]
     fn test_influence() {
         let mut app = App::new();
@@ -152,4 +252,26 @@ mod tests {
         assert_eq!(influenced.main_influencer, Some(moon));
         assert_eq!(influenced.influencers.len(), 3);
     }
+
+    #[test]
+    fn test_laplace_sphere() {
+        let mut app = App::new();
+        app.add_plugins(
+            ClientPlugin::testing()
+                .with_bodies(BodiesConfig::SmallestBodyType(BodyType::Planet))
+                .in_mode(ClientMode::Singleplayer),
+        );
+        app.update();
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0[&id_from("terre")];
+        let hill = world.get::<HillRadius>(earth).unwrap().0;
+
+        world.insert_resource(InfluenceModel::Laplace);
+        app.update();
+        let world = app.world_mut();
+        let laplace = world.get::<HillRadius>(earth).unwrap().0;
+        // The sphere of influence of the Earth is about 925 000 km wide, smaller than its Hill sphere
+        assert!((laplace - 9.25e5).abs() < 1e4);
+        assert!(laplace < hill);
+    }
 }
@@ -0,0 +1,424 @@
+//! Numerical schemes used to propagate ships (and predictions) under gravity.
+use bevy::{math::DVec3, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use super::leapfrog::{get_dv, get_dx};
+
+/// Maximum number of sub-steps that the adaptive integrator can take during a single step
+const MAX_ADAPTIVE_SUBSTEPS: usize = 1000;
+const MIN_STEP_FACTOR: f64 = 0.2;
+const MAX_STEP_FACTOR: f64 = 5.;
+const SAFETY_FACTOR: f64 = 0.9;
+
+/// The integrator used by both the live physics and the predictions
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Integrator {
+    /// Second order symplectic kick-drift-kick scheme (velocity Verlet)
+    #[default]
+    Leapfrog,
+    /// Fourth order symplectic scheme built from three leapfrog steps, see https://doi.org/10.1016/0375-9601(90)90092-3
+    Yoshida4,
+    /// Classical fourth order Runge-Kutta
+    RK4,
+    /// Adaptive fifth order Runge-Kutta with embedded fourth order error estimate.
+    /// The step is split in as many sub-steps as needed so that the relative error stays below the tolerance.
+    DormandPrince { tolerance: f64 },
+}
+
+/// The state of an object at the end of an integration step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IntegrationStep {
+    pub pos: DVec3,
+    pub speed: DVec3,
+    /// The acceleration at the new position and time
+    pub acc: DVec3,
+}
+
+/// The positions, velocities and accelerations of several objects integrated together
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SystemState {
+    pub pos: Vec<DVec3>,
+    pub speed: Vec<DVec3>,
+    pub acc: Vec<DVec3>,
+}
+
+// Dormand-Prince tableau, see https://en.wikipedia.org/wiki/Dormand%E2%80%93Prince_method
+const DP_C: [f64; 7] = [0., 1. / 5., 3. / 10., 4. / 5., 8. / 9., 1., 1.];
+const DP_A: [&[f64]; 7] = [
+    &[],
+    &[1. / 5.],
+    &[3. / 40., 9. / 40.],
+    &[44. / 45., -56. / 15., 32. / 9.],
+    &[
+        19372. / 6561.,
+        -25360. / 2187.,
+        64448. / 6561.,
+        -212. / 729.,
+    ],
+    &[
+        9017. / 3168.,
+        -355. / 33.,
+        46732. / 5247.,
+        49. / 176.,
+        -5103. / 18656.,
+    ],
+    &[
+        35. / 384.,
+        0.,
+        500. / 1113.,
+        125. / 192.,
+        -2187. / 6784.,
+        11. / 84.,
+    ],
+];
+const DP_B: [f64; 7] = [
+    35. / 384.,
+    0.,
+    500. / 1113.,
+    125. / 192.,
+    -2187. / 6784.,
+    11. / 84.,
+    0.,
+];
+const DP_B_LOW: [f64; 7] = [
+    5179. / 57600.,
+    0.,
+    7571. / 16695.,
+    393. / 640.,
+    -92097. / 339200.,
+    187. / 2100.,
+    1. / 40.,
+];
+
+const RK4_C: [f64; 4] = [0., 0.5, 0.5, 1.];
+const RK4_A: [&[f64]; 4] = [&[], &[0.5], &[0., 0.5], &[0., 0., 1.]];
+const RK4_B: [f64; 4] = [1. / 6., 1. / 3., 1. / 3., 1. / 6.];
+
+impl Integrator {
+    /// Advances the object from `time` to `time + dt`.
+    ///
+    /// `acc` is the acceleration at the initial position and time, and `acceleration` computes the acceleration
+    /// at any position and time.
+    pub fn step(
+        &self,
+        pos: DVec3,
+        speed: DVec3,
+        acc: DVec3,
+        time: f64,
+        dt: f64,
+        mut acceleration: impl FnMut(DVec3, f64) -> DVec3,
+    ) -> IntegrationStep {
+        match *self {
+            Integrator::Leapfrog => {
+                let pos = pos + get_dx(speed, acc, dt);
+                let new_acc = acceleration(pos, time + dt);
+                IntegrationStep {
+                    pos,
+                    speed: speed + get_dv(acc, new_acc, dt),
+                    acc: new_acc,
+                }
+            }
+            Integrator::Yoshida4 => {
+                let cbrt2 = 2f64.cbrt();
+                let w1 = 1. / (2. - cbrt2);
+                let w0 = -cbrt2 * w1;
+                let drifts = [w1 / 2., (w0 + w1) / 2., (w0 + w1) / 2., w1 / 2.];
+                let kicks = [w1, w0, w1];
+                let (mut pos, mut speed, mut t) = (pos, speed, time);
+                for i in 0..3 {
+                    pos += drifts[i] * speed * dt;
+                    t += drifts[i] * dt;
+                    speed += kicks[i] * acceleration(pos, t) * dt;
+                }
+                pos += drifts[3] * speed * dt;
+                IntegrationStep {
+                    pos,
+                    speed,
+                    acc: acceleration(pos, time + dt),
+                }
+            }
+            Integrator::RK4 => {
+                let k = rk_stages(pos, speed, acc, time, dt, &RK4_C, &RK4_A, &mut acceleration);
+                let (pos, speed) = combine(pos, speed, dt, &k, &RK4_B);
+                IntegrationStep {
+                    pos,
+                    speed,
+                    acc: acceleration(pos, time + dt),
+                }
+            }
+            Integrator::DormandPrince { tolerance } => {
+                let end = time + dt;
+                let (mut pos, mut speed, mut acc, mut t) = (pos, speed, acc, time);
+                let mut h = dt;
+                for _ in 0..MAX_ADAPTIVE_SUBSTEPS {
+                    let last = t + h >= end;
+                    if last {
+                        h = end - t;
+                    }
+                    let k = rk_stages(pos, speed, acc, t, h, &DP_C, &DP_A, &mut acceleration);
+                    let (new_pos, new_speed) = combine(pos, speed, h, &k, &DP_B);
+                    let (low_pos, low_speed) = combine(pos, speed, h, &k, &DP_B_LOW);
+                    let error = ((new_pos - low_pos).length()
+                        / (tolerance * new_pos.length().max(1.)))
+                    .max(
+                        (new_speed - low_speed).length() / (tolerance * new_speed.length().max(1.)),
+                    );
+                    if error <= 1. {
+                        // The last stage is evaluated at the new position (first same as last)
+                        (pos, speed, acc, t) = (new_pos, new_speed, k[6].1, t + h);
+                        if last {
+                            return IntegrationStep { pos, speed, acc };
+                        }
+                    }
+                    h *= (SAFETY_FACTOR * error.powf(-0.2)).clamp(MIN_STEP_FACTOR, MAX_STEP_FACTOR);
+                }
+                // Too many sub-steps, finish with a single step to stay deterministic
+                Integrator::RK4.step(pos, speed, acc, t, end - t, acceleration)
+            }
+        }
+    }
+
+    /// Same as [Integrator::step] for several objects attracting each other, whose accelerations are all written at
+    /// once into the given slice
+    pub fn step_system(
+        &self,
+        state: &mut SystemState,
+        time: f64,
+        dt: f64,
+        mut accelerations: impl FnMut(&[DVec3], f64, &mut [DVec3]),
+    ) {
+        let n = state.pos.len();
+        match *self {
+            Integrator::Leapfrog => {
+                let SystemState { pos, speed, acc } = state;
+                for i in 0..n {
+                    pos[i] += get_dx(speed[i], acc[i], dt);
+                }
+                let mut new_acc = vec![DVec3::ZERO; n];
+                accelerations(pos, time + dt, &mut new_acc);
+                for i in 0..n {
+                    speed[i] += get_dv(acc[i], new_acc[i], dt);
+                }
+                *acc = new_acc;
+            }
+            Integrator::Yoshida4 => {
+                let cbrt2 = 2f64.cbrt();
+                let w1 = 1. / (2. - cbrt2);
+                let w0 = -cbrt2 * w1;
+                let drifts = [w1 / 2., (w0 + w1) / 2., (w0 + w1) / 2., w1 / 2.];
+                let kicks = [w1, w0, w1];
+                let SystemState { pos, speed, acc } = state;
+                let mut t = time;
+                for i in 0..3 {
+                    for j in 0..n {
+                        pos[j] += drifts[i] * speed[j] * dt;
+                    }
+                    t += drifts[i] * dt;
+                    accelerations(pos, t, acc);
+                    for j in 0..n {
+                        speed[j] += kicks[i] * acc[j] * dt;
+                    }
+                }
+                for j in 0..n {
+                    pos[j] += drifts[3] * speed[j] * dt;
+                }
+                accelerations(pos, time + dt, acc);
+            }
+            Integrator::RK4 => {
+                let k = rk_system_stages(state, time, dt, &RK4_C, &RK4_A, &mut accelerations);
+                (state.pos, state.speed) = combine_system(state, dt, &k, &RK4_B);
+                accelerations(&state.pos, time + dt, &mut state.acc);
+            }
+            Integrator::DormandPrince { tolerance } => {
+                let end = time + dt;
+                let mut t = time;
+                let mut h = dt;
+                for _ in 0..MAX_ADAPTIVE_SUBSTEPS {
+                    let last = t + h >= end;
+                    if last {
+                        h = end - t;
+                    }
+                    let mut k = rk_system_stages(state, t, h, &DP_C, &DP_A, &mut accelerations);
+                    let (new_pos, new_speed) = combine_system(state, h, &k, &DP_B);
+                    let (low_pos, low_speed) = combine_system(state, h, &k, &DP_B_LOW);
+                    let error = (0..n)
+                        .map(|i| {
+                            ((new_pos[i] - low_pos[i]).length()
+                                / (tolerance * new_pos[i].length().max(1.)))
+                            .max(
+                                (new_speed[i] - low_speed[i]).length()
+                                    / (tolerance * new_speed[i].length().max(1.)),
+                            )
+                        })
+                        .fold(0., f64::max);
+                    if error <= 1. {
+                        (state.pos, state.speed, state.acc) =
+                            (new_pos, new_speed, k.swap_remove(6).1);
+                        t += h;
+                        if last {
+                            return;
+                        }
+                    }
+                    h *= (SAFETY_FACTOR * error.powf(-0.2)).clamp(MIN_STEP_FACTOR, MAX_STEP_FACTOR);
+                }
+                Integrator::RK4.step_system(state, t, end - t, accelerations);
+            }
+        }
+    }
+}
+
+/// Same as [rk_stages] for several objects integrated together
+fn rk_system_stages(
+    state: &SystemState,
+    time: f64,
+    dt: f64,
+    c: &[f64],
+    a: &[&[f64]],
+    accelerations: &mut impl FnMut(&[DVec3], f64, &mut [DVec3]),
+) -> Vec<(Vec<DVec3>, Vec<DVec3>)> {
+    let mut k = vec![(state.speed.clone(), state.acc.clone())];
+    for i in 1..c.len() {
+        let (stage_pos, stage_speed) = combine_system(state, dt, &k, a[i]);
+        let mut stage_acc = vec![DVec3::ZERO; stage_pos.len()];
+        accelerations(&stage_pos, time + c[i] * dt, &mut stage_acc);
+        k.push((stage_speed, stage_acc));
+    }
+    k
+}
+
+fn combine_system(
+    state: &SystemState,
+    dt: f64,
+    k: &[(Vec<DVec3>, Vec<DVec3>)],
+    weights: &[f64],
+) -> (Vec<DVec3>, Vec<DVec3>) {
+    let (mut pos, mut speed) = (state.pos.clone(), state.speed.clone());
+    for ((dp, ds), w) in k.iter().zip(weights) {
+        for i in 0..pos.len() {
+            pos[i] += dp[i] * *w * dt;
+            speed[i] += ds[i] * *w * dt;
+        }
+    }
+    (pos, speed)
+}
+
+/// Computes the stages (velocity, acceleration) of an explicit Runge-Kutta method
+#[allow(clippy::too_many_arguments)]
+fn rk_stages<const N: usize>(
+    pos: DVec3,
+    speed: DVec3,
+    acc: DVec3,
+    time: f64,
+    dt: f64,
+    c: &[f64; N],
+    a: &[&[f64]; N],
+    acceleration: &mut impl FnMut(DVec3, f64) -> DVec3,
+) -> [(DVec3, DVec3); N] {
+    let mut k = [(speed, acc); N];
+    for i in 1..N {
+        let (stage_pos, stage_speed) = combine(pos, speed, dt, &k[..i], a[i]);
+        k[i] = (stage_speed, acceleration(stage_pos, time + c[i] * dt));
+    }
+    k
+}
+
+fn combine(
+    pos: DVec3,
+    speed: DVec3,
+    dt: f64,
+    k: &[(DVec3, DVec3)],
+    weights: &[f64],
+) -> (DVec3, DVec3) {
+    k.iter()
+        .zip(weights)
+        .fold((pos, speed), |(p, s), ((dp, ds), w)| {
+            (p + *dp * *w * dt, s + *ds * *w * dt)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use bevy::math::DVec3;
+
+    use crate::physics::{leapfrog::get_acceleration, G};
+
+    use super::{Integrator, SystemState};
+
+    const EARTH_MASS: f64 = 5.97237e24;
+
+    /// Returns the distance between the initial and final positions after one period of a circular orbit
+    fn circular_orbit_error(integrator: Integrator, steps: usize) -> f64 {
+        let radius: f64 = 1e4;
+        let mu = G * EARTH_MASS;
+        let period = 2. * PI * (radius.powi(3) / mu).sqrt();
+        let dt = period / steps as f64;
+        let acceleration =
+            |pos: DVec3, _| get_acceleration(pos, [(DVec3::ZERO, EARTH_MASS)].into_iter());
+        let start = DVec3::new(radius, 0., 0.);
+        let (mut pos, mut speed) = (start, DVec3::new(0., (mu / radius).sqrt(), 0.));
+        let mut acc = acceleration(pos, 0.);
+        for i in 0..steps {
+            let step = integrator.step(pos, speed, acc, i as f64 * dt, dt, acceleration);
+            (pos, speed, acc) = (step.pos, step.speed, step.acc);
+        }
+        (pos - start).length()
+    }
+
+    #[test]
+    fn test_integrators_accuracy() {
+        let leapfrog = circular_orbit_error(Integrator::Leapfrog, 100);
+        let yoshida = circular_orbit_error(Integrator::Yoshida4, 100);
+        let rk4 = circular_orbit_error(Integrator::RK4, 100);
+        let dormand_prince =
+            circular_orbit_error(Integrator::DormandPrince { tolerance: 1e-10 }, 10);
+        assert!(leapfrog < 1e3);
+        assert!(yoshida < leapfrog / 10.);
+        assert!(rk4 < leapfrog / 10.);
+        assert!(dormand_prince < 1.);
+    }
+
+    #[test]
+    fn test_integrators_order() {
+        // Halving the step of a fourth order method divides the error by about 16
+        for integrator in [Integrator::Yoshida4, Integrator::RK4] {
+            let ratio =
+                circular_orbit_error(integrator, 100) / circular_orbit_error(integrator, 200);
+            assert!(ratio > 10.);
+        }
+    }
+
+    #[test]
+    fn test_system_step() {
+        // Two objects that do not attract each other move like a single one
+        let acceleration =
+            |pos: DVec3| get_acceleration(pos, [(DVec3::ZERO, EARTH_MASS)].into_iter());
+        let (pos, speed) = (DVec3::new(1e4, 0., 0.), DVec3::new(0., 5e5, 0.));
+        for integrator in [
+            Integrator::Leapfrog,
+            Integrator::Yoshida4,
+            Integrator::RK4,
+            Integrator::DormandPrince { tolerance: 1e-10 },
+        ] {
+            let step = integrator.step(pos, speed, acceleration(pos), 0., 1e-3, |p, _| {
+                acceleration(p)
+            });
+            let mut state = SystemState {
+                pos: vec![pos, -pos],
+                speed: vec![speed, -speed],
+                acc: vec![acceleration(pos), acceleration(-pos)],
+            };
+            integrator.step_system(&mut state, 0., 1e-3, |positions, _, acc| {
+                for (p, a) in positions.iter().zip(acc) {
+                    *a = acceleration(*p);
+                }
+            });
+            for (i, sign) in [1., -1.].into_iter().enumerate() {
+                assert!((state.pos[i] - sign * step.pos).length() < 1e-9 * pos.length());
+                assert!((state.speed[i] - sign * step.speed).length() < 1e-9 * speed.length());
+                assert!((state.acc[i] - sign * step.acc).length() < 1e-9 * step.acc.length());
+            }
+        }
+    }
+}
@@ -1,26 +1,41 @@
-use bevy::{math::DVec3, prelude::*};
+use bevy::{math::DVec3, prelude::*, utils::Parallel};
 
 use super::{
+    collision::{first_impact, Landed, ShipImpact},
+    influence::HillRadius,
+    integrator::Integrator,
+    on_rails::{apply_crossing, propagate_on_rails, OnRailsThreshold},
+    perturbations::{
+        DragProfile, PerturbationModel, Perturbations, RadiationProfile, SurfaceCoefficients,
+    },
+    predictions::{bodies_coordinates_at, BodyStates},
     prelude::*,
     time::{SimStepSize, GAMETIME_PER_SIMTICK},
     G,
 };
-use crate::game::InGame;
+use crate::{
+    game::InGame,
+    objects::{
+        prelude::*,
+        ships::{propulsion::ShipMass, trajectory::CurrentTrajectory},
+    },
+};
+
+/// Fraction of the local dynamical time (inverse square root of the gravitational gradient) that a sub-step can last
+const GRADIENT_STEP_RATIO: f64 = 0.1;
+/// Maximum number of sub-steps an object can be split in during a single step
+pub const MAX_SUBSTEPS: u64 = 1024;
 
-// See https://en.wikipedia.org/wiki/Leapfrog_integration#Algorithm
+// See https://en.wikipedia.org/wiki/Leapfrog_integration#Algorithm for the default integrator
 pub fn plugin(app: &mut App) {
-    app.configure_sets(
-        FixedUpdate,
-        LeapfrogUpdate
-            .run_if(resource_equals(ToggleTime(true)))
-            .run_if(in_state(InGame)),
-    )
-    .add_systems(
-        FixedUpdate,
-        (update_position, update_acceleration, update_velocity)
-            .chain()
-            .in_set(LeapfrogUpdate),
-    );
+    app.init_resource::<Integrator>()
+        .configure_sets(
+            FixedUpdate,
+            LeapfrogUpdate
+                .run_if(resource_equals(ToggleTime(true)))
+                .run_if(in_state(InGame)),
+        )
+        .add_systems(FixedUpdate, integrate.in_set(LeapfrogUpdate));
 }
 
 #[derive(SystemSet, Debug, PartialEq, Eq, Hash, Clone)]
@@ -41,40 +56,253 @@ impl Acceleration {
     }
 }
 
-fn update_acceleration(
-    mut gravity_bound: Query<(&Position, &mut Acceleration, &Influenced)>,
-    bodies: Query<(&Position, &Mass)>,
-) {
-    gravity_bound
-        .par_iter_mut()
-        .for_each(|(object_pos, mut acceleration, influenced)| {
-            acceleration.previous = acceleration.current;
-            acceleration.current = get_acceleration(
-                object_pos.0,
-                bodies
-                    .iter_many(&influenced.influencers)
-                    .map(|(p, m)| (p.0, m.0)),
-            );
-        });
+/// A finite-duration burn in progress, adding a constant acceleration to gravity during a time interval
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct ActiveBurn {
+    /// The acceleration in global coordinates (in km/d²)
+    pub acceleration: DVec3,
+    /// Game time at which the burn starts
+    pub start: f64,
+    /// Game time at which the burn ends
+    pub end: f64,
+}
+
+impl ActiveBurn {
+    /// The acceleration provided by the burn at the given game time
+    pub fn acceleration_at(&self, time: f64) -> DVec3 {
+        if time > self.start && time <= self.end {
+            self.acceleration
+        } else {
+            DVec3::ZERO
+        }
+    }
 }
 
-fn update_position(
-    mut query: Query<(&mut Position, &Velocity, &Acceleration)>,
+/// Advances every gravity bound object by one step of the selected [Integrator], split in sub-steps if needed
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn integrate(
+    mut gravity_bound: Query<
+        (
+            Entity,
+            &mut Position,
+            &mut Velocity,
+            &mut Acceleration,
+            &mut Influenced,
+            Option<&ActiveBurn>,
+            Option<&CurrentTrajectory>,
+            (
+                Option<&RadiationProfile>,
+                Option<&DragProfile>,
+                Option<&ShipMass>,
+            ),
+        ),
+        Without<Landed>,
+    >,
+    bodies: Query<(&'static EllipticalOrbit, &'static BodyInfo)>,
+    hill_radii: Query<&HillRadius>,
+    mapping: Res<BodiesMapping>,
+    integrator: Res<Integrator>,
     step: Res<SimStepSize>,
+    on_rails_threshold: Res<OnRailsThreshold>,
+    perturbations: Res<Perturbations>,
+    time: Res<GameTime>,
+    mut impacts: Local<Parallel<Vec<ShipImpact>>>,
+    mut impact_events: EventWriter<ShipImpact>,
 ) {
-    query.par_iter_mut().for_each(|(mut pos, speed, acc)| {
-        pos.0 += get_dx(speed.0, acc.current, GAMETIME_PER_SIMTICK * step.0 as f64)
-    });
+    let dt = GAMETIME_PER_SIMTICK * step.0 as f64;
+    let start = time.simtick.saturating_sub(step.0) as f64 * GAMETIME_PER_SIMTICK;
+    gravity_bound.par_iter_mut().for_each(
+        |(
+            ship,
+            mut pos,
+            mut speed,
+            mut acceleration,
+            mut influenced,
+            burn,
+            trajectory,
+            (radiation, drag, mass),
+        )| {
+            let (mut start, mut dt) = (start, dt);
+            let surface = SurfaceCoefficients::of(radiation, drag, mass);
+            let perturbation_model = |influenced: &Influenced| {
+                PerturbationModel::new(
+                    &perturbations,
+                    influenced.main_influencer,
+                    &influenced.influencers,
+                    surface,
+                    &bodies,
+                    &mapping.0,
+                )
+            };
+            let on_rails = step.0 >= on_rails_threshold.0
+                && burn.is_none()
+                && trajectory.is_none_or(CurrentTrajectory::is_finished);
+            if let Some(arc) = influenced
+                .main_influencer
+                .filter(|_| on_rails)
+                .and_then(|host| {
+                    propagate_on_rails(
+                        (pos.0, speed.0),
+                        host,
+                        start,
+                        dt,
+                        &bodies,
+                        &hill_radii,
+                        &mapping.0,
+                    )
+                })
+            {
+                (pos.0, speed.0) = (arc.pos, arc.speed);
+                if let Some(crossing) = arc.crossing {
+                    apply_crossing(&mut influenced, crossing, &bodies, &mapping.0);
+                }
+                // The rest of the step is integrated numerically, which also finds the impact point if any
+                (start, dt) = (arc.end, start + dt - arc.end);
+                if dt <= 0. {
+                    let (influencers, masses): (Vec<_>, Vec<_>) = influenced
+                        .influencers
+                        .iter()
+                        .filter_map(|e| Some((*e, bodies.get(*e).ok()?.1 .0.mass)))
+                        .unzip();
+                    let coords = bodies_coordinates_at(
+                        influencers.into_iter(),
+                        &bodies,
+                        &mapping.0,
+                        arc.end,
+                    );
+                    acceleration.previous = acceleration.current;
+                    acceleration.current = get_acceleration(
+                        pos.0,
+                        coords.into_iter().zip(masses).map(|((p, _), m)| (p, m)),
+                    ) + perturbation_model(&influenced)
+                        .acceleration(pos.0, arc.end, &bodies, &mapping.0);
+                    return;
+                }
+            }
+            let model = perturbation_model(&influenced);
+            // Bodies whose sphere of influence the object may cross: its influencers (first) and the children of its
+            // main influencer
+            let children = influenced
+                .main_influencer
+                .and_then(|main| bodies.get(main).ok())
+                .into_iter()
+                .flat_map(|(_, BodyInfo(data))| {
+                    data.orbiting_bodies
+                        .iter()
+                        .filter_map(|id| mapping.0.get(id))
+                });
+            let (mut simulated, mut masses, mut radii, mut spheres) =
+                (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+            for (is_influencer, body) in influenced
+                .influencers
+                .iter()
+                .map(|e| (true, *e))
+                .chain(children.map(|e| (false, *e)))
+            {
+                // Bodies missing from the query are skipped so that all the lists stay aligned
+                let Ok((_, BodyInfo(data))) = bodies.get(body) else {
+                    continue;
+                };
+                if is_influencer {
+                    masses.push(data.mass);
+                }
+                simulated.push(body);
+                radii.push(data.radius);
+                spheres.push(hill_radii.get(body).map_or(f64::INFINITY, |r| r.0));
+            }
+            let mut states = BodyStates::new(simulated, &bodies, &mapping.0);
+            let coords = states.at(start);
+            let n = substeps(
+                pos.0,
+                speed.0,
+                dt,
+                coords.iter().zip(&masses).map(|((p, _), m)| (*p, *m)),
+                coords.iter().zip(&spheres).map(|((p, v), r)| (*p, *v, *r)),
+            );
+            let h = dt / n as f64;
+            let mut bodies_start: Vec<_> = coords.iter().map(|(p, _)| *p).collect();
+            acceleration.previous = acceleration.current;
+            for i in 0..n {
+                let substep_start = start + i as f64 * h;
+                let result = integrator.step(
+                    pos.0,
+                    speed.0,
+                    acceleration.current,
+                    substep_start,
+                    h,
+                    |p, t| {
+                        get_acceleration(
+                            p,
+                            states.at(t).iter().zip(&masses).map(|((c, _), m)| (*c, *m)),
+                        ) + model.acceleration(p, t, &bodies, &mapping.0)
+                            + burn.map_or(DVec3::ZERO, |b| b.acceleration_at(t))
+                    },
+                );
+                let bodies_end = states.at(substep_start + h);
+                let impact = first_impact(
+                    pos.0,
+                    result.pos,
+                    bodies_start
+                        .iter()
+                        .zip(bodies_end)
+                        .zip(&radii)
+                        .map(|((s, (e, _)), r)| (*s, *e, *r)),
+                );
+                if let Some((j, fraction, impact_pos)) = impact {
+                    let body = states.bodies()[j];
+                    // The ship stays at the impact point relative to the body until the end of the step
+                    pos.0 = states.at(start + dt)[j].0 + impact_pos;
+                    speed.0 = speed.0.lerp(result.speed, fraction);
+                    impacts.scope(|impacts| {
+                        impacts.push(ShipImpact {
+                            ship,
+                            body,
+                            simtick: ((substep_start + fraction * h) / GAMETIME_PER_SIMTICK).round()
+                                as u64,
+                        })
+                    });
+                    break;
+                }
+                bodies_start.clear();
+                bodies_start.extend(bodies_end.iter().map(|(p, _)| *p));
+                pos.0 = result.pos;
+                speed.0 = model.apply_drag(
+                    result.pos,
+                    result.speed,
+                    substep_start + h,
+                    h,
+                    &bodies,
+                    &mapping.0,
+                );
+                acceleration.current = result.acc;
+            }
+        },
+    );
+    let mut new_impacts: Vec<_> = impacts.drain::<Vec<_>>().collect();
+    new_impacts.sort_by_key(|impact| (impact.simtick, impact.ship));
+    impact_events.send_batch(new_impacts);
 }
 
-fn update_velocity(mut query: Query<(&mut Velocity, &Acceleration)>, step: Res<SimStepSize>) {
-    query.par_iter_mut().for_each(|(mut speed, acc)| {
-        speed.0 += get_dv(
-            acc.previous,
-            acc.current,
-            GAMETIME_PER_SIMTICK * step.0 as f64,
-        )
-    });
+/// Computes the number of sub-steps needed to integrate an object over `dt`, more of them being needed close to
+/// massive bodies and to the boundaries of spheres of influence
+pub fn substeps(
+    pos: DVec3,
+    speed: DVec3,
+    dt: f64,
+    influencers: impl Iterator<Item = (DVec3, f64)>,
+    spheres: impl Iterator<Item = (DVec3, DVec3, f64)>,
+) -> u64 {
+    let gradient: f64 = influencers
+        .map(|(body_pos, mass)| 2. * G * mass / (pos - body_pos).length().powi(3))
+        .sum();
+    let max_dt = spheres.fold(
+        GRADIENT_STEP_RATIO / gradient.sqrt(),
+        |max_dt, (body_pos, body_speed, radius)| {
+            let distance = ((pos - body_pos).length() - radius).abs();
+            max_dt.min(distance / (speed - body_speed).length())
+        },
+    );
+    ((dt / max_dt).ceil() as u64).clamp(1, MAX_SUBSTEPS)
 }
 
 /// Computes the acceleration from the object's position, and an iterator of the influencers' positions and masses
@@ -103,49 +331,110 @@ pub fn get_dv(previous_acc: DVec3, acc: DVec3, dt: f64) -> DVec3 {
 mod tests {
     use std::f64::consts::PI;
 
-    use bevy::app::FixedMain;
-
     use super::*;
 
-    use crate::{prelude::*, utils::algebra::circular_orbit_around_body};
+    use crate::{
+        client::testing::{paused_game, run_to_time, spawn_in_orbit, spawn_ship, start_action},
+        prelude::*,
+    };
+
+    /// The position of the ship relative to its body
+    fn relative_pos(app: &mut App, body: Entity) -> DVec3 {
+        let world = app.world_mut();
+        let pos = world
+            .query_filtered::<&Position, With<Influenced>>()
+            .single(world)
+            .0;
+        pos - world.query::<&Position>().get(world, body).unwrap().0
+    }
 
     #[test]
     fn test_leapfrog() {
-        let mut app = App::new();
-        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
-        app.update();
+        let mut app = paused_game(ClientPlugin::testing());
+        let (earth, period) = spawn_in_orbit(&mut app, "terre", 1e5);
+        let spawn_pos = relative_pos(&mut app, earth);
+        start_action(&mut app);
+        run_to_time(&mut app, period);
+        assert!((spawn_pos - relative_pos(&mut app, earth)).length() < 2e4);
+    }
+
+    /// Returns the position of a ship relative to the Earth after one period of a circular orbit
+    fn orbit_around_earth(integrator: Integrator) -> DVec3 {
+        let mut app = paused_game(ClientPlugin::testing());
+        app.insert_resource(integrator);
         let world = app.world_mut();
-        let mapping = &world.resource::<BodiesMapping>().0;
-        let earth = mapping[&id_from("terre")];
-        let (&mass, &spawn_earth_pos, &spawn_earth_speed) = world
+        let earth = world.resource::<BodiesMapping>().0[&id_from("terre")];
+        let (&mass, &earth_pos, &earth_speed) = world
             .query::<(&Mass, &Position, &Velocity)>()
             .get(world, earth)
             .unwrap();
-        let (spawn_pos, spawn_speed) =
-            circular_orbit_around_body(1e5, mass.0, spawn_earth_pos.0, spawn_earth_speed.0);
-        app.world_mut().send_event(ShipEvent::Create(ShipInfo {
-            id: id_from("s"),
-            spawn_pos,
-            spawn_speed,
-        }));
-        app.update();
+        // Always the same orbit, so that the integrators can be compared
+        let spawn_pos = earth_pos.0 + DVec3::new(1e5, 0., 0.);
+        let spawn_speed = earth_speed.0 + DVec3::new(0., (G * mass.0 / 1e5).sqrt(), 0.);
+        spawn_ship(&mut app, "s", spawn_pos, spawn_speed);
         let period = 2. * PI * (1e5_f64).powf(3. / 2.) / (G * mass.0).sqrt();
-        app.world_mut()
-            .resource_mut::<NextState<GameStage>>()
-            .set(GameStage::Action);
-        app.update();
-        while app.world().resource::<GameTime>().time() < period {
-            app.update();
-            FixedMain::run_fixed_main(app.world_mut());
+        start_action(&mut app);
+        run_to_time(&mut app, period);
+        relative_pos(&mut app, earth)
+    }
+
+    #[test]
+    fn test_higher_order_integrators() {
+        let reference = orbit_around_earth(Integrator::DormandPrince { tolerance: 1e-12 });
+        let leapfrog = (orbit_around_earth(Integrator::Leapfrog) - reference).length();
+        for integrator in [
+            Integrator::Yoshida4,
+            Integrator::RK4,
+            Integrator::DormandPrince { tolerance: 1e-9 },
+        ] {
+            let error = (orbit_around_earth(integrator) - reference).length();
+            assert!(error < leapfrog / 100., "{integrator:?}: {error} km");
         }
-        let world = app.world_mut();
-        let pos = world
-            .query_filtered::<&Position, With<Influenced>>()
-            .single(world)
-            .0;
-        let &earth_pos = world.query::<&Position>().get(world, earth).unwrap();
-        // dbg!(spawn_pos - spawn_earth_pos.0);
-        // dbg!(pos - earth_pos.0);
-        assert!(((spawn_pos - spawn_earth_pos.0) - (pos - earth_pos.0)).length() < 2e4);
+    }
+
+    #[test]
+    fn test_substeps() {
+        let mass = 7.342e22;
+        let speed = DVec3::new(0., 1e5, 0.);
+        let far = substeps(
+            DVec3::new(1e6, 0., 0.),
+            speed,
+            1e-3,
+            [(DVec3::ZERO, mass)].into_iter(),
+            [(DVec3::ZERO, DVec3::ZERO, 6e4)].into_iter(),
+        );
+        assert_eq!(far, 1);
+        let low_orbit = |dt| {
+            substeps(
+                DVec3::new(2e3, 0., 0.),
+                speed,
+                dt,
+                [(DVec3::ZERO, mass)].into_iter(),
+                [(DVec3::ZERO, DVec3::ZERO, 6e4)].into_iter(),
+            )
+        };
+        assert!(low_orbit(16e-3) > low_orbit(1e-3));
+        assert_eq!(low_orbit(16e-3), low_orbit(16e-3));
+        let near_boundary = substeps(
+            DVec3::new(6e4 - 10., 0., 0.),
+            speed,
+            1e-3,
+            [(DVec3::ZERO, mass)].into_iter(),
+            [(DVec3::ZERO, DVec3::ZERO, 6e4)].into_iter(),
+        );
+        assert!(near_boundary > 1);
+    }
+
+    #[test]
+    fn test_low_orbit_with_large_step() {
+        let mut app = paused_game(
+            ClientPlugin::testing().with_bodies(BodiesConfig::SmallestBodyType(BodyType::Moon)),
+        );
+        let (moon, period) = spawn_in_orbit(&mut app, "lune", 2e3);
+        app.world_mut().resource_mut::<SimStepSize>().0 = 16;
+        start_action(&mut app);
+        run_to_time(&mut app, 3. * period);
+        let altitude = relative_pos(&mut app, moon).length();
+        assert!((altitude - 2e3).abs() < 20., "{altitude} km");
     }
 }
@@ -0,0 +1,325 @@
+//! Optional N-body dynamics for celestial bodies, replacing their Keplerian orbits
+//! by their osculating orbits after each step.
+use std::{fmt::Display, iter::once};
+
+use bevy::{math::DVec3, prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::objects::prelude::*;
+
+use super::{
+    integrator::{Integrator, SystemState},
+    leapfrog::{get_acceleration, substeps},
+    orbit::{update_global, OrbitsUpdate},
+    prelude::*,
+    time::{SimStepSize, GAMETIME_PER_SIMTICK},
+    G,
+};
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<BodyDynamics>().add_systems(
+        FixedUpdate,
+        (
+            integrate_bodies
+                .before(update_global)
+                .in_set(OrbitsUpdate)
+                .run_if(resource_equals(BodyDynamics::NBody)),
+            clear_drift.run_if(
+                resource_equals(BodyDynamics::OnRails).and_then(resource_exists::<NBodyDrift>),
+            ),
+        ),
+    );
+}
+
+/// How the celestial bodies move
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BodyDynamics {
+    /// Bodies follow their Keplerian orbits around their host
+    #[default]
+    OnRails,
+    /// Bodies are integrated under the attraction of every other body
+    NBody,
+}
+
+/// Drift of the conserved quantities of the bodies since the N-body mode was switched on, which measures the
+/// accuracy of the integration
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct NBodyDrift {
+    /// The simtick at which the N-body mode was switched on
+    pub since: u64,
+    pub initial_energy: f64,
+    pub initial_momentum: DVec3,
+    /// Change of the total energy, relative to its initial value
+    pub energy: f64,
+    /// Norm of the change of the total angular momentum, relative to its initial norm
+    pub momentum: f64,
+}
+
+impl NBodyDrift {
+    fn new(since: u64, (energy, momentum): (f64, DVec3)) -> Self {
+        Self {
+            since,
+            initial_energy: energy,
+            initial_momentum: momentum,
+            energy: 0.,
+            momentum: 0.,
+        }
+    }
+
+    fn update(&mut self, (energy, momentum): (f64, DVec3)) {
+        self.energy = ((energy - self.initial_energy) / self.initial_energy).abs();
+        self.momentum =
+            (momentum - self.initial_momentum).length() / self.initial_momentum.length();
+    }
+}
+
+impl Display for NBodyDrift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Energy drift {:.3e}, angular momentum drift {:.3e} since simtick {}",
+            self.energy, self.momentum, self.since
+        )
+    }
+}
+
+/// Writes the acceleration of each body relative to the primary body, given the positions and masses of the bodies
+/// (relative to the primary body as well). Each pair of bodies is only computed once.
+pub fn relative_accelerations(
+    positions: &[DVec3],
+    masses: &[f64],
+    primary_mass: f64,
+    accelerations: &mut [DVec3],
+) {
+    // The primary body is pulled by all the other bodies
+    let indirect = get_acceleration(
+        DVec3::ZERO,
+        positions.iter().copied().zip(masses.iter().copied()),
+    );
+    for (acc, pos) in accelerations.iter_mut().zip(positions) {
+        *acc = get_acceleration(*pos, once((DVec3::ZERO, primary_mass))) - indirect;
+    }
+    for i in 0..positions.len() {
+        for j in i + 1..positions.len() {
+            let r = positions[j] - positions[i];
+            let factor = G / r.length().powi(3);
+            accelerations[i] += r * factor * masses[j];
+            accelerations[j] -= r * factor * masses[i];
+        }
+    }
+}
+
+/// Computes the total energy and angular momentum of a system of bodies, given their positions, velocities and
+/// masses relative to the primary body. Both are computed in the barycentric frame.
+pub fn conserved_quantities(primary_mass: f64, bodies: &[(DVec3, DVec3, f64)]) -> (f64, DVec3) {
+    let all: Vec<_> = once((DVec3::ZERO, DVec3::ZERO, primary_mass))
+        .chain(bodies.iter().copied())
+        .collect();
+    let total_mass: f64 = all.iter().map(|(_, _, m)| m).sum();
+    let (center, center_speed) = all.iter().fold((DVec3::ZERO, DVec3::ZERO), |(p, v), b| {
+        (p + b.0 * b.2 / total_mass, v + b.1 * b.2 / total_mass)
+    });
+    let mut energy = 0.;
+    let mut momentum = DVec3::ZERO;
+    for (i, &(pos, speed, mass)) in all.iter().enumerate() {
+        let (pos, speed) = (pos - center, speed - center_speed);
+        energy += 0.5 * mass * speed.length_squared();
+        momentum += mass * pos.cross(speed);
+        for &(other_pos, _, other_mass) in &all[i + 1..] {
+            energy -= G * mass * other_mass / (pos + center - other_pos).length();
+        }
+    }
+    (energy, momentum)
+}
+
+/// Advances all the bodies together by one step of the selected [Integrator]
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn integrate_bodies(
+    mut commands: Commands,
+    mut bodies: Query<
+        (
+            Entity,
+            &mut Position,
+            &mut Velocity,
+            &mut EllipticalOrbit,
+            &BodyInfo,
+        ),
+        Without<PrimaryBody>,
+    >,
+    primary: Query<&BodyInfo, With<PrimaryBody>>,
+    mapping: Res<BodiesMapping>,
+    integrator: Res<Integrator>,
+    step: Res<SimStepSize>,
+    time: Res<GameTime>,
+    drift: Option<ResMut<NBodyDrift>>,
+) {
+    let Ok(BodyInfo(primary_data)) = primary.get_single() else {
+        return;
+    };
+    let primary_mass = primary_data.mass;
+    let mut states: Vec<_> = bodies
+        .iter()
+        .map(|(e, pos, speed, _, BodyInfo(data))| (e, data.id, pos.0, speed.0, data.mass))
+        .collect();
+    // The order of the bodies does not change the result, but keep it deterministic anyway
+    states.sort_by_key(|(_, id, ..)| *id);
+    let masses: Vec<_> = states.iter().map(|s| s.4).collect();
+    let mut state = SystemState {
+        pos: states.iter().map(|s| s.2).collect(),
+        speed: states.iter().map(|s| s.3).collect(),
+        acc: vec![DVec3::ZERO; states.len()],
+    };
+    let system = |state: &SystemState| -> Vec<_> {
+        (0..masses.len())
+            .map(|i| (state.pos[i], state.speed[i], masses[i]))
+            .collect()
+    };
+    if drift.is_none() {
+        commands.insert_resource(NBodyDrift::new(
+            time.simtick.saturating_sub(step.0),
+            conserved_quantities(primary_mass, &system(&state)),
+        ));
+    }
+
+    let dt = GAMETIME_PER_SIMTICK * step.0 as f64;
+    let start = time.simtick.saturating_sub(step.0) as f64 * GAMETIME_PER_SIMTICK;
+    let n = (0..masses.len())
+        .map(|i| {
+            let others = state
+                .pos
+                .iter()
+                .zip(&masses)
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, (p, m))| (*p, *m));
+            substeps(
+                state.pos[i],
+                state.speed[i],
+                dt,
+                once((DVec3::ZERO, primary_mass)).chain(others),
+                std::iter::empty(),
+            )
+        })
+        .max()
+        .unwrap_or(1);
+    let h = dt / n as f64;
+    relative_accelerations(&state.pos, &masses, primary_mass, &mut state.acc);
+    for k in 0..n {
+        integrator.step_system(&mut state, start + k as f64 * h, h, |positions, _, acc| {
+            relative_accelerations(positions, &masses, primary_mass, acc)
+        });
+    }
+    if let Some(mut drift) = drift {
+        drift.update(conserved_quantities(primary_mass, &system(&state)));
+    }
+    let SystemState {
+        pos: positions,
+        speed: speeds,
+        ..
+    } = state;
+
+    let index: HashMap<_, _> = states
+        .iter()
+        .enumerate()
+        .map(|(i, (e, ..))| (*e, i))
+        .collect();
+    for (e, mut pos, mut speed, mut orbit, BodyInfo(data)) in bodies.iter_mut() {
+        let i = index[&e];
+        (pos.0, speed.0) = (positions[i], speeds[i]);
+        let (host_pos, host_speed, host_mass) = match data.host_body.and_then(|h| mapping.0.get(&h))
+        {
+            Some(host) => match index.get(host) {
+                Some(&j) => (positions[j], speeds[j], masses[j]),
+                None => (DVec3::ZERO, DVec3::ZERO, primary_mass),
+            },
+            None => continue,
+        };
+        // The relative motion of two bodies only depends on the sum of their masses
+        if let Some(osculating) = EllipticalOrbit::from_state_vectors(
+            positions[i] - host_pos,
+            speeds[i] - host_speed,
+            host_mass + masses[i],
+            time.time(),
+        ) {
+            *orbit = osculating;
+        }
+    }
+}
+
+fn clear_drift(mut commands: Commands) {
+    commands.remove_resource::<NBodyDrift>();
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::system::SystemState, math::DVec3, prelude::*};
+
+    use crate::{
+        client::testing::{paused_game, run_for, start_action},
+        physics::predictions::bodies_coordinates_at,
+        prelude::*,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_conserved_quantities() {
+        // Two bodies on circular orbits around their barycenter
+        let (m1, m2, d) = (3e24, 1e24, 1e5);
+        let speed = (G * (m1 + m2) / d).sqrt();
+        let (energy, momentum) = conserved_quantities(
+            m1,
+            &[(DVec3::new(d, 0., 0.), DVec3::new(0., speed, 0.), m2)],
+        );
+        let reduced_mass = m1 * m2 / (m1 + m2);
+        assert!((energy + G * m1 * m2 / (2. * d)).abs() < 1e-9 * energy.abs());
+        assert!(
+            (momentum - DVec3::Z * reduced_mass * d * speed).length() < 1e-9 * momentum.length()
+        );
+        // Both bodies fall towards each other with the sum of their masses
+        let mut acc = [DVec3::ZERO];
+        relative_accelerations(&[DVec3::new(d, 0., 0.)], &[m2], m1, &mut acc);
+        let [acc] = acc;
+        assert!((acc + DVec3::X * G * (m1 + m2) / (d * d)).length() < 1e-9 * acc.length());
+    }
+
+    #[test]
+    fn test_nbody_mode() {
+        let mut app = paused_game(ClientPlugin::testing());
+        app.insert_resource(BodyDynamics::NBody);
+        app.world_mut().resource_mut::<SimStepSize>().0 = 16;
+        start_action(&mut app);
+        run_for(&mut app, 200);
+        let world = app.world_mut();
+        let drift = *world.resource::<NBodyDrift>();
+        assert!(drift.energy < 1e-7, "{drift}");
+        assert!(drift.momentum < 1e-7, "{drift}");
+
+        let mapping = world.resource::<BodiesMapping>().0.clone();
+        let earth = mapping[&id_from("terre")];
+        let time = world.resource::<GameTime>().time();
+        let pos = world.query::<&Position>().get(world, earth).unwrap().0;
+        let data = world
+            .query::<&BodyInfo>()
+            .get(world, earth)
+            .unwrap()
+            .0
+            .clone();
+        // The Earth stays close to its Keplerian orbit over a few days
+        let mut on_rails = EllipticalOrbit::from(&data);
+        on_rails.update_pos(time);
+        assert!((pos - on_rails.local_pos).length() < 1e-4 * pos.length());
+        #[allow(clippy::type_complexity)]
+        let mut system_state: SystemState<Query<(&EllipticalOrbit, &BodyInfo)>> =
+            SystemState::new(world);
+        let bodies = system_state.get(world);
+        // Positions computed from the orbits follow the integrated bodies
+        let (from_orbits, _) =
+            bodies_coordinates_at([earth].into_iter(), &bodies, &mapping, time)[0];
+        assert!((pos - from_orbits).length() < 1e-7 * pos.length());
+
+        world.insert_resource(BodyDynamics::OnRails);
+        run_for(&mut app, 1);
+        assert!(app.world().get_resource::<NBodyDrift>().is_none());
+    }
+}
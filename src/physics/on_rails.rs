@@ -0,0 +1,301 @@
+//! Analytic propagation of ships during high time warp, until they cross a
+//! sphere of influence or the surface of their host.
+use bevy::{math::DVec3, prelude::*, utils::HashMap};
+
+use crate::objects::prelude::*;
+
+use super::{
+    influence::{HillRadius, Influenced},
+    orbit::{ConicType, EllipticalOrbit},
+    predictions::bodies_coordinates_at,
+    time::SimStepSize,
+};
+
+/// Number of bisections used to find the time of a crossing
+const CROSSING_ITERATIONS: usize = 60;
+/// Maximum fraction of a revolution between two checks of the boundaries
+const MAX_REVOLUTION_FRACTION: f64 = 1. / 32.;
+/// Maximum number of boundary checks during a step
+const MAX_CHECKS: usize = 4096;
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<OnRailsThreshold>();
+}
+
+/// Step size (in simticks) from which ships are propagated on rails
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OnRailsThreshold(pub u64);
+
+impl Default for OnRailsThreshold {
+    fn default() -> Self {
+        Self(64)
+    }
+}
+
+pub fn steps_on_rails(step: Res<SimStepSize>, threshold: Res<OnRailsThreshold>) -> bool {
+    step.0 >= threshold.0
+}
+
+/// A boundary crossed by a ship on rails, where the propagation stops
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Crossing {
+    /// Leaves the sphere of influence of its host
+    Exit,
+    /// Enters the sphere of influence of a body orbiting its host
+    Entry(Entity),
+    /// Hits the surface of its host, or the top of its atmosphere where the drag has to be integrated
+    Surface,
+}
+
+/// The end of a Kepler arc followed by a ship
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeplerArc {
+    pub pos: DVec3,
+    pub speed: DVec3,
+    /// Game time at which the arc ends
+    pub end: f64,
+    pub crossing: Option<Crossing>,
+}
+
+/// Propagates a ship on its Kepler orbit around `host` from `start` during at most `dt`, stopping at the first
+/// boundary crossing. Returns `None` if the orbit is degenerate or if the ship is outside the sphere of influence
+pub fn propagate_on_rails(
+    (pos, speed): (DVec3, DVec3),
+    host: Entity,
+    start: f64,
+    dt: f64,
+    bodies: &Query<(&EllipticalOrbit, &BodyInfo)>,
+    hill_radii: &Query<&HillRadius>,
+    mapping: &HashMap<BodyID, Entity>,
+) -> Option<KeplerArc> {
+    let BodyInfo(host_data) = bodies.get(host).ok()?.1;
+    let host_hill = hill_radii.get(host).map_or(f64::INFINITY, |r| r.0);
+    let surface = host_data.radius + host_data.atmosphere.map_or(0., |a| a.height());
+    let children: Vec<_> = host_data
+        .orbiting_bodies
+        .iter()
+        .filter_map(|id| mapping.get(id))
+        .filter_map(|e| Some((*e, hill_radii.get(*e).ok()?.0)))
+        .collect();
+    let (host_pos, host_speed) =
+        bodies_coordinates_at([host].into_iter(), bodies, mapping, start)[0];
+    let orbit = EllipticalOrbit::from_state_vectors(
+        pos - host_pos,
+        speed - host_speed,
+        host_data.mass,
+        start,
+    )?;
+    let max_check = match orbit.conic_type() {
+        ConicType::Elliptic => MAX_REVOLUTION_FRACTION * orbit.revolution_period.abs(),
+        _ => dt,
+    }
+    .max(dt / MAX_CHECKS as f64);
+
+    // The global state of the ship at a given time, and how long it takes at least to cross a boundary
+    let check = |time: f64| {
+        let (rel_pos, rel_speed) = orbit.state_vectors(time);
+        let coords = bodies_coordinates_at(
+            [host]
+                .into_iter()
+                .chain(children.iter().map(|(child, _)| *child)),
+            bodies,
+            mapping,
+            time,
+        );
+        let (global_pos, global_speed) = (coords[0].0 + rel_pos, coords[0].1 + rel_speed);
+        let (distance, speed) = (rel_pos.length(), rel_speed.length());
+        let margins = [
+            ((host_hill - distance) / speed, Crossing::Exit),
+            ((distance - surface) / speed, Crossing::Surface),
+        ]
+        .into_iter()
+        .chain(children.iter().zip(&coords[1..]).map(
+            |((child, hill), (child_pos, child_speed))| {
+                (
+                    ((global_pos - *child_pos).length() - hill)
+                        / (global_speed - *child_speed).length(),
+                    Crossing::Entry(*child),
+                )
+            },
+        ));
+        let margin = margins.min_by(|a, b| a.0.total_cmp(&b.0)).unwrap();
+        (global_pos, global_speed, margin)
+    };
+
+    let end = start + dt;
+    let (mut previous, mut time) = (start, start);
+    loop {
+        let (pos, speed, (margin, _)) = check(time);
+        if margin <= 0. {
+            if time == start {
+                return None;
+            }
+            // The first crossing happens between the previous check and this one
+            let (mut low, mut high) = (previous, time);
+            for _ in 0..CROSSING_ITERATIONS {
+                let mid = (low + high) / 2.;
+                if check(mid).2 .0 <= 0. {
+                    high = mid;
+                } else {
+                    low = mid;
+                }
+            }
+            let (pos, speed, (_, crossing)) = check(high);
+            return Some(KeplerArc {
+                pos,
+                speed,
+                end: high,
+                crossing: Some(crossing),
+            });
+        }
+        if time >= end {
+            return Some(KeplerArc {
+                pos,
+                speed,
+                end,
+                crossing: None,
+            });
+        }
+        previous = time;
+        time = (time + margin.clamp(dt / MAX_CHECKS as f64, max_check)).min(end);
+    }
+}
+
+/// Switches the influence of a ship at the boundary it crossed
+pub fn apply_crossing(
+    influenced: &mut Influenced,
+    crossing: Crossing,
+    bodies: &Query<(&EllipticalOrbit, &BodyInfo)>,
+    mapping: &HashMap<BodyID, Entity>,
+) {
+    let Some(host) = influenced.main_influencer else {
+        return;
+    };
+    match crossing {
+        Crossing::Exit => {
+            influenced.influencers.retain(|e| *e != host);
+            influenced.main_influencer = bodies
+                .get(host)
+                .ok()
+                .and_then(|(_, BodyInfo(data))| data.host_body)
+                .and_then(|id| mapping.get(&id))
+                .copied();
+            if let Some(parent) = influenced.main_influencer {
+                if !influenced.influencers.contains(&parent) {
+                    influenced.influencers.push(parent);
+                }
+            }
+        }
+        Crossing::Entry(body) => {
+            influenced.influencers.push(body);
+            influenced.main_influencer = Some(body);
+        }
+        Crossing::Surface => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::SystemState;
+
+    use crate::{
+        client::testing::{paused_game, run_to_time, spawn_in_orbit, spawn_ship, start_action},
+        physics::time::{SimStepSize, GAMETIME_PER_SIMTICK},
+        prelude::*,
+        utils::algebra::circular_orbit_around_body,
+    };
+
+    use super::*;
+
+    fn run(app: &mut App, step: u64, duration: f64) {
+        app.world_mut().resource_mut::<SimStepSize>().0 = step;
+        start_action(app);
+        run_to_time(app, duration);
+    }
+
+    #[test]
+    fn test_circular_orbit_on_rails() {
+        let mut app = paused_game(ClientPlugin::testing());
+        let (earth, period) = spawn_in_orbit(&mut app, "terre", 1e4);
+        // Steps last several revolutions, which the leapfrog could not integrate
+        let step = 1024;
+        assert!(step as f64 * GAMETIME_PER_SIMTICK > 3. * period);
+        run(&mut app, step, 100.);
+        let world = app.world_mut();
+        let pos = world
+            .query_filtered::<&Position, With<Influenced>>()
+            .single(world)
+            .0;
+        let earth_pos = world.query::<&Position>().get(world, earth).unwrap().0;
+        let altitude = (pos - earth_pos).length();
+        assert!((altitude - 1e4).abs() < 1., "{altitude} km");
+    }
+
+    #[test]
+    fn test_soi_exit() {
+        let mut app = paused_game(ClientPlugin::testing());
+        let world = app.world_mut();
+        let mapping = world.resource::<BodiesMapping>().0.clone();
+        let (earth, sun) = (mapping[&id_from("terre")], mapping[&id_from("soleil")]);
+        let (&mass, &earth_pos, &earth_speed) = world
+            .query::<(&Mass, &Position, &Velocity)>()
+            .get(world, earth)
+            .unwrap();
+        let hill = world.query::<&HillRadius>().get(world, earth).unwrap().0;
+        // Escape trajectory, leaving the sphere of influence of the Earth after a few days
+        let (spawn_pos, spawn_speed) =
+            circular_orbit_around_body(1e5, mass.0, earth_pos.0, earth_speed.0);
+        let spawn_speed = earth_speed.0 + (spawn_speed - earth_speed.0) * 3.;
+        spawn_ship(&mut app, "s", spawn_pos, spawn_speed);
+
+        let world = app.world_mut();
+        #[allow(clippy::type_complexity)]
+        let mut system_state: SystemState<(
+            Query<(&EllipticalOrbit, &BodyInfo)>,
+            Query<&HillRadius>,
+        )> = SystemState::new(world);
+        let (bodies, hill_radii) = system_state.get(world);
+        let arc = propagate_on_rails(
+            (spawn_pos, spawn_speed),
+            earth,
+            0.,
+            100.,
+            &bodies,
+            &hill_radii,
+            &mapping,
+        )
+        .unwrap();
+        assert_eq!(arc.crossing, Some(Crossing::Exit));
+        let (earth_end, _) =
+            bodies_coordinates_at([earth].into_iter(), &bodies, &mapping, arc.end)[0];
+        assert!(((arc.pos - earth_end).length() - hill).abs() < 1e-6 * hill);
+
+        run(&mut app, 1024, arc.end + 2.);
+        let world = app.world_mut();
+        let (&pos, influence) = world.query::<(&Position, &Influenced)>().single(world);
+        assert_eq!(influence.main_influencer, Some(sun));
+        let earth_pos = world.query::<&Position>().get(world, earth).unwrap().0;
+        assert!((pos.0 - earth_pos).length() > hill);
+    }
+
+    #[test]
+    fn test_soi_exit_into_grandparent() {
+        let mut app = paused_game(
+            ClientPlugin::testing().with_bodies(BodiesConfig::SmallestBodyType(BodyType::Moon)),
+        );
+        let world = app.world_mut();
+        let mapping = world.resource::<BodiesMapping>().0.clone();
+        let (moon, earth) = (mapping[&id_from("lune")], mapping[&id_from("terre")]);
+        let mut system_state: SystemState<Query<(&EllipticalOrbit, &BodyInfo)>> =
+            SystemState::new(world);
+        let bodies = system_state.get(world);
+        // The Earth is missing from the influencers of a ship that only knew about the Moon
+        let mut influenced = Influenced {
+            main_influencer: Some(moon),
+            influencers: vec![moon],
+        };
+        apply_crossing(&mut influenced, Crossing::Exit, &bodies, &mapping);
+        assert_eq!(influenced.main_influencer, Some(earth));
+        assert_eq!(influenced.influencers, vec![earth]);
+    }
+}
@@ -7,12 +7,17 @@ use bevy::{
 
 use crate::{
     game::Loaded,
-    objects::prelude::*,
+    objects::{prelude::*, ObjectsUpdate},
     physics::prelude::*,
     utils::algebra::{mod_180, rotate},
 };
 
-use super::time::GameTime;
+use super::{
+    leapfrog::LeapfrogUpdate,
+    nbody::BodyDynamics,
+    time::{GameTime, TickEvent},
+    PhysicsUpdate, G,
+};
 
 pub fn plugin(app: &mut App) {
     app.add_systems(
@@ -23,17 +28,44 @@ pub fn plugin(app: &mut App) {
     )
     .add_systems(
         FixedUpdate,
-        (update_local, update_global).chain().in_set(OrbitsUpdate),
+        (
+            (update_local, update_global)
+                .chain()
+                .in_set(OrbitsUpdate)
+                .run_if(resource_equals(BodyDynamics::OnRails)),
+            update_osculating_orbits
+                .after(LeapfrogUpdate)
+                .in_set(PhysicsUpdate)
+                .run_if(on_event::<TickEvent>()),
+        ),
+    )
+    .add_systems(
+        Update,
+        insert_osculating_orbits
+            .after(ObjectsUpdate)
+            .run_if(in_state(Loaded)),
     );
 }
 
 #[derive(SystemSet, Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub struct OrbitsUpdate;
 
+/// The kind of conic section followed by an orbit, determined by its eccentricity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConicType {
+    Elliptic,
+    Parabolic,
+    Hyperbolic,
+}
+
+/// Keplerian orbit around a host body. Despite its name, this also supports open orbits (with a negative
+/// semimajor axis for hyperbolic ones)
 #[derive(Component, Default, Clone, Debug)]
 pub struct EllipticalOrbit {
     pub eccentricity: f64,
     pub semimajor_axis: f64,
+    /// Distance of closest approach to the host body (in kilometers)
+    pub periapsis: f64,
     pub inclination: f64,
     pub long_asc_node: f64,
     pub arg_periapsis: f64,
@@ -41,6 +73,8 @@ pub struct EllipticalOrbit {
     pub revolution_period: f64,
 
     pub mean_anomaly: f64,
+    /// Eccentric anomaly for elliptic orbits, hyperbolic anomaly for hyperbolic orbits
+    /// and true anomaly for parabolic orbits (in degrees)
     pub eccentric_anomaly: f64,
     /// 2D position in the orbital plane around the host body
     pub orbital_position: DVec2,
@@ -52,18 +86,45 @@ pub struct EllipticalOrbit {
 }
 
 const E_TOLERANCE: f64 = 1e-6;
+/// Eccentricities in `[1, 1 + PARABOLIC_TOLERANCE)` are treated as parabolic
+const PARABOLIC_TOLERANCE: f64 = 1e-9;
+const H_MAX_ITERATIONS: usize = 50;
+/// Relative threshold under which a vector is considered null when computing orbital elements
+const DEGENERACY_TOLERANCE: f64 = 1e-12;
 // see https://ssd.jpl.nasa.gov/planets/approx_pos.html
 #[allow(non_snake_case)]
 impl EllipticalOrbit {
+    pub fn conic_type(&self) -> ConicType {
+        let e = self.eccentricity;
+        if e < 1. {
+            ConicType::Elliptic
+        } else if e < 1. + PARABOLIC_TOLERANCE {
+            ConicType::Parabolic
+        } else {
+            ConicType::Hyperbolic
+        }
+    }
+
     fn update_M(&mut self, time: f64) {
         if self.revolution_period == 0. {
             return;
         }
-        self.mean_anomaly =
-            mod_180(self.initial_mean_anomaly + 360. * time / self.revolution_period);
+        let M = self.initial_mean_anomaly + 360. * time / self.revolution_period;
+        // The mean anomaly of an open orbit is not periodic
+        self.mean_anomaly = match self.conic_type() {
+            ConicType::Elliptic => mod_180(M),
+            _ => M,
+        };
     }
     fn update_E(&mut self, time: f64) {
         self.update_M(time);
+        match self.conic_type() {
+            ConicType::Elliptic => self.solve_elliptic(),
+            ConicType::Parabolic => self.solve_parabolic(),
+            ConicType::Hyperbolic => self.solve_hyperbolic(),
+        }
+    }
+    fn solve_elliptic(&mut self) {
         let M = self.mean_anomaly;
         let e = self.eccentricity;
         let ed = e.to_degrees();
@@ -81,8 +142,36 @@ impl EllipticalOrbit {
         }
         self.eccentric_anomaly = E;
     }
+    /// Solves Barker's equation M = D + D^3 / 3 where D = tan(nu / 2)
+    fn solve_parabolic(&mut self) {
+        let A = 1.5 * self.mean_anomaly.to_radians();
+        let B = (A + (A * A + 1.).sqrt()).cbrt();
+        let D = B - 1. / B;
+        self.eccentric_anomaly = (2. * D.atan()).to_degrees();
+    }
+    /// Solves the hyperbolic Kepler equation M = e sinh(H) - H using Newton's method
+    fn solve_hyperbolic(&mut self) {
+        let M = self.mean_anomaly.to_radians();
+        let e = self.eccentricity;
+        let mut H = (M / e).asinh();
+        for _ in 0..H_MAX_ITERATIONS {
+            let dH = (M - (e * H.sinh() - H)) / (e * H.cosh() - 1.);
+            H += dH;
+            if dH.abs().to_degrees() <= E_TOLERANCE {
+                break;
+            }
+        }
+        self.eccentric_anomaly = H.to_degrees();
+    }
     fn update_orb_pos(&mut self, time: f64) {
         self.update_E(time);
+        match self.conic_type() {
+            ConicType::Elliptic => self.update_elliptic_orb_pos(),
+            ConicType::Parabolic => self.update_parabolic_orb_pos(),
+            ConicType::Hyperbolic => self.update_hyperbolic_orb_pos(),
+        }
+    }
+    fn update_elliptic_orb_pos(&mut self) {
         let a = self.semimajor_axis;
         let E = self.eccentric_anomaly.to_radians();
         let e = self.eccentricity;
@@ -98,6 +187,30 @@ impl EllipticalOrbit {
         let Qdot = a * (E.cos()) * Edot * (1. - e * e).sqrt();
         self.orbital_velocity = DVec2::new(Pdot, Qdot);
     }
+    fn update_parabolic_orb_pos(&mut self) {
+        let q = self.periapsis;
+        let D = (self.eccentric_anomaly.to_radians() / 2.).tan();
+        self.orbital_position = DVec2::new(q * (1. - D * D), 2. * q * D);
+        if self.revolution_period == 0. {
+            return;
+        }
+        let Mdot = 2. * PI / self.revolution_period;
+        let Ddot = Mdot / (1. + D * D);
+        self.orbital_velocity = DVec2::new(-2. * q * D * Ddot, 2. * q * Ddot);
+    }
+    fn update_hyperbolic_orb_pos(&mut self) {
+        let a = self.semimajor_axis.abs();
+        let H = self.eccentric_anomaly.to_radians();
+        let e = self.eccentricity;
+        let b = a * (e * e - 1.).sqrt();
+        self.orbital_position = DVec2::new(a * (e - H.cosh()), b * H.sinh());
+        if self.revolution_period == 0. {
+            return;
+        }
+        let Mdot = 2. * PI / self.revolution_period;
+        let Hdot = Mdot / (e * H.cosh() - 1.);
+        self.orbital_velocity = DVec2::new(-a * H.sinh() * Hdot, b * H.cosh() * Hdot);
+    }
 
     pub fn update_pos(&mut self, time: f64) {
         self.update_orb_pos(time);
@@ -107,13 +220,118 @@ impl EllipticalOrbit {
         self.local_pos = rotate(self.orbital_position, o, O, I);
         self.local_speed = rotate(self.orbital_velocity, o, O, I);
     }
+
+    /// Computes the orbital elements of an object from its position and velocity relative to its host body at a given time.
+    ///
+    /// Returns `None` if the trajectory is degenerate (radial, with no angular momentum).
+    /// For circular orbits the argument of periapsis is zero, and for equatorial orbits so is the longitude of the ascending node.
+    pub fn from_state_vectors(
+        relative_pos: DVec3,
+        relative_speed: DVec3,
+        host_mass: f64,
+        time: f64,
+    ) -> Option<Self> {
+        let mu = G * host_mass;
+        let (r, v) = (relative_pos, relative_speed);
+        let h = r.cross(v);
+        if h.length() <= DEGENERACY_TOLERANCE * r.length() * v.length() || mu <= 0. {
+            return None;
+        }
+        let normal = h.normalize();
+        let node = DVec3::Z.cross(h);
+        let eccentricity_vector = ((v.length_squared() - mu / r.length()) * r - r.dot(v) * v) / mu;
+        let e = eccentricity_vector.length();
+
+        let inclination = normal.z.clamp(-1., 1.).acos();
+        let long_asc_node = if node.length() > DEGENERACY_TOLERANCE * h.length() {
+            node.y.atan2(node.x)
+        } else {
+            0.
+        };
+        let node_direction = DVec3::new(long_asc_node.cos(), long_asc_node.sin(), 0.);
+        // Signed angle from u to w in the orbital plane, in the direction of motion
+        let angle = |u: DVec3, w: DVec3| normal.dot(u.cross(w)).atan2(u.dot(w));
+        let periapsis_direction = if e > DEGENERACY_TOLERANCE {
+            eccentricity_vector / e
+        } else {
+            node_direction
+        };
+        let arg_periapsis = angle(node_direction, periapsis_direction);
+        let true_anomaly = angle(periapsis_direction, r);
+
+        let mut orbit = Self {
+            eccentricity: e,
+            semimajor_axis: -mu / (v.length_squared() - 2. * mu / r.length()),
+            periapsis: h.length_squared() / (mu * (1. + e)),
+            inclination: inclination.to_degrees(),
+            long_asc_node: long_asc_node.to_degrees(),
+            arg_periapsis: arg_periapsis.to_degrees(),
+            ..Default::default()
+        };
+        let (mean_anomaly, revolution_period) = match orbit.conic_type() {
+            ConicType::Elliptic => {
+                let a = orbit.semimajor_axis;
+                let (sin, cos) = (true_anomaly / 2.).sin_cos();
+                let E = 2. * ((1. - e).sqrt() * sin).atan2((1. + e).sqrt() * cos);
+                (E - e * E.sin(), 2. * PI * (a.powi(3) / mu).sqrt())
+            }
+            ConicType::Parabolic => {
+                let D = (true_anomaly / 2.).tan();
+                (
+                    D + D.powi(3) / 3.,
+                    2. * PI * (2. * orbit.periapsis.powi(3) / mu).sqrt(),
+                )
+            }
+            ConicType::Hyperbolic => {
+                let a = orbit.semimajor_axis.abs();
+                let H = 2. * (((e - 1.) / (e + 1.)).sqrt() * (true_anomaly / 2.).tan()).atanh();
+                (e * H.sinh() - H, 2. * PI * (a.powi(3) / mu).sqrt())
+            }
+        };
+        let initial_mean_anomaly = mean_anomaly.to_degrees() - 360. * time / revolution_period;
+        orbit.initial_mean_anomaly = match orbit.conic_type() {
+            ConicType::Elliptic => mod_180(initial_mean_anomaly),
+            _ => initial_mean_anomaly,
+        };
+        orbit.revolution_period = revolution_period;
+        orbit.update_pos(time);
+        Some(orbit)
+    }
+
+    /// Position and velocity relative to the host body at a given time (inverse of [EllipticalOrbit::from_state_vectors])
+    pub fn state_vectors(&self, time: f64) -> (DVec3, DVec3) {
+        let mut orbit = self.clone();
+        orbit.update_pos(time);
+        (orbit.local_pos, orbit.local_speed)
+    }
+
+    /// Farthest distance from the host body, infinite for open orbits
+    pub fn apoapsis(&self) -> f64 {
+        match self.conic_type() {
+            ConicType::Elliptic => self.semimajor_axis * (1. + self.eccentricity),
+            _ => f64::INFINITY,
+        }
+    }
 }
 
 impl From<&BodyData> for EllipticalOrbit {
     fn from(data: &BodyData) -> Self {
+        let e = data.eccentricity;
+        // Hyperbolic orbits have a negative semimajor axis
+        let semimajor_axis = if e > 1. {
+            -data.semimajor_axis.abs()
+        } else {
+            data.semimajor_axis
+        };
+        let periapsis = if data.periapsis > 0. {
+            data.periapsis
+        } else {
+            semimajor_axis * (1. - e)
+        };
         Self {
-            eccentricity: data.eccentricity,
-            semimajor_axis: data.semimajor_axis,
+            eccentricity: e,
+            semimajor_axis,
+            periapsis,
             inclination: data.inclination,
             long_asc_node: data.long_asc_node,
             arg_periapsis: data.arg_periapsis,
@@ -153,6 +371,57 @@ pub fn update_global(
     }
 }
 
+/// The Keplerian orbit that a ship would follow around its main influencer if no other force acted on it
+#[derive(Component, Clone, Debug)]
+pub struct OsculatingOrbit {
+    pub host: Entity,
+    pub orbit: EllipticalOrbit,
+}
+
+impl OsculatingOrbit {
+    pub fn new(
+        pos: DVec3,
+        speed: DVec3,
+        influence: &Influenced,
+        bodies: &Query<(&Position, &Velocity, &Mass)>,
+        time: f64,
+    ) -> Option<Self> {
+        let host = influence.main_influencer?;
+        let (Position(host_pos), Velocity(host_speed), Mass(host_mass)) = bodies.get(host).ok()?;
+        EllipticalOrbit::from_state_vectors(pos - *host_pos, speed - *host_speed, *host_mass, time)
+            .map(|orbit| Self { host, orbit })
+    }
+}
+
+fn insert_osculating_orbits(
+    mut commands: Commands,
+    ships: Query<(Entity, &Position, &Velocity, &Influenced), Without<OsculatingOrbit>>,
+    bodies: Query<(&Position, &Velocity, &Mass)>,
+    time: Res<GameTime>,
+) {
+    for (e, pos, speed, influence) in ships.iter() {
+        if let Some(orbit) = OsculatingOrbit::new(pos.0, speed.0, influence, &bodies, time.time()) {
+            commands.entity(e).insert(orbit);
+        }
+    }
+}
+
+pub fn update_osculating_orbits(
+    mut ships: Query<(&Position, &Velocity, &Influenced, &mut OsculatingOrbit)>,
+    bodies: Query<(&Position, &Velocity, &Mass)>,
+    time: Res<GameTime>,
+) {
+    ships
+        .par_iter_mut()
+        .for_each(|(pos, speed, influence, mut orbit)| {
+            if let Some(new_orbit) =
+                OsculatingOrbit::new(pos.0, speed.0, influence, &bodies, time.time())
+            {
+                *orbit = new_orbit;
+            }
+        });
+}
+
 #[derive(Resource)]
 pub struct SystemSize(pub f64);
 
@@ -167,9 +436,141 @@ pub fn insert_system_size(mut commands: Commands, body_positions: Query<&Positio
 
 #[cfg(test)]
 mod tests {
-    use bevy::app::App;
+    use std::f64::consts::PI;
+
+    use bevy::{app::App, math::DVec3};
+
+    use crate::{physics::G, prelude::*, utils::algebra::circular_orbit_around_body};
+
+    use super::ConicType;
+
+    const EARTH_MASS: f64 = 5.97237e24;
+
+    fn open_orbit(eccentricity: f64, periapsis: f64) -> EllipticalOrbit {
+        let mu = G * EARTH_MASS;
+        let (semimajor_axis, revolution_period) = if eccentricity > 1. {
+            let a = periapsis / (1. - eccentricity);
+            (a, 2. * PI * (a.abs().powi(3) / mu).sqrt())
+        } else {
+            (0., 2. * PI * (2. * periapsis.powi(3) / mu).sqrt())
+        };
+        EllipticalOrbit {
+            eccentricity,
+            semimajor_axis,
+            periapsis,
+            inclination: 10.,
+            long_asc_node: 20.,
+            arg_periapsis: 30.,
+            revolution_period,
+            ..Default::default()
+        }
+    }
+
+    /// Checks the vis-viva equation and the conservation of angular momentum along the orbit
+    fn check_open_orbit(mut orbit: EllipticalOrbit, expected: ConicType) {
+        let mu = G * EARTH_MASS;
+        assert_eq!(orbit.conic_type(), expected);
+        orbit.update_pos(0.);
+        assert!((orbit.local_pos.length() - orbit.periapsis).abs() < 1e-6 * orbit.periapsis);
+        let momentum = orbit.local_pos.cross(orbit.local_speed).length();
+        for time in [-3., -0.5, 0.1, 1., 10.] {
+            orbit.update_pos(time);
+            let r = orbit.local_pos.length();
+            let v2 = orbit.local_speed.length_squared();
+            let expected_v2 = match expected {
+                ConicType::Parabolic => 2. * mu / r,
+                _ => mu * (2. / r - 1. / orbit.semimajor_axis),
+            };
+            assert!((v2 - expected_v2).abs() < 1e-6 * expected_v2);
+            let h = orbit.local_pos.cross(orbit.local_speed).length();
+            assert!((h - momentum).abs() < 1e-6 * momentum);
+        }
+    }
+
+    #[test]
+    fn test_hyperbolic_orbit() {
+        check_open_orbit(open_orbit(1.8, 1e4), ConicType::Hyperbolic);
+    }
+
+    #[test]
+    fn test_parabolic_orbit() {
+        check_open_orbit(open_orbit(1., 1e4), ConicType::Parabolic);
+    }
+
+    #[test]
+    fn test_state_vectors_round_trip() {
+        let mu = G * EARTH_MASS;
+        let circular_speed = (mu / 1e4).sqrt();
+        let states = [
+            // Inclined elliptic orbit
+            (
+                DVec3::new(1e4, 2e3, -3e3),
+                DVec3::new(-1e4, 1.1 * circular_speed, 2e4),
+            ),
+            // Circular equatorial orbit
+            (DVec3::new(1e4, 0., 0.), DVec3::new(0., circular_speed, 0.)),
+            // Retrograde equatorial orbit
+            (
+                DVec3::new(0., 1e4, 0.),
+                DVec3::new(1.2 * circular_speed, 0., 0.),
+            ),
+            // Hyperbolic orbit
+            (
+                DVec3::new(1e4, 1e3, 1e3),
+                DVec3::new(1e4, 2. * circular_speed, 0.),
+            ),
+        ];
+        for (pos, speed) in states {
+            let orbit = EllipticalOrbit::from_state_vectors(pos, speed, EARTH_MASS, 2.5).unwrap();
+            let (new_pos, new_speed) = orbit.state_vectors(2.5);
+            assert!((new_pos - pos).length() < 1e-6 * pos.length());
+            assert!((new_speed - speed).length() < 1e-6 * speed.length());
+        }
+    }
+
+    #[test]
+    fn test_osculating_orbit() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        app.update();
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0[&id_from("terre")];
+        let (&mass, &earth_pos, &earth_speed) = world
+            .query::<(&Mass, &Position, &Velocity)>()
+            .get(world, earth)
+            .unwrap();
+        let (spawn_pos, spawn_speed) =
+            circular_orbit_around_body(1e5, mass.0, earth_pos.0, earth_speed.0);
+        world.send_event(ShipEvent::Create(ShipInfo {
+            id: id_from("s"),
+            spawn_pos,
+            spawn_speed,
+        }));
+        app.update();
+        app.update();
+        let world = app.world_mut();
+        let OsculatingOrbit { host, orbit } = world.query::<&OsculatingOrbit>().single(world);
+        assert_eq!(*host, earth);
+        assert!(orbit.eccentricity < 1e-6);
+        assert!((orbit.semimajor_axis - 1e5).abs() < 1e-3);
+        assert!((orbit.apoapsis() - orbit.periapsis).abs() < 1e-3);
+    }
 
-    use crate::prelude::*;
+    #[test]
+    fn test_load_comets() {
+        let mut app = App::new();
+        app.add_plugins(
+            ClientPlugin::testing()
+                .in_mode(ClientMode::Explorer)
+                .with_bodies(BodiesConfig::SmallestBodyType(BodyType::Comet)),
+        );
+        app.update();
+        let world = app.world_mut();
+        assert!(world
+            .query::<(&Position, &Velocity)>()
+            .iter(world)
+            .all(|(p, v)| p.0.is_finite() && v.0.is_finite()));
+    }
 
     #[test]
     fn test_update_local() {
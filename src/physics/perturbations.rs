@@ -0,0 +1,431 @@
+//! Forces acting on ships besides the point-mass attraction of their influencers,
+//! applied the same way by the live physics and by the predictions.
+use bevy::{math::DVec3, prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::objects::{bodies::body_data::Atmosphere, prelude::*, ships::propulsion::ShipMass};
+
+use super::{
+    leapfrog::get_acceleration, orbit::EllipticalOrbit, predictions::bodies_coordinates_at,
+    rotation::BodyRotation, G, SECONDS_PER_DAY,
+};
+
+/// Radiation pressure of the Sun on an absorbing surface at one astronomical unit (in N/m²)
+pub const SOLAR_PRESSURE_AT_1AU: f64 = 4.56e-6;
+/// Astronomical unit (in km)
+pub const AU: f64 = 149_597_870.7;
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<Perturbations>();
+}
+
+/// The perturbations applied to ships, all of them being disabled by default
+#[derive(Resource, Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Perturbations {
+    /// Oblateness of the main influencer
+    pub j2: bool,
+    /// Radiation pressure of the primary body
+    pub radiation_pressure: bool,
+    /// Atmospheric drag of the main influencer
+    pub drag: bool,
+    /// Bodies attracting ships even when they are not among their influencers
+    pub third_bodies: Vec<BodyID>,
+}
+
+/// The surface of a ship exposed to the radiation of the primary body
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct RadiationProfile {
+    /// Cross-sectional area (in m²)
+    pub area: f64,
+    /// Radiation pressure coefficient, from 1 for a black body to 2 for a perfect mirror
+    pub reflectivity: f64,
+}
+
+impl Default for RadiationProfile {
+    fn default() -> Self {
+        Self {
+            area: 20.,
+            reflectivity: 1.3,
+        }
+    }
+}
+
+impl RadiationProfile {
+    /// The acceleration due to the radiation pressure at one astronomical unit from the primary body (in km/d²)
+    pub fn acceleration_at_1au(&self, mass: &ShipMass) -> f64 {
+        let area_to_mass = self.area / mass.total();
+        SOLAR_PRESSURE_AT_1AU
+            * self.reflectivity
+            * area_to_mass
+            * SECONDS_PER_DAY
+            * SECONDS_PER_DAY
+            * 1e-3
+    }
+}
+
+/// The aerodynamic properties of a ship
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct DragProfile {
+    /// Drag coefficient, about 2.2 for a spacecraft in free molecular flow
+    pub coefficient: f64,
+    /// Cross-sectional area facing the flow (in m²)
+    pub area: f64,
+}
+
+impl Default for DragProfile {
+    fn default() -> Self {
+        Self {
+            coefficient: 2.2,
+            area: 20.,
+        }
+    }
+}
+
+impl DragProfile {
+    /// The drag coefficient times the area over the mass of the ship (in m²/kg)
+    pub fn ballistic_coefficient(&self, mass: &ShipMass) -> f64 {
+        self.coefficient * self.area / mass.total()
+    }
+}
+
+/// How much the surface of a ship makes it sensitive to the radiation pressure and to the drag
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SurfaceCoefficients {
+    /// Radiation pressure acceleration at one astronomical unit (in km/d²), see [RadiationProfile::acceleration_at_1au]
+    pub radiation: f64,
+    /// See [DragProfile::ballistic_coefficient] (in m²/kg)
+    pub ballistic: f64,
+}
+
+impl SurfaceCoefficients {
+    /// The coefficients of a ship, which are zero for ships without the corresponding profile or without mass
+    pub fn of(
+        radiation: Option<&RadiationProfile>,
+        drag: Option<&DragProfile>,
+        mass: Option<&ShipMass>,
+    ) -> Self {
+        Self {
+            radiation: radiation
+                .zip(mass)
+                .map_or(0., |(profile, mass)| profile.acceleration_at_1au(mass)),
+            ballistic: drag
+                .zip(mass)
+                .map_or(0., |(profile, mass)| profile.ballistic_coefficient(mass)),
+        }
+    }
+}
+
+/// The gravity field of an oblate body
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Oblateness {
+    body: Entity,
+    j2: f64,
+    mass: f64,
+    radius: f64,
+    axis: DVec3,
+}
+
+/// The atmosphere of a body, rotating with it
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct AtmosphereDrag {
+    body: Entity,
+    atmosphere: Atmosphere,
+    radius: f64,
+    angular_velocity: DVec3,
+}
+
+/// The perturbations acting on an object, resolved from [Perturbations] for its current influence
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PerturbationModel {
+    oblateness: Option<Oblateness>,
+    /// The main influencer, whose shadow hides the primary body, with its radius
+    occulting: Option<(Entity, f64)>,
+    third_bodies: Vec<(Entity, f64)>,
+    /// Radiation pressure acceleration at one astronomical unit (in km/d²)
+    radiation: f64,
+    atmosphere: Option<AtmosphereDrag>,
+    /// Ballistic coefficient (in m²/kg)
+    ballistic: f64,
+}
+
+impl PerturbationModel {
+    /// Resolves the perturbations for an object given its influencers and its surface
+    pub fn new(
+        perturbations: &Perturbations,
+        main_influencer: Option<Entity>,
+        influencers: &[Entity],
+        surface: SurfaceCoefficients,
+        bodies: &Query<(&EllipticalOrbit, &BodyInfo)>,
+        mapping: &HashMap<BodyID, Entity>,
+    ) -> Self {
+        let main = main_influencer.and_then(|e| Some((e, &bodies.get(e).ok()?.1 .0)));
+        Self {
+            oblateness: main
+                .filter(|(_, data)| perturbations.j2 && data.j2 != 0.)
+                .map(|(body, data)| Oblateness {
+                    body,
+                    j2: data.j2,
+                    mass: data.mass,
+                    radius: data.equatorial_radius,
+                    axis: BodyRotation::from(data).axis(),
+                }),
+            occulting: main
+                .filter(|(_, data)| data.host_body.is_some())
+                .map(|(body, data)| (body, data.radius)),
+            third_bodies: perturbations
+                .third_bodies
+                .iter()
+                .filter_map(|id| mapping.get(id))
+                .filter(|e| !influencers.contains(e))
+                .filter_map(|e| Some((*e, bodies.get(*e).ok()?.1 .0.mass)))
+                .collect(),
+            radiation: if perturbations.radiation_pressure {
+                surface.radiation
+            } else {
+                0.
+            },
+            atmosphere: main
+                .filter(|_| perturbations.drag && surface.ballistic > 0.)
+                .and_then(|(body, data)| {
+                    Some(AtmosphereDrag {
+                        body,
+                        atmosphere: data.atmosphere?,
+                        radius: data.radius,
+                        angular_velocity: BodyRotation::from(data).angular_velocity(),
+                    })
+                }),
+            ballistic: surface.ballistic,
+        }
+    }
+
+    /// Whether no perturbation adds to the acceleration, the drag being applied separately
+    pub fn is_empty(&self) -> bool {
+        self.oblateness.is_none() && self.third_bodies.is_empty() && self.radiation == 0.
+    }
+
+    /// The acceleration of the perturbations on an object at the given position and game time
+    pub fn acceleration(
+        &self,
+        pos: DVec3,
+        time: f64,
+        bodies: &Query<(&EllipticalOrbit, &BodyInfo)>,
+        mapping: &HashMap<BodyID, Entity>,
+    ) -> DVec3 {
+        if self.is_empty() {
+            return DVec3::ZERO;
+        }
+        let main = self
+            .oblateness
+            .map(|o| o.body)
+            .or(self.occulting.map(|o| o.0));
+        let coords = bodies_coordinates_at(
+            main.into_iter()
+                .chain(self.third_bodies.iter().map(|(e, _)| *e)),
+            bodies,
+            mapping,
+            time,
+        );
+        let (main_pos, third_bodies_coords) = match main {
+            Some(_) => (coords.first().map(|c| c.0), &coords[1..]),
+            None => (None, &coords[..]),
+        };
+        let mut acc = get_acceleration(
+            pos,
+            third_bodies_coords
+                .iter()
+                .zip(&self.third_bodies)
+                .map(|((p, _), (_, m))| (*p, *m)),
+        );
+        if let (Some(o), Some(body_pos)) = (self.oblateness, main_pos) {
+            acc += j2_acceleration(pos - body_pos, o.j2, o.mass, o.radius, o.axis);
+        }
+        if self.radiation > 0. {
+            let shadowed = self
+                .occulting
+                .zip(main_pos)
+                .is_some_and(|((_, radius), body_pos)| in_shadow(pos, body_pos, radius));
+            if !shadowed {
+                acc += radiation_acceleration(pos, self.radiation);
+            }
+        }
+        acc
+    }
+
+    /// The velocity of an object at the given position after `dt` of atmospheric drag, ending at the given game time.
+    ///
+    /// The drag is the only force considered, and its direction is assumed constant during `dt`.
+    pub fn apply_drag(
+        &self,
+        pos: DVec3,
+        speed: DVec3,
+        time: f64,
+        dt: f64,
+        bodies: &Query<(&EllipticalOrbit, &BodyInfo)>,
+        mapping: &HashMap<BodyID, Entity>,
+    ) -> DVec3 {
+        let Some(drag) = self.atmosphere else {
+            return speed;
+        };
+        let (body_pos, body_speed) =
+            bodies_coordinates_at([drag.body].into_iter(), bodies, mapping, time)[0];
+        let relative_pos = pos - body_pos;
+        let density = drag
+            .atmosphere
+            .density_at(relative_pos.length() - drag.radius);
+        if density == 0. {
+            return speed;
+        }
+        // The atmosphere rotates with the body
+        let wind = body_speed + drag.angular_velocity.cross(relative_pos);
+        wind + drag_velocity(speed - wind, density, self.ballistic, dt)
+    }
+}
+
+/// The velocity relative to the air after `dt` of drag only, given the density (in kg/m³) and the ballistic coefficient
+/// (in m²/kg), solving `dv/dt = -ρ B |v| v / 2` exactly
+pub fn drag_velocity(relative_speed: DVec3, density: f64, ballistic: f64, dt: f64) -> DVec3 {
+    // The density times the ballistic coefficient is in m⁻¹
+    let k = 0.5 * density * ballistic * 1e3;
+    relative_speed / (1. + k * relative_speed.length() * dt)
+}
+
+/// The acceleration due to the oblateness of a body, given the position of the object relative to it
+pub fn j2_acceleration(relative_pos: DVec3, j2: f64, mass: f64, radius: f64, axis: DVec3) -> DVec3 {
+    let r2 = relative_pos.length_squared();
+    let z = relative_pos.dot(axis);
+    let factor = -1.5 * j2 * G * mass * radius * radius / r2.powf(2.5);
+    factor * (relative_pos * (1. - 5. * z * z / r2) + 2. * z * axis)
+}
+
+/// The acceleration due to the radiation pressure of the primary body, at the origin of the global frame, given the
+/// acceleration at one astronomical unit
+pub fn radiation_acceleration(pos: DVec3, acceleration_at_1au: f64) -> DVec3 {
+    let distance = pos.length();
+    if distance == 0. {
+        return DVec3::ZERO;
+    }
+    pos / distance * acceleration_at_1au * (AU / distance).powi(2)
+}
+
+/// Whether an object is in the cylindrical shadow that a body casts away from the primary body
+pub fn in_shadow(pos: DVec3, body_pos: DVec3, radius: f64) -> bool {
+    let direction = body_pos.normalize_or_zero();
+    let relative_pos = pos - body_pos;
+    let along = relative_pos.dot(direction);
+    along > 0. && (relative_pos - direction * along).length() < radius
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use bevy::math::DQuat;
+
+    use crate::{
+        client::testing::{paused_game, run_until, spawn_ship, start_action},
+        physics::{
+            time::{SimStepSize, GAMETIME_PER_SIMTICK},
+            G,
+        },
+        prelude::*,
+        utils::algebra::circular_orbit_around_body,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_perturbation_terms() {
+        // The equatorial bulge pulls objects above the equator, and reduces the attraction above the poles
+        let (mass, radius, j2) = (5.972e24, 6378., 1.08263e-3);
+        let pole = j2_acceleration(DVec3::Z * 7e3, j2, mass, radius, DVec3::Z);
+        let equator = j2_acceleration(DVec3::X * 7e3, j2, mass, radius, DVec3::Z);
+        assert!(pole.x == 0. && pole.z > 0.);
+        assert!(equator.x < 0. && equator.z == 0.);
+        assert!((pole.z + 2. * equator.x).abs() < 1e-12 * pole.z.abs());
+        let point_mass = G * mass / 49e6;
+        assert!(-equator.x / point_mass < 3. * j2);
+
+        // Radiation pressure pushes away from the Sun, and decreases with the square of the distance
+        let acc = radiation_acceleration(DVec3::Y * AU, 1.);
+        assert!((acc - DVec3::Y).length() < 1e-12);
+        let far = radiation_acceleration(DVec3::Y * 2. * AU, 1.);
+        assert!((far.length() - 0.25).abs() < 1e-12);
+        let profile = RadiationProfile::default();
+        let at_1au = profile.acceleration_at_1au(&ShipMass::default());
+        // About 3e-9 m/s² for the default ship
+        assert!((at_1au * 1e3 / SECONDS_PER_DAY.powi(2) - 2.964e-9).abs() < 1e-12);
+
+        let earth = DVec3::X * AU;
+        assert!(in_shadow(earth + DVec3::new(1e4, 10., 0.), earth, 6378.));
+        assert!(!in_shadow(earth - DVec3::new(1e4, 0., 0.), earth, 6378.));
+        assert!(!in_shadow(earth + DVec3::new(1e4, 1e4, 0.), earth, 6378.));
+    }
+
+    /// Returns the rotation of the ascending node (in radians) of a ship in an inclined low Earth orbit after some
+    /// days
+    fn node_precession(perturbations: Perturbations, days: f64) -> f64 {
+        let mut app = paused_game(ClientPlugin::testing());
+        app.insert_resource(perturbations);
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0[&id_from("terre")];
+        let (&mass, &earth_pos, &earth_speed, info) = world
+            .query::<(&Mass, &Position, &Velocity, &BodyInfo)>()
+            .get(world, earth)
+            .unwrap();
+        let axis = BodyRotation::from(&info.0).axis();
+        // Inclined by 60° from the equator of the Earth
+        let (rel_pos, rel_speed) =
+            circular_orbit_around_body(7e3, mass.0, DVec3::ZERO, DVec3::ZERO);
+        let rotation = DQuat::from_rotation_arc(DVec3::Z, axis)
+            * DQuat::from_axis_angle(rel_pos.normalize(), PI / 3.);
+        let (rel_pos, rel_speed) = (rotation * rel_pos, rotation * rel_speed);
+        // Node line in the equatorial plane of the Earth
+        let initial_node = axis.cross(rel_pos.cross(rel_speed));
+        let (spawn_pos, spawn_speed) = (earth_pos.0 + rel_pos, earth_speed.0 + rel_speed);
+        spawn_ship(&mut app, "s", spawn_pos, spawn_speed);
+        app.world_mut().resource_mut::<SimStepSize>().0 = 1;
+        start_action(&mut app);
+        let end = (days / GAMETIME_PER_SIMTICK).round() as u64;
+        run_until(&mut app, |world| world.resource::<GameTime>().simtick >= end);
+        let world = app.world_mut();
+        let (&pos, &speed) = world
+            .query_filtered::<(&Position, &Velocity), With<Influenced>>()
+            .single(world);
+        let (&earth_pos, &earth_speed) = world
+            .query::<(&Position, &Velocity)>()
+            .get(world, earth)
+            .unwrap();
+        let node = axis.cross((pos.0 - earth_pos.0).cross(speed.0 - earth_speed.0));
+        let angle = initial_node.angle_between(node);
+        if initial_node.cross(node).dot(axis) < 0. {
+            -angle
+        } else {
+            angle
+        }
+    }
+
+    #[test]
+    fn test_j2_precession() {
+        let days = 1.;
+        let without = node_precession(Perturbations::default(), days);
+        let with = node_precession(
+            Perturbations {
+                j2: true,
+                ..Default::default()
+            },
+            days,
+        );
+        // Secular regression of the node: -3/2 n J2 (R/a)² cos(i), about -5°/d at this altitude
+        let (radius, a, j2) = (6378.137, 7e3_f64, 1.08263e-3);
+        let n = (G * 5.972e24 / a.powi(3)).sqrt();
+        let expected = -1.5 * n * j2 * (radius / a).powi(2) * (PI / 3.).cos() * days;
+        let drift = with - without;
+        assert!(drift < 0.);
+        assert!(
+            (drift - expected).abs() < 0.1 * expected.abs(),
+            "{} deg instead of {} deg",
+            drift.to_degrees(),
+            expected.to_degrees()
+        );
+    }
+}
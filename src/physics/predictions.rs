@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
 
 use bevy::{ecs::system::QueryLens, math::DVec3, prelude::*, utils::HashMap};
 
@@ -9,8 +9,12 @@ use crate::{
 };
 
 use super::{
+    collision::first_impact,
+    frames::ReferenceFrame,
     influence::HillRadius,
-    leapfrog::{get_acceleration, get_dv, get_dx},
+    integrator::Integrator,
+    leapfrog::{get_acceleration, substeps, ActiveBurn},
+    perturbations::{PerturbationModel, Perturbations, SurfaceCoefficients},
     time::{GAMETIME_PER_SIMTICK, SIMTICKS_PER_TICK},
 };
 
@@ -25,32 +29,183 @@ pub struct Prediction {
     pub simtick: u64,
 }
 
+/// The future coordinates of an object, as computed by [PredictionStart::compute_predictions]
+#[derive(Debug, Default, Clone)]
+pub struct Predictions {
+    /// The positions and velocities of the object at each simtick after the start, relative to the reference frame as
+    /// it is at that simtick
+    pub coords: Vec<(DVec3, DVec3)>,
+    /// The impact on a body that ends the predictions early, if any
+    pub impact: Option<PredictedImpact>,
+    /// The changes of main influencer along the predicted trajectory, in chronological order
+    pub soi_events: Vec<SoiEvent>,
+    /// The point of the predicted trajectory that is the closest to the target, if one was provided
+    pub closest_approach: Option<ClosestApproach>,
+    /// The simtick of the first burn that is truncated because the delta-v budget is exhausted
+    pub propellant_exhausted: Option<u64>,
+    /// The state at the start of each tick, to compute the predictions again after a maneuver node
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl Predictions {
+    /// Drops the predictions from the last checkpoint before the given simtick, which is returned
+    fn resume_from(&mut self, start: u64, simtick: u64) -> Option<Checkpoint> {
+        let i = self
+            .checkpoints
+            .iter()
+            .rposition(|checkpoint| checkpoint.simtick <= simtick)?;
+        let checkpoint = self.checkpoints.drain(i..).next()?;
+        self.coords.truncate((checkpoint.simtick - start - 1) as usize);
+        self.soi_events.truncate(checkpoint.soi_events);
+        self.closest_approach = checkpoint.closest_approach;
+        self.propellant_exhausted = checkpoint.propellant_exhausted;
+        self.impact = None;
+        Some(checkpoint)
+    }
+}
+
+/// The state of the predicted object at the start of a tick, before the maneuver node of that tick
+#[derive(Debug, Clone)]
+struct Checkpoint {
+    simtick: u64,
+    pos: DVec3,
+    speed: DVec3,
+    acc: DVec3,
+    influence: Influenced,
+    delta_v: f64,
+    burn: Option<ActiveBurn>,
+    /// Number of events that happened before
+    soi_events: usize,
+    closest_approach: Option<ClosestApproach>,
+    propellant_exhausted: Option<u64>,
+}
+
+/// An object whose distance to the predicted trajectory is monitored
+#[derive(Debug, Clone, Copy)]
+pub enum ApproachTarget<'a> {
+    Body(Entity),
+    /// An object, such as a ship or a Lagrange point, whose global positions are known at each simtick following
+    /// `simtick`
+    Ship {
+        simtick: u64,
+        positions: &'a [DVec3],
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClosestApproach {
+    pub distance: f64,
+    pub simtick: u64,
+}
+
+/// The predicted trajectory crosses the boundary of the sphere of influence of a body
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SoiEvent {
+    pub body: Entity,
+    pub simtick: u64,
+    pub kind: SoiEventKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SoiEventKind {
+    /// Entering the sphere of influence, with the periapsis of the osculating orbit around the body at that moment
+    Entry {
+        periapsis: Option<f64>,
+    },
+    Exit,
+}
+
+/// A predicted crash of an object on the surface of a body
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PredictedImpact {
+    pub body: Entity,
+    pub simtick: u64,
+}
+
 #[derive(Debug)]
 pub struct PredictionStart {
     pub pos: DVec3,
     pub speed: DVec3,
     pub acc: DVec3,
     pub simtick: u64,
+    /// The remaining delta-v budget, burns are truncated like in the live physics once it is exhausted
+    pub delta_v: f64,
+    /// How the object is affected by the radiation pressure and the atmospheric drag
+    pub surface: SurfaceCoefficients,
 }
 
 impl PredictionStart {
-    /// Compute the future positions of this point with respect to a given referential and considering some influencer's gravitationnal pull on it
+    /// Compute the future positions of this point in a given reference frame and considering some influencer's gravitationnal pull on it
+    #[allow(clippy::too_many_arguments)]
     pub fn compute_predictions(
         &self,
         number: usize,
         influence: &Influenced,
-        reference: Option<Entity>,
+        frame: ReferenceFrame,
+        bodies: &mut QueryLens<(&EllipticalOrbit, &BodyInfo, &HillRadius)>,
+        mapping: &HashMap<BodyID, Entity>,
+        nodes: &BTreeMap<u64, ManeuverNode>,
+        integrator: &Integrator,
+        perturbations: &Perturbations,
+        target: Option<ApproachTarget>,
+    ) -> Predictions {
+        let mut predictions = Predictions::default();
+        self.update_predictions(
+            &mut predictions,
+            0,
+            number,
+            influence,
+            frame,
+            bodies,
+            mapping,
+            nodes,
+            integrator,
+            perturbations,
+            target,
+        );
+        predictions
+    }
+
+    /// Computes again the predictions from the given tick, where the maneuver nodes start to differ from the ones
+    /// they were computed with. The other arguments must be the same as when they were computed.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update_predictions(
+        &self,
+        predictions: &mut Predictions,
+        from_tick: u64,
+        number: usize,
+        influence: &Influenced,
+        frame: ReferenceFrame,
         bodies: &mut QueryLens<(&EllipticalOrbit, &BodyInfo, &HillRadius)>,
         mapping: &HashMap<BodyID, Entity>,
         nodes: &BTreeMap<u64, ManeuverNode>,
-    ) -> Vec<(DVec3, DVec3)> {
+        integrator: &Integrator,
+        perturbations: &Perturbations,
+        target: Option<ApproachTarget>,
+    ) {
         let dt = GAMETIME_PER_SIMTICK;
         let mut bodies = bodies.query();
-        let mut pos = self.pos;
-        let mut speed = self.speed;
-        let mut predictions = Vec::new();
+        let checkpoint = predictions
+            .resume_from(self.simtick, from_tick.saturating_mul(SIMTICKS_PER_TICK))
+            .unwrap_or_else(|| {
+                *predictions = Predictions::default();
+                Checkpoint {
+                    simtick: self.simtick + 1,
+                    pos: self.pos,
+                    speed: self.speed,
+                    acc: self.acc,
+                    influence: influence.clone(),
+                    delta_v: self.delta_v,
+                    burn: None,
+                    soi_events: 0,
+                    closest_approach: None,
+                    propellant_exhausted: None,
+                }
+            });
+        let (mut pos, mut speed, mut acc) = (checkpoint.pos, checkpoint.speed, checkpoint.acc);
+        let (mut burn, mut delta_v) = (checkpoint.burn, checkpoint.delta_v);
         let simulated = simulated_from_influence(
-            influence,
+            &checkpoint.influence,
             &mut bodies.transmute_lens::<&BodyInfo>(),
             mapping,
         );
@@ -58,7 +213,8 @@ impl PredictionStart {
             .iter()
             .map(|e| (*e, (DVec3::ZERO, DVec3::ZERO, bodies.get(*e).unwrap().2 .0)))
             .collect::<HashMap<_, _>>();
-        let mut influencers = influence
+        let mut influencers = checkpoint
+            .influence
             .influencers
             .iter()
             .map(|e| {
@@ -66,25 +222,8 @@ impl PredictionStart {
                 (*e, comp.1 .0.mass)
             })
             .collect::<HashMap<_, _>>();
-        let mut main = influence.main_influencer;
-        let initial_bodies_coords = get_bodies_coordinates(
-            map.keys().cloned(),
-            &mut bodies.transmute_lens::<(&EllipticalOrbit, &BodyInfo)>(),
-            mapping,
-            self.simtick,
-        );
-        map.values_mut()
-            .enumerate()
-            .for_each(|(i, v)| (v.0, v.1) = initial_bodies_coords[i]);
-        let initial_ref_coords = reference.and_then(|r| map.get(&r).cloned()).unwrap_or((
-            DVec3::ZERO,
-            DVec3::ZERO,
-            f64::INFINITY,
-        ));
-        let mut acc = self.acc;
-        let mut previous_acc;
-        for i in 1..number + 1 {
-            let simtick = self.simtick + i as u64;
+        let mut main = checkpoint.influence.main_influencer;
+        for simtick in checkpoint.simtick..=self.simtick + number as u64 {
             let bodies_coords = get_bodies_coordinates(
                 map.keys().cloned(),
                 &mut bodies.transmute_lens::<(&EllipticalOrbit, &BodyInfo)>(),
@@ -96,12 +235,51 @@ impl PredictionStart {
                 .for_each(|(i, v)| (v.0, v.1) = bodies_coords[i]);
 
             if simtick % SIMTICKS_PER_TICK == 0 {
+                predictions.checkpoints.push(Checkpoint {
+                    simtick,
+                    pos,
+                    speed,
+                    acc,
+                    influence: Influenced {
+                        main_influencer: main,
+                        influencers: influencers.keys().cloned().collect(),
+                    },
+                    delta_v,
+                    burn,
+                    soi_events: predictions.soi_events.len(),
+                    closest_approach: predictions.closest_approach,
+                    propellant_exhausted: predictions.propellant_exhausted,
+                });
                 if let Some(node) = nodes.get(&(simtick / SIMTICKS_PER_TICK)) {
                     // For now, the origin body must be simulated
                     if let Some(node_origin) = mapping.get(&node.origin) {
                         if let Some(&(origin_pos, origin_speed, _)) = map.get(node_origin) {
-                            speed += orbital_to_global_matrix(origin_pos, origin_speed, pos, speed)
-                                * node.thrust;
+                            let thrust =
+                                orbital_to_global_matrix(origin_pos, origin_speed, pos, speed)
+                                    * node.thrust;
+                            let requested = thrust.length();
+                            let applied = requested.min(delta_v);
+                            delta_v -= applied;
+                            if applied < requested {
+                                predictions.propellant_exhausted.get_or_insert(simtick);
+                            }
+                            let ratio = if requested > 0. {
+                                applied / requested
+                            } else {
+                                0.
+                            };
+                            // Like in the live physics, burns start at the beginning of the step
+                            match node.burn_duration() {
+                                Some(duration) => {
+                                    let start = (simtick - 1) as f64 * dt;
+                                    burn = Some(ActiveBurn {
+                                        acceleration: thrust / duration,
+                                        start,
+                                        end: start + duration * ratio,
+                                    });
+                                }
+                                None => speed += thrust * ratio,
+                            }
                         }
                     }
                 }
@@ -135,30 +313,152 @@ impl PredictionStart {
                             }),
                         );
                         if radius > new_radius {
-                            influencers.insert(new_main, bodies.get(new_main).unwrap().1 .0.mass);
+                            let mass = bodies.get(new_main).unwrap().1 .0.mass;
+                            influencers.insert(new_main, mass);
+                            let (body_pos, body_speed) = get_bodies_coordinates(
+                                [new_main].into_iter(),
+                                &mut bodies.transmute_lens::<(&EllipticalOrbit, &BodyInfo)>(),
+                                mapping,
+                                simtick - 1,
+                            )[0];
+                            let periapsis = EllipticalOrbit::from_state_vectors(
+                                pos - body_pos,
+                                speed - body_speed,
+                                mass,
+                                (simtick - 1) as f64 * dt,
+                            )
+                            .map(|orbit| orbit.periapsis);
+                            predictions.soi_events.push(SoiEvent {
+                                body: new_main,
+                                simtick,
+                                kind: SoiEventKind::Entry { periapsis },
+                            });
                         } else {
                             influencers.remove(&main_entity);
+                            predictions.soi_events.push(SoiEvent {
+                                body: main_entity,
+                                simtick,
+                                kind: SoiEventKind::Exit,
+                            });
                         }
                         main = Some(new_main);
                     }
                 }
             }
 
-            pos += get_dx(speed, acc, dt);
-            let ref_coords = reference.and_then(|r| map.get(&r).cloned()).unwrap_or((
-                DVec3::ZERO,
-                DVec3::ZERO,
-                f64::INFINITY,
-            ));
-            previous_acc = acc;
-            acc = get_acceleration(pos, influencers.iter().map(|(e, m)| (map[e].0, *m)));
-            speed += get_dv(previous_acc, acc, dt);
-            predictions.push((
-                pos - ref_coords.0 + initial_ref_coords.0,
-                speed - ref_coords.1,
-            ));
+            // Influencers first, so that their masses line up with the first body states
+            let keys: Vec<_> = influencers
+                .keys()
+                .chain(map.keys().filter(|e| !influencers.contains_key(*e)))
+                .cloned()
+                .collect();
+            let masses: Vec<_> = keys
+                .iter()
+                .map_while(|e| influencers.get(e).cloned())
+                .collect();
+            let radii: Vec<_> = keys
+                .iter()
+                .map(|e| bodies.get(*e).map_or(0., |(_, BodyInfo(data), _)| data.radius))
+                .collect();
+            let spheres: Vec<_> = keys.iter().map(|e| map[e].2).collect();
+            let mut orbits = bodies.transmute_lens::<(&EllipticalOrbit, &BodyInfo)>();
+            let orbits = orbits.query();
+            let mut states = BodyStates::new(keys, &orbits, mapping);
+            let start_coords = states.at((simtick - 1) as f64 * dt);
+            let n = substeps(
+                pos,
+                speed,
+                dt,
+                start_coords.iter().zip(&masses).map(|((p, _), m)| (*p, *m)),
+                start_coords
+                    .iter()
+                    .zip(&spheres)
+                    .map(|((p, v), r)| (*p, *v, *r)),
+            );
+            let h = dt / n as f64;
+            let mut bodies_start: Vec<_> = start_coords.iter().map(|(p, _)| *p).collect();
+            let model = PerturbationModel::new(
+                perturbations,
+                main,
+                &influencers.keys().cloned().collect::<Vec<_>>(),
+                self.surface,
+                &orbits,
+                mapping,
+            );
+            for j in 0..n {
+                let substep_start = (simtick - 1) as f64 * dt + j as f64 * h;
+                let step = integrator.step(pos, speed, acc, substep_start, h, |p, t| {
+                    get_acceleration(
+                        p,
+                        states.at(t).iter().zip(&masses).map(|((c, _), m)| (*c, *m)),
+                    ) + model.acceleration(p, t, &orbits, mapping)
+                        + burn.map_or(DVec3::ZERO, |b| b.acceleration_at(t))
+                });
+                let bodies_end = states.at(substep_start + h);
+                let impact = first_impact(
+                    pos,
+                    step.pos,
+                    bodies_start
+                        .iter()
+                        .zip(bodies_end)
+                        .zip(&radii)
+                        .map(|((s, (e, _)), r)| (*s, *e, *r)),
+                );
+                if let Some((k, fraction, impact_pos)) = impact {
+                    // Like in the live physics, the ship stays at the impact point relative to the body
+                    let body = states.bodies()[k];
+                    let (body_pos, body_speed, _) = map[&body];
+                    (pos, speed) = (body_pos + impact_pos, body_speed);
+                    predictions.impact = Some(PredictedImpact {
+                        body,
+                        simtick: ((substep_start + fraction * h) / GAMETIME_PER_SIMTICK).round()
+                            as u64,
+                    });
+                    break;
+                }
+                bodies_start.clear();
+                bodies_start.extend(bodies_end.iter().map(|(p, _)| *p));
+                let speed_after_drag =
+                    model.apply_drag(step.pos, step.speed, substep_start + h, h, &orbits, mapping);
+                (pos, speed, acc) = (step.pos, speed_after_drag, step.acc);
+            }
+            predictions.coords.push(
+                frame
+                    .state_at(&orbits, mapping, simtick as f64 * dt)
+                    .to_frame(pos, speed),
+            );
+            let target_pos = match target {
+                Some(ApproachTarget::Body(body)) => Some(
+                    get_bodies_coordinates(
+                        [body].into_iter(),
+                        &mut bodies.transmute_lens::<(&EllipticalOrbit, &BodyInfo)>(),
+                        mapping,
+                        simtick,
+                    )[0]
+                    .0,
+                ),
+                Some(ApproachTarget::Ship {
+                    simtick: start,
+                    positions,
+                }) => simtick
+                    .checked_sub(start + 1)
+                    .and_then(|i| positions.get(i as usize))
+                    .cloned(),
+                None => None,
+            };
+            if let Some(target_pos) = target_pos {
+                let distance = (pos - target_pos).length();
+                if predictions
+                    .closest_approach
+                    .is_none_or(|approach| distance < approach.distance)
+                {
+                    predictions.closest_approach = Some(ClosestApproach { distance, simtick });
+                }
+            }
+            if predictions.impact.is_some() {
+                break;
+            }
         }
-        predictions
     }
 }
 
@@ -195,23 +495,38 @@ pub fn get_bodies_coordinates(
     bodies: &mut QueryLens<(&EllipticalOrbit, &BodyInfo)>,
     mapping: &HashMap<BodyID, Entity>,
     tick: u64,
+) -> Vec<(DVec3, DVec3)> {
+    bodies_coordinates_at(
+        selected_bodies,
+        &bodies.query(),
+        mapping,
+        tick as f64 * GAMETIME_PER_SIMTICK,
+    )
+}
+
+/// Computes the global positions and velocities of the selected bodies at any game time
+pub fn bodies_coordinates_at(
+    selected_bodies: impl Iterator<Item = Entity>,
+    bodies: &Query<(&EllipticalOrbit, &BodyInfo)>,
+    mapping: &HashMap<BodyID, Entity>,
+    time: f64,
 ) -> Vec<(DVec3, DVec3)> {
     fn compute_pos_rec(
         e: Entity,
         map: &mut HashMap<Entity, (DVec3, DVec3)>,
-        tick: u64,
-        bodies: Query<(&EllipticalOrbit, &BodyInfo)>,
+        time: f64,
+        bodies: &Query<(&EllipticalOrbit, &BodyInfo)>,
         mapping: &HashMap<BodyID, Entity>,
     ) -> (DVec3, DVec3) {
-        let (orbit, BodyInfo(data)) = bodies.get(e).unwrap();
-        let mut o = orbit.clone();
-        o.update_pos(tick as f64 * GAMETIME_PER_SIMTICK);
         if let Some(coords) = map.get(&e) {
             *coords
         } else {
+            let (orbit, BodyInfo(data)) = bodies.get(e).unwrap();
+            let mut o = orbit.clone();
+            o.update_pos(time);
             let (pos, speed) = data.host_body.map_or((DVec3::ZERO, DVec3::ZERO), |parent| {
                 let (parent_pos, parent_speed) =
-                    compute_pos_rec(mapping[&parent], map, tick, bodies, mapping);
+                    compute_pos_rec(mapping[&parent], map, time, bodies, mapping);
                 (parent_pos + o.local_pos, parent_speed + o.local_speed)
             });
             map.insert(e, (pos, speed));
@@ -222,16 +537,71 @@ pub fn get_bodies_coordinates(
     let mut map = HashMap::new();
 
     selected_bodies
-        .map(|e| compute_pos_rec(e, &mut map, tick, bodies.query(), mapping))
+        .map(|e| compute_pos_rec(e, &mut map, time, bodies, mapping))
         .collect()
 }
 
+/// The global states of a set of bodies, only computed once for each of the last game times they were requested at
+pub struct BodyStates<'a, 'w, 's> {
+    bodies: Vec<Entity>,
+    orbits: &'a Query<'w, 's, (&'static EllipticalOrbit, &'static BodyInfo)>,
+    mapping: &'a HashMap<BodyID, Entity>,
+    cache: VecDeque<(f64, Vec<(DVec3, DVec3)>)>,
+}
+
+impl<'a, 'w, 's> BodyStates<'a, 'w, 's> {
+    /// Number of game times kept in the cache, enough for all the stages of the integrators
+    const CACHED_TIMES: usize = 8;
+
+    pub fn new(
+        bodies: Vec<Entity>,
+        orbits: &'a Query<'w, 's, (&'static EllipticalOrbit, &'static BodyInfo)>,
+        mapping: &'a HashMap<BodyID, Entity>,
+    ) -> Self {
+        Self {
+            bodies,
+            orbits,
+            mapping,
+            cache: VecDeque::with_capacity(Self::CACHED_TIMES),
+        }
+    }
+
+    pub fn bodies(&self) -> &[Entity] {
+        &self.bodies
+    }
+
+    /// The positions and velocities of the bodies at the given game time, in the order of [Self::bodies]
+    pub fn at(&mut self, time: f64) -> &[(DVec3, DVec3)] {
+        let i = match self.cache.iter().position(|(t, _)| *t == time) {
+            Some(i) => i,
+            None => {
+                if self.cache.len() == Self::CACHED_TIMES {
+                    self.cache.pop_front();
+                }
+                let states = bodies_coordinates_at(
+                    self.bodies.iter().cloned(),
+                    self.orbits,
+                    self.mapping,
+                    time,
+                );
+                self.cache.push_back((time, states));
+                self.cache.len() - 1
+            }
+        };
+        &self.cache[i].1
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use bevy::{ecs::system::SystemState, prelude::*};
 
     use crate::{
-        physics::leapfrog::get_acceleration, prelude::*, utils::algebra::circular_orbit_around_body,
+        client::testing::{paused_game, run_for, spawn_ship, start_action},
+        objects::ships::trajectory::{Trajectory, TrajectoryEvent},
+        physics::leapfrog::get_acceleration,
+        prelude::*,
+        utils::algebra::circular_orbit_around_body,
     };
 
     use super::*;
@@ -266,24 +636,317 @@ mod tests {
             pos,
             speed,
             simtick: 0,
+            delta_v: f64::INFINITY,
+            surface: SurfaceCoefficients::default(),
             acc: get_acceleration(pos, query.iter_many(&influencers).map(|(p, m)| (p.0, m.0))),
         }
         .compute_predictions(
             3,
             &influence,
-            Some(earth),
+            ReferenceFrame::BodyCentered(earth),
             &mut bodies.as_query_lens(),
             &mapping.0,
             &BTreeMap::new(),
+            &Integrator::default(),
+            &Perturbations::default(),
+            None,
         );
-        for (i, (p, _)) in predictions.into_iter().enumerate() {
+        assert_eq!(predictions.impact, None);
+        for (i, (p, _)) in predictions.coords.into_iter().enumerate() {
             // dbg!(p);
             // dbg!(pos + (i + 1) as f64 * (speed - earth_speed.0) * GAMETIME_PER_SIMTICK);
             assert!(
-                (p - (pos + (i + 1) as f64 * (speed - earth_speed.0) * GAMETIME_PER_SIMTICK))
+                (p - (pos - earth_pos.0
+                    + (i + 1) as f64 * (speed - earth_speed.0) * GAMETIME_PER_SIMTICK))
                     .length()
                     <= 5e4
             );
         }
     }
+
+    #[test]
+    fn test_predictions_impact() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        app.update();
+        let world = app.world_mut();
+        let mapping = &world.resource::<BodiesMapping>().0;
+        let earth = *mapping.get(&id_from("terre")).unwrap();
+        let sun = *mapping.get(&id_from("soleil")).unwrap();
+        let (&earth_pos, &earth_speed, BodyInfo(data)) = world
+            .query::<(&Position, &Velocity, &BodyInfo)>()
+            .get(world, earth)
+            .unwrap();
+        let radius = data.radius;
+        // Heading straight to the Earth at 10 km/s, from 10000 km above the surface
+        let (pos, speed) = (
+            earth_pos.0 + DVec3::new(radius + 1e4, 0., 0.),
+            earth_speed.0 + DVec3::new(-864000., 0., 0.),
+        );
+        let influencers = vec![sun, earth];
+        let influence = Influenced {
+            main_influencer: Some(earth),
+            influencers: influencers.clone(),
+        };
+        #[allow(clippy::type_complexity)]
+        let mut system_state: SystemState<(
+            Res<BodiesMapping>,
+            Query<(&EllipticalOrbit, &BodyInfo, &HillRadius)>,
+            Query<(&Position, &Mass)>,
+        )> = SystemState::new(world);
+        let (mapping, mut bodies, query) = system_state.get(world);
+        let predictions = PredictionStart {
+            pos,
+            speed,
+            simtick: 0,
+            delta_v: f64::INFINITY,
+            surface: SurfaceCoefficients::default(),
+            acc: get_acceleration(pos, query.iter_many(&influencers).map(|(p, m)| (p.0, m.0))),
+        }
+        .compute_predictions(
+            100,
+            &influence,
+            ReferenceFrame::BodyCentered(earth),
+            &mut bodies.as_query_lens(),
+            &mapping.0,
+            &BTreeMap::new(),
+            &Integrator::default(),
+            &Perturbations::default(),
+            None,
+        );
+        let impact = predictions.impact.unwrap();
+        assert_eq!(impact.body, earth);
+        // Gravity makes the ship a bit faster than 10 km/s, so it takes a bit less than 1000 seconds
+        assert!((9..=12).contains(&impact.simtick));
+        // The predictions stop at the end of the step during which the impact happens
+        assert!(predictions.coords.len() as u64 - impact.simtick <= 1);
+        let (last, _) = predictions.coords.last().unwrap();
+        // The coordinates are relative to the Earth
+        assert!((last.length() - radius).abs() < 1.);
+    }
+
+    #[test]
+    fn test_predictions_encounters() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        app.update();
+        let world = app.world_mut();
+        let mapping = &world.resource::<BodiesMapping>().0;
+        let earth = *mapping.get(&id_from("terre")).unwrap();
+        let sun = *mapping.get(&id_from("soleil")).unwrap();
+        let (&earth_pos, &earth_speed) = world
+            .query::<(&Position, &Velocity)>()
+            .get(world, earth)
+            .unwrap();
+        // Heading to the Earth at 10 km/s from outside of its sphere of influence, with an offset of 20000 km
+        let (pos, speed) = (
+            earth_pos.0 + DVec3::new(2e6, 2e4, 0.),
+            earth_speed.0 + DVec3::new(-864000., 0., 0.),
+        );
+        let influence = Influenced {
+            main_influencer: Some(sun),
+            influencers: vec![sun],
+        };
+        #[allow(clippy::type_complexity)]
+        let mut system_state: SystemState<(
+            Res<BodiesMapping>,
+            Query<(&EllipticalOrbit, &BodyInfo, &HillRadius)>,
+            Query<(&Position, &Mass)>,
+        )> = SystemState::new(world);
+        let (mapping, mut bodies, query) = system_state.get(world);
+        let start = PredictionStart {
+            pos,
+            speed,
+            simtick: 0,
+            delta_v: f64::INFINITY,
+            surface: SurfaceCoefficients::default(),
+            acc: get_acceleration(pos, query.iter_many([sun]).map(|(p, m)| (p.0, m.0))),
+        };
+        let predictions = start.compute_predictions(
+            3000,
+            &influence,
+            ReferenceFrame::Inertial,
+            &mut bodies.as_query_lens(),
+            &mapping.0,
+            &BTreeMap::new(),
+            &Integrator::default(),
+            &Perturbations::default(),
+            Some(ApproachTarget::Body(earth)),
+        );
+        assert_eq!(predictions.impact, None);
+        let entry = predictions.soi_events[0];
+        assert_eq!(entry.body, earth);
+        let SoiEventKind::Entry {
+            periapsis: Some(periapsis),
+        } = entry.kind
+        else {
+            panic!("{:?}", entry.kind);
+        };
+        // The gravity of the Earth bends the trajectory towards it
+        assert!(1e4 < periapsis && periapsis < 2e4);
+        let approach = predictions.closest_approach.unwrap();
+        assert!((approach.distance - periapsis).abs() < 1e3);
+        assert!(approach.simtick > entry.simtick);
+
+        // A ship following the exact same path is always at zero distance
+        let positions: Vec<_> = predictions.coords.iter().map(|(p, _)| *p).collect();
+        let predictions = start.compute_predictions(
+            100,
+            &influence,
+            ReferenceFrame::Inertial,
+            &mut bodies.as_query_lens(),
+            &mapping.0,
+            &BTreeMap::new(),
+            &Integrator::default(),
+            &Perturbations::default(),
+            Some(ApproachTarget::Ship {
+                simtick: 0,
+                positions: &positions,
+            }),
+        );
+        assert_eq!(predictions.closest_approach.unwrap().distance, 0.);
+    }
+
+    #[test]
+    fn test_update_predictions() {
+        let mut app = paused_game(ClientPlugin::testing());
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0[&id_from("terre")];
+        let (&earth_pos, &earth_speed) = world
+            .query::<(&Position, &Velocity)>()
+            .get(world, earth)
+            .unwrap();
+        let node = |thrust| ManeuverNode {
+            name: "Burn".into(),
+            thrust,
+            origin: id_from("terre"),
+            duration: None,
+            thrust_magnitude: None,
+        };
+        let nodes = BTreeMap::from([(2, node(DVec3::new(1e4, 0., 0.)))]);
+        let mut changed = nodes.clone();
+        changed.insert(5, node(DVec3::new(0., 2e4, 0.)));
+        let start = PredictionStart {
+            pos: earth_pos.0 + DVec3::new(1e5, 0., 0.),
+            speed: earth_speed.0 + DVec3::new(0., 1.5e5, 0.),
+            acc: DVec3::ZERO,
+            simtick: 0,
+            delta_v: f64::INFINITY,
+            surface: SurfaceCoefficients::default(),
+        };
+        let influence = Influenced {
+            main_influencer: Some(earth),
+            influencers: vec![earth],
+        };
+        #[allow(clippy::type_complexity)]
+        let mut system_state: SystemState<(
+            Res<BodiesMapping>,
+            Query<(&EllipticalOrbit, &BodyInfo, &HillRadius)>,
+        )> = SystemState::new(world);
+        let (mapping, mut bodies) = system_state.get(world);
+        let mut compute = |nodes| {
+            start.compute_predictions(
+                100,
+                &influence,
+                ReferenceFrame::Inertial,
+                &mut bodies.as_query_lens(),
+                &mapping.0,
+                nodes,
+                &Integrator::default(),
+                &Perturbations::default(),
+                None,
+            )
+        };
+        let mut predictions = compute(&nodes);
+        let expected = compute(&changed);
+        start.update_predictions(
+            &mut predictions,
+            5,
+            100,
+            &influence,
+            ReferenceFrame::Inertial,
+            &mut bodies.as_query_lens(),
+            &mapping.0,
+            &changed,
+            &Integrator::default(),
+            &Perturbations::default(),
+            None,
+        );
+        assert_eq!(predictions.coords, expected.coords);
+    }
+
+    #[test]
+    fn test_predictions_finite_burn() {
+        let mut app = paused_game(ClientPlugin::testing());
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0[&id_from("terre")];
+        let (&earth_pos, &earth_speed) = world
+            .query::<(&Position, &Velocity)>()
+            .get(world, earth)
+            .unwrap();
+        spawn_ship(
+            &mut app,
+            "s",
+            earth_pos.0 + DVec3::new(1e5, 0., 0.),
+            earth_speed.0 + DVec3::new(0., 1.5e5, 0.),
+        );
+        let nodes = BTreeMap::from([(
+            2,
+            ManeuverNode {
+                name: "Burn".into(),
+                thrust: DVec3::new(5e4, 1e4, 0.),
+                origin: id_from("terre"),
+                duration: Some(0.03),
+                thrust_magnitude: None,
+            },
+        )]);
+        app.world_mut().send_event(TrajectoryEvent::Create {
+            ship: id_from("s"),
+            trajectory: Trajectory {
+                nodes: nodes.clone(),
+            },
+        });
+        app.update();
+        let world = app.world_mut();
+        let (&Position(pos), &Velocity(speed), acc, influence) = world
+            .query::<(&Position, &Velocity, &Acceleration, &Influenced)>()
+            .single(world);
+        let start = PredictionStart {
+            pos,
+            speed,
+            acc: acc.current,
+            simtick: world.resource::<GameTime>().simtick,
+            delta_v: f64::INFINITY,
+            surface: SurfaceCoefficients::default(),
+        };
+        let influence = influence.clone();
+        #[allow(clippy::type_complexity)]
+        let mut system_state: SystemState<(
+            Res<BodiesMapping>,
+            Query<(&EllipticalOrbit, &BodyInfo, &HillRadius)>,
+        )> = SystemState::new(world);
+        let (mapping, mut bodies) = system_state.get(world);
+        let predictions = start.compute_predictions(
+            100,
+            &influence,
+            ReferenceFrame::Inertial,
+            &mut bodies.as_query_lens(),
+            &mapping.0,
+            &nodes,
+            &Integrator::default(),
+            &Perturbations::default(),
+            None,
+        );
+
+        start_action(&mut app);
+        run_for(&mut app, 80);
+        let world = app.world_mut();
+        let simtick = world.resource::<GameTime>().simtick;
+        let (pos, _) = predictions.coords[(simtick - start.simtick - 1) as usize];
+        let &Position(live_pos) = world
+            .query_filtered::<&Position, With<ShipInfo>>()
+            .single(world);
+        // The burn lasts 30 simticks and changes the position by several thousands of kilometers
+        assert!((pos - live_pos).length() < 1.);
+    }
 }
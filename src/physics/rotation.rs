@@ -0,0 +1,216 @@
+//! Axial rotation of celestial bodies and body-fixed reference frames.
+use std::f64::consts::TAU;
+
+use bevy::{
+    math::{DQuat, DVec3},
+    prelude::*,
+};
+
+use crate::{game::Loaded, objects::prelude::*};
+
+use super::{orbit::OrbitsUpdate, time::GameTime, Position};
+
+pub fn plugin(app: &mut App) {
+    app.add_systems(OnEnter(Loaded), update_rotations.in_set(OrbitsUpdate))
+        .add_systems(FixedUpdate, update_rotations.in_set(OrbitsUpdate));
+}
+
+/// Rotation of a body around its own axis
+#[derive(Component, Default, Clone, Copy, Debug, PartialEq)]
+pub struct BodyRotation {
+    /// Time required to rotate around itself (in days), negative for retrograde rotations and zero for bodies that
+    /// do not rotate
+    pub period: f64,
+    /// Angle between the rotation axis and the z axis of the global frame (in degrees)
+    pub axial_tilt: f64,
+    /// Angle of the prime meridian at time zero (in degrees)
+    pub initial_angle: f64,
+    /// Current angle of the prime meridian (in degrees)
+    pub angle: f64,
+}
+
+impl From<&BodyData> for BodyRotation {
+    fn from(data: &BodyData) -> Self {
+        Self {
+            // The rotation period is given in hours
+            period: data.rotation_period / 24.,
+            axial_tilt: data.axial_tilt,
+            initial_angle: 0.,
+            angle: 0.,
+        }
+    }
+}
+
+impl BodyRotation {
+    /// The angle of the prime meridian at the given time (in degrees, in [0, 360))
+    pub fn angle_at(&self, time: f64) -> f64 {
+        if self.period == 0. {
+            return self.initial_angle.rem_euclid(360.);
+        }
+        (self.initial_angle + 360. * time / self.period).rem_euclid(360.)
+    }
+
+    /// The rotation that transforms body-fixed coordinates into global coordinates at the given time
+    pub fn orientation_at(&self, time: f64) -> DQuat {
+        self.orientation_for(self.angle_at(time))
+    }
+
+    /// The current rotation that transforms body-fixed coordinates into global coordinates
+    pub fn orientation(&self) -> DQuat {
+        self.orientation_for(self.angle)
+    }
+
+    fn orientation_for(&self, angle: f64) -> DQuat {
+        DQuat::from_rotation_x(self.axial_tilt.to_radians())
+            * DQuat::from_rotation_z(angle.to_radians())
+    }
+
+    /// The direction of the north pole in the global frame
+    pub fn axis(&self) -> DVec3 {
+        DQuat::from_rotation_x(self.axial_tilt.to_radians()) * DVec3::Z
+    }
+
+    /// The angular velocity of the body (in radians per day)
+    pub fn angular_velocity(&self) -> DVec3 {
+        if self.period == 0. {
+            return DVec3::ZERO;
+        }
+        self.axis() * TAU / self.period
+    }
+
+    /// Converts a position relative to the center of the body from the body-fixed frame to the global frame
+    pub fn to_global(&self, offset: DVec3, time: f64) -> DVec3 {
+        self.orientation_at(time) * offset
+    }
+
+    /// Converts a position relative to the center of the body from the global frame to the body-fixed frame
+    pub fn to_body_fixed(&self, offset: DVec3, time: f64) -> DVec3 {
+        self.orientation_at(time).inverse() * offset
+    }
+
+    /// The velocity of a point that is fixed relative to the body, compared to the center of the body
+    pub fn surface_speed(&self, global_offset: DVec3) -> DVec3 {
+        self.angular_velocity().cross(global_offset)
+    }
+}
+
+/// A location on the surface of a body
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SurfaceLocation {
+    /// Angle from the equator, positive towards the north pole (in degrees)
+    pub latitude: f64,
+    /// Angle from the prime meridian, positive eastwards (in degrees)
+    pub longitude: f64,
+}
+
+impl SurfaceLocation {
+    pub fn new(latitude: f64, longitude: f64) -> Self {
+        Self {
+            latitude,
+            longitude,
+        }
+    }
+
+    /// Computes the location below a position given in the body-fixed frame
+    pub fn from_body_fixed(offset: DVec3) -> Self {
+        Self {
+            latitude: (offset.z / offset.length()).asin().to_degrees(),
+            longitude: offset.y.atan2(offset.x).to_degrees(),
+        }
+    }
+
+    /// The position of the location in the body-fixed frame, at the given distance from the center of the body
+    pub fn body_fixed_position(&self, distance: f64) -> DVec3 {
+        let (lat, lon) = (self.latitude.to_radians(), self.longitude.to_radians());
+        distance * DVec3::new(lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin())
+    }
+
+    /// The global position of the location on the surface of a body at the given time
+    pub fn global_position(
+        &self,
+        body_pos: DVec3,
+        radius: f64,
+        rotation: &BodyRotation,
+        time: f64,
+    ) -> DVec3 {
+        body_pos + rotation.to_global(self.body_fixed_position(radius), time)
+    }
+}
+
+/// Finds the global position of a location on the surface of a body at the current time
+pub fn surface_position(
+    body: Entity,
+    location: SurfaceLocation,
+    bodies: &Query<(&Position, &BodyInfo, &BodyRotation)>,
+    time: &GameTime,
+) -> Option<DVec3> {
+    bodies
+        .get(body)
+        .ok()
+        .map(|(pos, BodyInfo(data), rotation)| {
+            location.global_position(pos.0, data.radius, rotation, time.time())
+        })
+}
+
+pub fn update_rotations(mut rotations: Query<&mut BodyRotation>, time: Res<GameTime>) {
+    rotations
+        .iter_mut()
+        .for_each(|mut r| r.angle = r.angle_at(time.time()));
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{app::FixedMain, math::DVec3, prelude::*};
+
+    use crate::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_surface_location() {
+        let rotation = BodyRotation {
+            period: 1.,
+            axial_tilt: 0.,
+            ..Default::default()
+        };
+        let location = SurfaceLocation::new(0., 90.);
+        let radius = 10.;
+        assert!((location.body_fixed_position(radius) - DVec3::new(0., 10., 0.)).length() < 1e-9);
+        // After a quarter of a rotation the location has moved from the y axis to the -x axis
+        let pos = location.global_position(DVec3::ZERO, radius, &rotation, 0.25);
+        assert!((pos - DVec3::new(-10., 0., 0.)).length() < 1e-9);
+        assert!((rotation.surface_speed(pos) - DVec3::new(0., -TAU * 10., 0.)).length() < 1e-9);
+
+        let tilted = BodyRotation {
+            axial_tilt: 30.,
+            ..rotation
+        };
+        let north_pole =
+            SurfaceLocation::new(90., 0.).global_position(DVec3::ZERO, 1., &tilted, 0.3);
+        assert!((north_pole - tilted.axis()).length() < 1e-9);
+        let location = SurfaceLocation::new(45., -60.);
+        let offset =
+            tilted.to_body_fixed(location.global_position(DVec3::ZERO, 1., &tilted, 0.7), 0.7);
+        let round_trip = SurfaceLocation::from_body_fixed(offset);
+        assert!((round_trip.latitude - 45.).abs() < 1e-9);
+        assert!((round_trip.longitude + 60.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_update_rotations() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Explorer));
+        app.update();
+        for _ in 0..100 {
+            app.update();
+            FixedMain::run_fixed_main(app.world_mut());
+        }
+        let world = app.world_mut();
+        let time = world.resource::<GameTime>().time();
+        let earth = world.resource::<BodiesMapping>().0[&id_from("terre")];
+        let rotation = *world.query::<&BodyRotation>().get(world, earth).unwrap();
+        assert!((rotation.period - 0.997).abs() < 1e-3);
+        assert!((rotation.axial_tilt - 23.4393).abs() < 1e-9);
+        assert_eq!(rotation.angle, rotation.angle_at(time));
+    }
+}
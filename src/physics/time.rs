@@ -22,7 +22,7 @@ pub fn plugin(app: &mut App) {
         .add_event::<TickEvent>()
         .add_systems(
             FixedUpdate,
-            (update_simtick, update_tick).in_set(TimeUpdate),
+            (update_simtick, update_tick).chain().in_set(TimeUpdate),
         )
         .add_systems(Update, handle_time_events);
 }
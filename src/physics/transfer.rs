@@ -0,0 +1,551 @@
+//! Planning of transfers between celestial bodies.
+use std::{
+    f64::consts::{PI, TAU},
+    ops::RangeInclusive,
+};
+
+use bevy::{ecs::system::QueryLens, math::DVec3, prelude::*, utils::HashMap};
+
+use crate::{
+    objects::{prelude::*, ships::trajectory::ManeuverNode},
+    physics::prelude::*,
+    utils::algebra::global_to_orbital_matrix,
+};
+
+use super::{
+    orbit::ConicType,
+    predictions::get_bodies_coordinates,
+    time::{GAMETIME_PER_SIMTICK, SIMTICKS_PER_TICK},
+    G,
+};
+
+const LAMBERT_ITERATIONS: usize = 200;
+const LAMBERT_TOLERANCE: f64 = 1e-12;
+
+/// Stumpff function C(z)
+fn stumpff_c(z: f64) -> f64 {
+    if z > 1e-6 {
+        (1. - z.sqrt().cos()) / z
+    } else if z < -1e-6 {
+        ((-z).sqrt().cosh() - 1.) / -z
+    } else {
+        1. / 2. - z / 24.
+    }
+}
+
+/// Stumpff function S(z)
+fn stumpff_s(z: f64) -> f64 {
+    if z > 1e-6 {
+        let s = z.sqrt();
+        (s - s.sin()) / s.powi(3)
+    } else if z < -1e-6 {
+        let s = (-z).sqrt();
+        (s.sinh() - s) / s.powi(3)
+    } else {
+        1. / 6. - z / 120.
+    }
+}
+
+/// Finds the velocities at both ends of the counterclockwise arc going from `r1` to `r2` in `flight_time`,
+/// see <https://en.wikipedia.org/wiki/Lambert%27s_problem> (universal variable formulation)
+pub fn lambert(r1: DVec3, r2: DVec3, flight_time: f64, mu: f64) -> Option<(DVec3, DVec3)> {
+    let (r1_norm, r2_norm) = (r1.length(), r2.length());
+    if flight_time <= 0. || mu <= 0. || r1_norm == 0. || r2_norm == 0. {
+        return None;
+    }
+    let mut angle = (r1.dot(r2) / (r1_norm * r2_norm)).clamp(-1., 1.).acos();
+    if r1.cross(r2).z < 0. {
+        angle = TAU - angle;
+    }
+    let a = angle.sin() * (r1_norm * r2_norm / (1. - angle.cos())).sqrt();
+    if !a.is_finite() || a.abs() < 1e-9 * (r1_norm + r2_norm) {
+        return None;
+    }
+    let y = |z: f64| r1_norm + r2_norm + a * (z * stumpff_s(z) - 1.) / stumpff_c(z).sqrt();
+    // The time of flight increases with z, and y < 0 corresponds to flights that are too short
+    let time_error = |z: f64| {
+        let y = y(z);
+        if y < 0. {
+            -flight_time
+        } else {
+            ((y / stumpff_c(z)).powf(1.5) * stumpff_s(z) + a * y.sqrt()) / mu.sqrt() - flight_time
+        }
+    };
+
+    let mut high = 4. * PI * PI * (1. - 1e-6);
+    let mut low = -4. * PI * PI;
+    while time_error(low) > 0. {
+        low *= 2.;
+        if low < -1e6 {
+            return None;
+        }
+    }
+    if time_error(high) < 0. {
+        return None;
+    }
+    let mut z = (low + high) / 2.;
+    for _ in 0..LAMBERT_ITERATIONS {
+        z = (low + high) / 2.;
+        let error = time_error(z);
+        if error.abs() < LAMBERT_TOLERANCE * flight_time {
+            break;
+        }
+        if error < 0. {
+            low = z;
+        } else {
+            high = z;
+        }
+    }
+
+    let y = y(z);
+    let f = 1. - y / r1_norm;
+    let g = a * (y / mu).sqrt();
+    let g_dot = 1. - y / r2_norm;
+    Some(((r2 - f * r1) / g, (g_dot * r2 - r1) / g))
+}
+
+/// The dates and delta-v of a transfer between two bodies
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TransferWindow {
+    pub departure_tick: u64,
+    pub arrival_tick: u64,
+    /// Total delta-v of the transfer (in km/d), that is the sum of the relative speeds at departure and arrival
+    pub delta_v: f64,
+    /// Velocity change needed at departure, in the global frame
+    pub departure_burn: DVec3,
+    /// Velocity change needed at arrival to match the velocity of the destination, in the global frame
+    pub arrival_burn: DVec3,
+    /// Position and velocity on the transfer arc right after departure, relative to the host
+    departure_state: (DVec3, DVec3),
+    /// Position and velocity on the transfer arc right before arrival, relative to the host
+    arrival_state: (DVec3, DVec3),
+}
+
+impl TransferWindow {
+    /// Converts the transfer into maneuver nodes (with their tick) that can be inserted into a ship's trajectory.
+    ///
+    /// The thrusts are expressed relative to the common host of both bodies, assuming that the ship is close to the
+    /// departure body when leaving and follows the transfer arc until arrival.
+    pub fn maneuver_nodes(&self, host: BodyID) -> [(u64, ManeuverNode); 2] {
+        let node = |name: &str, (pos, speed): (DVec3, DVec3), burn: DVec3| ManeuverNode {
+            name: name.into(),
+            thrust: global_to_orbital_matrix(DVec3::ZERO, DVec3::ZERO, pos, speed) * burn,
+            origin: host,
+            duration: None,
+            thrust_magnitude: None,
+        };
+        let (pos, speed) = self.departure_state;
+        [
+            (
+                self.departure_tick,
+                node(
+                    "Departure",
+                    (pos, speed - self.departure_burn),
+                    self.departure_burn,
+                ),
+            ),
+            (
+                self.arrival_tick,
+                node("Arrival", self.arrival_state, self.arrival_burn),
+            ),
+        ]
+    }
+}
+
+/// The departure ticks and flight durations (in ticks) explored by [porkchop]
+#[derive(Clone, Debug)]
+pub struct PorkchopSearch {
+    pub departure: RangeInclusive<u64>,
+    pub flight_time: RangeInclusive<u64>,
+    pub step: u64,
+}
+
+/// Computes the transfers between two bodies orbiting the same host for every departure and flight time of the grid
+pub fn porkchop(
+    origin: Entity,
+    destination: Entity,
+    search: &PorkchopSearch,
+    bodies: &mut QueryLens<(&EllipticalOrbit, &BodyInfo)>,
+    mapping: &HashMap<BodyID, Entity>,
+) -> Vec<TransferWindow> {
+    let query = bodies.query();
+    let (Ok((_, BodyInfo(origin_data))), Ok((_, BodyInfo(destination_data)))) =
+        (query.get(origin), query.get(destination))
+    else {
+        return Vec::new();
+    };
+    let Some(host) = origin_data
+        .host_body
+        .filter(|host| Some(*host) == destination_data.host_body)
+        .and_then(|host| mapping.get(&host))
+    else {
+        return Vec::new();
+    };
+    let mu = G * query.get(*host).unwrap().1 .0.mass;
+    let coordinates = |bodies: &mut QueryLens<(&EllipticalOrbit, &BodyInfo)>, body, tick| {
+        let coords = get_bodies_coordinates(
+            [body, *host].into_iter(),
+            bodies,
+            mapping,
+            tick * SIMTICKS_PER_TICK,
+        );
+        (coords[0].0 - coords[1].0, coords[0].1 - coords[1].1)
+    };
+
+    let step = search.step.max(1);
+    let mut windows = Vec::new();
+    for departure_tick in search.departure.clone().step_by(step as usize) {
+        let (r1, v_origin) = coordinates(bodies, origin, departure_tick);
+        for flight_time in search.flight_time.clone().step_by(step as usize) {
+            let arrival_tick = departure_tick + flight_time;
+            let (r2, v_destination) = coordinates(bodies, destination, arrival_tick);
+            let duration = (flight_time * SIMTICKS_PER_TICK) as f64 * GAMETIME_PER_SIMTICK;
+            if let Some((v1, v2)) = lambert(r1, r2, duration, mu) {
+                let (departure_burn, arrival_burn) = (v1 - v_origin, v_destination - v2);
+                windows.push(TransferWindow {
+                    departure_tick,
+                    arrival_tick,
+                    delta_v: departure_burn.length() + arrival_burn.length(),
+                    departure_burn,
+                    arrival_burn,
+                    departure_state: (r1, v1),
+                    arrival_state: (r2, v2),
+                });
+            }
+        }
+    }
+    windows
+}
+
+/// Returns the transfer of the search grid that needs the least delta-v
+pub fn best_transfer(
+    origin: Entity,
+    destination: Entity,
+    search: &PorkchopSearch,
+    bodies: &mut QueryLens<(&EllipticalOrbit, &BodyInfo)>,
+    mapping: &HashMap<BodyID, Entity>,
+) -> Option<TransferWindow> {
+    porkchop(origin, destination, search, bodies, mapping)
+        .into_iter()
+        .min_by(|a, b| a.delta_v.total_cmp(&b.delta_v))
+}
+
+/// Speed of an object on an orbit of the given semimajor axis, at some distance from the host (vis-viva equation)
+pub fn orbital_speed(mu: f64, distance: f64, semimajor_axis: f64) -> f64 {
+    (mu * (2. / distance - 1. / semimajor_axis)).sqrt()
+}
+
+/// Converts a duration in days into a number of ticks
+fn duration_in_ticks(duration: f64) -> u64 {
+    (duration / (GAMETIME_PER_SIMTICK * SIMTICKS_PER_TICK as f64)).round() as u64
+}
+
+/// The state of a ship relative to its main influencer, from which maneuvers between circular orbits are planned
+#[derive(Clone, Copy, Debug)]
+pub struct ShipOrbitState {
+    /// Position relative to the host
+    pub pos: DVec3,
+    /// Velocity relative to the host
+    pub speed: DVec3,
+    pub simtick: u64,
+    pub host: BodyID,
+    pub host_mass: f64,
+}
+
+impl ShipOrbitState {
+    fn mu(&self) -> f64 {
+        G * self.host_mass
+    }
+
+    /// The first tick at which a maneuver node can be placed
+    fn first_tick(&self) -> u64 {
+        self.simtick.div_ceil(SIMTICKS_PER_TICK)
+    }
+
+    /// A burn along the velocity of the ship, in the forward/right/down frame of [crate::utils::algebra::relative_axes]
+    fn prograde_node(&self, name: &str, delta_v: f64) -> ManeuverNode {
+        ManeuverNode {
+            name: name.into(),
+            thrust: DVec3::X * delta_v,
+            origin: self.host,
+            duration: None,
+            thrust_magnitude: None,
+        }
+    }
+
+    /// Generates the two nodes of a Hohmann transfer from the current (roughly circular) orbit to a circular orbit
+    /// of the given radius, the first one being placed as soon as possible
+    pub fn hohmann(&self, radius: f64) -> [(u64, ManeuverNode); 2] {
+        let mu = self.mu();
+        let start = self.pos.length();
+        let transfer_axis = (start + radius) / 2.;
+        let tick = self.first_tick();
+        let transfer_time = PI * (transfer_axis.powi(3) / mu).sqrt();
+        [
+            (
+                tick,
+                self.prograde_node(
+                    "Hohmann departure",
+                    orbital_speed(mu, start, transfer_axis) - self.speed.length(),
+                ),
+            ),
+            (
+                tick + duration_in_ticks(transfer_time),
+                self.prograde_node(
+                    "Hohmann arrival",
+                    (mu / radius).sqrt() - orbital_speed(mu, radius, transfer_axis),
+                ),
+            ),
+        ]
+    }
+
+    /// Generates the three nodes of a bi-elliptic transfer from the current (roughly circular) orbit to a circular
+    /// orbit of the given radius, going through the apoapsis `intermediate_radius`
+    pub fn bi_elliptic(&self, radius: f64, intermediate_radius: f64) -> [(u64, ManeuverNode); 3] {
+        let mu = self.mu();
+        let start = self.pos.length();
+        let (first_axis, second_axis) = (
+            (start + intermediate_radius) / 2.,
+            (radius + intermediate_radius) / 2.,
+        );
+        let first_tick = self.first_tick();
+        let second_tick = first_tick + duration_in_ticks(PI * (first_axis.powi(3) / mu).sqrt());
+        let third_tick = second_tick + duration_in_ticks(PI * (second_axis.powi(3) / mu).sqrt());
+        [
+            (
+                first_tick,
+                self.prograde_node(
+                    "Bi-elliptic departure",
+                    orbital_speed(mu, start, first_axis) - self.speed.length(),
+                ),
+            ),
+            (
+                second_tick,
+                self.prograde_node(
+                    "Bi-elliptic apoapsis",
+                    orbital_speed(mu, intermediate_radius, second_axis)
+                        - orbital_speed(mu, intermediate_radius, first_axis),
+                ),
+            ),
+            (
+                third_tick,
+                self.prograde_node(
+                    "Bi-elliptic arrival",
+                    (mu / radius).sqrt() - orbital_speed(mu, radius, second_axis),
+                ),
+            ),
+        ]
+    }
+
+    /// Generates the node placed at the next apoapsis of the current orbit that changes the periapsis to the given
+    /// distance from the host. Returns `None` for open orbits.
+    pub fn set_periapsis(&self, periapsis: f64) -> Option<(u64, ManeuverNode)> {
+        let time = self.simtick as f64 * GAMETIME_PER_SIMTICK;
+        let orbit =
+            EllipticalOrbit::from_state_vectors(self.pos, self.speed, self.host_mass, time)?;
+        if orbit.conic_type() != ConicType::Elliptic {
+            return None;
+        }
+        let mu = self.mu();
+        let apoapsis = orbit.apoapsis();
+        let delay = (180. - orbit.mean_anomaly) / 360. * orbit.revolution_period;
+        let current_speed = orbital_speed(mu, apoapsis, orbit.semimajor_axis);
+        let target_speed = orbital_speed(mu, apoapsis, (apoapsis + periapsis) / 2.);
+        Some((
+            duration_in_ticks(time + delay).max(self.first_tick()),
+            self.prograde_node("Periapsis change", target_speed - current_speed),
+        ))
+    }
+
+    /// Generates the node placed at the next apoapsis of the current orbit that makes it circular
+    pub fn circularize_at_apoapsis(&self) -> Option<(u64, ManeuverNode)> {
+        let time = self.simtick as f64 * GAMETIME_PER_SIMTICK;
+        let apoapsis =
+            EllipticalOrbit::from_state_vectors(self.pos, self.speed, self.host_mass, time)?
+                .apoapsis();
+        self.set_periapsis(apoapsis).map(|(tick, mut node)| {
+            node.name = "Circularization".into();
+            (tick, node)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::system::SystemState, math::DVec3, prelude::*};
+
+    use crate::{
+        objects::ships::trajectory::Trajectory,
+        physics::{integrator::Integrator, time::GAMETIME_PER_SIMTICK, G},
+        prelude::*,
+        utils::algebra::orbital_to_global_matrix,
+    };
+
+    use super::*;
+
+    const SUN_MASS: f64 = 1.9885e30;
+    const EARTH_MASS: f64 = 5.97237e24;
+    const KM_S: f64 = 86400.;
+
+    /// Propagates a ship around a point mass until the given tick, applying the maneuver nodes on the way
+    fn follow_nodes(
+        state: &ShipOrbitState,
+        nodes: &[(u64, ManeuverNode)],
+        end_tick: u64,
+    ) -> EllipticalOrbit {
+        let acceleration = |pos: DVec3, _| -G * state.host_mass * pos / pos.length().powi(3);
+        let (mut pos, mut speed) = (state.pos, state.speed);
+        for simtick in state.simtick..end_tick * SIMTICKS_PER_TICK {
+            if simtick % SIMTICKS_PER_TICK == 0 {
+                for (_, node) in nodes
+                    .iter()
+                    .filter(|(t, _)| *t * SIMTICKS_PER_TICK == simtick)
+                {
+                    speed += orbital_to_global_matrix(DVec3::ZERO, DVec3::ZERO, pos, speed)
+                        * node.thrust;
+                }
+            }
+            let step = Integrator::RK4.step(
+                pos,
+                speed,
+                acceleration(pos, 0.),
+                0.,
+                GAMETIME_PER_SIMTICK,
+                acceleration,
+            );
+            (pos, speed) = (step.pos, step.speed);
+        }
+        EllipticalOrbit::from_state_vectors(pos, speed, state.host_mass, 0.).unwrap()
+    }
+
+    fn circular_state(radius: f64, speed_factor: f64) -> ShipOrbitState {
+        ShipOrbitState {
+            pos: DVec3::new(radius, 0., 0.),
+            speed: DVec3::new(0., speed_factor * (G * EARTH_MASS / radius).sqrt(), 0.),
+            simtick: 5,
+            host: id_from("terre"),
+            host_mass: EARTH_MASS,
+        }
+    }
+
+    #[test]
+    fn test_hohmann() {
+        for (start, end) in [(1e5, 3e5), (3e5, 1e5)] {
+            let state = circular_state(start, 1.);
+            let nodes = state.hohmann(end);
+            assert_eq!(nodes[0].0, 1);
+            let orbit = follow_nodes(&state, &nodes, nodes[1].0 + 100);
+            assert!(orbit.eccentricity < 1e-2);
+            assert!((orbit.semimajor_axis - end).abs() < 1e-2 * end);
+        }
+    }
+
+    #[test]
+    fn test_bi_elliptic() {
+        let state = circular_state(1e5, 1.);
+        let nodes = state.bi_elliptic(3e5, 6e5);
+        let orbit = follow_nodes(&state, &nodes, nodes[2].0 + 100);
+        assert!(orbit.eccentricity < 1e-2);
+        assert!((orbit.semimajor_axis - 3e5).abs() < 3e3);
+        // Bi-elliptic transfers are less efficient for such small radius ratios
+        let total = |nodes: &[(u64, ManeuverNode)]| {
+            nodes.iter().map(|(_, n)| n.thrust.length()).sum::<f64>()
+        };
+        assert!(total(&nodes) > total(&state.hohmann(3e5)));
+    }
+
+    #[test]
+    fn test_periapsis_change() {
+        let state = circular_state(1e5, 1.1);
+        let apoapsis = EllipticalOrbit::from_state_vectors(state.pos, state.speed, EARTH_MASS, 0.)
+            .unwrap()
+            .apoapsis();
+        let nodes = [state.circularize_at_apoapsis().unwrap()];
+        let orbit = follow_nodes(&state, &nodes, nodes[0].0 + 100);
+        assert!(orbit.eccentricity < 1e-2);
+        assert!((orbit.semimajor_axis - apoapsis).abs() < 1e-2 * apoapsis);
+
+        let nodes = [state.set_periapsis(1.5e5).unwrap()];
+        let orbit = follow_nodes(&state, &nodes, nodes[0].0 + 100);
+        assert!((orbit.periapsis - 1.5e5).abs() < 1.5e3);
+        assert!((orbit.apoapsis() - apoapsis).abs() < 1e-2 * apoapsis);
+    }
+
+    #[test]
+    fn test_lambert_circular() {
+        let mu = G * SUN_MASS;
+        let radius: f64 = 1.5e8;
+        let period = TAU * (radius.powi(3) / mu).sqrt();
+        let speed = (mu / radius).sqrt();
+        let (v1, v2) = lambert(
+            DVec3::new(radius, 0., 0.),
+            DVec3::new(0., radius, 0.),
+            period / 4.,
+            mu,
+        )
+        .unwrap();
+        assert!((v1 - DVec3::new(0., speed, 0.)).length() < 1e-6 * speed);
+        assert!((v2 - DVec3::new(-speed, 0., 0.)).length() < 1e-6 * speed);
+    }
+
+    #[test]
+    fn test_lambert_round_trip() {
+        let mu = G * SUN_MASS;
+        for (pos, speed, duration) in [
+            (DVec3::new(1.5e8, 0., 0.), DVec3::new(0., 2.8e6, 1e5), 100.),
+            (
+                DVec3::new(-1e8, 5e7, 0.),
+                DVec3::new(-1e6, -2.5e6, 0.),
+                120.,
+            ),
+            // Hyperbolic trajectory
+            (DVec3::new(1e8, 0., 0.), DVec3::new(0., 5e6, 0.), 50.),
+        ] {
+            let orbit = EllipticalOrbit::from_state_vectors(pos, speed, SUN_MASS, 0.).unwrap();
+            let (end_pos, end_speed) = orbit.state_vectors(duration);
+            let (v1, v2) = lambert(pos, end_pos, duration, mu).unwrap();
+            assert!((v1 - speed).length() < 1e-6 * speed.length());
+            assert!((v2 - end_speed).length() < 1e-6 * end_speed.length());
+        }
+    }
+
+    #[test]
+    fn test_porkchop() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        app.update();
+        let world = app.world_mut();
+        #[allow(clippy::type_complexity)]
+        let mut system_state: SystemState<(
+            Res<BodiesMapping>,
+            Query<(&EllipticalOrbit, &BodyInfo)>,
+        )> = SystemState::new(world);
+        let (mapping, mut bodies) = system_state.get(world);
+        let (earth, mars) = (mapping.0[&id_from("terre")], mapping.0[&id_from("mars")]);
+        let ticks_per_day = (1. / (GAMETIME_PER_SIMTICK * SIMTICKS_PER_TICK as f64)) as u64;
+        // A synodic period of departures, and flights between 150 and 350 days
+        let search = PorkchopSearch {
+            departure: 0..=780 * ticks_per_day,
+            flight_time: 150 * ticks_per_day..=350 * ticks_per_day,
+            step: 10 * ticks_per_day,
+        };
+        let best = best_transfer(
+            earth,
+            mars,
+            &search,
+            &mut bodies.as_query_lens(),
+            &mapping.0,
+        )
+        .unwrap();
+        // A Hohmann transfer between circular orbits needs 5.6 km/s
+        assert!(4.5 * KM_S < best.delta_v && best.delta_v < 8. * KM_S);
+
+        let mut trajectory = Trajectory::default();
+        trajectory
+            .nodes
+            .extend(best.maneuver_nodes(id_from("soleil")));
+        assert_eq!(trajectory.nodes.len(), 2);
+        let departure = &trajectory.nodes[&best.departure_tick];
+        assert!((departure.thrust.length() - best.departure_burn.length()).abs() < 1e-6 * KM_S);
+    }
+}
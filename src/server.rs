@@ -1,6 +1,6 @@
 use std::net::IpAddr;
 
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
 use bevy_quinnet::{
     server::{
         certificate::CertificateRetrievalMode, QuinnetServer, QuinnetServerPlugin,
@@ -14,9 +14,16 @@ pub mod prelude {
 }
 
 use crate::{
-    game::GamePlugin,
-    network::{ServerChannel, ServerMessage},
-    prelude::{BodiesConfig, GameTime},
+    game::{GamePlugin, GameSeed},
+    network::{ClientMessage, ServerChannel, ServerMessage},
+    objects::{
+        companies::{CompanyEvent, CompanyID},
+        contracts::{ContractEvent, ContractUpdate, Contracts},
+        markets::{Market, TradeEvent, TradeOutcome},
+        ships::{trajectory::TrajectoryEvent, ShipEvent},
+        ObjectsUpdate,
+    },
+    prelude::{BodiesConfig, BodyInfo, GameTime, Owner, ShipsMapping},
     utils::ecs::exit_on_error_if_app,
 };
 
@@ -27,27 +34,50 @@ pub struct ServerPlugin {
 
 impl Plugin for ServerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins((GamePlugin::default(), QuinnetServerPlugin::default()))
-            .add_event::<ClientConnectionEvent>()
-            .insert_resource(self.server_address.clone())
-            .insert_resource(self.config.clone())
-            .insert_resource(Clients::default())
-            .insert_resource(PeriodicUpdatesTimer(Timer::from_seconds(
-                1.,
-                TimerMode::Repeating,
-            )))
-            .add_systems(Startup, start_endpoint.pipe(exit_on_error_if_app))
-            .add_systems(
-                Update,
-                (
-                    update_clients,
-                    handle_connection_events.pipe(exit_on_error_if_app),
-                    send_periodic_updates,
-                ),
-            );
+        app.add_plugins((
+            GamePlugin::default(),
+            QuinnetServerPlugin::default(),
+            commands_plugin,
+        ))
+        .add_event::<ClientConnectionEvent>()
+        .insert_resource(self.server_address.clone())
+        .insert_resource(self.config.clone())
+        .insert_resource(Clients::default())
+        .insert_resource(PeriodicUpdatesTimer(Timer::from_seconds(
+            1.,
+            TimerMode::Repeating,
+        )))
+        .add_systems(Startup, start_endpoint.pipe(exit_on_error_if_app))
+        .add_systems(
+            Update,
+            (
+                update_clients,
+                handle_connection_events.pipe(exit_on_error_if_app),
+                receive_client_messages.before(handle_client_commands),
+                send_periodic_updates,
+                send_contracts
+                    .run_if(resource_exists::<Contracts>)
+                    .run_if(on_event::<ContractUpdate>()),
+            ),
+        )
+        .add_systems(PostUpdate, send_replies);
     }
 }
 
+/// Applies the commands of the clients, apart from the network so that it can run without connections
+fn commands_plugin(app: &mut App) {
+    app.add_event::<ClientCommand>()
+        .add_event::<ServerReply>()
+        .init_resource::<ClientCompanies>()
+        .add_systems(
+            Update,
+            (
+                handle_client_commands.before(ObjectsUpdate),
+                send_trade_outcomes.after(ObjectsUpdate),
+            ),
+        );
+}
+
 #[derive(Clone, Resource)]
 pub struct ServerNetworkInfo(pub IpAddr, pub u16);
 
@@ -60,6 +90,24 @@ enum ClientConnectionEvent {
     Disconnected(ClientId),
 }
 
+/// A message received from a client
+#[derive(Event)]
+struct ClientCommand {
+    client: ClientId,
+    message: ClientMessage,
+}
+
+/// A message sent to a single client
+#[derive(Event)]
+struct ServerReply {
+    client: ClientId,
+    message: ServerMessage,
+}
+
+/// The company that each client plays, bound when it joins
+#[derive(Resource, Default)]
+struct ClientCompanies(HashMap<ClientId, CompanyID>);
+
 #[derive(Resource)]
 struct PeriodicUpdatesTimer(Timer);
 
@@ -99,7 +147,10 @@ fn update_clients(
 fn handle_connection_events(
     mut reader: EventReader<ClientConnectionEvent>,
     mut server: ResMut<QuinnetServer>,
+    mut companies: ResMut<ClientCompanies>,
     bodies_config: Res<BodiesConfig>,
+    seed: Option<Res<GameSeed>>,
+    contracts: Option<Res<Contracts>>,
 ) -> color_eyre::Result<()> {
     let endpoint = server.endpoint_mut();
     for event in reader.read() {
@@ -110,27 +161,331 @@ fn handle_connection_events(
                     *id,
                     ServerChannel::Once,
                     ServerMessage::BodiesConfig(bodies_config.clone()),
-                )?
+                )?;
+                if let Some(seed) = &seed {
+                    endpoint.send_message_on(
+                        *id,
+                        ServerChannel::Once,
+                        ServerMessage::GameSeed(seed.0),
+                    )?;
+                }
+                if let Some(contracts) = &contracts {
+                    endpoint.send_message_on(
+                        *id,
+                        ServerChannel::ReliableUpdates,
+                        ServerMessage::UpdateContracts(contracts.as_ref().clone()),
+                    )?;
+                }
             }
             ClientConnectionEvent::Disconnected(id) => {
                 println!("Client disconnected with id {id}");
+                // Its company can be joined again
+                companies.0.remove(id);
             }
         }
     }
     Ok(())
 }
 
+fn receive_client_messages(
+    mut server: ResMut<QuinnetServer>,
+    clients: Res<Clients>,
+    mut writer: EventWriter<ClientCommand>,
+) {
+    let endpoint = server.endpoint_mut();
+    for client in &clients.0 {
+        while let Some((_, message)) = endpoint.try_receive_message_from::<ClientMessage>(*client) {
+            writer.send(ClientCommand {
+                client: *client,
+                message,
+            });
+        }
+    }
+}
+
+/// Forwards the commands of the clients on behalf of the company they joined with, ownership being checked here for
+/// trajectories and when applying trades
+#[allow(clippy::too_many_arguments)]
+fn handle_client_commands(
+    mut reader: EventReader<ClientCommand>,
+    mut replies: EventWriter<ServerReply>,
+    mut bound: ResMut<ClientCompanies>,
+    mut companies: EventWriter<CompanyEvent>,
+    mut ship_events: EventWriter<ShipEvent>,
+    mut trades: EventWriter<TradeEvent>,
+    mut trajectories: EventWriter<TrajectoryEvent>,
+    mut contracts: EventWriter<ContractEvent>,
+    ships: Option<Res<ShipsMapping>>,
+    owners: Query<&Owner>,
+) {
+    for ClientCommand { client, message } in reader.read() {
+        match (message, bound.0.get(client).copied()) {
+            (ClientMessage::Join(company), _) => {
+                if bound
+                    .0
+                    .iter()
+                    .any(|(c, joined)| joined == company && c != client)
+                {
+                    println!("Client {client} cannot join as {company}, which is already played");
+                    replies.send(ServerReply {
+                        client: *client,
+                        message: ServerMessage::JoinRejected(*company),
+                    });
+                } else {
+                    bound.0.insert(*client, *company);
+                    companies.send(CompanyEvent::Create(*company));
+                }
+            }
+            (_, None) => println!("Client {client} has to join before sending commands"),
+            (ClientMessage::CreateShip(info), Some(company)) => {
+                if ships
+                    .as_ref()
+                    .is_some_and(|ships| ships.0.contains_key(&info.id))
+                {
+                    println!(
+                        "Client {client} cannot create ship {}, which already exists",
+                        info.id
+                    );
+                    continue;
+                }
+                ship_events.send(ShipEvent::Create(info.clone()));
+                ship_events.send(ShipEvent::SetOwner {
+                    ship: info.id,
+                    owner: Some(company),
+                });
+            }
+            (ClientMessage::Trade(order), Some(company)) => {
+                trades.send(TradeEvent {
+                    company,
+                    order: order.clone(),
+                });
+            }
+            (ClientMessage::Trajectory(event), Some(company)) => {
+                let owned = ships
+                    .as_ref()
+                    .and_then(|ships| ships.0.get(&event.ship()))
+                    .and_then(|e| owners.get(*e).ok())
+                    .is_some_and(|Owner(owner)| *owner == company);
+                if owned {
+                    trajectories.send(event.clone());
+                } else {
+                    println!(
+                        "Client {client} cannot change the trajectory of a ship of another company"
+                    );
+                }
+            }
+            (ClientMessage::Contract(action), Some(company)) => {
+                contracts.send(ContractEvent {
+                    company,
+                    action: action.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// The outcomes are sent to the client playing the company that requested the trade
+fn send_trade_outcomes(
+    mut outcomes: EventReader<TradeOutcome>,
+    mut replies: EventWriter<ServerReply>,
+    bound: Res<ClientCompanies>,
+    bodies: Query<&BodyInfo>,
+) {
+    for outcome in outcomes.read() {
+        let (TradeOutcome::Completed { trade, .. } | TradeOutcome::Rejected { trade, .. }) =
+            outcome;
+        let Some((client, _)) = bound.0.iter().find(|(_, c)| **c == trade.company) else {
+            continue;
+        };
+        let market = match outcome {
+            TradeOutcome::Completed { market, .. } => bodies.get(*market).ok(),
+            TradeOutcome::Rejected { .. } => None,
+        };
+        replies.send(ServerReply {
+            client: *client,
+            message: ServerMessage::TradeOutcome {
+                outcome: outcome.clone(),
+                market: market.map(|BodyInfo(data)| data.id),
+            },
+        });
+    }
+}
+
+fn send_replies(server: Res<QuinnetServer>, mut replies: EventReader<ServerReply>) {
+    for ServerReply { client, message } in replies.read() {
+        server
+            .endpoint()
+            .try_send_message_on(*client, ServerChannel::Once, message);
+    }
+}
+
 fn send_periodic_updates(
     mut timer: ResMut<PeriodicUpdatesTimer>,
     time: Res<Time>,
     mut server: ResMut<QuinnetServer>,
     game_time: Res<GameTime>,
+    markets: Query<(&BodyInfo, &Market)>,
 ) {
     timer.0.tick(time.delta());
     if timer.0.finished() {
-        server.endpoint_mut().try_broadcast_message_on(
+        let endpoint = server.endpoint_mut();
+        endpoint.try_broadcast_message_on(
             ServerChannel::PeriodicUpdates,
             ServerMessage::UpdateTime(game_time.simtick),
         );
+        if !markets.is_empty() {
+            endpoint.try_broadcast_message_on(
+                ServerChannel::ReliableUpdates,
+                ServerMessage::UpdateMarkets(
+                    markets
+                        .iter()
+                        .map(|(BodyInfo(data), market)| (data.id, market.clone()))
+                        .collect(),
+                ),
+            );
+        }
+    }
+}
+
+fn send_contracts(mut server: ResMut<QuinnetServer>, contracts: Res<Contracts>) {
+    server.endpoint_mut().try_broadcast_message_on(
+        ServerChannel::ReliableUpdates,
+        ServerMessage::UpdateContracts(contracts.clone()),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::event::Events, math::DVec3, prelude::*};
+    use bevy_quinnet::shared::ClientId;
+
+    use crate::{
+        network::{ClientMessage, ServerMessage},
+        objects::{companies::CompaniesMapping, markets::TradeError},
+        prelude::*,
+    };
+
+    use super::{commands_plugin, ClientCommand, ClientCompanies, ServerReply};
+
+    /// An authoritative game applying the commands of clients, without connecting them
+    fn server_game() -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            ClientPlugin::testing().in_mode(ClientMode::Singleplayer),
+            commands_plugin,
+        ));
+        app.update();
+        app
+    }
+
+    fn send(app: &mut App, client: ClientId, message: ClientMessage) -> Vec<ServerReply> {
+        app.world_mut()
+            .send_event(ClientCommand { client, message });
+        app.update();
+        app.world_mut()
+            .resource_mut::<Events<ServerReply>>()
+            .drain()
+            .collect()
+    }
+
+    #[test]
+    fn test_join() {
+        let mut app = server_game();
+        let company = id_from("a");
+        assert!(send(&mut app, 1, ClientMessage::Join(company)).is_empty());
+        assert!(app
+            .world()
+            .resource::<CompaniesMapping>()
+            .0
+            .contains_key(&company));
+
+        // Another client cannot play the same company
+        let replies = send(&mut app, 2, ClientMessage::Join(company));
+        assert!(matches!(
+            &replies[..],
+            [ServerReply {
+                client: 2,
+                message: ServerMessage::JoinRejected(c)
+            }] if *c == company
+        ));
+        let bound = &app.world().resource::<ClientCompanies>().0;
+        assert_eq!(bound.get(&1), Some(&company));
+        assert_eq!(bound.get(&2), None);
+    }
+
+    #[test]
+    fn test_create_ship() {
+        let mut app = server_game();
+        let (a, b) = (id_from("a"), id_from("b"));
+        send(&mut app, 1, ClientMessage::Join(a));
+        send(&mut app, 2, ClientMessage::Join(b));
+        let info = ShipInfo {
+            id: id_from("s"),
+            ..Default::default()
+        };
+        send(&mut app, 1, ClientMessage::CreateShip(info.clone()));
+        let ship = app.world().resource::<ShipsMapping>().0[&info.id];
+        assert_eq!(app.world().get::<Owner>(ship), Some(&Owner(a)));
+
+        // Creating a ship with the same id does not take it from its company
+        send(&mut app, 2, ClientMessage::CreateShip(info));
+        assert_eq!(app.world().get::<Owner>(ship), Some(&Owner(a)));
+    }
+
+    #[test]
+    fn test_trade() {
+        let mut app = server_game();
+        let (a, b) = (id_from("a"), id_from("b"));
+        send(&mut app, 1, ClientMessage::Join(a));
+        send(&mut app, 2, ClientMessage::Join(b));
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0[&id_from("terre")];
+        let (earth_pos, BodyInfo(data)) = world
+            .query::<(&Position, &BodyInfo)>()
+            .get(world, earth)
+            .unwrap();
+        let info = ShipInfo {
+            id: id_from("s"),
+            spawn_pos: earth_pos.0 + DVec3::new(data.radius * 1.2, 0., 0.),
+            spawn_speed: DVec3::ZERO,
+        };
+        send(&mut app, 1, ClientMessage::CreateShip(info));
+
+        // The outcome only goes back to the client that requested the trade
+        let buy = TradeOrder::Buy {
+            ship: id_from("s"),
+            commodity: id_from("water"),
+            quantity: 5,
+        };
+        let replies = send(&mut app, 1, ClientMessage::Trade(buy.clone()));
+        let [ServerReply {
+            client: 1,
+            message:
+                ServerMessage::TradeOutcome {
+                    outcome: TradeOutcome::Completed { trade, .. },
+                    market,
+                },
+        }] = &replies[..]
+        else {
+            panic!("the trade should have been completed for the first client");
+        };
+        assert_eq!(trade.company, a);
+        assert_eq!(*market, Some(id_from("terre")));
+
+        // The second client trades on behalf of its own company, which does not own the ship
+        let replies = send(&mut app, 2, ClientMessage::Trade(buy));
+        assert!(matches!(
+            &replies[..],
+            [ServerReply {
+                client: 2,
+                message: ServerMessage::TradeOutcome {
+                    outcome: TradeOutcome::Rejected {
+                        error: TradeError::NotOwned(_),
+                        ..
+                    },
+                    market: None,
+                },
+            }]
+        ));
     }
 }
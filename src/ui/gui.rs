@@ -13,10 +13,16 @@ use bevy::{
 };
 
 use crate::{
-    physics::{influence::HillRadius, orbit::SystemSize},
+    physics::{
+        frames::FrameState,
+        influence::HillRadius,
+        orbit::{ConicType, SystemSize},
+        predictions::Prediction,
+    },
     prelude::*,
     utils::{
         algebra::{center_to_periapsis_direction, ellipse_half_sizes},
+        bvh::{self, Bvh},
         ui::EllipseBuilder,
     },
 };
@@ -42,6 +48,7 @@ impl Plugin for GuiPlugin {
         app.add_plugins(editor_gui::plugin)
             .insert_resource(ClearColor(Color::Srgba(BLACK)))
             .add_event::<SelectObjectEvent>()
+            .init_resource::<SelectionIndex>()
             .add_systems(Startup, (camera_setup, color_setup))
             .add_systems(
                 OnEnter(Loaded),
@@ -52,7 +59,7 @@ impl Plugin for GuiPlugin {
             .add_systems(
                 PostUpdate,
                 (
-                    (update_transform, update_camera_pos)
+                    (update_transform, update_camera_pos, update_selection_index)
                         .chain()
                         .in_set(UiUpdate),
                     draw_gizmos.in_set(RenderSet),
@@ -118,6 +125,77 @@ impl SelectionRadius {
     }
 }
 
+/// Index of the selection circles of the objects on the map, rebuilt once they stop moving
+#[derive(Resource, Default)]
+pub struct SelectionIndex {
+    bvh: Bvh<Entity>,
+    up_to_date: bool,
+}
+
+/// The selection circle of an object, flattened on the map plane since the camera looks at it from above
+fn selection_circle(
+    (e, pos, rad): (Entity, &GlobalTransform, &SelectionRadius),
+    zoom_level: f64,
+) -> bvh::Sphere<Entity> {
+    bvh::Sphere::new(
+        pos.translation().xy().as_dvec2().extend(0.),
+        rad.radius(zoom_level) as f64,
+        e,
+    )
+}
+
+impl SelectionIndex {
+    pub fn invalidate(&mut self) {
+        self.up_to_date = false;
+    }
+
+    pub fn rebuild<'a>(
+        &mut self,
+        objects: impl IntoIterator<Item = (Entity, &'a GlobalTransform, &'a SelectionRadius)>,
+        zoom_level: f64,
+    ) {
+        self.bvh = objects
+            .into_iter()
+            .map(|object| selection_circle(object, zoom_level))
+            .collect();
+        self.up_to_date = true;
+    }
+
+    /// The object whose selection circle contains the point with the closest center, if any. The objects are scanned
+    /// when the index is outdated, which is cheaper than rebuilding it for a single click
+    pub fn select<'a>(
+        &self,
+        objects: impl IntoIterator<Item = (Entity, &'a GlobalTransform, &'a SelectionRadius)>,
+        zoom_level: f64,
+        point: DVec3,
+    ) -> Option<Entity> {
+        if self.up_to_date {
+            closest_center(
+                self.bvh.containing(point).map(|s| (s.center, s.value)),
+                point,
+            )
+        } else {
+            closest_center(
+                objects
+                    .into_iter()
+                    .map(|object| selection_circle(object, zoom_level))
+                    .filter(|s| s.contains(point))
+                    .map(|s| (s.center, s.value)),
+                point,
+            )
+        }
+    }
+}
+
+fn closest_center(circles: impl Iterator<Item = (DVec3, Entity)>, point: DVec3) -> Option<Entity> {
+    circles
+        .min_by(|a, b| {
+            a.0.distance_squared(point)
+                .total_cmp(&b.0.distance_squared(point))
+        })
+        .map(|(_, e)| e)
+}
+
 #[derive(Resource)]
 pub struct Colors {
     stars: Handle<StandardMaterial>,
@@ -161,6 +239,7 @@ fn insert_display_components(
     mut commands: Commands,
     bodies: Query<(Entity, &BodyInfo)>,
     ships: Query<Entity, With<ShipInfo>>,
+    points: Query<Entity, With<LagrangePoint>>,
     mut meshes: ResMut<Assets<Mesh>>,
     colors: Res<Colors>,
     system_size: Res<SystemSize>,
@@ -205,7 +284,19 @@ fn insert_display_components(
         }
     });
     for e in ships.iter() {
-        commands.entity(e).insert(TransformBundle::default());
+        commands.entity(e).insert((
+            TransformBundle::default(),
+            SelectionRadius {
+                min_radius: MAX_HEIGHT / 100.,
+                actual_radius: 0.,
+            },
+        ));
+    }
+    for e in points.iter() {
+        commands.entity(e).insert(SelectionRadius {
+            min_radius: MAX_HEIGHT / 200.,
+            actual_radius: 0.,
+        });
     }
 }
 
@@ -245,6 +336,7 @@ fn send_select_object_event(
     window: Query<&Window, With<PrimaryWindow>>,
     cam: Query<(&Camera, &GlobalTransform)>,
     mut writer: EventWriter<SelectObjectEvent>,
+    index: Res<SelectionIndex>,
     objects: Query<(Entity, &GlobalTransform, &SelectionRadius)>,
     map: Res<SpaceMap>,
 ) {
@@ -256,31 +348,48 @@ fn send_select_object_event(
         ) {
             if let Some(cursor_pos) = window.single().cursor_position() {
                 if let Some(translation) = cam.viewport_to_world_2d(cam_transform, cursor_pos) {
-                    objects
-                        .iter()
-                        .find(|(_, pos, rad)| {
-                            (pos.translation().xy() - translation).length()
-                                < rad.radius(map.zoom_level)
-                        })
-                        .map(|(entity, _, _)| {
-                            writer.send(SelectObjectEvent { entity, cursor_pos })
-                        });
+                    let point = translation.as_dvec2().extend(0.);
+                    if let Some(entity) = index.select(&objects, map.zoom_level, point) {
+                        writer.send(SelectObjectEvent { entity, cursor_pos });
+                    }
                 }
             }
         }
     }
 }
 
+/// Rebuilds the selection index once the objects stopped moving, at most once per frame, since rebuilding it at every
+/// click while time runs costs more than scanning the objects
+#[allow(clippy::type_complexity)]
+fn update_selection_index(
+    mut index: ResMut<SelectionIndex>,
+    changed: Query<(), Or<(Changed<GlobalTransform>, Changed<SelectionRadius>)>>,
+    mut removed: RemovedComponents<SelectionRadius>,
+    objects: Query<(Entity, &GlobalTransform, &SelectionRadius)>,
+    map: Res<SpaceMap>,
+) {
+    if removed.read().count() > 0 || !changed.is_empty() || map.is_changed() {
+        index.invalidate();
+    } else if !index.up_to_date {
+        index.rebuild(&objects, map.zoom_level);
+    }
+}
+
 fn update_camera_pos(
     space_map: Res<SpaceMap>,
     mut cam: Query<(&mut Transform, &mut Projection)>,
+    bodies: Query<(&Position, &Velocity, &BodyInfo)>,
     positions: Query<&Position>,
 ) {
     let scale = MAX_HEIGHT as f64 / space_map.system_size;
     let (mut cam_pos, mut proj) = cam.single_mut();
+    // The camera follows the focus object, which is at the origin of the frame unless it is a synodic frame or a
+    // Lagrange point
+    let frame = space_map.frame.state(&bodies);
     let focus_pos = space_map
         .focus_body
-        .map_or(DVec3::default(), |f| positions.get(f).unwrap().0);
+        .and_then(|f| positions.get(f).ok())
+        .map_or(DVec3::ZERO, |pos| frame.to_frame(pos.0, DVec3::ZERO).0);
     cam_pos.translation = ((focus_pos
         + DVec3::new(space_map.offset_amount.x, space_map.offset_amount.y, 0.))
         * scale)
@@ -291,10 +400,21 @@ fn update_camera_pos(
     }
 }
 
-fn update_transform(system_size: Res<SystemSize>, mut query: Query<(&mut Transform, &Position)>) {
+/// Places the objects at their position in the frame of the space map, except predictions which are placed by the editor
+fn update_transform(
+    system_size: Res<SystemSize>,
+    space_map: Option<Res<SpaceMap>>,
+    mut query: Query<(&mut Transform, &Position), Without<Prediction>>,
+    bodies: Query<(&Position, &Velocity, &BodyInfo)>,
+) {
     let scale = MAX_HEIGHT as f64 / system_size.0;
+    let frame = space_map.map_or_else(FrameState::default, |map| map.frame.state(&bodies));
     for (mut transform, Position(pos)) in query.iter_mut() {
-        transform.translation = (*pos * scale).as_vec3();
+        // Only touch the transforms that moved, so that a paused game keeps its selection index
+        let translation = (frame.to_frame(*pos, DVec3::ZERO).0 * scale).as_vec3();
+        if transform.translation != translation {
+            transform.translation = translation;
+        }
     }
 }
 
@@ -311,8 +431,26 @@ fn draw_gizmos(
     )>,
     ships: Query<(&Transform, &Velocity, &Influenced), With<ShipInfo>>,
     mapping: Res<BodiesMapping>,
+    frame_bodies: Query<(&Position, &Velocity, &BodyInfo)>,
+    points: Query<(Entity, &Transform), With<LagrangePoint>>,
 ) {
     let scale = MAX_HEIGHT as f64 / space_map.system_size;
+    let frame = space_map.frame.state(&frame_bodies);
+    let frame_rotation = frame.rotation().inverse();
+    // Display Lagrange points
+    for (e, t) in points.iter() {
+        let selected = space_map.selected == Some(e);
+        gizmos.circle_2d(
+            t.translation.xy(),
+            (MAX_HEIGHT as f64 / (if selected { 100. } else { 300. } * space_map.zoom_level))
+                as f32,
+            if selected {
+                Color::WHITE.with_alpha(0.5)
+            } else {
+                Color::srgba(1., 0., 1., 0.3)
+            },
+        );
+    }
     if let &SpaceMap {
         zoom_level,
         selected: Some(s),
@@ -337,6 +475,11 @@ fn draw_gizmos(
                 .iter()
                 .filter_map(|id| mapping.0.get(id))
             {
+                let orbit = bodies.get(i).unwrap().4;
+                // Only closed orbits can be drawn as ellipses
+                if orbit.conic_type() != ConicType::Elliptic {
+                    continue;
+                }
                 let &EllipticalOrbit {
                     semimajor_axis: a,
                     inclination: I,
@@ -346,7 +489,7 @@ fn draw_gizmos(
                     eccentric_anomaly: E,
                     revolution_period,
                     ..
-                } = bodies.get(i).unwrap().4;
+                } = orbit;
                 let (o, O, I, E) = (
                     o.to_radians(),
                     O.to_radians(),
@@ -362,14 +505,15 @@ fn draw_gizmos(
                 //         continue;
                 //     }
                 // }
-                let position =
-                    (scale * (peri - a) * center_to_periapsis_direction(o, O, I).normalize())
-                        .as_vec3()
-                        + parent_translation;
+                let position = (frame_rotation
+                    * (scale * (peri - a) * center_to_periapsis_direction(o, O, I).normalize()))
+                .as_vec3()
+                    + parent_translation;
                 let resolution = ((zoom_level * 100.) as usize).min(1000);
                 EllipseBuilder {
                     position,
-                    rotation: Quat::from_rotation_z(O as f32)
+                    rotation: frame_rotation.as_quat()
+                        * Quat::from_rotation_z(O as f32)
                         * Quat::from_rotation_x(I as f32)
                         * Quat::from_rotation_z(o as f32),
                     half_size: (ellipse_half_sizes(a, e) * scale).as_vec2(),
@@ -395,7 +539,8 @@ fn draw_gizmos(
             let ref_speed = influence
                 .main_influencer
                 .map_or(DVec3::ZERO, |e| bodies.get(e).unwrap().1 .0);
-            let speed = ((speed.0 - ref_speed).normalize_or(DVec3::X) * MAX_HEIGHT as f64
+            let speed = ((frame_rotation * (speed.0 - ref_speed)).normalize_or(DVec3::X)
+                * MAX_HEIGHT as f64
                 / (30. * zoom_level))
                 .xy()
                 .as_vec2();
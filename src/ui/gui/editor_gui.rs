@@ -1,5 +1,9 @@
 use crate::{
-    physics::{predictions::Prediction, time::SIMTICKS_PER_TICK},
+    physics::{
+        orbit::{EllipticalOrbit, SystemSize},
+        predictions::Prediction,
+        time::{GAMETIME_PER_SIMTICK, SIMTICKS_PER_TICK},
+    },
     prelude::*,
     ui::{
         gui::SelectionRadius,
@@ -11,7 +15,7 @@ use crate::{
             ClearOnEditorExit, EditorContext, SelectNode,
         },
         widget::space_map::SpaceMap,
-        RenderSet,
+        RenderSet, UiUpdate,
     },
     utils::algebra::relative_axes,
 };
@@ -22,6 +26,7 @@ use bevy::{
         mouse::{MouseButtonInput, MouseMotion, MouseScrollUnit, MouseWheel},
         ButtonState,
     },
+    math::DVec3,
     prelude::*,
     window::PrimaryWindow,
 };
@@ -41,9 +46,11 @@ pub fn plugin(app: &mut App) {
     app.init_resource::<CurrentGizmo>()
         .add_systems(
             PostUpdate,
-            (draw_predictions, draw_maneuver_node)
-                .in_set(RenderSet)
-                .run_if(resource_exists::<EditorContext>),
+            (
+                update_prediction_transform.in_set(UiUpdate),
+                (draw_predictions, draw_maneuver_node).in_set(RenderSet),
+            )
+                .run_if(resource_exists::<EditorContext>.and_then(resource_exists::<SpaceMap>)),
         )
         .add_systems(
             Update,
@@ -94,6 +101,35 @@ pub(super) struct ArrowGizmo {
     pub local_direction: Vec3,
 }
 
+/// Places each prediction in the frame of the space map, moving both frames to the time of the prediction
+fn update_prediction_transform(
+    ctx: Res<EditorContext>,
+    space_map: Res<SpaceMap>,
+    system_size: Res<SystemSize>,
+    mut predictions: Query<(&mut Transform, &Position, &Prediction)>,
+    bodies: Query<(&EllipticalOrbit, &BodyInfo)>,
+    mapping: Res<BodiesMapping>,
+) {
+    let scale = MAX_HEIGHT as f64 / system_size.0;
+    for (mut transform, &Position(pos), prediction) in predictions.iter_mut() {
+        let pos = if ctx.predictions_frame == space_map.frame {
+            pos
+        } else {
+            let time = (prediction.simtick + 1) as f64 * GAMETIME_PER_SIMTICK;
+            let (global, _) = ctx
+                .predictions_frame
+                .state_at(&bodies, &mapping.0, time)
+                .to_global(pos, DVec3::ZERO);
+            space_map
+                .frame
+                .state_at(&bodies, &mapping.0, time)
+                .to_frame(global, DVec3::ZERO)
+                .0
+        };
+        transform.translation = (pos * scale).as_vec3();
+    }
+}
+
 fn draw_predictions(
     mut gizmos: Gizmos,
     predictions: Query<(&Transform, &Prediction, Option<&TempPrediction>)>,
@@ -1,5 +1,6 @@
 use bevy::prelude::*;
 use bevy_ratatui::{event::KeyEvent, terminal::RatatuiContext};
+use contracts::{ContractsContext, ContractsScreen};
 use editor::{EditorContext, EditorScreen};
 use explorer::{ExplorerContext, ExplorerScreen};
 use fleet::{FleetContext, FleetScreen};
@@ -13,6 +14,7 @@ use crate::{
 
 use super::{widget::space_map::SpaceMap, InputReading, RenderSet};
 
+pub mod contracts;
 pub mod editor;
 pub mod explorer;
 pub mod fleet;
@@ -27,6 +29,7 @@ pub enum AppScreen {
     StartMenu,
     Explorer,
     Fleet,
+    Contracts,
     Editor(ShipID),
 }
 
@@ -38,6 +41,7 @@ pub fn plugin(app: &mut App) {
         start::plugin,
         explorer::plugin,
         fleet::plugin,
+        contracts::plugin,
         editor::plugin,
     ))
     .init_state::<AppScreen>()
@@ -83,12 +87,14 @@ fn clear_key_events(mut events: ResMut<Events<KeyEvent>>) {
     events.clear();
 }
 
+#[allow(clippy::too_many_arguments)]
 fn render(
     mut ctx: ResMut<RatatuiContext>,
     screen: Res<State<AppScreen>>,
     start_menu: Option<ResMut<StartMenuContext>>,
     explorer: Option<ResMut<ExplorerContext>>,
     fleet: Option<ResMut<FleetContext>>,
+    contracts: Option<ResMut<ContractsContext>>,
     editor: Option<ResMut<EditorContext>>,
     space_map: Option<ResMut<SpaceMap>>,
 ) -> color_eyre::Result<()> {
@@ -110,6 +116,11 @@ fn render(
         AppScreen::Fleet => {
             f.render_stateful_widget(FleetScreen, f.size(), fleet.unwrap().as_mut())
         }
+        AppScreen::Contracts => {
+            if let Some(mut contracts) = contracts {
+                f.render_stateful_widget(ContractsScreen, f.size(), contracts.as_mut())
+            }
+        }
         AppScreen::Editor(_) => {
             f.render_stateful_widget(EditorScreen, f.size(), editor.unwrap().as_mut())
         }
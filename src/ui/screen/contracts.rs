@@ -0,0 +1,286 @@
+use bevy::prelude::*;
+use bevy_ratatui::event::KeyEvent;
+use crossterm::event::KeyEventKind;
+use ratatui::{
+    layout::{Constraint, Layout},
+    widgets::{Block, List, ListState, Paragraph, StatefulWidget, Widget},
+};
+
+use crate::{
+    objects::contracts::{ContractID, Issuer},
+    prelude::*,
+    ui::UiUpdate,
+};
+
+pub fn plugin(app: &mut App) {
+    app.add_event::<ContractsScreenEvent>()
+        .add_systems(
+            Update,
+            (
+                read_input.in_set(InputReading),
+                handle_contracts_events.in_set(EventHandling),
+            )
+                .run_if(in_loaded_screen::<ContractsContext>(AppScreen::Contracts)),
+        )
+        .add_systems(
+            PostUpdate,
+            update_contracts_context
+                .run_if(resource_exists::<ContractsContext>)
+                .run_if(resource_exists_and_changed::<Contracts>)
+                .in_set(UiUpdate),
+        )
+        .add_systems(OnEnter(AppScreen::Contracts), create_screen)
+        .add_systems(OnExit(AppScreen::Contracts), clear_screen);
+}
+
+fn create_screen(
+    mut commands: Commands,
+    contracts: Option<Res<Contracts>>,
+    player: Res<PlayerCompany>,
+) {
+    let mut ctx = ContractsContext::default();
+    if let Some(contracts) = contracts {
+        ctx.update(&contracts, &player);
+    }
+    commands.insert_resource(ctx);
+}
+
+fn clear_screen(mut commands: Commands) {
+    commands.remove_resource::<ContractsContext>();
+}
+
+#[derive(Resource, Default)]
+pub struct ContractsContext {
+    list_state: ListState,
+    /// The open contracts and the ones of the player
+    contracts: Vec<Contract>,
+}
+
+impl ContractsContext {
+    fn update(&mut self, contracts: &Contracts, player: &PlayerCompany) {
+        self.contracts = contracts
+            .contracts
+            .values()
+            .filter(|c| c.status == ContractStatus::Open || c.contractor() == Some(&player.0))
+            .cloned()
+            .collect();
+        let len = self.contracts.len();
+        if self.list_state.selected().is_some_and(|i| i >= len) {
+            self.list_state.select(len.checked_sub(1));
+        }
+    }
+
+    fn selected_contract(&self) -> Option<&Contract> {
+        self.list_state
+            .selected()
+            .and_then(|i| self.contracts.get(i))
+    }
+}
+
+impl ClampedList for ContractsContext {
+    fn list_state(&mut self) -> &mut ListState {
+        &mut self.list_state
+    }
+
+    fn len(&self) -> usize {
+        self.contracts.len()
+    }
+}
+
+#[derive(Event, Clone, Copy)]
+pub enum ContractsScreenEvent {
+    Select(Direction2),
+    Accept,
+    Abandon,
+    Back,
+}
+
+pub struct ContractsScreen;
+
+fn read_input(
+    mut key_event: EventReader<KeyEvent>,
+    keymap: Res<Keymap>,
+    mut internal_event: EventWriter<ContractsScreenEvent>,
+) {
+    use ContractsScreenEvent::*;
+    use Direction2::*;
+    let keymap = &keymap.contracts_screen;
+    for KeyEvent(event) in key_event.read() {
+        if event.kind == KeyEventKind::Release {
+            return;
+        }
+        match event {
+            e if keymap.select_next.matches(e) => {
+                internal_event.send(Select(Down));
+            }
+            e if keymap.select_previous.matches(e) => {
+                internal_event.send(Select(Up));
+            }
+            e if keymap.accept.matches(e) => {
+                internal_event.send(Accept);
+            }
+            e if keymap.abandon.matches(e) => {
+                internal_event.send(Abandon);
+            }
+            e if keymap.back.matches(e) => {
+                internal_event.send(Back);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn handle_contracts_events(
+    mut context: ResMut<ContractsContext>,
+    mut next_screen: ResMut<NextState<AppScreen>>,
+    mut events: EventReader<ContractsScreenEvent>,
+    mut contract_events: EventWriter<ContractEvent>,
+    player: Res<PlayerCompany>,
+) {
+    for event in events.read() {
+        let selected: Option<ContractID> = context.selected_contract().map(|c| c.id);
+        match event {
+            ContractsScreenEvent::Select(d) => context.select_adjacent(*d),
+            ContractsScreenEvent::Accept => {
+                if let Some(contract) = selected {
+                    contract_events.send(ContractEvent {
+                        company: player.0,
+                        action: ContractAction::Accept(contract),
+                    });
+                }
+            }
+            ContractsScreenEvent::Abandon => {
+                if let Some(contract) = selected {
+                    contract_events.send(ContractEvent {
+                        company: player.0,
+                        action: ContractAction::Abandon(contract),
+                    });
+                }
+            }
+            ContractsScreenEvent::Back => next_screen.set(AppScreen::Fleet),
+        }
+    }
+}
+
+fn update_contracts_context(
+    mut ctx: ResMut<ContractsContext>,
+    contracts: Res<Contracts>,
+    player: Res<PlayerCompany>,
+) {
+    ctx.update(&contracts, &player);
+}
+
+impl StatefulWidget for ContractsScreen {
+    type State = ContractsContext;
+
+    fn render(
+        self,
+        area: ratatui::prelude::Rect,
+        buf: &mut ratatui::prelude::Buffer,
+        state: &mut Self::State,
+    ) {
+        let chunks =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Fill(1)]).split(area);
+
+        // Contract list
+        let entries = state
+            .contracts
+            .iter()
+            .map(|c| format!("#{} {} to {}", c.id, c.kind, c.destination));
+        let list = List::new(entries)
+            .highlight_symbol(">")
+            .block(Block::bordered().title_top("Contracts"));
+        <List as StatefulWidget>::render(list, chunks[0], buf, &mut state.list_state);
+
+        // Contract details
+        if let Some(contract) = state.selected_contract() {
+            let issuer = match &contract.issuer {
+                Issuer::Body(id) => id.to_string(),
+                Issuer::Faction(name) => name.clone(),
+            };
+            let cargo = contract
+                .cargo
+                .map_or("none".to_owned(), |(commodity, quantity)| {
+                    format!("{quantity} {commodity}")
+                });
+            let route = contract
+                .origin
+                .map_or(contract.destination.to_string(), |origin| {
+                    format!("{} → {}", origin, contract.destination)
+                });
+            Paragraph::new(format!(
+                "Kind: {}\nIssuer: {}\nRoute: {}\nCargo: {}\nDeadline: tick {}\nReward: {:.2} credits\nPenalty: {:.2} credits\nStatus: {}",
+                contract.kind,
+                issuer,
+                route,
+                cargo,
+                contract.deadline,
+                contract.reward,
+                contract.penalty,
+                contract.status,
+            ))
+            .block(Block::bordered().title_top("Contract info"))
+            .render(chunks[1], buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{app::App, state::state::NextState};
+
+    use crate::{
+        objects::contracts::{ContractKind, ContractsConfig, Issuer},
+        prelude::*,
+    };
+
+    use super::{ContractsContext, ContractsScreenEvent};
+
+    #[test]
+    fn test_accept_contract() {
+        let mut app = App::new();
+        app.add_plugins((
+            ClientPlugin::testing().in_mode(ClientMode::Singleplayer),
+            TuiPlugin::testing(),
+        ));
+        // No contract is posted automatically
+        app.insert_resource(ContractsConfig {
+            max_open: 0,
+            ..Default::default()
+        });
+        app.update();
+        app.update();
+        let id = app.world_mut().resource_mut::<Contracts>().post(Contract {
+            id: 0,
+            issuer: Issuer::Faction("Test".into()),
+            kind: ContractKind::Survey,
+            origin: None,
+            destination: id_from("mars"),
+            cargo: None,
+            deadline: u64::MAX,
+            reward: 1.,
+            penalty: 1.,
+            status: ContractStatus::Open,
+        });
+        app.world_mut()
+            .resource_mut::<NextState<AppScreen>>()
+            .set(AppScreen::Contracts);
+        app.update();
+        app.update();
+        assert_eq!(
+            app.world().resource::<ContractsContext>().contracts.len(),
+            1
+        );
+
+        app.world_mut()
+            .send_event(ContractsScreenEvent::Select(Direction2::Down));
+        app.world_mut().send_event(ContractsScreenEvent::Accept);
+        app.update();
+        app.update();
+        let player = app.world().resource::<PlayerCompany>().0;
+        assert_eq!(
+            app.world().resource::<Contracts>().contracts[&id].contractor(),
+            Some(&player)
+        );
+    }
+}
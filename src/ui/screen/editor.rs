@@ -2,17 +2,21 @@ use std::collections::BTreeMap;
 
 use bevy::{math::DVec3, prelude::*};
 use bevy_ratatui::event::KeyEvent;
-use crossterm::event::KeyEventKind;
+use crossterm::event::{KeyCode, KeyEventKind};
 use ratatui::{
     layout::{Constraint, Layout},
-    widgets::{Block, List, ListState, Paragraph, StatefulWidget, Widget},
+    widgets::{Block, List, ListState, Paragraph, StatefulWidget, Widget, WidgetRef},
 };
 
 use crate::{
-    objects::ships::trajectory::ManeuverNode, physics::time::SIMTICKS_PER_TICK, prelude::*,
+    objects::ships::{propulsion::delta_v_budget, trajectory::ManeuverNode},
+    physics::{frames::ReferenceFrame, orbit::OsculatingOrbit, time::SIMTICKS_PER_TICK},
+    prelude::*,
+    ui::{widget::orbit::OrbitWidget, UiUpdate},
 };
 
 use super::AppScreen;
+use editor_backend::{CachedPredictions, EditorCommand};
 
 pub mod editor_backend;
 
@@ -34,6 +38,12 @@ pub fn plugin(app: &mut App) {
                 .run_if(in_state(InEditor))
                 .run_if(resource_exists::<EditorContext>),
         )
+        .add_systems(
+            PostUpdate,
+            update_orbit
+                .run_if(resource_exists::<EditorContext>)
+                .in_set(UiUpdate),
+        )
         .add_systems(OnEnter(InEditor), create_screen)
         .add_systems(OnExit(InEditor), clear_screen);
 }
@@ -70,6 +80,25 @@ pub struct EditorContext {
     temp_predictions: Vec<Entity>,
     /// This field stores the thrust that will be added to a node when we are editing one
     editing_data: Option<DVec3>,
+    /// The current osculating orbit of the ship
+    pub orbit: Option<OrbitWidget>,
+    /// The object (body or ship) whose closest approach to the predicted trajectory is computed
+    pub target: Option<Entity>,
+    /// When the target is a ship, its own predicted global positions along with the simtick they start from
+    target_path: Option<(u64, Vec<DVec3>)>,
+    /// The last predictions of the ship and of the target ship, kept to only compute again what follows a changed node
+    ship_predictions: Option<CachedPredictions>,
+    target_predictions: Option<CachedPredictions>,
+    /// The frame the predictions are expressed in
+    pub predictions_frame: ReferenceFrame,
+    /// Descriptions of the notable events of the predicted trajectory (encounters, closest approach, impact)
+    pub predicted_events: Vec<String>,
+    /// The command being typed, if the command prompt is open
+    pub command: Option<String>,
+    /// The outcome of the last command
+    pub command_status: Option<String>,
+    /// The velocity change the ship can produce with its remaining propellant (in km/d)
+    pub delta_v_budget: f64,
 }
 
 impl EditorContext {
@@ -91,6 +120,30 @@ impl EditorContext {
             predictions: Vec::new(),
             temp_predictions: Vec::new(),
             editing_data: None,
+            orbit: None,
+            target: None,
+            target_path: None,
+            ship_predictions: None,
+            target_predictions: None,
+            predictions_frame: ReferenceFrame::Inertial,
+            predicted_events: Vec::new(),
+            command: None,
+            command_status: None,
+            delta_v_budget: f64::INFINITY,
+        }
+    }
+
+    /// The delta-v budget left after the planned nodes
+    fn delta_v_summary(&self) -> String {
+        let planned: f64 = self.nodes.values().map(|n| n.thrust.length()).sum();
+        if self.delta_v_budget.is_finite() {
+            format!(
+                "Δv left: {:.0}/{:.0} km/d",
+                self.delta_v_budget - planned,
+                self.delta_v_budget
+            )
+        } else {
+            format!("Δv planned: {planned:.0} km/d")
         }
     }
 
@@ -169,11 +222,21 @@ impl ClampedList for EditorContext {
 
 pub struct EditorScreen;
 
-#[allow(clippy::too_many_arguments)]
+/// Opens the editor on a ship of the player, going back to the fleet for other ships
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn create_screen(
     mut commands: Commands,
     screen: Res<State<AppScreen>>,
-    ships: Query<(&ShipInfo, &Position, &Velocity, &Influenced)>,
+    mut next_screen: ResMut<NextState<AppScreen>>,
+    ships: Query<(
+        &ShipInfo,
+        &Position,
+        &Velocity,
+        &Influenced,
+        Option<(&ShipMass, &Isp)>,
+        Option<&Owner>,
+    )>,
+    player: Res<PlayerCompany>,
     ships_mapping: Res<ShipsMapping>,
     bodies_mapping: Res<BodiesMapping>,
     bodies: Query<&BodyInfo>,
@@ -189,14 +252,16 @@ fn create_screen(
                 Influenced {
                     main_influencer, ..
                 },
+                propulsion,
+                owner,
             ) = ships.get(*e).unwrap();
-            commands.insert_resource(EditorContext::new(
-                *e,
-                info.clone(),
-                pos,
-                speed,
-                time.simtick,
-            ));
+            if owner != Some(&Owner(player.0)) {
+                next_screen.set(AppScreen::Fleet);
+                return;
+            }
+            let mut context = EditorContext::new(*e, info.clone(), pos, speed, time.simtick);
+            context.delta_v_budget = delta_v_budget(propulsion);
+            commands.insert_resource(context);
             let mut map = SpaceMap::new(system_size.0, *main_influencer, *main_influencer);
             map.autoscale(&bodies_mapping.0, &bodies);
             commands.insert_resource(map);
@@ -214,9 +279,11 @@ fn clear_screen(mut commands: Commands, query: Query<Entity, With<ClearOnEditorE
 }
 
 fn read_input(
+    mut context: ResMut<EditorContext>,
     mut key_event: EventReader<KeyEvent>,
     keymap: Res<Keymap>,
     mut internal_event: EventWriter<SelectNode>,
+    mut command_event: EventWriter<EditorCommand>,
     mut next_screen: ResMut<NextState<AppScreen>>,
 ) {
     use Direction2::*;
@@ -226,10 +293,38 @@ fn read_input(
         if event.kind == KeyEventKind::Release {
             return;
         }
+        if let Some(mut command) = context.command.take() {
+            match event {
+                e if keymap.back.matches(e) => {}
+                e if keymap.validate_command.matches(e) => match command.parse() {
+                    Ok(c) => {
+                        command_event.send(c);
+                    }
+                    Err(e) => context.command_status = Some(e.to_string()),
+                },
+                e if keymap.delete_char.matches(e) => {
+                    command.pop();
+                    context.command = Some(command);
+                }
+                KeyEvent(crossterm::event::KeyEvent {
+                    code: KeyCode::Char(c),
+                    ..
+                }) => {
+                    command.push(*c);
+                    context.command = Some(command);
+                }
+                _ => context.command = Some(command),
+            }
+            continue;
+        }
         internal_event.send(match event {
             e if keymap.select_next.matches(e) => SelectAdjacent(Down),
             e if keymap.select_previous.matches(e) => SelectAdjacent(Up),
             e if keymap.back.matches(e) => return next_screen.set(AppScreen::Fleet),
+            e if keymap.command.matches(e) => {
+                context.command = Some(String::new());
+                continue;
+            }
             // e if keymap.new_node.matches(e) => NewNode(None),
             _ => return,
         });
@@ -262,6 +357,8 @@ fn handle_editor_events(
                         name: "Node".into(),
                         thrust: DVec3::ZERO,
                         origin,
+                        duration: None,
+                        thrust_magnitude: None,
                     },
                 );
             }
@@ -269,6 +366,22 @@ fn handle_editor_events(
     }
 }
 
+fn update_orbit(
+    mut context: ResMut<EditorContext>,
+    ships: Query<&OsculatingOrbit>,
+    bodies: Query<&BodyInfo>,
+) {
+    context.orbit = ships
+        .get(context.ship)
+        .ok()
+        .and_then(|OsculatingOrbit { host, orbit }| {
+            bodies.get(*host).ok().map(|BodyInfo(data)| OrbitWidget {
+                host_name: data.name.clone(),
+                orbit: orbit.clone(),
+            })
+        });
+}
+
 fn handle_select_prediction(
     mut select_events: EventReader<SelectObjectEvent>,
     mut editor_events: EventWriter<SelectNode>,
@@ -290,19 +403,44 @@ impl StatefulWidget for EditorScreen {
         buf: &mut ratatui::prelude::Buffer,
         state: &mut Self::State,
     ) {
+        let prompt = state.command.as_ref().map_or_else(
+            || state.command_status.clone().unwrap_or_default(),
+            |command| format!(":{}", command),
+        );
+        let main = Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).split(area);
+        Paragraph::new(prompt).render(main[1], buf);
         let chunks =
-            Layout::horizontal([Constraint::Percentage(30), Constraint::Fill(1)]).split(area);
+            Layout::horizontal([Constraint::Percentage(30), Constraint::Fill(1)]).split(main[0]);
         let list = List::new(state.nodes.values().map(|n| &n.name[..]))
             .highlight_symbol(">")
-            .block(Block::bordered().title_top("Maneuver nodes"));
+            .block(
+                Block::bordered()
+                    .title_top("Maneuver nodes")
+                    .title_bottom(state.delta_v_summary()),
+            );
         StatefulWidget::render(list, chunks[0], buf, &mut state.list_state);
 
+        let side = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Length(state.predicted_events.len() as u16 + 2),
+            Constraint::Fill(1),
+        ])
+        .split(chunks[1]);
         if let Some((tick, node)) = state.selected_entry() {
+            let burn = node
+                .burn_duration()
+                .map_or_else(|| "instantaneous".into(), |d| format!("{:.3} days", d));
             Paragraph::new(format!(
-                "Tick: {}\nThrust: {}\nOrigin: {}",
-                tick, node.thrust, node.origin
+                "Tick: {}\nThrust: {} ({})\nOrigin: {}",
+                tick, node.thrust, burn, node.origin
             ))
-            .render(chunks[1], buf);
+            .render(side[0], buf);
+        }
+        Paragraph::new(state.predicted_events.join("\n"))
+            .block(Block::bordered().title_top("Predicted events"))
+            .render(side[1], buf);
+        if let Some(orbit) = &state.orbit {
+            orbit.render_ref(side[2], buf);
         }
     }
 }
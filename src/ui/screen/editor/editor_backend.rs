@@ -1,11 +1,25 @@
-use std::time::Duration;
+use std::{
+    collections::BTreeMap, error::Error, num::ParseFloatError, str::FromStr, time::Duration,
+};
 
 use crate::{
     game::GameFiles,
-    objects::ships::trajectory::{read_ship_trajectory, Trajectory, TrajectoryEvent},
+    objects::ships::{
+        propulsion::{delta_v_budget, PropellantEvent},
+        trajectory::{read_ship_trajectory, ManeuverNode, Trajectory, TrajectoryEvent},
+    },
     physics::{
+        atmosphere::PeriapsisInAtmosphere,
+        frames::ReferenceFrame,
         influence::HillRadius,
-        predictions::{Prediction, PredictionStart},
+        integrator::Integrator,
+        perturbations::{DragProfile, Perturbations, RadiationProfile, SurfaceCoefficients},
+        predictions::{
+            bodies_coordinates_at, ApproachTarget, ClosestApproach, PredictedImpact, Prediction,
+            PredictionStart, Predictions, SoiEvent, SoiEventKind,
+        },
+        time::{GAMETIME_PER_SIMTICK, SIMTICKS_PER_TICK},
+        transfer::ShipOrbitState,
     },
     prelude::*,
     ui::gui::SelectionRadius,
@@ -26,6 +40,7 @@ pub fn plugin(app: &mut App) {
         .add_event::<ChangePredictionsNumber>()
         .add_event::<ChangeNodeTick>()
         .add_event::<ReloadPredictions>()
+        .add_event::<EditorCommand>()
         .init_resource::<PredictionDelay>()
         .init_resource::<NumberOfPredictions>()
         .add_systems(
@@ -55,6 +70,7 @@ pub fn plugin(app: &mut App) {
                     ),
                     (
                         explicitly_clear_predictions,
+                        update_target_path,
                         create_predictions,
                         update_temp_predictions,
                         copy_predictions,
@@ -64,6 +80,9 @@ pub fn plugin(app: &mut App) {
                 )
                     .chain(),
                 handle_update_thrust.run_if(on_event::<UpdateThrust>()),
+                handle_editor_commands.run_if(on_event::<EditorCommand>()),
+                handle_propellant_events.run_if(on_event::<PropellantEvent>()),
+                handle_atmosphere_events.run_if(on_event::<PeriapsisInAtmosphere>()),
                 (
                     tick_prediction_delay,
                     update_temp_predictions.run_if(on_event::<PredictionDelayEvent>()),
@@ -232,13 +251,365 @@ fn handle_change_focus(
     mut events: EventReader<SelectObjectEvent>,
     mut reload: EventWriter<ReloadPredictions>,
     bodies: Query<&BodyInfo>,
+    ships: Query<(), With<ShipInfo>>,
+    points: Query<(), With<LagrangePoint>>,
     mut space_map: ResMut<SpaceMap>,
+    mut ctx: ResMut<EditorContext>,
 ) {
     for event in events.read() {
         let e = event.entity;
-        if bodies.get(e).is_ok() {
+        if bodies.get(e).is_ok() || points.get(e).is_ok() {
             space_map.focus(e);
+            ctx.target = Some(e);
             reload.send_default();
+        } else if ships.get(e).is_ok() && e != ctx.ship {
+            ctx.target = Some(e);
+            reload.send_default();
+        }
+    }
+}
+
+/// Computes the predicted path of the target when it is a ship, assuming it follows its saved trajectory, or when
+/// it is a Lagrange point
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn update_target_path(
+    mut ctx: ResMut<EditorContext>,
+    predictions_number: Res<NumberOfPredictions>,
+    ships: Query<(
+        &ShipInfo,
+        &Position,
+        &Velocity,
+        &Acceleration,
+        &Influenced,
+        Option<(&ShipMass, &Isp)>,
+        Option<&RadiationProfile>,
+        Option<&DragProfile>,
+    )>,
+    points: Query<&LagrangePoint>,
+    mut bodies: Query<(&EllipticalOrbit, &BodyInfo, &HillRadius)>,
+    bodies_mapping: Res<BodiesMapping>,
+    gamefiles: Res<GameFiles>,
+    integrator: Res<Integrator>,
+    perturbations: Res<Perturbations>,
+    time: Res<GameTime>,
+) {
+    if let Some(point) = ctx.target.and_then(|target| points.get(target).ok()) {
+        let mut orbits = bodies.transmute_lens::<(&EllipticalOrbit, &BodyInfo)>();
+        let orbits = orbits.query();
+        let positions = (time.simtick + 1..=time.simtick + predictions_number.0 as u64)
+            .filter_map(|simtick| {
+                point.coordinates_at(
+                    &orbits,
+                    &bodies_mapping.0,
+                    simtick as f64 * GAMETIME_PER_SIMTICK,
+                )
+            })
+            .map(|(pos, _)| pos)
+            .collect();
+        ctx.target_path = Some((time.simtick, positions));
+        return;
+    }
+    let Some((target, (info, pos, speed, acc, influence, propulsion, radiation, drag))) = ctx
+        .target
+        .and_then(|target| Some((target, ships.get(target).ok()?)))
+    else {
+        ctx.target_path = None;
+        ctx.target_predictions = None;
+        return;
+    };
+    let nodes = read_ship_trajectory(&gamefiles.trajectories, info.id)
+        .map(|t| t.nodes)
+        .unwrap_or_default();
+    let key = PredictionsKey {
+        object: target,
+        simtick: time.simtick,
+        number: predictions_number.0,
+        frame: ReferenceFrame::Inertial,
+        target: None,
+        integrator: *integrator,
+        perturbations: perturbations.clone(),
+        delta_v: delta_v_budget(propulsion),
+    };
+    let mut cached = CachedPredictions::take(&mut ctx.target_predictions, key);
+    if let Some(from_tick) = cached.stale_from(&nodes) {
+        PredictionStart {
+            pos: pos.0,
+            speed: speed.0,
+            acc: acc.current,
+            simtick: time.simtick,
+            delta_v: delta_v_budget(propulsion),
+            surface: SurfaceCoefficients::of(radiation, drag, propulsion.map(|(mass, _)| mass)),
+        }
+        .update_predictions(
+            &mut cached.predictions,
+            from_tick,
+            predictions_number.0,
+            influence,
+            ReferenceFrame::Inertial,
+            &mut bodies.as_query_lens(),
+            &bodies_mapping.0,
+            &nodes,
+            integrator.as_ref(),
+            perturbations.as_ref(),
+            None,
+        );
+        cached.nodes = nodes;
+        ctx.target_path = Some((
+            time.simtick,
+            cached.predictions.coords.iter().map(|(p, _)| *p).collect(),
+        ));
+        // The closest approach to the target has changed
+        ctx.ship_predictions = None;
+    }
+    ctx.target_predictions = Some(cached);
+}
+
+/// What predictions depend on besides the maneuver nodes
+#[derive(Clone, Debug, PartialEq)]
+struct PredictionsKey {
+    object: Entity,
+    simtick: u64,
+    number: usize,
+    frame: ReferenceFrame,
+    target: Option<Entity>,
+    integrator: Integrator,
+    perturbations: Perturbations,
+    /// Delta-v budget of the ship, which changes with its propellant
+    delta_v: f64,
+}
+
+/// Predictions along with the nodes they were computed with
+#[derive(Debug)]
+pub struct CachedPredictions {
+    key: PredictionsKey,
+    nodes: BTreeMap<u64, ManeuverNode>,
+    predictions: Predictions,
+    /// Whether the predictions were never computed
+    empty: bool,
+}
+
+impl CachedPredictions {
+    /// Takes the cached predictions if they were computed for the same key
+    fn take(cache: &mut Option<Self>, key: PredictionsKey) -> Self {
+        cache.take().filter(|c| c.key == key).unwrap_or(Self {
+            key,
+            nodes: BTreeMap::new(),
+            predictions: Predictions::default(),
+            empty: true,
+        })
+    }
+
+    /// The tick from which the predictions must be computed again for the given nodes, if they are not up to date
+    fn stale_from(&mut self, nodes: &BTreeMap<u64, ManeuverNode>) -> Option<u64> {
+        if std::mem::take(&mut self.empty) {
+            return Some(0);
+        }
+        self.nodes
+            .keys()
+            .chain(nodes.keys())
+            .filter(|tick| self.nodes.get(tick) != nodes.get(tick))
+            .min()
+            .cloned()
+    }
+}
+
+/// Commands typed in the editor that generate maneuver nodes
+#[derive(Event, Clone, Debug, PartialEq)]
+pub enum EditorCommand {
+    /// Makes the orbit circular at its next apoapsis
+    CircularizeAtApoapsis,
+    /// Changes the periapsis to the given altitude above the surface of the main influencer (in km), with a burn
+    /// at the next apoapsis
+    RaisePeriapsis(f64),
+    /// Transfers to the orbit of a body (given by its name or ID) orbiting the same host, with a Hohmann transfer
+    MatchAltitude(String),
+    /// Spreads the velocity change of the selected node over a burn of the given duration (in days), or makes it
+    /// instantaneous if the duration is zero
+    SetBurnDuration(f64),
+}
+
+#[derive(Clone, Debug)]
+pub enum EditorCommandError {
+    ParseError(ParseFloatError),
+    UnknownCommand(String),
+    MissingArgument(&'static str),
+    NoMainInfluencer,
+    OpenOrbit,
+    UnknownBody(String),
+    DifferentHost(String),
+    NoSelectedNode,
+    NodeAlreadyExists(u64),
+}
+
+impl From<ParseFloatError> for EditorCommandError {
+    fn from(value: ParseFloatError) -> Self {
+        Self::ParseError(value)
+    }
+}
+
+impl Error for EditorCommandError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EditorCommandError::ParseError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for EditorCommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EditorCommandError::ParseError(e) => write!(f, "Invalid number: {}", e),
+            EditorCommandError::UnknownCommand(c) => write!(
+                f,
+                "Unknown command {}, expected circularize, periapsis <altitude>, match <body> or burn <duration>",
+                c
+            ),
+            EditorCommandError::MissingArgument(arg) => write!(f, "Missing argument: {}", arg),
+            EditorCommandError::NoMainInfluencer => {
+                write!(f, "The ship is not orbiting any body")
+            }
+            EditorCommandError::OpenOrbit => write!(f, "The orbit of the ship is not closed"),
+            EditorCommandError::UnknownBody(name) => write!(f, "Unknown body {}", name),
+            EditorCommandError::DifferentHost(name) => {
+                write!(f, "{} does not orbit the main influencer of the ship", name)
+            }
+            EditorCommandError::NoSelectedNode => write!(f, "No maneuver node is selected"),
+            EditorCommandError::NodeAlreadyExists(tick) => {
+                write!(f, "A maneuver node already exists at tick {}", tick)
+            }
+        }
+    }
+}
+
+impl FromStr for EditorCommand {
+    type Err = EditorCommandError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        match words.next().unwrap_or_default() {
+            "circularize" => Ok(Self::CircularizeAtApoapsis),
+            "periapsis" => Ok(Self::RaisePeriapsis(
+                words
+                    .next()
+                    .ok_or(EditorCommandError::MissingArgument("altitude"))?
+                    .parse()?,
+            )),
+            "match" => {
+                let body = words.collect::<Vec<_>>().join(" ");
+                if body.is_empty() {
+                    Err(EditorCommandError::MissingArgument("body"))
+                } else {
+                    Ok(Self::MatchAltitude(body))
+                }
+            }
+            "burn" => Ok(Self::SetBurnDuration(
+                words
+                    .next()
+                    .ok_or(EditorCommandError::MissingArgument("duration"))?
+                    .parse()?,
+            )),
+            command => Err(EditorCommandError::UnknownCommand(command.into())),
+        }
+    }
+}
+
+/// Generates the maneuver nodes corresponding to a command, from the state of the ship at the start of the
+/// predictions
+fn plan_command(
+    command: &EditorCommand,
+    ctx: &EditorContext,
+    influence: &Influenced,
+    bodies: &Query<(&EllipticalOrbit, &BodyInfo)>,
+    mapping: &BodiesMapping,
+) -> Result<Vec<(u64, ManeuverNode)>, EditorCommandError> {
+    let host = influence
+        .main_influencer
+        .ok_or(EditorCommandError::NoMainInfluencer)?;
+    let BodyInfo(host_data) = bodies.get(host).unwrap().1;
+    let (host_pos, host_speed) = bodies_coordinates_at(
+        [host].into_iter(),
+        bodies,
+        &mapping.0,
+        ctx.simtick as f64 * GAMETIME_PER_SIMTICK,
+    )[0];
+    let state = ShipOrbitState {
+        pos: ctx.pos - host_pos,
+        speed: ctx.speed - host_speed,
+        simtick: ctx.simtick,
+        host: host_data.id,
+        host_mass: host_data.mass,
+    };
+    let nodes = match command {
+        EditorCommand::CircularizeAtApoapsis => state
+            .circularize_at_apoapsis()
+            .map(|node| vec![node])
+            .ok_or(EditorCommandError::OpenOrbit),
+        EditorCommand::RaisePeriapsis(altitude) => state
+            .set_periapsis(host_data.radius + altitude)
+            .map(|node| vec![node])
+            .ok_or(EditorCommandError::OpenOrbit),
+        EditorCommand::MatchAltitude(name) => {
+            let (_, BodyInfo(data)) = bodies
+                .iter()
+                .find(|(_, BodyInfo(data))| {
+                    data.name.eq_ignore_ascii_case(name) || data.id.to_string() == *name
+                })
+                .ok_or_else(|| EditorCommandError::UnknownBody(name.clone()))?;
+            if data.host_body != Some(host_data.id) {
+                return Err(EditorCommandError::DifferentHost(data.name.clone()));
+            }
+            Ok(state.hohmann(data.semimajor_axis).into())
+        }
+        EditorCommand::SetBurnDuration(duration) => {
+            let (&tick, node) = ctx
+                .selected_entry()
+                .ok_or(EditorCommandError::NoSelectedNode)?;
+            Ok(vec![(
+                tick,
+                ManeuverNode {
+                    duration: (*duration > 0.).then_some(*duration),
+                    ..node.clone()
+                },
+            )])
+        }
+    }?;
+    // Only the burn duration command edits an existing node, the others must not replace one
+    if !matches!(command, EditorCommand::SetBurnDuration(_)) {
+        if let Some((tick, _)) = nodes.iter().find(|(tick, _)| ctx.nodes.contains_key(tick)) {
+            return Err(EditorCommandError::NodeAlreadyExists(*tick));
+        }
+    }
+    Ok(nodes)
+}
+
+fn handle_editor_commands(
+    mut events: EventReader<EditorCommand>,
+    mut ctx: ResMut<EditorContext>,
+    mut traj_event: EventWriter<TrajectoryEvent>,
+    mut reload: EventWriter<ReloadPredictions>,
+    ships: Query<&Influenced>,
+    bodies: Query<(&EllipticalOrbit, &BodyInfo)>,
+    mapping: Res<BodiesMapping>,
+) {
+    for command in events.read() {
+        let influence = ships.get(ctx.ship).unwrap();
+        match plan_command(command, &ctx, influence, &bodies, &mapping) {
+            Ok(nodes) => {
+                let ship = ctx.ship_info.id;
+                ctx.command_status = Some(format!("Added {} maneuver node(s)", nodes.len()));
+                for (tick, node) in nodes {
+                    traj_event.send(TrajectoryEvent::AddNode {
+                        ship,
+                        node: node.clone(),
+                        tick,
+                    });
+                    ctx.nodes.insert(tick, node);
+                    ctx.select_tick(tick);
+                }
+                reload.send_default();
+            }
+            Err(e) => ctx.command_status = Some(e.to_string()),
         }
     }
 }
@@ -302,42 +673,203 @@ fn tick_prediction_delay(
     }
 }
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn update_temp_predictions(
-    ctx: Res<EditorContext>,
+    mut ctx: ResMut<EditorContext>,
     predictions_number: Res<NumberOfPredictions>,
-    query: Query<(&Acceleration, &Influenced)>,
+    query: Query<(
+        &Acceleration,
+        &Influenced,
+        Option<(&ShipMass, &Isp)>,
+        Option<&RadiationProfile>,
+        Option<&DragProfile>,
+    )>,
     mut bodies: Query<(&EllipticalOrbit, &BodyInfo, &HillRadius)>,
+    ships: Query<&ShipInfo>,
+    points: Query<&LagrangeInfo>,
     bodies_mapping: Res<BodiesMapping>,
     mut coords: Query<(&mut Position, &mut Velocity), With<TempPrediction>>,
     space_map: Res<SpaceMap>,
+    integrator: Res<Integrator>,
+    perturbations: Res<Perturbations>,
 ) {
-    let (&Acceleration { current: acc, .. }, influence) = query.get(ctx.ship).unwrap();
+    let (&Acceleration { current: acc, .. }, influence, propulsion, radiation, drag) =
+        query.get(ctx.ship).unwrap();
     let start = PredictionStart {
         pos: ctx.pos,
         speed: ctx.speed,
         simtick: ctx.simtick,
         acc,
+        delta_v: delta_v_budget(propulsion),
+        surface: SurfaceCoefficients::of(radiation, drag, propulsion.map(|(mass, _)| mass)),
     };
     let thrust = ctx.editing_data.unwrap_or_default();
     let mut nodes = ctx.nodes.clone();
     if let Some(tick) = ctx.selected_tick() {
         nodes.get_mut(&tick).unwrap().thrust += thrust;
     }
-    let reference = space_map.focus_body.or(influence.main_influencer);
-    let predictions = start.compute_predictions(
-        predictions_number.0,
-        influence,
-        reference,
-        &mut bodies.as_query_lens(),
-        &bodies_mapping.0,
-        &nodes,
-    );
+    let frame = match space_map.frame {
+        ReferenceFrame::Inertial => influence
+            .main_influencer
+            .map_or(ReferenceFrame::Inertial, ReferenceFrame::BodyCentered),
+        frame => frame,
+    };
+    let key = PredictionsKey {
+        object: ctx.ship,
+        simtick: ctx.simtick,
+        number: predictions_number.0,
+        frame,
+        target: ctx.target,
+        integrator: *integrator,
+        perturbations: perturbations.clone(),
+        delta_v: start.delta_v,
+    };
+    let mut cached = CachedPredictions::take(&mut ctx.ship_predictions, key);
+    let target = ctx.target.and_then(|target| {
+        if bodies.get(target).is_ok() {
+            Some(ApproachTarget::Body(target))
+        } else {
+            ctx.target_path
+                .as_ref()
+                .map(|(simtick, positions)| ApproachTarget::Ship {
+                    simtick: *simtick,
+                    positions,
+                })
+        }
+    });
+    if let Some(from_tick) = cached.stale_from(&nodes) {
+        start.update_predictions(
+            &mut cached.predictions,
+            from_tick,
+            predictions_number.0,
+            influence,
+            frame,
+            &mut bodies.as_query_lens(),
+            &bodies_mapping.0,
+            &nodes,
+            integrator.as_ref(),
+            perturbations.as_ref(),
+            target,
+        );
+        cached.nodes = nodes;
+    }
+    ctx.predictions_frame = frame;
+    let predictions = &cached.predictions;
+    // The predictions stop at the impact point, if any
+    let last = predictions
+        .coords
+        .last()
+        .cloned()
+        .unwrap_or((ctx.pos, ctx.speed));
     let mut i = 0;
     let mut iter = coords.iter_many_mut(&ctx.temp_predictions);
     while let Some((mut pos, mut speed)) = iter.fetch_next() {
-        (pos.0, speed.0) = predictions[i];
+        (pos.0, speed.0) = predictions.coords.get(i).cloned().unwrap_or(last);
         i += 1;
     }
+
+    let name = |e: Entity| {
+        bodies
+            .get(e)
+            .map(|(_, BodyInfo(data), _)| data.name.clone())
+            .or_else(|_| ships.get(e).map(|info| info.id.to_string()))
+            .or_else(|_| points.get(e).map(|info| info.name.clone()))
+            .unwrap_or_default()
+    };
+    let mut events: Vec<_> = predictions
+        .soi_events
+        .iter()
+        .map(
+            |SoiEvent {
+                 body,
+                 simtick,
+                 kind,
+             }| {
+                let tick = simtick / SIMTICKS_PER_TICK;
+                match kind {
+                    SoiEventKind::Entry {
+                        periapsis: Some(periapsis),
+                    } => format!(
+                        "Enters {} SOI at tick {}, periapsis {:.0} km",
+                        name(*body),
+                        tick,
+                        periapsis
+                    ),
+                    SoiEventKind::Entry { periapsis: None } => {
+                        format!("Enters {} SOI at tick {}", name(*body), tick)
+                    }
+                    SoiEventKind::Exit => format!("Leaves {} SOI at tick {}", name(*body), tick),
+                }
+            },
+        )
+        .collect();
+    if let (Some(target), Some(ClosestApproach { distance, simtick })) =
+        (ctx.target, predictions.closest_approach)
+    {
+        events.push(format!(
+            "Closest approach to {}: {:.0} km at tick {}",
+            name(target),
+            distance,
+            simtick / SIMTICKS_PER_TICK
+        ));
+    }
+    if let Some(PredictedImpact { body, simtick }) = predictions.impact {
+        events.push(format!(
+            "Impact on {} at tick {}",
+            name(body),
+            simtick / SIMTICKS_PER_TICK
+        ));
+    }
+    if let Some(simtick) = predictions.propellant_exhausted {
+        events.push(format!(
+            "Out of propellant at tick {}",
+            simtick / SIMTICKS_PER_TICK
+        ));
+    }
+    ctx.predicted_events = events;
+    ctx.ship_predictions = Some(cached);
+}
+
+/// Reports the nodes of the edited ship that were refused for lack of propellant or overlapping burns
+fn handle_propellant_events(
+    mut ctx: ResMut<EditorContext>,
+    mut events: EventReader<PropellantEvent>,
+) {
+    for event in events.read() {
+        let status = match event {
+            PropellantEvent::NodeRejected {
+                ship,
+                tick,
+                required,
+                budget,
+            } if *ship == ctx.ship_info.id => format!(
+                "Node at tick {tick} rejected: {required:.0} km/d needed, {budget:.0} km/d available"
+            ),
+            PropellantEvent::BurnOverlap { ship, tick } if *ship == ctx.ship_info.id => {
+                format!("Node at tick {tick} rejected: it starts during the previous burn")
+            }
+            _ => continue,
+        };
+        ctx.command_status = Some(status);
+    }
+}
+
+/// Warns when the periapsis of the edited ship goes below the top of an atmosphere
+fn handle_atmosphere_events(
+    mut ctx: ResMut<EditorContext>,
+    mut events: EventReader<PeriapsisInAtmosphere>,
+    bodies: Query<&BodyInfo>,
+) {
+    let ship = ctx.ship;
+    for event in events.read().filter(|event| event.ship == ship) {
+        let body = bodies
+            .get(event.body)
+            .map_or("its host".to_owned(), |BodyInfo(data)| data.name.clone());
+        ctx.command_status = Some(format!(
+            "Periapsis {:.0} km above {body}, inside its atmosphere",
+            event.altitude
+        ));
+    }
 }
 
 fn copy_predictions(
@@ -353,3 +885,172 @@ fn copy_predictions(
         (pos.0, speed.0) = (new_pos.0, new_speed.0);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::{app::App, ecs::entity::Entity, state::state::NextState};
+
+    use crate::{
+        physics::atmosphere::PeriapsisInAtmosphere, prelude::*, ui::screen::editor::EditorContext,
+        utils::algebra::circular_orbit_around_body,
+    };
+
+    use super::{
+        CachedPredictions, EditorCommand, Integrator, NumberOfPredictions, Perturbations,
+        PredictionsKey, ReferenceFrame,
+    };
+
+    #[test]
+    fn test_command_does_not_replace_node() {
+        let mut app = App::new();
+        app.add_plugins((
+            ClientPlugin::testing().in_mode(ClientMode::Singleplayer),
+            TuiPlugin::testing(),
+        ))
+        .insert_resource(NumberOfPredictions(100));
+        app.update();
+        app.update();
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0[&id_from("terre")];
+        let (&Mass(mass), &Position(pos), &Velocity(speed)) = world
+            .query::<(&Mass, &Position, &Velocity)>()
+            .get(world, earth)
+            .unwrap();
+        let (spawn_pos, spawn_speed) = circular_orbit_around_body(2e4, mass, pos, speed);
+        // A slightly eccentric orbit, so that its apoapsis is well defined
+        let spawn_speed = speed + 1.1 * (spawn_speed - speed);
+        let ship = id_from("s");
+        let player = world.resource::<PlayerCompany>().0;
+        world.send_event(ShipEvent::Create(ShipInfo {
+            id: ship,
+            spawn_pos,
+            spawn_speed,
+        }));
+        world.send_event(ShipEvent::SetOwner {
+            ship,
+            owner: Some(player),
+        });
+        app.update();
+        app.world_mut()
+            .resource_mut::<NextState<AppScreen>>()
+            .set(AppScreen::Editor(ship));
+        app.update();
+        app.update();
+
+        app.world_mut()
+            .send_event(EditorCommand::RaisePeriapsis(1e4));
+        app.update();
+        assert_eq!(app.world().resource::<EditorContext>().nodes.len(), 1);
+        let node = app
+            .world()
+            .resource::<EditorContext>()
+            .selected_node()
+            .cloned();
+
+        app.world_mut()
+            .send_event(EditorCommand::RaisePeriapsis(5e3));
+        app.update();
+        let ctx = app.world().resource::<EditorContext>();
+        assert_eq!(ctx.nodes.len(), 1);
+        assert_eq!(ctx.selected_node().cloned(), node);
+        assert!(ctx
+            .command_status
+            .as_ref()
+            .is_some_and(|status| status.contains("already exists")));
+    }
+
+    #[test]
+    fn test_atmosphere_warning() {
+        let mut app = App::new();
+        app.add_plugins((
+            ClientPlugin::testing().in_mode(ClientMode::Singleplayer),
+            TuiPlugin::testing(),
+        ))
+        .insert_resource(NumberOfPredictions(100));
+        app.update();
+        app.update();
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0[&id_from("terre")];
+        let (&Mass(mass), &Position(pos), &Velocity(speed)) = world
+            .query::<(&Mass, &Position, &Velocity)>()
+            .get(world, earth)
+            .unwrap();
+        let (spawn_pos, spawn_speed) = circular_orbit_around_body(2e4, mass, pos, speed);
+        let ship = id_from("s");
+        let player = world.resource::<PlayerCompany>().0;
+        world.send_event(ShipEvent::Create(ShipInfo {
+            id: ship,
+            spawn_pos,
+            spawn_speed,
+        }));
+        world.send_event(ShipEvent::SetOwner {
+            ship,
+            owner: Some(player),
+        });
+        app.update();
+        app.world_mut()
+            .resource_mut::<NextState<AppScreen>>()
+            .set(AppScreen::Editor(ship));
+        app.update();
+        app.update();
+
+        let entity = app.world().resource::<EditorContext>().ship;
+        app.world_mut().send_event(PeriapsisInAtmosphere {
+            ship: entity,
+            body: earth,
+            altitude: 50.,
+        });
+        app.update();
+        let status = app
+            .world()
+            .resource::<EditorContext>()
+            .command_status
+            .clone();
+        assert_eq!(
+            status.as_deref(),
+            Some("Periapsis 50 km above Earth, inside its atmosphere")
+        );
+    }
+
+    #[test]
+    fn test_predictions_key() {
+        let key = PredictionsKey {
+            object: Entity::PLACEHOLDER,
+            simtick: 0,
+            number: 100,
+            frame: ReferenceFrame::Inertial,
+            target: None,
+            integrator: Integrator::Leapfrog,
+            perturbations: Perturbations::default(),
+            delta_v: 1.,
+        };
+        let mut cache = Some(CachedPredictions::take(&mut None, key.clone()));
+        cache.as_mut().unwrap().empty = false;
+        let cached = CachedPredictions::take(&mut cache, key.clone());
+        assert!(!cached.empty);
+        // Changing the physics or the propellant of the ship discards the predictions
+        for other in [
+            PredictionsKey {
+                integrator: Integrator::RK4,
+                ..key.clone()
+            },
+            PredictionsKey {
+                perturbations: Perturbations {
+                    j2: true,
+                    ..Default::default()
+                },
+                ..key.clone()
+            },
+            PredictionsKey {
+                delta_v: 0.5,
+                ..key.clone()
+            },
+        ] {
+            let mut cache = Some(CachedPredictions {
+                empty: false,
+                ..CachedPredictions::take(&mut None, key.clone())
+            });
+            assert!(CachedPredictions::take(&mut cache, other).empty);
+        }
+    }
+}
@@ -9,7 +9,7 @@ use ratatui::{
 use crate::{
     client::ClientMode,
     game::GameStage,
-    physics::{orbit::SystemSize, time::TimeEvent},
+    physics::{nbody::NBodyDrift, orbit::SystemSize, time::TimeEvent},
     ui::{
         gui::SelectObjectEvent,
         widget::{
@@ -24,7 +24,7 @@ use crate::{
 };
 use crate::{input::prelude::Keymap, objects::prelude::*};
 use crate::{
-    physics::Position,
+    physics::{Position, Velocity},
     ui::{
         prelude::*,
         widget::{
@@ -52,7 +52,7 @@ pub fn plugin(app: &mut App) {
                     ),
                 )
                     .in_set(EventHandling),
-                update_space_map.in_set(UiUpdate),
+                (update_space_map, update_drift).in_set(UiUpdate),
             )
                 .run_if(in_loaded_screen::<ExplorerContext>(AppScreen::Explorer)),
         )
@@ -67,11 +67,12 @@ fn create_screen(
     mut commands: Commands,
     primary: Query<Entity, With<PrimaryBody>>,
     bodies: Query<&BodyInfo>,
+    points: Query<&LagrangeInfo>,
     system_size: Res<SystemSize>,
 ) {
     let primary = primary.single();
     commands.insert_resource(SpaceMap::new(system_size.0, Some(primary), Some(primary)));
-    commands.insert_resource(ExplorerContext::new(primary, &bodies));
+    commands.insert_resource(ExplorerContext::new(primary, &bodies, &points));
 }
 
 fn clear_screen(mut commands: Commands) {
@@ -97,28 +98,62 @@ pub struct ExplorerContext {
 }
 
 impl ExplorerContext {
-    pub fn new(primary: Entity, bodies: &Query<&BodyInfo>) -> ExplorerContext {
+    pub fn new(
+        primary: Entity,
+        bodies: &Query<&BodyInfo>,
+        points: &Query<&LagrangeInfo>,
+    ) -> ExplorerContext {
         let primary_data = &bodies.get(primary).unwrap().0;
-        let infos: Vec<_> = bodies.iter().map(|i| &i.0).collect();
         ExplorerContext {
             side_pane_mode: SidePaneMode::default(),
             info_toggle: false,
-            tree_state: TreeState::new(primary_data, Some(primary_data), infos.clone().into_iter()),
-            search_state: SearchState::new(infos.into_iter()),
+            tree_state: TreeState::new(
+                primary_data,
+                Some(primary_data),
+                bodies.iter().map(|i| &i.0),
+                points.iter(),
+            ),
+            search_state: SearchState::new(search_entries(bodies, points).into_iter()),
             info: InfoWidget {
                 body_info: primary_data.clone(),
+                drift: None,
             },
             space_map: SpaceMapWidget::default(),
         }
     }
     fn update_info(&mut self, mapping: &HashMap<BodyID, Entity>, bodies: &Query<&BodyInfo>) {
         let id = self.tree_state.selected_body_id();
-        if let Ok(body_info) = bodies.get(mapping[&id]) {
+        if let Some(Ok(body_info)) = mapping.get(&id).map(|e| bodies.get(*e)) {
             self.info.body_info = body_info.0.clone();
         }
     }
 }
 
+/// The ids and names of the bodies and Lagrange points that can be searched
+fn search_entries<'a>(
+    bodies: &'a Query<&BodyInfo>,
+    points: &'a Query<&LagrangeInfo>,
+) -> Vec<(BodyID, &'a str)> {
+    bodies
+        .iter()
+        .map(|BodyInfo(data)| (data.id, data.name.as_str()))
+        .chain(points.iter().map(|info| (info.id, info.name.as_str())))
+        .collect()
+}
+
+/// The entity of a body or a Lagrange point
+fn object_entity(
+    id: &BodyID,
+    mapping: &BodiesMapping,
+    lagrange_mapping: &LagrangeMapping,
+) -> Option<Entity> {
+    mapping
+        .0
+        .get(id)
+        .or_else(|| lagrange_mapping.0.get(id))
+        .copied()
+}
+
 #[derive(Event)]
 pub(crate) enum ExplorerEvent {
     Tree(TreeEvent),
@@ -182,6 +217,7 @@ fn read_input(
                     e if codes.map_offset_reset.matches(e) => SpaceMap(MapOffsetReset),
                     e if codes.focus.matches(e) => SpaceMap(FocusBody),
                     e if codes.autoscale.matches(e) => SpaceMap(Autoscale),
+                    e if codes.toggle_synodic.matches(e) => SpaceMap(ToggleSynodic),
                     e if codes.enter_search.matches(e) => {
                         View(ChangeSidePaneMode(SidePaneMode::Search))
                     }
@@ -231,7 +267,10 @@ fn handle_explorer_events(
 
     mut events: EventReader<ExplorerEvent>,
     mapping: Res<BodiesMapping>,
+    lagrange_mapping: Res<LagrangeMapping>,
     bodies: Query<&BodyInfo>,
+    points: Query<&LagrangePoint>,
+    point_infos: Query<&LagrangeInfo>,
     mut time_events: ResMut<Events<TimeEvent>>,
     fuzzy_matcher: Res<SearchMatcher>,
 ) {
@@ -252,8 +291,10 @@ fn handle_explorer_events(
                 match event {
                     DeleteChar => {
                         ctx.search_state.delete_char();
-                        ctx.search_state
-                            .update_search_entries(bodies.iter(), &fuzzy_matcher.0);
+                        ctx.search_state.update_search_entries(
+                            search_entries(&bodies, &point_infos).into_iter(),
+                            &fuzzy_matcher.0,
+                        );
                     }
                     Select(d) => ctx.search_state.select_adjacent(*d),
                     ValidateSearch => {
@@ -265,8 +306,10 @@ fn handle_explorer_events(
                     }
                     WriteChar(char) => {
                         ctx.search_state.enter_char(*char);
-                        ctx.search_state
-                            .update_search_entries(bodies.iter(), &fuzzy_matcher.0);
+                        ctx.search_state.update_search_entries(
+                            search_entries(&bodies, &point_infos).into_iter(),
+                            &fuzzy_matcher.0,
+                        );
                     }
                 }
             }
@@ -277,12 +320,31 @@ fn handle_explorer_events(
                     MapOffset(d) => space_map.offset(*d),
                     MapOffsetReset => space_map.reset_offset(),
                     FocusBody => {
-                        if let Some(entity) = mapping.0.get(&ctx.tree_state.selected_body_id()) {
-                            space_map.focus(*entity);
-                            ctx.tree_state.focus_body(bodies.get(*entity).unwrap().0.id)
+                        let id = ctx.tree_state.selected_body_id();
+                        if let Some(entity) = object_entity(&id, &mapping, &lagrange_mapping) {
+                            space_map.focus(entity);
+                            ctx.tree_state.focus_body(id)
                         }
                     }
                     Autoscale => space_map.autoscale(&mapping.0, &bodies),
+                    ToggleSynodic => {
+                        let selected = ctx.tree_state.selected_body_id();
+                        // A Lagrange point is displayed in the synodic frame of its pair of bodies
+                        let pair = lagrange_mapping
+                            .0
+                            .get(&selected)
+                            .and_then(|e| points.get(*e).ok())
+                            .map(|point| (point.primary, point.secondary));
+                        if let Some((primary, secondary)) = pair.or_else(|| {
+                            mapping
+                                .0
+                                .get(&selected)
+                                .and_then(|e| Some((bodies.get(*e).ok()?.0.host_body?, *e)))
+                                .and_then(|(host, e)| Some((*mapping.0.get(&host)?, e)))
+                        }) {
+                            space_map.toggle_synodic(primary, secondary);
+                        }
+                    }
                 }
             }
             ExplorerEvent::View(event) => match *event {
@@ -324,23 +386,35 @@ fn handle_explorer_events(
 fn update_space_map(
     mut ctx: ResMut<ExplorerContext>,
     mut space_map: ResMut<SpaceMap>,
-    query: Query<(Entity, &Position, &BodyInfo)>,
+    query: Query<(Entity, &Position, &Velocity, &BodyInfo)>,
+    points: Query<(Entity, &Position, &LagrangeInfo)>,
     mapping: Res<BodiesMapping>,
+    lagrange_mapping: Res<LagrangeMapping>,
 ) {
-    space_map.selected = mapping.0.get(&ctx.selected_body()).cloned();
-    ctx.space_map.update_map(space_map.as_ref(), &query);
+    space_map.selected = object_entity(&ctx.selected_body(), &mapping, &lagrange_mapping);
+    ctx.space_map
+        .update_map(space_map.as_ref(), &query, &points);
+}
+
+fn update_drift(mut ctx: ResMut<ExplorerContext>, drift: Option<Res<NBodyDrift>>) {
+    ctx.info.drift = drift.map(|drift| *drift);
 }
 
 fn focus_on_select_body(
     mut events: EventReader<SelectObjectEvent>,
     info: Query<&BodyInfo>,
+    points: Query<&LagrangeInfo>,
     mut space_map: ResMut<SpaceMap>,
     mut ctx: ResMut<ExplorerContext>,
 ) {
     for event in events.read() {
-        if let Ok(info) = info.get(event.entity) {
+        let id = info
+            .get(event.entity)
+            .map(|info| info.0.id)
+            .or_else(|_| points.get(event.entity).map(|info| info.id));
+        if let Ok(id) = id {
             space_map.focus(event.entity);
-            ctx.tree_state.focus_body(info.0.id)
+            ctx.tree_state.focus_body(id)
         }
     }
 }
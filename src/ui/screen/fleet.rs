@@ -1,19 +1,21 @@
 use std::{error::Error, num::ParseFloatError};
 
 use arrayvec::CapacityError;
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
 use bevy_ratatui::event::KeyEvent;
 use crossterm::event::{KeyCode, KeyEventKind};
 use ratatui::{
     layout::{Alignment, Constraint, Layout},
     style::Stylize,
-    widgets::{Block, Clear, List, ListState, Paragraph, StatefulWidget, Widget},
+    text::Line,
+    widgets::{Block, Clear, List, ListState, Paragraph, StatefulWidget, Widget, WidgetRef},
 };
 
 use crate::{
-    objects::id::MAX_ID_LENGTH,
+    objects::{companies::CompaniesMapping, id::MAX_ID_LENGTH},
+    physics::orbit::OsculatingOrbit,
     prelude::*,
-    ui::UiUpdate,
+    ui::{widget::orbit::OrbitWidget, UiUpdate},
     utils::{algebra::circular_orbit_around_body, list::OptionsList, ui::centered_rect},
 };
 
@@ -31,11 +33,22 @@ pub fn plugin(app: &mut App) {
         )
         .add_systems(
             PostUpdate,
-            update_fleet_context
-                .run_if(state_exists::<GameStage>)
-                .run_if(
-                    state_changed::<GameStage>.or_else(resource_exists_and_changed::<ShipsMapping>),
+            (
+                update_fleet_context
+                    .run_if(state_exists::<GameStage>.and_then(resource_exists::<ShipsMapping>))
+                    .run_if(
+                        state_changed::<GameStage>
+                            .or_else(resource_exists_and_changed::<ShipsMapping>)
+                            .or_else(|owners: Query<(), Changed<Owner>>| !owners.is_empty()),
+                    ),
+                (
+                    update_fleet_orbits,
+                    update_fleet_propellant,
+                    update_fleet_balance.run_if(resource_exists::<CompaniesMapping>),
                 )
+                    .run_if(resource_exists::<FleetContext>),
+            )
+                .chain()
                 .in_set(UiUpdate),
         )
         .add_systems(OnEnter(InGame), create_screen)
@@ -49,11 +62,34 @@ fn create_screen(
     mut commands: Commands,
     mut next_screen: ResMut<NextState<AppScreen>>,
     ships: Query<&ShipInfo>,
+    owners: Query<&Owner>,
+    mapping: Option<Res<ShipsMapping>>,
+    player: Res<PlayerCompany>,
 ) {
-    commands.insert_resource(FleetContext::new(ships.iter().cloned()));
+    let owned = mapping.map_or(Vec::new(), |mapping| {
+        player_ships(&ships, &mapping, &owners, &player)
+    });
+    commands.insert_resource(FleetContext::new(owned.into_iter().cloned()));
     next_screen.set(AppScreen::Fleet);
 }
 
+/// Only the ships of the player are shown on the fleet screen
+fn player_ships<'a>(
+    ships: &'a Query<&ShipInfo>,
+    mapping: &ShipsMapping,
+    owners: &Query<&Owner>,
+    player: &PlayerCompany,
+) -> Vec<&'a ShipInfo> {
+    ships
+        .iter_many(
+            mapping
+                .owned_by(&player.0, owners)
+                .into_iter()
+                .map(|(_, e)| e),
+        )
+        .collect()
+}
+
 fn clear_screen(mut commands: Commands) {
     commands.remove_resource::<FleetContext>();
 }
@@ -64,6 +100,11 @@ pub struct FleetContext {
     ships: Vec<ShipInfo>,
     popup_context: Option<CreateShipContext>,
     stage: GameStage,
+    orbits: HashMap<ShipID, OrbitWidget>,
+    /// The remaining propellant (in kg) and delta-v budget (in km/d) of each ship
+    propellant: HashMap<ShipID, (f64, f64)>,
+    /// The credits of the company of the player
+    balance: Option<f64>,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -73,6 +114,7 @@ pub enum FleetScreenEvent {
     TryNewShip(CreateShipContext),
     EditTrajectory,
     EnterExplorer,
+    EnterContracts,
     Back,
 }
 
@@ -258,6 +300,9 @@ fn read_input(
                 e if keymap.enter_explorer.matches(e) => {
                     internal_event.send(EnterExplorer);
                 }
+                e if keymap.open_contracts.matches(e) => {
+                    internal_event.send(EnterContracts);
+                }
                 _ => {}
             },
             Some(ctx) => match event {
@@ -280,6 +325,7 @@ fn read_input(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn handle_fleet_events(
     mut context: ResMut<FleetContext>,
     mut next_screen: ResMut<NextState<AppScreen>>,
@@ -288,6 +334,7 @@ fn handle_fleet_events(
     mut ship_events: EventWriter<ShipEvent>,
     bodies: Query<(&Mass, &Position, &Velocity)>,
     mapping: Res<BodiesMapping>,
+    player: Res<PlayerCompany>,
 ) -> color_eyre::eyre::Result<()> {
     for event in events.read() {
         match event {
@@ -296,6 +343,10 @@ fn handle_fleet_events(
                 let info = ctx.to_info(context.ships.iter(), &bodies, mapping.as_ref())?;
                 context.ships.push(info.clone());
                 ship_events.send(ShipEvent::Create(info.clone()));
+                ship_events.send(ShipEvent::SetOwner {
+                    ship: info.id,
+                    owner: Some(player.0),
+                });
                 context.popup_context = None;
             }
             FleetScreenEvent::EditTrajectory => {
@@ -305,6 +356,7 @@ fn handle_fleet_events(
             }
             FleetScreenEvent::Back => next_mode.set(ClientMode::None),
             FleetScreenEvent::EnterExplorer => next_screen.set(AppScreen::Explorer),
+            FleetScreenEvent::EnterContracts => next_screen.set(AppScreen::Contracts),
         }
     }
     Ok(())
@@ -313,17 +365,62 @@ fn handle_fleet_events(
 fn update_fleet_context(
     stage: Res<State<GameStage>>,
     ships: Query<&ShipInfo>,
+    owners: Query<&Owner>,
+    mapping: Res<ShipsMapping>,
+    player: Res<PlayerCompany>,
     mut ctx: ResMut<FleetContext>,
 ) {
     ctx.stage = stage.get().clone();
-    ctx.ships.retain(|i| ships.iter().any(|j| i == j));
-    let diff = ships
-        .iter()
-        .find(|i| !ctx.ships.iter().any(|j| *i == j))
-        .cloned();
+    let owned = player_ships(&ships, &mapping, &owners, &player);
+    ctx.ships.retain(|i| owned.contains(&i));
+    let diff = owned.into_iter().find(|i| !ctx.ships.contains(i)).cloned();
     ctx.ships.extend(diff);
 }
 
+fn update_fleet_orbits(
+    mut ctx: ResMut<FleetContext>,
+    ships: Query<(&ShipInfo, &OsculatingOrbit)>,
+    bodies: Query<&BodyInfo>,
+) {
+    ctx.orbits = ships
+        .iter()
+        .filter_map(|(info, OsculatingOrbit { host, orbit })| {
+            bodies.get(*host).ok().map(|BodyInfo(data)| {
+                (
+                    info.id,
+                    OrbitWidget {
+                        host_name: data.name.clone(),
+                        orbit: orbit.clone(),
+                    },
+                )
+            })
+        })
+        .collect();
+}
+
+fn update_fleet_propellant(
+    mut ctx: ResMut<FleetContext>,
+    ships: Query<(&ShipInfo, &ShipMass, &Isp)>,
+) {
+    ctx.propellant = ships
+        .iter()
+        .map(|(info, mass, isp)| (info.id, (mass.propellant, mass.delta_v(isp))))
+        .collect();
+}
+
+fn update_fleet_balance(
+    mut ctx: ResMut<FleetContext>,
+    companies: Query<&Company>,
+    mapping: Res<CompaniesMapping>,
+    player: Res<PlayerCompany>,
+) {
+    ctx.balance = mapping
+        .0
+        .get(&player.0)
+        .and_then(|e| companies.get(*e).ok())
+        .map(Company::credits);
+}
+
 impl StatefulWidget for FleetScreen {
     type State = FleetContext;
 
@@ -338,21 +435,36 @@ impl StatefulWidget for FleetScreen {
 
         // Ship list
         let entries = state.ships.iter().map(|s| s.id.to_string());
+        let balance = state.balance.map_or("unknown".to_owned(), |credits| {
+            format!("{credits:.2} credits")
+        });
         let list = List::new(entries).highlight_symbol(">").block(
             Block::bordered()
                 .title_top("Ships")
+                .title_top(Line::from(format!("Balance: {balance}")).right_aligned())
                 .title_bottom(format!("Current stage: {}", state.stage)),
         );
         <List as StatefulWidget>::render(list, chunks[0], buf, &mut state.list_state);
 
         // Ship info
         if let Some(info) = state.selected_ship() {
+            let info_chunks =
+                Layout::vertical([Constraint::Length(6), Constraint::Fill(1)]).split(chunks[1]);
+            let propellant = state.propellant.get(&info.id).map_or(
+                "Propellant: unknown".to_owned(),
+                |(propellant, delta_v)| {
+                    format!("Propellant: {propellant:.0} kg (Δv budget: {delta_v:.0} km/d)")
+                },
+            );
             Paragraph::new(format!(
-                "ID: {}\nSpawn position: {}\nSpawn velocity: {}",
-                info.id, info.spawn_pos, info.spawn_speed
+                "ID: {}\nSpawn position: {}\nSpawn velocity: {}\n{}",
+                info.id, info.spawn_pos, info.spawn_speed, propellant
             ))
             .block(Block::bordered().title_top("Ship info"))
-            .render(chunks[1], buf);
+            .render(info_chunks[0], buf);
+            if let Some(orbit) = state.orbits.get(&info.id) {
+                orbit.render_ref(info_chunks[1], buf);
+            }
         }
 
         // Ship creation popup
@@ -434,6 +546,16 @@ mod tests {
             id: id_from("s"),
             ..default()
         }));
+        // Ships of other companies are not shown
+        app.world_mut().send_event(ShipEvent::Create(ShipInfo {
+            id: id_from("t"),
+            ..default()
+        }));
+        let player = app.world().resource::<PlayerCompany>().0;
+        app.world_mut().send_event(ShipEvent::SetOwner {
+            ship: id_from("s"),
+            owner: Some(player),
+        });
         app.world_mut()
             .resource_mut::<NextState<GameStage>>()
             .set(GameStage::Action);
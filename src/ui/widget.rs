@@ -1,4 +1,5 @@
 pub mod tree;
 pub mod search;
 pub mod space_map;
-pub mod info;
\ No newline at end of file
+pub mod info;
+pub mod orbit;
\ No newline at end of file
@@ -3,16 +3,18 @@ use ratatui::{
     widgets::{Block, Borders, Paragraph, WidgetRef},
 };
 
-use crate::objects::prelude::BodyData;
+use crate::{objects::prelude::BodyData, physics::nbody::NBodyDrift};
 
 pub struct InfoWidget {
     pub body_info: BodyData,
+    /// The accuracy of the N-body integration, if the bodies are not on rails
+    pub drift: Option<NBodyDrift>,
 }
 
 impl WidgetRef for InfoWidget {
     fn render_ref(&self, area: ratatui::layout::Rect, buf: &mut Buffer) {
         let body_info = &self.body_info;
-        let info = Paragraph::new(format!(
+        let mut text = format!(
             "Body type: {}\n\
             N of orbiting bodies: {}\n\
             Radius: {} km\n\
@@ -21,8 +23,11 @@ impl WidgetRef for InfoWidget {
             body_info.orbiting_bodies.len(),
             body_info.radius,
             body_info.revolution_period,
-        ))
-        .block(
+        );
+        if let Some(drift) = &self.drift {
+            text += &format!("\n\nN-body mode\n{drift}");
+        }
+        let info = Paragraph::new(text).block(
             Block::default()
                 .title(&body_info.name[..])
                 .borders(Borders::ALL),
@@ -0,0 +1,42 @@
+use ratatui::{
+    buffer::Buffer,
+    widgets::{Block, Borders, Paragraph, WidgetRef},
+};
+
+use crate::physics::orbit::{ConicType, EllipticalOrbit};
+
+/// Displays the osculating orbit of a ship around its host body
+#[derive(Clone)]
+pub struct OrbitWidget {
+    pub host_name: String,
+    pub orbit: EllipticalOrbit,
+}
+
+impl WidgetRef for OrbitWidget {
+    fn render_ref(&self, area: ratatui::layout::Rect, buf: &mut Buffer) {
+        let orbit = &self.orbit;
+        let (apoapsis, period) = match orbit.conic_type() {
+            ConicType::Elliptic => (
+                format!("{:.0} km", orbit.apoapsis()),
+                format!("{:.3} earth days", orbit.revolution_period),
+            ),
+            _ => ("none (open orbit)".into(), "none (open orbit)".into()),
+        };
+        let info = Paragraph::new(format!(
+            "Host body: {}\n\
+            Periapsis: {:.0} km\n\
+            Apoapsis: {}\n\
+            Eccentricity: {:.4}\n\
+            Inclination: {:.2}°\n\
+            Period: {}",
+            self.host_name,
+            orbit.periapsis,
+            apoapsis,
+            orbit.eccentricity,
+            orbit.inclination,
+            period,
+        ))
+        .block(Block::default().title("Orbit").borders(Borders::ALL));
+        info.render_ref(area, buf);
+    }
+}
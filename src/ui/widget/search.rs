@@ -39,11 +39,11 @@ struct SearchEntry {
     name: String,
 }
 
-impl From<&BodyData> for SearchEntry {
-    fn from(value: &BodyData) -> Self {
+impl From<(BodyID, &str)> for SearchEntry {
+    fn from((id, name): (BodyID, &str)) -> Self {
         Self {
-            id: value.id,
-            name: value.name.clone(),
+            id,
+            name: name.to_owned(),
         }
     }
 }
@@ -93,8 +93,9 @@ impl ClampedList for SearchState {
 }
 
 impl SearchState {
-    pub fn new<'a>(bodies: impl Iterator<Item = &'a BodyData>) -> SearchState {
-        let search_entries: Vec<_> = bodies.map(|data| data.into()).collect();
+    /// Creates the search over the given ids and names of bodies and other points of interest
+    pub fn new<'a>(entries: impl Iterator<Item = (BodyID, &'a str)>) -> SearchState {
+        let search_entries: Vec<_> = entries.map(|entry| entry.into()).collect();
         SearchState {
             search_entries,
             search_input: String::new(),
@@ -124,17 +125,17 @@ impl SearchState {
 
     pub fn update_search_entries<'a>(
         &mut self,
-        bodies: impl Iterator<Item = &'a BodyInfo>,
+        entries: impl Iterator<Item = (BodyID, &'a str)>,
         fuzzy_matcher: &SkimMatcherV2,
     ) {
-        let mut ids_score: Vec<_> = bodies
-            .filter_map(|BodyInfo(body)| {
+        let mut ids_score: Vec<_> = entries
+            .filter_map(|entry| {
                 fuzzy_matcher
-                    .fuzzy_match(&body.name, &self.search_input)
-                    .map(|score| (body, score))
+                    .fuzzy_match(entry.1, &self.search_input)
+                    .map(|score| (entry, score))
             })
             .collect();
-        ids_score.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+        ids_score.sort_by(|a, b| a.0 .1.cmp(b.0 .1));
         ids_score.sort_by(|a, b| a.1.cmp(&b.1).reverse());
         self.search_entries = ids_score.into_iter().map(|(data, _)| data.into()).collect();
         if self.list_state.selected().is_none() && !self.search_entries.is_empty() {
@@ -9,12 +9,12 @@ use ratatui::{
     style::{Color, Stylize},
     widgets::{
         block::Title,
-        canvas::{Canvas, Circle},
+        canvas::{Canvas, Circle, Points},
         Block, StatefulWidgetRef, WidgetRef,
     },
 };
 
-use crate::{prelude::*, utils::algebra::project_onto_plane};
+use crate::{physics::frames::ReferenceFrame, prelude::*, utils::algebra::project_onto_plane};
 
 pub const OFFSET_STEP: f64 = 1e8;
 pub const ZOOM_STEP: f64 = 1.5;
@@ -26,6 +26,8 @@ pub enum SpaceMapEvent {
     MapOffsetReset,
     FocusBody,
     Autoscale,
+    /// Switches between the frame centered on the focus body and the synodic frame of the selected body and its host
+    ToggleSynodic,
 }
 
 #[derive(Debug, Resource)]
@@ -35,6 +37,8 @@ pub struct SpaceMap {
     pub system_size: f64,
     pub focus_body: Option<Entity>,
     pub selected: Option<Entity>,
+    /// The frame in which positions are displayed
+    pub frame: ReferenceFrame,
 }
 
 impl SpaceMap {
@@ -45,6 +49,7 @@ impl SpaceMap {
             system_size,
             focus_body,
             selected,
+            frame: focus_body.map_or(ReferenceFrame::Inertial, ReferenceFrame::BodyCentered),
         }
     }
 
@@ -81,7 +86,7 @@ impl SpaceMap {
     }
 
     pub fn autoscale(&mut self, id_mapping: &HashMap<BodyID, Entity>, bodies: &Query<&BodyInfo>) {
-        if let Some(focus_data) = self.focus_body.map(|f| &bodies.get(f).unwrap().0) {
+        if let Some(BodyInfo(focus_data)) = self.focus_body.and_then(|f| bodies.get(f).ok()) {
             if let Some(max_dist) = focus_data
                 .orbiting_bodies
                 .iter()
@@ -101,27 +106,56 @@ impl SpaceMap {
     pub fn focus(&mut self, entity: Entity) {
         self.reset_offset();
         self.focus_body = Some(entity);
+        self.frame = ReferenceFrame::BodyCentered(entity);
+    }
+
+    /// Displays the map in the synodic frame of the two bodies, or goes back to the frame centered on the focus
+    /// body if it is already the case
+    pub fn toggle_synodic(&mut self, primary: Entity, secondary: Entity) {
+        self.reset_offset();
+        self.frame = match self.frame {
+            ReferenceFrame::Synodic { .. } => self
+                .focus_body
+                .map_or(ReferenceFrame::Inertial, ReferenceFrame::BodyCentered),
+            _ => ReferenceFrame::Synodic { primary, secondary },
+        };
     }
 }
 
 #[derive(Default)]
 pub struct SpaceMapWidget {
     circles: Vec<Circle>,
+    /// Lagrange points, which have no size
+    markers: Vec<(f64, f64)>,
+    selected_marker: Vec<(f64, f64)>,
 }
 
 impl SpaceMapWidget {
     pub fn update_map(
         &mut self,
         space_map: &SpaceMap,
-        query: &Query<(Entity, &Position, &BodyInfo)>,
+        query: &Query<(Entity, &Position, &Velocity, &BodyInfo)>,
+        points: &Query<(Entity, &Position, &LagrangeInfo)>,
     ) {
         let mut circles = Vec::new();
-        let &Position(focus_pos) = space_map
-            .focus_body
-            .map_or(&Position::default(), |f| query.get(f).unwrap().1);
-        for (entity, &Position(pos), BodyInfo(data)) in query.iter() {
-            let proj =
-                project_onto_plane(pos - focus_pos, (DVec3::X, DVec3::Y)) - space_map.offset_amount;
+        let frame = space_map.frame.state_with(|e| {
+            query
+                .get(e)
+                .ok()
+                .map(|(_, pos, speed, info)| (pos.0, speed.0, info.0.mass))
+                .or_else(|| {
+                    points
+                        .get(e)
+                        .ok()
+                        .map(|(_, pos, _)| (pos.0, DVec3::ZERO, 0.))
+                })
+        });
+        let project = |pos| {
+            let (pos, _) = frame.to_frame(pos, DVec3::ZERO);
+            project_onto_plane(pos, (DVec3::X, DVec3::Y)) - space_map.offset_amount
+        };
+        for (entity, &Position(pos), _, BodyInfo(data)) in query.iter() {
+            let proj = project(pos);
             let color = match data.body_type {
                 _ if Some(entity) == space_map.selected => Color::Red,
                 BodyType::Star => Color::Yellow,
@@ -137,6 +171,16 @@ impl SpaceMapWidget {
             });
         }
         self.circles = circles;
+        self.markers.clear();
+        self.selected_marker.clear();
+        for (entity, &Position(pos), _) in points.iter() {
+            let proj = project(pos);
+            if Some(entity) == space_map.selected {
+                self.selected_marker.push((proj.x, proj.y));
+            } else {
+                self.markers.push((proj.x, proj.y));
+            }
+        }
     }
 }
 
@@ -159,6 +203,14 @@ impl StatefulWidgetRef for SpaceMapWidget {
                 for circle in &self.circles {
                     ctx.draw(circle);
                 }
+                ctx.draw(&Points {
+                    coords: &self.markers,
+                    color: Color::Magenta,
+                });
+                ctx.draw(&Points {
+                    coords: &self.selected_marker,
+                    color: Color::Red,
+                });
             })
             .render_ref(area, buf)
     }
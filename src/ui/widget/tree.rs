@@ -83,10 +83,13 @@ impl StatefulWidget for TreeWidget {
 }
 
 impl TreeState {
+    /// Builds the tree of the bodies, where the Lagrange points of each body and its host are listed after the
+    /// children of the body
     pub fn new<'a>(
         primary: &'a BodyData,
         focus_body: Option<&'a BodyData>,
         bodies: impl Iterator<Item = &'a BodyData>,
+        lagrange_points: impl Iterator<Item = &'a LagrangeInfo>,
     ) -> TreeState {
         #[derive(Clone)]
         struct Temp {
@@ -115,6 +118,21 @@ impl TreeState {
                     .total_cmp(&info_bis[b].semimajor_axis)
             });
         }
+        let mut lagrange_points: Vec<_> = lagrange_points.collect();
+        lagrange_points.sort_by_key(|point| point.id);
+        for point in lagrange_points {
+            if let Some(parent) = info.get_mut(&point.parent) {
+                parent.children.push(point.id);
+                info.insert(
+                    point.id,
+                    Temp {
+                        children: Vec::new(),
+                        semimajor_axis: 0.,
+                        name: point.name.clone(),
+                    },
+                );
+            }
+        }
         fn fill_tree_rec(
             tree: &mut Vec<TreeEntry>,
             info: &HashMap<BodyID, Temp>,
@@ -344,11 +362,18 @@ mod tests {
         let world = app.world();
         let ctx = world.resource::<ExplorerContext>();
         let tree = &ctx.tree_state;
-        assert_eq!(tree.system_tree.len(), 9);
+        // Each planet has five Lagrange points with the Sun
+        assert_eq!(tree.system_tree.len(), 9 + 8 * 5);
         assert!(tree.system_tree[0].is_last_child);
-        assert!(tree.system_tree[8].is_last_child);
-        for i in 1..8 {
-            assert!(!tree.system_tree[i].is_last_child);
+        let planets: Vec<_> = tree
+            .system_tree
+            .iter()
+            .filter(|entry| entry.index_of_parent == Some(0))
+            .collect();
+        assert_eq!(planets.len(), 8);
+        assert!(planets[7].is_last_child);
+        for planet in &planets[..7] {
+            assert!(!planet.is_last_child);
         }
     }
     #[test]
@@ -371,9 +396,10 @@ mod tests {
         tree.toggle_selection_expansion();
         assert_eq!(tree.visible_tree_entries.len(), 9);
         assert!(tree.nth_visible_entry(0).unwrap().is_expanded);
-        for i in 1..9 {
-            tree.toggle_visible_entry_expansion(i);
-        }
+        // Expanding a planet shows its Lagrange points
+        tree.toggle_visible_entry_expansion(1);
+        assert_eq!(tree.visible_tree_entries.len(), 14);
+        tree.toggle_visible_entry_expansion(1);
         assert_eq!(tree.visible_tree_entries.len(), 9);
         tree.toggle_selection_expansion();
         assert_eq!(tree.visible_tree_entries.len(), 1);
@@ -391,7 +417,7 @@ mod tests {
 
         assert_eq!(
             tree.compute_deepness_map(tree.index_of(id_from("lune")).unwrap()),
-            vec![true, false, true]
+            vec![true, false, false]
         );
     }
 
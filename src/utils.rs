@@ -1,5 +1,6 @@
 pub mod algebra;
 pub mod args;
+pub mod bvh;
 pub mod de;
 pub mod ecs;
 pub mod hash;
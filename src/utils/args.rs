@@ -1,6 +1,12 @@
 use std::{env::Args, error::Error};
 
-use crate::input::prelude::Keymap;
+use arrayvec::ArrayString;
+
+use crate::{
+    input::prelude::Keymap,
+    objects::companies::PlayerCompany,
+    physics::{diagnostics::DiagnosticsFile, nbody::BodyDynamics, perturbations::Perturbations},
+};
 
 pub fn get_keymap(mut args: Args) -> Result<Keymap, Box<dyn Error>> {
     let mut keymap = Keymap::default();
@@ -19,3 +25,38 @@ pub fn get_keymap(mut args: Args) -> Result<Keymap, Box<dyn Error>> {
     }
     Ok(keymap)
 }
+
+/// The celestial bodies are integrated as an N-body system if the `--nbody` flag is given
+pub fn get_body_dynamics(mut args: Args) -> BodyDynamics {
+    if args.any(|arg| arg == "--nbody") {
+        BodyDynamics::NBody
+    } else {
+        BodyDynamics::OnRails
+    }
+}
+
+/// The file given with `--diagnostics <path>`, to record the orbit diagnostics of the ships
+pub fn get_diagnostics_file(mut args: Args) -> Option<DiagnosticsFile> {
+    args.position(|arg| arg == "--diagnostics")?;
+    args.next().map(|path| DiagnosticsFile(path.into()))
+}
+
+/// The atmospheric drag is only simulated if the `--drag` flag is given
+pub fn get_perturbations(mut args: Args) -> Perturbations {
+    Perturbations {
+        drag: args.any(|arg| arg == "--drag"),
+        ..Default::default()
+    }
+}
+
+/// The company played with `--company <name>`, which is "player" by default. Several clients of a server must play
+/// different companies
+pub fn get_player_company(mut args: Args) -> Result<PlayerCompany, Box<dyn Error>> {
+    if args.position(|arg| arg == "--company").is_none() {
+        return Ok(PlayerCompany::default());
+    }
+    let name = args.next().ok_or("Expected company name")?;
+    Ok(PlayerCompany(
+        ArrayString::from(&name).map_err(|_| "Company name too long")?,
+    ))
+}
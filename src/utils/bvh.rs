@@ -0,0 +1,220 @@
+//! A bounding volume hierarchy over spheres, finding the spheres containing a point.
+use std::ops::Range;
+
+use bevy::math::DVec3;
+
+/// Maximum number of spheres stored in a leaf of the hierarchy
+const LEAF_SIZE: usize = 4;
+
+/// A sphere carrying a value, usually the entity it belongs to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sphere<T> {
+    pub center: DVec3,
+    pub radius: f64,
+    pub value: T,
+}
+
+impl<T> Sphere<T> {
+    pub fn new(center: DVec3, radius: f64, value: T) -> Self {
+        Self {
+            center,
+            radius,
+            value,
+        }
+    }
+
+    pub fn contains(&self, point: DVec3) -> bool {
+        self.center.distance_squared(point) < self.radius * self.radius
+    }
+}
+
+#[derive(Clone, Debug)]
+enum NodeKind {
+    Leaf(Range<usize>),
+    Branch(usize, usize),
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    min: DVec3,
+    max: DVec3,
+    kind: NodeKind,
+}
+
+impl Node {
+    fn contains(&self, point: DVec3) -> bool {
+        point.cmpge(self.min).all() && point.cmple(self.max).all()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Bvh<T> {
+    spheres: Vec<Sphere<T>>,
+    nodes: Vec<Node>,
+}
+
+impl<T> Default for Bvh<T> {
+    fn default() -> Self {
+        Self {
+            spheres: Vec::new(),
+            nodes: Vec::new(),
+        }
+    }
+}
+
+impl<T> FromIterator<Sphere<T>> for Bvh<T> {
+    fn from_iter<I: IntoIterator<Item = Sphere<T>>>(iter: I) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+impl<T> Bvh<T> {
+    pub fn new(mut spheres: Vec<Sphere<T>>) -> Self {
+        let mut nodes = Vec::new();
+        if !spheres.is_empty() {
+            build(&mut spheres, 0, &mut nodes);
+        }
+        Self { spheres, nodes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.spheres.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spheres.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Sphere<T>> {
+        self.spheres.iter()
+    }
+
+    /// The spheres containing the point, in no particular order
+    pub fn containing(&self, point: DVec3) -> Containing<'_, T> {
+        Containing {
+            bvh: self,
+            point,
+            stack: if self.nodes.is_empty() {
+                Vec::new()
+            } else {
+                vec![0]
+            },
+            leaf: [].iter(),
+        }
+    }
+}
+
+/// Builds the subtree of the spheres, which start at the given offset in the whole list, and returns the index of its
+/// root. The spheres are split at the median of the axis along which their centers are the most spread out.
+fn build<T>(spheres: &mut [Sphere<T>], offset: usize, nodes: &mut Vec<Node>) -> usize {
+    let (min, max) = spheres.iter().fold(
+        (DVec3::INFINITY, DVec3::NEG_INFINITY),
+        |(min, max), s| {
+            (
+                min.min(s.center - s.radius),
+                max.max(s.center + s.radius),
+            )
+        },
+    );
+    let index = nodes.len();
+    nodes.push(Node {
+        min,
+        max,
+        kind: NodeKind::Leaf(offset..offset + spheres.len()),
+    });
+    if spheres.len() > LEAF_SIZE {
+        let (cmin, cmax) = spheres.iter().fold(
+            (DVec3::INFINITY, DVec3::NEG_INFINITY),
+            |(min, max), s| (min.min(s.center), max.max(s.center)),
+        );
+        let extent = cmax - cmin;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        let mid = spheres.len() / 2;
+        spheres.select_nth_unstable_by(mid, |a, b| a.center[axis].total_cmp(&b.center[axis]));
+        let (left, right) = spheres.split_at_mut(mid);
+        let left = build(left, offset, nodes);
+        let right = build(right, offset + mid, nodes);
+        nodes[index].kind = NodeKind::Branch(left, right);
+    }
+    index
+}
+
+/// Iterator over the spheres of a [Bvh] containing a point
+pub struct Containing<'a, T> {
+    bvh: &'a Bvh<T>,
+    point: DVec3,
+    stack: Vec<usize>,
+    leaf: std::slice::Iter<'a, Sphere<T>>,
+}
+
+impl<'a, T> Iterator for Containing<'a, T> {
+    type Item = &'a Sphere<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let point = self.point;
+        loop {
+            if let Some(sphere) = self.leaf.find(|s| s.contains(point)) {
+                return Some(sphere);
+            }
+            let node = &self.bvh.nodes[self.stack.pop()?];
+            if !node.contains(point) {
+                continue;
+            }
+            match &node.kind {
+                NodeKind::Leaf(range) => self.leaf = self.bvh.spheres[range.clone()].iter(),
+                NodeKind::Branch(left, right) => self.stack.extend([*right, *left]),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::DVec3;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{Bvh, Sphere};
+
+    #[test]
+    fn test_containing_matches_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut random_point = |scale: f64| {
+            DVec3::new(
+                rng.gen_range(-scale..scale),
+                rng.gen_range(-scale..scale),
+                rng.gen_range(-scale..scale),
+            )
+        };
+        let spheres: Vec<_> = (0..1000)
+            .map(|i| Sphere::new(random_point(100.), (i % 17) as f64, i))
+            .chain([Sphere::new(DVec3::ZERO, f64::INFINITY, 1000)])
+            .collect();
+        let bvh = Bvh::new(spheres.clone());
+        assert_eq!(bvh.len(), spheres.len());
+        for _ in 0..1000 {
+            let point = random_point(120.);
+            let mut found: Vec<_> = bvh.containing(point).map(|s| s.value).collect();
+            let mut expected: Vec<_> = spheres
+                .iter()
+                .filter(|s| s.contains(point))
+                .map(|s| s.value)
+                .collect();
+            found.sort();
+            expected.sort();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        let bvh = Bvh::<()>::default();
+        assert!(bvh.is_empty());
+        assert_eq!(bvh.containing(DVec3::ZERO).count(), 0);
+    }
+}